@@ -0,0 +1,373 @@
+//! `fontcull build`: scan, subset and write stylesheets as `fontcull.toml` says
+
+use std::path::{Path, PathBuf};
+
+use clap::ArgMatches;
+use color_eyre::eyre::{Context, Result, bail};
+
+use crate::{
+    GlyphSets, ScanResult,
+    cache::{Cache, CacheOptions},
+    capture::CaptureOptions,
+    config::{Config, FontGroup, OutputTarget},
+    crawl::CrawlOptions,
+    glyph_script::CharCounts,
+    klippa_backend, output,
+    output::SubsetFont,
+    page_settings::{BrowserOptions, PageOptions, PageRules},
+    serve::StaticServer,
+    verify, watch,
+};
+
+/// Run every step described by a project config file
+#[derive(clap::Args, Debug)]
+pub struct BuildCommand {
+    #[command(flatten)]
+    pub build: BuildArgs,
+
+    /// Build again whenever the config file, the served `dirs` or the source
+    /// fonts change
+    #[arg(long)]
+    pub watch: bool,
+
+    /// Load every page again with the subset fonts in place of the originals,
+    /// failing if any text falls back to another font
+    #[arg(long)]
+    pub verify: bool,
+}
+
+/// Options shared by the commands that read a project config file
+#[derive(clap::Args, Debug)]
+pub struct BuildArgs {
+    /// Project config file
+    #[arg(
+        long,
+        short = 'c',
+        default_value = "fontcull.toml",
+        value_name = "FILE"
+    )]
+    pub config: PathBuf,
+
+    /// Scan these URLs instead of the ones in the config file
+    pub urls: Vec<String>,
+
+    #[command(flatten)]
+    pub crawl: CrawlOptions,
+
+    #[command(flatten)]
+    pub capture: CaptureOptions,
+
+    #[command(flatten)]
+    pub page: PageOptions,
+
+    #[command(flatten)]
+    pub browser: BrowserOptions,
+
+    #[command(flatten)]
+    pub cache: CacheOptions,
+}
+
+/// Build the project, with options given on the command line (per `matches`)
+/// taking precedence over the config file
+pub async fn run(command: &BuildCommand, matches: &ArgMatches) -> Result<()> {
+    let args = &command.build;
+    if !command.watch {
+        let config = load_config(args, matches)?;
+        let cache = Cache::from_options(&config.cache)?;
+        return build(&config, cache.as_ref(), command.verify).await;
+    }
+
+    let config = load_config(args, matches)?;
+    let mut paths = vec![args.config.clone()];
+    paths.extend(config.scan.dirs.iter().cloned());
+    for group in &config.fonts {
+        paths.extend(group_sources(group)?);
+    }
+    // Without a cache directory, unchanged pages are still reused between runs
+    let cache = Cache::open(&match &config.cache.cache_dir {
+        Some(dir) => dir.clone(),
+        None => std::env::temp_dir().join(format!("fontcull-watch-{}", std::process::id())),
+    })?;
+
+    watch::watch(&paths, || async {
+        // Read again, as it may be one of the changed files
+        let config = load_config(args, matches)?;
+        build(&config, Some(&cache), command.verify).await
+    })
+    .await
+}
+
+/// Scan, subset every font group and write the outputs, then verify them if
+/// asked to
+async fn build(config: &Config, cache: Option<&Cache>, verify: bool) -> Result<()> {
+    let servers = serve(config).await?;
+    let scan = scan(config, &servers, cache).await?;
+
+    let mut fonts: Vec<(&str, SubsetFont)> = Vec::new();
+    for group in &config.fonts {
+        let chars = group_chars(group, &scan.glyph_sets);
+        let texts = group_texts(group, &scan.glyph_sets);
+        for source in group_sources(group)? {
+            fonts.push((
+                &group.name,
+                subset_source(group, &source, &chars, &texts, cache)?,
+            ));
+        }
+    }
+
+    write_outputs(&config.outputs, &fonts, &scan.char_counts)?;
+
+    if verify {
+        let fonts: Vec<SubsetFont> = fonts.into_iter().map(|(_, font)| font).collect();
+        let page_rules = PageRules::load(&config.page.options())?;
+        verify::run(
+            &scan,
+            &servers,
+            &fonts,
+            &config.capture,
+            &page_rules,
+            &config.browser,
+        )
+        .await?;
+    }
+    Ok(())
+}
+
+/// Load the config file and apply the command-line overrides
+pub fn load_config(args: &BuildArgs, matches: &ArgMatches) -> Result<Config> {
+    let mut config = Config::load(&args.config)?;
+    config.override_from_cli(
+        matches,
+        &args.crawl,
+        &args.capture,
+        &args.page,
+        &args.browser,
+        &args.cache,
+    );
+    if !args.urls.is_empty() {
+        config.scan.urls = args.urls.clone();
+    }
+    Ok(config)
+}
+
+/// Serve the configured directories
+pub async fn serve(config: &Config) -> Result<Vec<StaticServer>> {
+    let mut servers = Vec::new();
+    for dir in &config.scan.dirs {
+        servers.push(StaticServer::start(dir).await?);
+    }
+    Ok(servers)
+}
+
+/// Crawl the configured URLs and the served directories in a browser
+pub async fn scan(
+    config: &Config,
+    servers: &[StaticServer],
+    cache: Option<&Cache>,
+) -> Result<ScanResult> {
+    let mut seeds = config.scan.urls.clone();
+    for server in servers {
+        seeds.extend(server.html_pages()?);
+    }
+
+    if seeds.is_empty() {
+        tracing::info!("Nothing to scan, subsetting to the configured characters only");
+        return Ok(ScanResult::default());
+    }
+    let page_rules = PageRules::load(&config.page.options())?;
+    crate::scan(
+        &seeds,
+        servers,
+        &config.crawl,
+        &config.capture,
+        &page_rules,
+        &config.browser,
+        cache,
+    )
+    .await
+}
+
+/// Characters a group's fonts are subset to: what its families use on the
+/// pages, plus the configured extras
+pub fn group_chars(group: &FontGroup, glyph_sets: &GlyphSets) -> Vec<u32> {
+    let families = (!group.families.is_empty()).then(|| group.families.join(","));
+    let mut chars = glyph_sets.get_for_families(families.as_deref());
+    chars.extend(group.extra_chars());
+    chars.sort();
+    chars.dedup();
+    tracing::info!("Font group {}: {} characters", group.name, chars.len());
+    chars
+}
+
+/// Texts the text closure of a group's fonts is limited to: what its families
+/// render on the pages, if the group has `text_closure` on
+pub fn group_texts(group: &FontGroup, glyph_sets: &GlyphSets) -> Vec<String> {
+    if !group.subset.text_closure {
+        return Vec::new();
+    }
+    let families = (!group.families.is_empty()).then(|| group.families.join(","));
+    let texts = glyph_sets.get_texts_for_families(families.as_deref());
+    tracing::info!("Font group {}: {} texts", group.name, texts.len());
+    texts
+}
+
+/// Expand a group's source patterns; every pattern must match a file
+pub fn group_sources(group: &FontGroup) -> Result<Vec<PathBuf>> {
+    let mut sources = Vec::new();
+    for pattern in &group.sources {
+        let before = sources.len();
+        for entry in glob::glob(pattern).wrap_err("Invalid glob pattern")? {
+            sources.push(entry.wrap_err("Glob error")?);
+        }
+        if sources.len() == before {
+            bail!(
+                "No font files match {pattern:?} in font group {:?}",
+                group.name
+            );
+        }
+    }
+    Ok(sources)
+}
+
+/// Subset one source font of a group, writing each configured format
+pub fn subset_source(
+    group: &FontGroup,
+    source: &Path,
+    chars: &[u32],
+    texts: &[String],
+    cache: Option<&Cache>,
+) -> Result<SubsetFont> {
+    tracing::info!("Subsetting font: {}", source.display());
+
+    let encoded = klippa_backend::subset_to_formats(
+        source,
+        chars,
+        &group.subset.options(texts),
+        &group.formats,
+        cache,
+    )?;
+    let mut outputs = Vec::new();
+    for (&format, data) in group.formats.iter().zip(encoded) {
+        let path =
+            klippa_backend::output_file_name(&group.output, source, &group.name, format, &data)?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .wrap_err_with(|| format!("Failed to create {}", dir.display()))?;
+        }
+        std::fs::write(&path, &data)
+            .wrap_err_with(|| format!("Failed to write subset font: {}", path.display()))?;
+        tracing::info!("Created: {}", path.display());
+        outputs.push((path, format));
+    }
+
+    SubsetFont::describe(source, &outputs, group.families.clone(), chars)
+}
+
+/// Write `[[output]]` targets, each with the fonts of the groups it includes
+pub fn write_outputs<'a>(
+    targets: impl IntoIterator<Item = &'a OutputTarget>,
+    fonts: &[(&str, SubsetFont)],
+    char_counts: &CharCounts,
+) -> Result<()> {
+    for target in targets {
+        let mut selected: Vec<SubsetFont> = fonts
+            .iter()
+            .filter(|(group, _)| target.includes(group))
+            .map(|(_, font)| font.clone())
+            .collect();
+        output::write_all(&mut selected, &target.options(), char_counts)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, FromArgMatches};
+
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::{Args, Command};
+
+    #[tokio::test]
+    async fn test_build_without_scanning() {
+        let dir = std::env::temp_dir().join(format!("fontcull-build-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("fonts")).unwrap();
+        std::fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/fonts/Roboto-Regular.abc.ttf"),
+            dir.join("fonts/Roboto.ttf"),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("fontcull.toml"),
+            r#"
+                [[font]]
+                name = "body"
+                sources = ["fonts/*.ttf"]
+                whitelist = "ab"
+                formats = ["woff2", "ttf"]
+                output = "dist/{stem}-{group}.{ext}"
+
+                [[output]]
+                css = "dist/fonts.css"
+                manifest = "dist/fonts.json"
+            "#,
+        )
+        .unwrap();
+
+        let config = dir.join("fontcull.toml");
+        let matches = Args::command()
+            .try_get_matches_from(["fontcull", "build", "-c", config.to_str().unwrap()])
+            .unwrap();
+        let Some(Command::Build(args)) = Args::from_arg_matches(&matches).unwrap().command else {
+            panic!("expected the build subcommand");
+        };
+        run(&args, matches.subcommand_matches("build").unwrap())
+            .await
+            .unwrap();
+
+        let ttf = std::fs::read(dir.join("dist/Roboto-body.ttf")).unwrap();
+        let info = fontcull::font_info(&ttf).unwrap();
+        assert_eq!(info.codepoints, vec!['a' as u32, 'b' as u32]);
+        assert!(dir.join("dist/Roboto-body.woff2").is_file());
+
+        let css = std::fs::read_to_string(dir.join("dist/fonts.css")).unwrap();
+        assert!(css.contains(
+            r#"src: url("Roboto-body.woff2") format("woff2"), url("Roboto-body.ttf") format("truetype");"#
+        ));
+        assert!(css.contains("unicode-range: U+61-62;"));
+
+        let manifest = std::fs::read_to_string(dir.join("dist/fonts.json")).unwrap();
+        assert!(manifest.contains(r#""format": "ttf""#));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_group_texts() {
+        let mut glyph_sets = GlyphSets::default();
+        glyph_sets.merge_texts(&HashMap::from([
+            ("*".to_string(), vec!["fi".to_string(), "x".to_string()]),
+            ("Inter".to_string(), vec!["fi".to_string()]),
+            ("Mono".to_string(), vec!["x".to_string()]),
+        ]));
+        let group = |toml: &str| -> FontGroup {
+            toml::from_str(&format!("name = \"body\"\nsources = []\n{toml}")).unwrap()
+        };
+
+        let body = group("families = [\"Inter\"]\nsubset = { text_closure = true }");
+        assert_eq!(group_texts(&body, &glyph_sets), vec!["fi"]);
+        let any = group("subset = { text_closure = true }");
+        assert_eq!(group_texts(&any, &glyph_sets), vec!["fi", "x"]);
+        // Without the text closure, the texts aren't needed
+        assert!(group_texts(&group(""), &glyph_sets).is_empty());
+    }
+
+    #[test]
+    fn test_build_requires_no_urls() {
+        let matches = Args::command().try_get_matches_from(["fontcull", "build"]);
+        assert!(matches.is_ok());
+        let matches = Args::command().try_get_matches_from(["fontcull"]);
+        assert!(matches.is_err());
+    }
+}
@@ -0,0 +1,272 @@
+//! Persistent cache of page captures and subset fonts, so that unchanged pages
+//! aren't captured again and unchanged fonts aren't subset again
+
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use color_eyre::eyre::{Context, Result};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    capture::CaptureOptions, glyph_script::GLYPH_SCRIPT, page_settings::PageSettings,
+    serve::StaticServer,
+};
+
+/// Bumped by releases, as captures and subsets may differ between versions
+const CACHE_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Stands in for a served directory's origin in cached captures, as the local
+/// server gets a new port every run
+const SITE_ORIGIN: &str = "fontcull-site:/";
+
+/// Where to keep the cache
+#[derive(clap::Args, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheOptions {
+    /// Cache page captures and subset fonts in this directory, reusing them while
+    /// pages and fonts are unchanged
+    #[arg(long, value_name = "DIR")]
+    #[serde(rename = "dir")]
+    pub cache_dir: Option<PathBuf>,
+}
+
+/// A directory of cache entries, each a file named after its key
+#[derive(Debug)]
+pub struct Cache {
+    dir: PathBuf,
+}
+
+impl Cache {
+    pub fn open(dir: &Path) -> Result<Self> {
+        for kind in ["pages", "fonts"] {
+            std::fs::create_dir_all(dir.join(kind))
+                .wrap_err_with(|| format!("Failed to create cache {}", dir.display()))?;
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    pub fn from_options(options: &CacheOptions) -> Result<Option<Self>> {
+        options.cache_dir.as_deref().map(Self::open).transpose()
+    }
+
+    fn entry(&self, kind: &str, key: &str) -> PathBuf {
+        self.dir.join(kind).join(key)
+    }
+
+    /// A cached entry, if there is one
+    pub fn get(&self, kind: &str, key: &str) -> Option<Vec<u8>> {
+        std::fs::read(self.entry(kind, key)).ok()
+    }
+
+    /// Store an entry atomically, so an interrupted run can't leave half of one
+    pub fn put(&self, kind: &str, key: &str, data: &[u8]) -> Result<()> {
+        let path = self.entry(kind, key);
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, data)
+            .wrap_err_with(|| format!("Failed to write cache entry {}", tmp.display()))?;
+        std::fs::rename(&tmp, &path)
+            .wrap_err_with(|| format!("Failed to write cache entry {}", path.display()))
+    }
+}
+
+/// Hex SHA-256 of `parts`, each length-prefixed so that they can't run together
+pub fn key(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(CACHE_VERSION.as_bytes());
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    hasher
+        .finalize()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Decides which page captures can be reused: those whose URL, settings and
+/// content are the same as when they were cached
+pub struct PageCache<'a> {
+    cache: &'a Cache,
+    /// Served directories, with a hash of the assets every page may load
+    sites: Vec<(&'a StaticServer, String)>,
+    client: reqwest::Client,
+    hits: AtomicUsize,
+}
+
+impl<'a> PageCache<'a> {
+    pub fn new(cache: &'a Cache, servers: &'a [StaticServer]) -> Result<Self> {
+        let sites = servers
+            .iter()
+            .map(|server| Ok((server, server.asset_fingerprint()?)))
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            cache,
+            sites,
+            client: reqwest::Client::new(),
+            hits: AtomicUsize::new(0),
+        })
+    }
+
+    /// The served directory `url` belongs to, with its asset hash
+    fn site(&self, url: &str) -> Option<&(&'a StaticServer, String)> {
+        self.sites
+            .iter()
+            .find(|(server, _)| server.site_path(url).is_some())
+    }
+
+    /// The cache key for a page, or `None` if its content can't be fetched
+    ///
+    /// A page in a served directory is identified by its path, its file and the
+    /// directory's stylesheets and scripts. Any other page is identified by its
+    /// URL and HTML alone, so changes to the assets it loads go unnoticed.
+    pub async fn key(
+        &self,
+        url: &str,
+        settings: &PageSettings,
+        capture_options: &CaptureOptions,
+    ) -> Option<String> {
+        let (name, content, assets) = match self.site(url) {
+            Some((server, assets)) => (
+                server.site_path(url)?,
+                std::fs::read(server.file_for_url(url)?).ok()?,
+                assets.as_str(),
+            ),
+            None => {
+                let response = settings.http_request(&self.client, url).send().await.ok()?;
+                let response = response.error_for_status().ok()?;
+                (url.to_string(), response.bytes().await.ok()?.to_vec(), "")
+            }
+        };
+
+        Some(key(&[
+            name.as_bytes(),
+            &content,
+            assets.as_bytes(),
+            format!("{settings:?}").as_bytes(),
+            format!("{capture_options:?}").as_bytes(),
+            // a new script may capture more of the page
+            GLYPH_SCRIPT.as_bytes(),
+        ]))
+    }
+
+    /// A cached capture of `url`, counted as a hit
+    pub fn load<T: DeserializeOwned>(&self, url: &str, key: &str) -> Option<T> {
+        let mut json = String::from_utf8(self.cache.get("pages", key)?).ok()?;
+        if let Some((server, _)) = self.site(url) {
+            json = json.replace(SITE_ORIGIN, &server.url_for("/"));
+        }
+        let value = serde_json::from_str(&json).ok()?;
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(value)
+    }
+
+    /// Cache a capture of `url`; failing to is not worth failing the crawl for
+    pub fn store(&self, url: &str, key: &str, value: &impl Serialize) {
+        let result = serde_json::to_string(value)
+            .map_err(Into::into)
+            .and_then(|mut json| {
+                if let Some((server, _)) = self.site(url) {
+                    json = json.replace(&server.url_for("/"), SITE_ORIGIN);
+                }
+                self.cache.put("pages", key, json.as_bytes())
+            });
+        if let Err(e) = result {
+            tracing::warn!("Failed to cache page capture: {e}");
+        }
+    }
+
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key() {
+        assert_eq!(key(&[b"ab", b"c"]), key(&[b"ab", b"c"]));
+        assert_ne!(key(&[b"ab", b"c"]), key(&[b"a", b"bc"]));
+        assert_eq!(key(&[]).len(), 64);
+    }
+
+    #[test]
+    fn test_get_and_put() {
+        let dir = std::env::temp_dir().join(format!("fontcull-cache-{}", std::process::id()));
+        let cache = Cache::open(&dir).unwrap();
+        assert_eq!(cache.get("fonts", "k"), None);
+        cache.put("fonts", "k", b"data").unwrap();
+        assert_eq!(cache.get("fonts", "k").as_deref(), Some(&b"data"[..]));
+        assert_eq!(cache.get("pages", "k"), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    async fn page_key(cache: &Cache, server: &StaticServer, url: &str) -> String {
+        let page_cache = PageCache::new(cache, std::slice::from_ref(server)).unwrap();
+        page_cache
+            .key(url, &PageSettings::default(), &CaptureOptions::default())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_page_key_follows_content() {
+        let dir = std::env::temp_dir().join(format!("fontcull-page-key-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("index.html"), "<p>one</p>").unwrap();
+        std::fs::write(dir.join("site.css"), "p { color: red }").unwrap();
+
+        let cache = Cache::open(&dir.join("cache")).unwrap();
+        let server = StaticServer::start(&dir).await.unwrap();
+        let url = server.url_for("/");
+        let first = page_key(&cache, &server, &url).await;
+        assert_eq!(first, page_key(&cache, &server, &url).await);
+
+        std::fs::write(dir.join("index.html"), "<p>two</p>").unwrap();
+        let second = page_key(&cache, &server, &url).await;
+        assert_ne!(first, second);
+
+        std::fs::write(dir.join("site.css"), "p { color: blue }").unwrap();
+        let third = page_key(&cache, &server, &url).await;
+        assert_ne!(second, third);
+
+        // Fonts and images don't change what text a page shows
+        std::fs::write(dir.join("font.woff2"), "x").unwrap();
+        assert_eq!(third, page_key(&cache, &server, &url).await);
+
+        // Nor does the port the directory is served on
+        let other_server = StaticServer::start(&dir).await.unwrap();
+        let other_url = other_server.url_for("/");
+        assert_ne!(url, other_url);
+        assert_eq!(third, page_key(&cache, &other_server, &other_url).await);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_load_moves_captures_to_current_port() {
+        let dir = std::env::temp_dir().join(format!("fontcull-page-load-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cache = Cache::open(&dir.join("cache")).unwrap();
+
+        let first = [StaticServer::start(&dir).await.unwrap()];
+        let page_cache = PageCache::new(&cache, &first).unwrap();
+        let url = first[0].url_for("/a/");
+        page_cache.store(&url, "k", &vec![url.clone()]);
+
+        let second = [StaticServer::start(&dir).await.unwrap()];
+        let page_cache = PageCache::new(&cache, &second).unwrap();
+        let url = second[0].url_for("/a/");
+        assert_eq!(page_cache.load::<Vec<String>>(&url, "k"), Some(vec![url]));
+        assert_eq!(page_cache.hits(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,556 @@
+use std::{fmt, str::FromStr, time::Duration};
+
+use chromiumoxide::{
+    Page,
+    cdp::browser_protocol::{
+        css::ForcePseudoStateParams,
+        emulation::{
+            ClearDeviceMetricsOverrideParams, MediaFeature, SetDeviceMetricsOverrideParams,
+            SetTouchEmulationEnabledParams,
+        },
+        network::{EventLoadingFailed, EventLoadingFinished, EventRequestWillBeSent},
+    },
+};
+use color_eyre::eyre::{Context, Result, bail};
+use futures::StreamExt;
+use serde::Deserialize;
+
+use crate::glyph_script::{self, PageGlyphs};
+
+/// How long the network must stay quiet before a page is considered idle
+const NETWORK_IDLE_QUIET: Duration = Duration::from_millis(500);
+
+/// How long to let the page react after scrolling or clicking
+const SETTLE_DELAY: Duration = Duration::from_millis(250);
+
+/// Upper bound on scroll steps, for pages that keep growing (infinite scroll)
+const MAX_SCROLL_STEPS: usize = 50;
+
+/// Options controlling how many times, and in which states, each page is captured
+#[derive(clap::Args, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CaptureOptions {
+    /// Capture at these viewport sizes: `WIDTHxHEIGHT[@SCALE]`, prefix with `mobile:` to emulate a mobile device (repeatable)
+    #[arg(long = "viewport", value_name = "VIEWPORT")]
+    pub viewports: Vec<Viewport>,
+
+    /// Capture with these `prefers-color-scheme` values (repeatable)
+    #[arg(long = "color-scheme", value_name = "SCHEME")]
+    pub color_schemes: Vec<ColorScheme>,
+
+    /// Scroll through the page to trigger lazy-loaded content before capturing
+    #[arg(long)]
+    pub scroll: bool,
+
+    /// Wait until the network is idle before capturing
+    #[arg(long)]
+    pub wait_network_idle: bool,
+
+    /// Wait until an element matching this selector exists before capturing
+    #[arg(long, value_name = "SELECTOR")]
+    pub wait_for_selector: Option<String>,
+
+    /// Maximum time to wait for network idle or a selector, in milliseconds
+    #[arg(long, default_value = "10000", value_name = "MS")]
+    pub wait_timeout: u64,
+
+    /// Click every element matching this selector, then capture again (repeatable)
+    #[arg(long = "click", value_name = "SELECTOR")]
+    pub click: Vec<String>,
+
+    /// Force `:hover` on every element matching this selector, then capture again (repeatable)
+    #[arg(long = "hover", value_name = "SELECTOR")]
+    pub hover: Vec<String>,
+
+    /// Force `:focus` on every element matching this selector, then capture again (repeatable)
+    #[arg(long = "focus", value_name = "SELECTOR")]
+    pub focus: Vec<String>,
+}
+
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        Self {
+            viewports: Vec::new(),
+            color_schemes: Vec::new(),
+            scroll: false,
+            wait_network_idle: false,
+            wait_for_selector: None,
+            wait_timeout: 10000,
+            click: Vec::new(),
+            hover: Vec::new(),
+            focus: Vec::new(),
+        }
+    }
+}
+
+/// A viewport to emulate for one capture pass
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Viewport {
+    pub width: u32,
+    pub height: u32,
+    pub device_scale_factor: f64,
+    pub mobile: bool,
+}
+
+impl FromStr for Viewport {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (mobile, rest) = match s.strip_prefix("mobile:") {
+            Some(rest) => (true, rest),
+            None => (false, s),
+        };
+        let (size, scale) = match rest.split_once('@') {
+            Some((size, scale)) => (size, Some(scale)),
+            None => (rest, None),
+        };
+        let (width, height) = size
+            .split_once('x')
+            .ok_or_else(|| format!("invalid viewport {s:?}, expected WIDTHxHEIGHT[@SCALE]"))?;
+
+        let width: u32 = width
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid viewport width in {s:?}"))?;
+        let height: u32 = height
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid viewport height in {s:?}"))?;
+        let device_scale_factor = match scale {
+            Some(scale) => scale
+                .trim()
+                .parse()
+                .map_err(|_| format!("invalid device scale factor in {s:?}"))?,
+            None => 1.0,
+        };
+
+        if width == 0 || height == 0 || device_scale_factor <= 0.0 {
+            return Err(format!("viewport {s:?} must have a non-zero size"));
+        }
+
+        Ok(Viewport {
+            width,
+            height,
+            device_scale_factor,
+            mobile,
+        })
+    }
+}
+
+impl TryFrom<String> for Viewport {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Viewport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.mobile {
+            write!(f, "mobile:")?;
+        }
+        write!(f, "{}x{}", self.width, self.height)?;
+        if self.device_scale_factor != 1.0 {
+            write!(f, "@{}", self.device_scale_factor)?;
+        }
+        Ok(())
+    }
+}
+
+/// A `prefers-color-scheme` value to emulate
+#[derive(clap::ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorScheme {
+    Light,
+    Dark,
+}
+
+impl ColorScheme {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ColorScheme::Light => "light",
+            ColorScheme::Dark => "dark",
+        }
+    }
+}
+
+/// One combination of emulated viewport and color scheme
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Pass {
+    viewport: Option<Viewport>,
+    color_scheme: Option<ColorScheme>,
+}
+
+impl CaptureOptions {
+    /// Every viewport × color scheme combination, or a single default pass
+    fn passes(&self) -> Vec<Pass> {
+        let viewports: Vec<Option<Viewport>> = if self.viewports.is_empty() {
+            vec![None]
+        } else {
+            self.viewports.iter().copied().map(Some).collect()
+        };
+        let schemes: Vec<Option<ColorScheme>> = if self.color_schemes.is_empty() {
+            vec![None]
+        } else {
+            self.color_schemes.iter().copied().map(Some).collect()
+        };
+
+        let mut passes = Vec::new();
+        for &viewport in &viewports {
+            for &color_scheme in &schemes {
+                passes.push(Pass {
+                    viewport,
+                    color_scheme,
+                });
+            }
+        }
+        passes
+    }
+
+    fn wait_timeout(&self) -> Duration {
+        Duration::from_millis(self.wait_timeout)
+    }
+}
+
+/// Capture glyphs from a loaded page once per configured pass
+///
+/// Each pass applies its emulation, waits and scrolls as configured, and captures
+/// the page as-is; clicks and forced pseudo-classes then each add another capture.
+/// The page is reloaded between passes that click or force pseudo-classes, so
+/// that each pass starts from the page as loaded.
+/// The returned sets are meant to be merged by the caller.
+pub async fn capture_page(page: &Page, options: &CaptureOptions) -> Result<Vec<PageGlyphs>> {
+    let mut captures = Vec::new();
+    let interacts =
+        !options.click.is_empty() || !options.hover.is_empty() || !options.focus.is_empty();
+
+    for (i, pass) in options.passes().into_iter().enumerate() {
+        tracing::debug!(?pass, "Starting capture pass");
+        apply_pass(page, &pass).await?;
+        // Clicks toggle menus back and forced states stay set, so undo the
+        // previous pass's by loading the page again
+        if i > 0 && interacts {
+            page.reload().await.wrap_err("Failed to reload page")?;
+        }
+        wait_until_ready(page, options).await?;
+
+        if options.scroll {
+            scroll_through(page).await?;
+            if options.wait_network_idle {
+                wait_for_network_idle(page, options.wait_timeout()).await;
+            }
+        }
+
+        captures.push(glyph_script::extract_glyphs(page).await?);
+
+        if !options.click.is_empty() {
+            for selector in &options.click {
+                click_all(page, selector).await?;
+            }
+            if options.wait_network_idle {
+                wait_for_network_idle(page, options.wait_timeout()).await;
+            }
+            captures.push(glyph_script::extract_glyphs(page).await?);
+        }
+
+        for (state, selectors) in [("hover", &options.hover), ("focus", &options.focus)] {
+            if selectors.is_empty() {
+                continue;
+            }
+            for selector in selectors {
+                force_pseudo_state(page, selector, state).await?;
+            }
+            captures.push(glyph_script::extract_glyphs(page).await?);
+        }
+    }
+
+    Ok(captures)
+}
+
+async fn apply_pass(page: &Page, pass: &Pass) -> Result<()> {
+    match pass.viewport {
+        Some(viewport) => {
+            page.execute(SetDeviceMetricsOverrideParams::new(
+                viewport.width,
+                viewport.height,
+                viewport.device_scale_factor,
+                viewport.mobile,
+            ))
+            .await
+            .wrap_err_with(|| format!("Failed to emulate viewport {viewport}"))?;
+            page.execute(SetTouchEmulationEnabledParams::new(viewport.mobile))
+                .await
+                .wrap_err("Failed to set touch emulation")?;
+        }
+        None => {
+            page.execute(ClearDeviceMetricsOverrideParams::default())
+                .await
+                .wrap_err("Failed to clear viewport emulation")?;
+            page.execute(SetTouchEmulationEnabledParams::new(false))
+                .await
+                .wrap_err("Failed to set touch emulation")?;
+        }
+    }
+
+    // An empty value resets the feature to the browser default
+    let scheme = pass.color_scheme.map(|s| s.as_str()).unwrap_or_default();
+    page.emulate_media_features(vec![MediaFeature::new("prefers-color-scheme", scheme)])
+        .await
+        .wrap_err("Failed to emulate prefers-color-scheme")?;
+
+    Ok(())
+}
+
+pub async fn wait_until_ready(page: &Page, options: &CaptureOptions) -> Result<()> {
+    if options.wait_network_idle {
+        wait_for_network_idle(page, options.wait_timeout()).await;
+    }
+    if let Some(selector) = &options.wait_for_selector {
+        wait_for_selector(page, selector, options.wait_timeout()).await?;
+    }
+    Ok(())
+}
+
+/// Wait until no request has started or finished for [`NETWORK_IDLE_QUIET`]
+///
+/// Gives up silently after `timeout`: a page that never goes idle (polling,
+/// analytics beacons...) is still worth capturing.
+async fn wait_for_network_idle(page: &Page, timeout: Duration) {
+    let (Ok(started), Ok(finished), Ok(failed)) = (
+        page.event_listener::<EventRequestWillBeSent>().await,
+        page.event_listener::<EventLoadingFinished>().await,
+        page.event_listener::<EventLoadingFailed>().await,
+    ) else {
+        tracing::warn!("Could not listen to network events, not waiting for network idle");
+        return;
+    };
+
+    let mut events = futures::stream::select_all([
+        started.map(|e| (true, e.request_id.clone())).boxed(),
+        finished.map(|e| (false, e.request_id.clone())).boxed(),
+        failed.map(|e| (false, e.request_id.clone())).boxed(),
+    ]);
+
+    let mut in_flight = std::collections::HashSet::new();
+    let deadline = tokio::time::Instant::now() + timeout;
+
+    loop {
+        let quiet_until = tokio::time::Instant::now() + NETWORK_IDLE_QUIET;
+        match tokio::time::timeout_at(quiet_until.min(deadline), events.next()).await {
+            Ok(Some((true, id))) => {
+                in_flight.insert(id);
+            }
+            Ok(Some((false, id))) => {
+                in_flight.remove(&id);
+            }
+            Ok(None) => return,
+            Err(_) if in_flight.is_empty() => return,
+            Err(_) => {}
+        }
+        if tokio::time::Instant::now() >= deadline {
+            tracing::warn!(
+                in_flight = in_flight.len(),
+                "Timed out waiting for network idle"
+            );
+            return;
+        }
+    }
+}
+
+async fn wait_for_selector(page: &Page, selector: &str, timeout: Duration) -> Result<()> {
+    let deadline = tokio::time::Instant::now() + timeout;
+    loop {
+        if page.find_element(selector).await.is_ok() {
+            return Ok(());
+        }
+        if tokio::time::Instant::now() >= deadline {
+            bail!("Timed out waiting for selector {selector:?}");
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+}
+
+/// Scroll down one viewport at a time until the bottom stops moving
+async fn scroll_through(page: &Page) -> Result<()> {
+    for _ in 0..MAX_SCROLL_STEPS {
+        let at_bottom: bool = page
+            .evaluate(
+                r#"(() => {
+                    const before = window.scrollY;
+                    window.scrollBy(0, window.innerHeight);
+                    const root = document.scrollingElement || document.documentElement;
+                    return window.scrollY === before
+                        || window.scrollY + window.innerHeight >= root.scrollHeight;
+                })()"#,
+            )
+            .await
+            .wrap_err("Failed to scroll page")?
+            .into_value()
+            .wrap_err("Failed to get scroll result")?;
+
+        tokio::time::sleep(SETTLE_DELAY).await;
+        if at_bottom {
+            break;
+        }
+    }
+
+    // Capture and click from the top, as the page was before scrolling
+    page.evaluate("window.scrollTo(0, 0)")
+        .await
+        .wrap_err("Failed to scroll back to top")?;
+    Ok(())
+}
+
+async fn click_all(page: &Page, selector: &str) -> Result<()> {
+    let elements = page
+        .find_elements(selector)
+        .await
+        .wrap_err_with(|| format!("Failed to query {selector:?}"))?;
+    tracing::debug!(selector, count = elements.len(), "Clicking elements");
+
+    for element in elements {
+        // Hidden or detached elements can't be clicked; that's fine
+        if let Err(e) = element.click().await {
+            tracing::debug!(selector, "Could not click element: {e}");
+        }
+        tokio::time::sleep(SETTLE_DELAY).await;
+    }
+    Ok(())
+}
+
+async fn force_pseudo_state(page: &Page, selector: &str, state: &str) -> Result<()> {
+    page.enable_css()
+        .await
+        .wrap_err("Failed to enable CSS domain")?;
+    let elements = page
+        .find_elements(selector)
+        .await
+        .wrap_err_with(|| format!("Failed to query {selector:?}"))?;
+    tracing::debug!(
+        selector,
+        state,
+        count = elements.len(),
+        "Forcing pseudo-class"
+    );
+
+    for element in elements {
+        page.execute(ForcePseudoStateParams::new(
+            element.node_id,
+            vec![state.to_string()],
+        ))
+        .await
+        .wrap_err_with(|| format!("Failed to force :{state} on {selector:?}"))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{TestBrowser, has};
+
+    #[test]
+    fn test_parse_viewport() {
+        assert_eq!(
+            "1280x800".parse::<Viewport>().unwrap(),
+            Viewport {
+                width: 1280,
+                height: 800,
+                device_scale_factor: 1.0,
+                mobile: false,
+            }
+        );
+        assert_eq!(
+            "mobile:390x844@3".parse::<Viewport>().unwrap(),
+            Viewport {
+                width: 390,
+                height: 844,
+                device_scale_factor: 3.0,
+                mobile: true,
+            }
+        );
+        assert!("1280".parse::<Viewport>().is_err());
+        assert!("0x800".parse::<Viewport>().is_err());
+        assert!("axb".parse::<Viewport>().is_err());
+        assert!("1280x800@0".parse::<Viewport>().is_err());
+    }
+
+    #[test]
+    fn test_viewport_display_roundtrip() {
+        for s in ["1280x800", "mobile:390x844@3", "768x1024@1.5"] {
+            assert_eq!(s.parse::<Viewport>().unwrap().to_string(), s);
+        }
+    }
+
+    #[test]
+    fn test_passes_cartesian_product() {
+        let options = CaptureOptions {
+            viewports: vec![
+                "1280x800".parse().unwrap(),
+                "mobile:390x844".parse().unwrap(),
+            ],
+            color_schemes: vec![ColorScheme::Light, ColorScheme::Dark],
+            ..Default::default()
+        };
+        let passes = options.passes();
+        assert_eq!(passes.len(), 4);
+        assert_eq!(passes[1].color_scheme, Some(ColorScheme::Dark));
+        assert!(passes[3].viewport.unwrap().mobile);
+    }
+
+    #[test]
+    fn test_default_single_pass() {
+        let passes = CaptureOptions::default().passes();
+        assert_eq!(
+            passes,
+            vec![Pass {
+                viewport: None,
+                color_scheme: None
+            }]
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Chrome, run with --ignored"]
+    async fn test_capture_passes_and_interactions() {
+        let browser = TestBrowser::launch().await;
+        let page = browser.open("interactive.html").await;
+
+        let options = CaptureOptions {
+            viewports: vec![
+                "1280x800".parse().unwrap(),
+                "mobile:390x844@2".parse().unwrap(),
+            ],
+            color_schemes: vec![ColorScheme::Light, ColorScheme::Dark],
+            click: vec!["#reveal".to_string()],
+            hover: vec![".menu".to_string()],
+            focus: vec![".field".to_string()],
+            ..Default::default()
+        };
+        let captures = capture_page(&page, &options).await.unwrap();
+        browser.close().await;
+
+        // 4 passes, each with a base, click, hover and focus capture
+        assert_eq!(captures.len(), 16);
+        let found =
+            |family: &str, text: &str| captures.iter().any(|c| has(&c.glyphs, family, text));
+        assert!(found("Dark Font", "Ѧ"), "prefers-color-scheme: dark");
+        assert!(found("Mobile Font", "Ѩ"), "mobile viewport");
+        assert!(found("Page Font", "Ѭ"), "content revealed by click");
+        assert!(found("Hover Font", "Ѫ"), "forced :hover");
+        assert!(found("Focus Font", "Ѯ"), "forced :focus");
+        // The first pass is light and desktop-sized
+        assert!(!has(&captures[0].glyphs, "Dark Font", "Ѧ"));
+        assert!(!has(&captures[0].glyphs, "Mobile Font", "Ѩ"));
+        // Each pass starts from the loaded page: #reveal toggles the panel, so a
+        // click left over from the previous pass would hide it again
+        for pass in captures.chunks(4) {
+            assert!(!has(&pass[0].glyphs, "Page Font", "Ѭ"));
+            assert!(!has(&pass[0].glyphs, "Hover Font", "Ѫ"));
+            assert!(has(&pass[1].glyphs, "Page Font", "Ѭ"));
+        }
+    }
+}
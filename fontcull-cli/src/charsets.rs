@@ -0,0 +1,116 @@
+//! Named character sets that can be added to a subset regardless of what the
+//! scanned pages contain: `presets` and `languages` in `fontcull.toml`
+
+/// Names accepted by [`preset`]
+pub const PRESETS: &[&str] = &["ascii", "latin-1", "digits", "punctuation", "currency"];
+
+/// A predefined character set, by name
+pub fn preset(name: &str) -> Option<Vec<u32>> {
+    let chars: Vec<u32> = match name {
+        "ascii" => (0x20..=0x7E).collect(),
+        "latin-1" => (0x20..=0x7E).chain(0xA0..=0xFF).collect(),
+        "digits" => ('0' as u32..='9' as u32).collect(),
+        "punctuation" => r##" !"#%&'()*,-./:;?@[\]_{}¡§«·»¿‐–—‘’‚“”„†‡•…‰′″‹›"##
+            .chars()
+            .chain(['\u{A0}', '\u{202F}'])
+            .map(|c| c as u32)
+            .collect(),
+        "currency" => "$¢£¤¥₠₡₢₣₤₥₦₧₨₩₪₫€₭₮₯₰₱₲₳₴₵₶₷₸₹₺₻₼₽₾₿"
+            .chars()
+            .map(|c| c as u32)
+            .collect(),
+        _ => return None,
+    };
+    Some(chars)
+}
+
+const BASIC_LATIN: &str = "abcdefghijklmnopqrstuvwxyz";
+
+/// Lowercase letters (and language-specific quotes) needed to write a language,
+/// by ISO 639-1 code
+fn exemplar(code: &str) -> Option<(&'static str, &'static str)> {
+    let latin = |extra| Some((BASIC_LATIN, extra));
+    match code {
+        "en" => latin(""),
+        "de" => latin("äöüß„“‚‘ẞ"),
+        "fr" => latin("àâæçéèêëîïôœùûüÿ«»"),
+        "es" => latin("áéíñóúü¡¿«»"),
+        "it" => latin("àèéìíîòóùú«»"),
+        "pt" => latin("áâãàçéêíóôõú«»"),
+        "nl" => latin("áéíóúàèëïöü"),
+        "pl" => latin("ąćęłńóśźż„”"),
+        "cs" => latin("áčďéěíňóřšťúůýž„“"),
+        "sk" => latin("áäčďéíĺľňóôŕšťúýž„“"),
+        "sv" => latin("åäöé"),
+        "da" | "nb" | "no" => latin("æøåéóô«»"),
+        "fi" => latin("åäöšž"),
+        "hu" => latin("áéíóöőúüű„”"),
+        "ro" => latin("ăâîșț„”"),
+        "tr" => latin("çğıİöşü"),
+        "is" => latin("áðéíóúýþæö„“"),
+        "et" => latin("äõöüšž„“"),
+        "lv" => latin("āčēģīķļņšūž"),
+        "lt" => latin("ąčęėįšųūž„“"),
+        "hr" | "sl" => latin("čćđšž„“"),
+        "ru" => Some(("абвгдеёжзийклмнопрстуфхцчшщъыьэюя«»„“", "")),
+        "uk" => Some(("абвгґдеєжзиіїйклмнопрстуфхцчшщьюя«»ʼ", "")),
+        "bg" => Some(("абвгдежзийклмнопрстуфхцчшщъьюя„“", "")),
+        "el" => Some(("αβγδεζηθικλμνξοπρσςτυφχψωάέήίόύώϊϋΐΰ«»", "")),
+        _ => None,
+    }
+}
+
+/// Upper- and lowercase letters needed to write a language, by ISO 639-1 code
+/// (`de`, `pl`, `ru`, …). A region suffix such as `pt-BR` is ignored.
+pub fn language(code: &str) -> Option<Vec<u32>> {
+    let base = code.split(['-', '_']).next()?.to_ascii_lowercase();
+    let (letters, extra) = exemplar(&base)?;
+
+    let mut chars = Vec::new();
+    for c in letters.chars().chain(extra.chars()) {
+        chars.push(c as u32);
+        // Letters like ß uppercase to several characters; those are covered anyway
+        let mut upper = c.to_uppercase();
+        if let (Some(u), None) = (upper.next(), upper.next()) {
+            chars.push(u as u32);
+        }
+    }
+    chars.sort();
+    chars.dedup();
+    Some(chars)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn has(chars: &[u32], c: char) -> bool {
+        chars.contains(&(c as u32))
+    }
+
+    #[test]
+    fn test_presets() {
+        for name in PRESETS {
+            assert!(preset(name).is_some(), "{name}");
+        }
+        assert_eq!(preset("ascii").unwrap().len(), 95);
+        assert_eq!(preset("latin-1").unwrap().len(), 95 + 96);
+        assert!(has(&preset("currency").unwrap(), '€'));
+        assert!(has(&preset("punctuation").unwrap(), '—'));
+        assert!(preset("klingon").is_none());
+    }
+
+    #[test]
+    fn test_languages() {
+        let de = language("de").unwrap();
+        assert!(has(&de, 'ä') && has(&de, 'Ä') && has(&de, 'ß') && has(&de, 'Z'));
+
+        let tr = language("tr-TR").unwrap();
+        assert!(has(&tr, 'ı') && has(&tr, 'İ') && has(&tr, 'I'));
+
+        let ru = language("RU").unwrap();
+        assert!(has(&ru, 'ж') && has(&ru, 'Ж') && !has(&ru, 'a'));
+
+        assert!(language("xx").is_none());
+    }
+}
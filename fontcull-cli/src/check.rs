@@ -0,0 +1,463 @@
+//! `fontcull check`: verify that existing subset fonts still cover the text of
+//! the site, for CI
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use clap::ArgMatches;
+use color_eyre::eyre::{Context, Result, bail};
+
+use crate::{
+    GlyphSets, ScanResult, build,
+    build::BuildArgs,
+    cache::Cache,
+    config::FontGroup,
+    klippa_backend::{self, OutputFormat},
+    output::{self, SubsetFont},
+};
+
+/// How many pages to list per missing character
+const MAX_PAGES_SHOWN: usize = 3;
+
+/// Check the subset fonts described by a project config file
+#[derive(clap::Args, Debug)]
+pub struct CheckArgs {
+    #[command(flatten)]
+    pub build: BuildArgs,
+
+    /// Parse the HTML and CSS of the configured `dirs` instead of rendering pages
+    /// in a browser (faster, but misses text added by scripts)
+    #[arg(long = "static")]
+    pub static_analysis: bool,
+
+    /// Regenerate the fonts that are missing characters, and the stylesheets
+    /// that list them
+    #[arg(long)]
+    pub fix: bool,
+}
+
+/// A way an existing subset font falls short
+#[derive(Debug, PartialEq)]
+enum Problem {
+    /// No file matches the group's `output` template for this format
+    NotFound { pattern: String },
+    /// The file lacks characters that the source font has and the site uses
+    Missing { file: PathBuf, codepoints: Vec<u32> },
+}
+
+/// Check every font group, printing what's missing; fails unless everything is
+/// covered or `--fix` regenerated what wasn't
+pub async fn run(args: &CheckArgs, matches: &ArgMatches) -> Result<()> {
+    let config = build::load_config(&args.build, matches)?;
+    let cache = Cache::from_options(&config.cache)?;
+    let scan = if args.static_analysis {
+        if !config.scan.urls.is_empty() {
+            tracing::warn!(
+                "--static only analyses local `dirs`, skipping {} URLs",
+                config.scan.urls.len()
+            );
+        }
+        static_scan(&config.scan.dirs)?
+    } else {
+        let servers = build::serve(&config).await?;
+        build::scan(&config, &servers, cache.as_ref()).await?
+    };
+
+    let mut fonts: Vec<(&str, SubsetFont)> = Vec::new();
+    let mut affected_groups = BTreeSet::new();
+    let mut stale = 0;
+    for group in &config.fonts {
+        let chars = build::group_chars(group, &scan.glyph_sets);
+        let texts = build::group_texts(group, &scan.glyph_sets);
+        let families = (!group.families.is_empty()).then(|| group.families.join(","));
+        let pages: Vec<(&str, Vec<u32>)> = scan
+            .pages
+            .iter()
+            .map(|(name, glyphs)| (name.as_str(), glyphs.get_for_families(families.as_deref())))
+            .collect();
+
+        for source in build::group_sources(group)? {
+            let outputs = existing_outputs(group, &source)?;
+            let problems = check_source(group, &source, &chars, &outputs)?;
+            if problems.is_empty() {
+                if args.fix {
+                    let font =
+                        SubsetFont::describe(&source, &outputs, group.families.clone(), &chars)?;
+                    fonts.push((&group.name, font));
+                }
+                continue;
+            }
+
+            stale += 1;
+            print!("{}", report(group, &source, &problems, &pages));
+            if args.fix {
+                let font = build::subset_source(group, &source, &chars, &texts, cache.as_ref())?;
+                // A new content hash means a new file name; don't leave the old one behind
+                for (old, _) in &outputs {
+                    if !font.outputs.iter().any(|o| &o.file == old) {
+                        std::fs::remove_file(old)
+                            .wrap_err_with(|| format!("Failed to remove {}", old.display()))?;
+                    }
+                }
+                fonts.push((&group.name, font));
+                affected_groups.insert(group.name.as_str());
+            }
+        }
+    }
+
+    if stale == 0 {
+        println!("All subset fonts cover the site");
+        return Ok(());
+    }
+    if !args.fix {
+        bail!("{stale} subset font(s) don't cover the site, run with --fix to regenerate them");
+    }
+
+    let targets = config
+        .outputs
+        .iter()
+        .filter(|target| affected_groups.iter().any(|group| target.includes(group)));
+    build::write_outputs(targets, &fonts, &scan.char_counts)?;
+    println!("Regenerated {stale} subset font(s)");
+    Ok(())
+}
+
+/// The files previously written for `source`, one per format that has any; of
+/// several hashed versions, the newest
+fn existing_outputs(group: &FontGroup, source: &Path) -> Result<Vec<(PathBuf, OutputFormat)>> {
+    let mut outputs = Vec::new();
+    for &format in &group.formats {
+        let pattern =
+            klippa_backend::output_file_pattern(&group.output, source, &group.name, format)?;
+        let newest = glob::glob(&pattern)
+            .wrap_err("Invalid glob pattern")?
+            .filter_map(|entry| entry.ok())
+            .max_by_key(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok());
+        if let Some(path) = newest {
+            outputs.push((path, format));
+        }
+    }
+    Ok(outputs)
+}
+
+/// Compare the subset files of `source` with the characters it should have
+fn check_source(
+    group: &FontGroup,
+    source: &Path,
+    chars: &[u32],
+    outputs: &[(PathBuf, OutputFormat)],
+) -> Result<Vec<Problem>> {
+    let mut problems = Vec::new();
+    for &format in &group.formats {
+        if !outputs.iter().any(|(_, f)| *f == format) {
+            let pattern =
+                klippa_backend::output_file_pattern(&group.output, source, &group.name, format)?;
+            problems.push(Problem::NotFound { pattern });
+        }
+    }
+
+    // Characters the source font doesn't have can't be in the subset either
+    let available: BTreeSet<u32> = codepoints(source)?.into_iter().collect();
+    let wanted: Vec<u32> = chars
+        .iter()
+        .copied()
+        .filter(|c| available.contains(c))
+        .collect();
+
+    for (file, _) in outputs {
+        let covered: BTreeSet<u32> = codepoints(file)?.into_iter().collect();
+        let missing: Vec<u32> = wanted
+            .iter()
+            .copied()
+            .filter(|c| !covered.contains(c))
+            .collect();
+        if !missing.is_empty() {
+            problems.push(Problem::Missing {
+                file: file.clone(),
+                codepoints: missing,
+            });
+        }
+    }
+    Ok(problems)
+}
+
+fn codepoints(path: &Path) -> Result<Vec<u32>> {
+    let data = output::read_font(path)?;
+    let info = fontcull::font_info(&data)
+        .map_err(|e| color_eyre::eyre::eyre!("{}", e))
+        .wrap_err_with(|| format!("Failed to read font file: {}", path.display()))?;
+    Ok(info.codepoints)
+}
+
+/// Describe the problems with one source font's subsets, listing the pages each
+/// missing character appears on
+fn report(
+    group: &FontGroup,
+    source: &Path,
+    problems: &[Problem],
+    pages: &[(&str, Vec<u32>)],
+) -> String {
+    let mut out = String::new();
+    for problem in problems {
+        match problem {
+            Problem::NotFound { pattern } => {
+                writeln!(
+                    out,
+                    "{pattern} ({}, from {}) was not found",
+                    group.name,
+                    source.display()
+                )
+                .unwrap();
+            }
+            Problem::Missing { file, codepoints } => {
+                writeln!(
+                    out,
+                    "{} ({}, from {}) is missing {} character(s):",
+                    file.display(),
+                    group.name,
+                    source.display(),
+                    codepoints.len()
+                )
+                .unwrap();
+                for &cp in codepoints {
+                    let shown = char::from_u32(cp)
+                        .filter(|c| !c.is_control() && !c.is_whitespace())
+                        .map(|c| format!(" {c}"))
+                        .unwrap_or_default();
+                    let on: Vec<&str> = pages
+                        .iter()
+                        .filter(|(_, chars)| chars.contains(&cp))
+                        .map(|(name, _)| *name)
+                        .collect();
+                    let location = match on.len() {
+                        0 => "configured for the group, not on any page".to_string(),
+                        n if n <= MAX_PAGES_SHOWN => on.join(", "),
+                        n => format!(
+                            "{} and {} more pages",
+                            on[..MAX_PAGES_SHOWN].join(", "),
+                            n - MAX_PAGES_SHOWN
+                        ),
+                    };
+                    writeln!(out, "  U+{cp:04X}{shown}  {location}").unwrap();
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Characters per font family in the HTML files of `dirs`, found by parsing the
+/// markup and its stylesheets rather than rendering it
+fn static_scan(dirs: &[PathBuf]) -> Result<ScanResult> {
+    let mut result = ScanResult::default();
+    for dir in dirs {
+        let root = glob::Pattern::escape(&dir.to_string_lossy());
+        let mut files = Vec::new();
+        for ext in ["html", "htm"] {
+            for entry in
+                glob::glob(&format!("{root}/**/*.{ext}")).wrap_err("Invalid glob pattern")?
+            {
+                files.push(entry.wrap_err("Glob error")?);
+            }
+        }
+        files.sort();
+
+        for file in files {
+            let html = std::fs::read_to_string(&file)
+                .wrap_err_with(|| format!("Failed to read {}", file.display()))?;
+            let mut css = fontcull::extract_css_from_html(&html);
+            for href in fontcull::stylesheet_links(&html) {
+                let Some(path) = local_stylesheet(dir, &file, &href) else {
+                    tracing::debug!("Skipping remote stylesheet {href} in {}", file.display());
+                    continue;
+                };
+                match std::fs::read_to_string(&path) {
+                    Ok(text) => {
+                        css.push_str(&text);
+                        css.push('\n');
+                    }
+                    Err(e) => tracing::warn!("Failed to read {}: {e}", path.display()),
+                }
+            }
+
+            let glyphs: HashMap<String, Vec<u32>> = fontcull::collect_chars_per_font(&html, &css)
+                .into_iter()
+                .map(|(family, chars)| (family, chars.into_iter().map(|c| c as u32).collect()))
+                .collect();
+            let mut page = GlyphSets::default();
+            page.merge(glyphs.clone());
+            result.glyph_sets.merge(glyphs);
+
+            let relative = file.strip_prefix(dir).unwrap_or(&file);
+            let name = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+                .join("/");
+            result.pages.push((format!("/{name}"), page));
+        }
+    }
+    tracing::info!("Analysed {} pages", result.pages.len());
+    Ok(result)
+}
+
+/// The file a stylesheet `href` in `page` refers to, unless it's on another host
+fn local_stylesheet(root: &Path, page: &Path, href: &str) -> Option<PathBuf> {
+    if href.contains("://") || href.starts_with("//") || href.starts_with("data:") {
+        return None;
+    }
+    let path = href.split(['?', '#']).next()?;
+    Some(match path.strip_prefix('/') {
+        Some(absolute) => root.join(absolute),
+        None => page.parent()?.join(path),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/fonts/Roboto-Regular.abc.ttf")
+    }
+
+    fn group(output: &str) -> FontGroup {
+        toml::from_str(&format!(
+            "name = \"body\"\nsources = []\nformats = [\"ttf\", \"woff2\"]\noutput = {output:?}"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_check_source() {
+        let dir = std::env::temp_dir().join(format!("fontcull-check-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let group = group(&format!("{}/{{stem}}.{{hash}}.{{ext}}", dir.display()));
+
+        // An `a`-only subset, checked against a site that also uses `b` and `z`
+        let subset = build::subset_source(&group, &fixture(), &['a' as u32], &[], None).unwrap();
+        std::fs::remove_file(&subset.outputs[1].file).unwrap();
+
+        let outputs = existing_outputs(&group, &fixture()).unwrap();
+        assert_eq!(
+            outputs,
+            vec![subset.outputs[0].file.clone()]
+                .into_iter()
+                .map(|f| (f, OutputFormat::Ttf))
+                .collect::<Vec<_>>()
+        );
+
+        let problems = check_source(
+            &group,
+            &fixture(),
+            &['a' as u32, 'b' as u32, 'z' as u32],
+            &outputs,
+        )
+        .unwrap();
+        assert_eq!(problems.len(), 2);
+        assert!(
+            matches!(&problems[0], Problem::NotFound { pattern } if pattern.ends_with(".????????.woff2"))
+        );
+        // The source font has no `z`, so only `b` is missing
+        assert_eq!(
+            problems[1],
+            Problem::Missing {
+                file: outputs[0].0.clone(),
+                codepoints: vec!['b' as u32],
+            }
+        );
+
+        assert!(
+            check_source(&group, &fixture(), &['a' as u32], &outputs)
+                .unwrap()
+                .iter()
+                .all(|p| matches!(p, Problem::NotFound { .. }))
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_report() {
+        let group = group("{stem}.{ext}");
+        let problems = [Problem::Missing {
+            file: PathBuf::from("dist/Inter.woff2"),
+            codepoints: vec![0xE9, 0x20AC, 0xA0],
+        }];
+        let pages = [
+            ("/", vec![0xE9]),
+            ("/a/", vec![0xE9, 0xA0]),
+            ("/b/", vec![0xE9]),
+            ("/c/", vec![0xE9]),
+            ("/d/", vec![0x41]),
+        ];
+        let report = report(&group, Path::new("fonts/Inter.ttf"), &problems, &pages);
+        assert_eq!(
+            report,
+            "dist/Inter.woff2 (body, from fonts/Inter.ttf) is missing 3 character(s):\n\
+             \x20 U+00E9 é  /, /a/, /b/ and 1 more pages\n\
+             \x20 U+20AC €  configured for the group, not on any page\n\
+             \x20 U+00A0  /a/\n"
+        );
+    }
+
+    #[test]
+    fn test_static_scan() {
+        let dir = std::env::temp_dir().join(format!("fontcull-static-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("blog")).unwrap();
+        std::fs::write(dir.join("site.css"), "h1 { font-family: \"Display\"; }").unwrap();
+        std::fs::write(
+            dir.join("index.html"),
+            r#"<link rel="stylesheet" href="/site.css?v=1">
+               <style>body { font-family: "Body"; }</style>
+               <h1>Hi</h1><p>ok</p>"#,
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("blog/post.html"),
+            r#"<link rel="stylesheet" href="../site.css"><h1>Zz</h1>"#,
+        )
+        .unwrap();
+
+        let result = static_scan(std::slice::from_ref(&dir)).unwrap();
+        let names: Vec<&str> = result.pages.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, vec!["/blog/post.html", "/index.html"]);
+
+        let mut display = result.glyph_sets.get_for_families(Some("Display"));
+        display.sort();
+        assert_eq!(
+            display,
+            vec!['H' as u32, 'Z' as u32, 'i' as u32, 'z' as u32]
+        );
+        let mut body = result.glyph_sets.get_for_families(Some("Body"));
+        body.sort();
+        assert_eq!(body, vec!['k' as u32, 'o' as u32]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_local_stylesheet() {
+        let root = Path::new("site");
+        let page = Path::new("site/blog/post.html");
+        assert_eq!(
+            local_stylesheet(root, page, "/css/a.css?v=2"),
+            Some(PathBuf::from("site/css/a.css"))
+        );
+        assert_eq!(
+            local_stylesheet(root, page, "b.css#x"),
+            Some(PathBuf::from("site/blog/b.css"))
+        );
+        assert_eq!(
+            local_stylesheet(root, page, "https://cdn.example.com/c.css"),
+            None
+        );
+        assert_eq!(
+            local_stylesheet(root, page, "//cdn.example.com/c.css"),
+            None
+        );
+    }
+}
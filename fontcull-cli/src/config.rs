@@ -0,0 +1,534 @@
+//! `fontcull.toml`: a project's font groups, the pages to scan for them and the
+//! stylesheets to write, for `fontcull build`
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use clap::{ArgMatches, parser::ValueSource};
+use color_eyre::eyre::{Context, Result, bail};
+use fontcull::SubsetOptions;
+use serde::Deserialize;
+
+use crate::{
+    cache::CacheOptions,
+    capture::CaptureOptions,
+    charsets,
+    crawl::CrawlOptions,
+    klippa_backend::{OutputFormat, SubsetFlag},
+    output::{FallbackFont, OutputOptions},
+    page_settings::{BrowserOptions, PageOptions, PageSettings},
+};
+
+/// The whole config file
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub scan: ScanConfig,
+    pub crawl: CrawlOptions,
+    pub capture: CaptureOptions,
+    pub page: PageConfig,
+    pub browser: BrowserOptions,
+    pub cache: CacheOptions,
+    #[serde(rename = "font")]
+    pub fonts: Vec<FontGroup>,
+    #[serde(rename = "output")]
+    pub outputs: Vec<OutputTarget>,
+}
+
+/// Where to look for text
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ScanConfig {
+    /// URLs to start crawling from
+    pub urls: Vec<String>,
+    /// Local build directories to serve and crawl, like `--serve-dir`
+    pub dirs: Vec<PathBuf>,
+}
+
+/// The `[page]` table: page settings for every URL, plus an optional rules file
+#[derive(Deserialize, Debug, Default)]
+pub struct PageConfig {
+    #[serde(flatten)]
+    pub defaults: PageSettings,
+    #[serde(default)]
+    pub page_rules: Option<PathBuf>,
+    /// Keys that aren't page settings, reported as errors
+    #[serde(flatten)]
+    unknown: HashMap<String, toml::Value>,
+}
+
+impl PageConfig {
+    pub fn options(&self) -> PageOptions {
+        PageOptions {
+            defaults: self.defaults.clone(),
+            page_rules: self.page_rules.clone(),
+        }
+    }
+}
+
+/// A `[[font]]` table: source fonts subset to the same characters
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct FontGroup {
+    pub name: String,
+    /// Font files to subset (glob patterns supported)
+    pub sources: Vec<String>,
+    /// Only include glyphs used by these font families (default: all text)
+    #[serde(default)]
+    pub families: Vec<String>,
+    /// Additional characters to always include
+    #[serde(default)]
+    pub whitelist: String,
+    /// Named character sets to always include, see [`charsets::PRESETS`]
+    #[serde(default)]
+    pub presets: Vec<String>,
+    /// Letters of these languages to always include, e.g. `de` or `pl`
+    #[serde(default)]
+    pub languages: Vec<String>,
+    #[serde(default = "default_formats")]
+    pub formats: Vec<OutputFormat>,
+    /// Naming template for subset files, see [`crate::klippa_backend::output_file_name`]
+    #[serde(default = "default_output")]
+    pub output: String,
+    #[serde(default)]
+    pub subset: SubsetConfig,
+}
+
+fn default_formats() -> Vec<OutputFormat> {
+    vec![OutputFormat::Woff2]
+}
+
+fn default_output() -> String {
+    "{dir}/{stem}-subset.{ext}".to_string()
+}
+
+impl FontGroup {
+    /// Characters added to what the pages use, from `whitelist`, `presets` and
+    /// `languages`
+    pub fn extra_chars(&self) -> Vec<u32> {
+        let mut chars: Vec<u32> = self.whitelist.chars().map(|c| c as u32).collect();
+        for name in &self.presets {
+            chars.extend(charsets::preset(name).unwrap_or_default());
+        }
+        for code in &self.languages {
+            chars.extend(charsets::language(code).unwrap_or_default());
+        }
+        chars
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.sources.is_empty() {
+            bail!("Font group {:?} has no sources", self.name);
+        }
+        if self.formats.is_empty() {
+            bail!("Font group {:?} has no formats", self.name);
+        }
+        if let Some(name) = self.presets.iter().find(|p| charsets::preset(p).is_none()) {
+            bail!(
+                "Unknown preset {name:?} in font group {:?}, expected one of: {}",
+                self.name,
+                charsets::PRESETS.join(", ")
+            );
+        }
+        if let Some(code) = self
+            .languages
+            .iter()
+            .find(|l| charsets::language(l).is_none())
+        {
+            bail!("Unknown language {code:?} in font group {:?}", self.name);
+        }
+        Ok(())
+    }
+}
+
+/// klippa options for a font group, in hb-subset syntax
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct SubsetConfig {
+    pub flags: Vec<SubsetFlag>,
+    pub gids: String,
+    pub glyphs: String,
+    pub drop_tables: String,
+    pub layout_scripts: String,
+    pub layout_features: String,
+    pub name_ids: String,
+    pub name_languages: String,
+    /// Only add the glyphs GSUB substitutions can produce for the texts found
+    /// on the pages, e.g. a ligature only if its letters follow each other
+    pub text_closure: bool,
+}
+
+impl SubsetConfig {
+    /// The options for subsetting to `texts`, which only limit GSUB closure
+    /// with `text_closure`
+    pub fn options(&self, texts: &[String]) -> SubsetOptions {
+        SubsetOptions {
+            flags: SubsetFlag::combine(&self.flags),
+            gids: self.gids.clone(),
+            glyphs: self.glyphs.clone(),
+            drop_tables: self.drop_tables.clone(),
+            layout_scripts: self.layout_scripts.clone(),
+            layout_features: self.layout_features.clone(),
+            name_ids: self.name_ids.clone(),
+            name_languages: self.name_languages.clone(),
+            texts: if self.text_closure {
+                texts.to_vec()
+            } else {
+                Vec::new()
+            },
+        }
+    }
+}
+
+/// An `[[output]]` table: a stylesheet, preload tags and/or manifest for some
+/// (by default all) font groups
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct OutputTarget {
+    /// Names of the font groups to include
+    #[serde(default)]
+    pub fonts: Vec<String>,
+    pub css: Option<PathBuf>,
+    pub preload: Option<PathBuf>,
+    pub manifest: Option<PathBuf>,
+    pub font_url_prefix: Option<String>,
+    #[serde(default = "default_font_display")]
+    pub font_display: String,
+    pub fallback_font: Option<FallbackFont>,
+}
+
+fn default_font_display() -> String {
+    OutputOptions::default().font_display
+}
+
+impl OutputTarget {
+    pub fn includes(&self, group: &str) -> bool {
+        self.fonts.is_empty() || self.fonts.iter().any(|name| name == group)
+    }
+
+    pub fn options(&self) -> OutputOptions {
+        OutputOptions {
+            css: self.css.clone(),
+            preload: self.preload.clone(),
+            manifest: self.manifest.clone(),
+            // Hashes go in the font group's `output` template instead
+            hash_names: false,
+            font_url_prefix: self.font_url_prefix.clone(),
+            font_display: self.font_display.clone(),
+            fallback_font: self.fallback_font.clone(),
+        }
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read config {}", path.display()))?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        Self::parse(&text, dir).wrap_err_with(|| format!("Invalid config {}", path.display()))
+    }
+
+    fn parse(text: &str, dir: &Path) -> Result<Self> {
+        let mut config: Config = toml::from_str(text)?;
+        if let Some(key) = config.page.unknown.keys().next() {
+            bail!("Unknown key {key:?} in [page]");
+        }
+        for (i, group) in config.fonts.iter().enumerate() {
+            group.validate()?;
+            if config.fonts[..i].iter().any(|g| g.name == group.name) {
+                bail!("Duplicate font group {:?}", group.name);
+            }
+        }
+        for output in &config.outputs {
+            if let Some(name) = output
+                .fonts
+                .iter()
+                .find(|name| !config.fonts.iter().any(|g| &g.name == *name))
+            {
+                bail!("[[output]] refers to unknown font group {name:?}");
+            }
+        }
+        config.resolve_paths(dir);
+        Ok(config)
+    }
+
+    /// Make relative paths relative to the config file's directory
+    fn resolve_paths(&mut self, dir: &Path) {
+        let resolve = |path: &mut PathBuf| *path = dir.join(&*path);
+
+        self.scan.dirs.iter_mut().for_each(resolve);
+        self.crawl.state.iter_mut().for_each(resolve);
+        self.cache.cache_dir.iter_mut().for_each(resolve);
+        self.page.page_rules.iter_mut().for_each(resolve);
+        self.page.defaults.resolve_paths(dir);
+
+        let escaped_dir = glob::Pattern::escape(&dir.to_string_lossy());
+        for group in &mut self.fonts {
+            for source in &mut group.sources {
+                if !escaped_dir.is_empty() && Path::new(source.as_str()).is_relative() {
+                    *source = format!("{}/{}", escaped_dir, source);
+                }
+            }
+            // `{dir}` is the source's directory, which is already resolved
+            if !group.output.starts_with("{dir}") && Path::new(&group.output).is_relative() {
+                group.output = dir.join(&group.output).to_string_lossy().into_owned();
+            }
+        }
+
+        for output in &mut self.outputs {
+            output.css.iter_mut().for_each(resolve);
+            output.preload.iter_mut().for_each(resolve);
+            output.manifest.iter_mut().for_each(resolve);
+            if let Some(FallbackFont::File(path)) = &mut output.fallback_font {
+                resolve(path);
+            }
+        }
+    }
+}
+
+/// Replace fields of a config section with the values given explicitly on the
+/// command line; everything else keeps its value from the config file
+macro_rules! override_from_cli {
+    ($matches:expr, $config:expr, $cli:expr, [$($field:ident),* $(,)?]) => {
+        $(
+            if $matches.value_source(stringify!($field)) == Some(ValueSource::CommandLine) {
+                $config.$field = $cli.$field.clone();
+            }
+        )*
+    };
+}
+
+impl Config {
+    /// Apply the options given to `fontcull build` on top of the config file
+    pub fn override_from_cli(
+        &mut self,
+        matches: &ArgMatches,
+        crawl: &CrawlOptions,
+        capture: &CaptureOptions,
+        page: &PageOptions,
+        browser: &BrowserOptions,
+        cache: &CacheOptions,
+    ) {
+        override_from_cli!(
+            matches,
+            self.crawl,
+            crawl,
+            [
+                spider_limit,
+                max_depth,
+                concurrency,
+                sitemap,
+                respect_robots,
+                include,
+                exclude,
+                page_timeout,
+                retries,
+                state,
+            ]
+        );
+        override_from_cli!(
+            matches,
+            self.capture,
+            capture,
+            [
+                viewports,
+                color_schemes,
+                scroll,
+                wait_network_idle,
+                wait_for_selector,
+                wait_timeout,
+                click,
+                hover,
+                focus,
+            ]
+        );
+        override_from_cli!(
+            matches,
+            self.page.defaults,
+            page.defaults,
+            [
+                headers,
+                cookies,
+                basic_auth,
+                user_agent,
+                locale,
+                timezone,
+                init_scripts,
+            ]
+        );
+        override_from_cli!(matches, self.page, page, [page_rules]);
+        override_from_cli!(matches, self.browser, browser, [chrome, chrome_args]);
+        override_from_cli!(matches, self.cache, cache, [cache_dir]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{CommandFactory, FromArgMatches};
+
+    use super::*;
+
+    const EXAMPLE: &str = r#"
+        [scan]
+        urls = ["https://example.com/"]
+        dirs = ["dist"]
+
+        [crawl]
+        max_depth = 2
+        exclude = ["/admin/**"]
+
+        [capture]
+        viewports = ["mobile:390x844@3"]
+        color_schemes = ["dark"]
+        scroll = true
+
+        [page]
+        locale = "de-DE"
+        headers = ["X-Test: 1"]
+        cookies = "cookies.txt"
+
+        [browser]
+        chrome_args = ["--lang=de"]
+
+        [cache]
+        dir = ".fontcull-cache"
+
+        [[font]]
+        name = "body"
+        sources = ["fonts/Inter-*.woff2"]
+        families = ["Inter"]
+        presets = ["digits"]
+        languages = ["de"]
+        formats = ["woff2", "ttf"]
+        output = "dist/fonts/{stem}.{hash}.{ext}"
+        subset = { flags = ["no-hinting"], layout_features = "*", text_closure = true }
+
+        [[font]]
+        name = "code"
+        sources = ["/usr/share/fonts/mono.ttf"]
+
+        [[output]]
+        css = "dist/fonts.css"
+        fallback_font = "arial"
+        fonts = ["body"]
+    "#;
+
+    #[test]
+    fn test_parse_config() {
+        let config = Config::parse(EXAMPLE, Path::new("site")).unwrap();
+        assert_eq!(config.scan.dirs, vec![PathBuf::from("site/dist")]);
+        assert_eq!(config.crawl.max_depth, Some(2));
+        assert_eq!(config.crawl.concurrency, 4);
+        assert_eq!(config.capture.viewports[0].width, 390);
+        assert!(config.capture.scroll);
+        assert_eq!(config.page.defaults.locale.as_deref(), Some("de-DE"));
+        assert_eq!(config.page.defaults.headers[0].name, "X-Test");
+        assert_eq!(
+            config.page.defaults.cookies,
+            Some(PathBuf::from("site/cookies.txt"))
+        );
+        assert_eq!(config.browser.chrome_args, vec!["--lang=de"]);
+        assert_eq!(
+            config.cache.cache_dir,
+            Some(PathBuf::from("site/.fontcull-cache"))
+        );
+
+        let body = &config.fonts[0];
+        assert_eq!(body.sources, vec!["site/fonts/Inter-*.woff2"]);
+        assert_eq!(body.output, "site/dist/fonts/{stem}.{hash}.{ext}");
+        assert_eq!(body.formats, vec![OutputFormat::Woff2, OutputFormat::Ttf]);
+        let texts = vec!["fi".to_string()];
+        let options = body.subset.options(&texts);
+        assert_eq!(
+            options.flags,
+            fontcull::SubsetFlags::SUBSET_FLAGS_NO_HINTING
+        );
+        assert_eq!(options.texts, texts);
+        assert!(config.fonts[1].subset.options(&texts).texts.is_empty());
+        let extra = body.extra_chars();
+        assert!(extra.contains(&('7' as u32)) && extra.contains(&('Ä' as u32)));
+
+        let code = &config.fonts[1];
+        assert_eq!(code.sources, vec!["/usr/share/fonts/mono.ttf"]);
+        assert_eq!(code.output, "{dir}/{stem}-subset.{ext}");
+        assert_eq!(code.formats, vec![OutputFormat::Woff2]);
+
+        let output = &config.outputs[0];
+        assert!(output.includes("body") && !output.includes("code"));
+        assert_eq!(
+            output.options().css,
+            Some(PathBuf::from("site/dist/fonts.css"))
+        );
+        assert_eq!(output.options().font_display, "swap");
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let invalid = [
+            "[crawl]\nspider = 3",
+            "[page]\nlocal = \"de\"",
+            "[[font]]\nname = \"a\"\nsources = [\"a.ttf\"]\npresets = [\"emoji\"]",
+            "[[font]]\nname = \"a\"\nsources = [\"a.ttf\"]\nlanguages = [\"xx\"]",
+            "[[font]]\nname = \"a\"\nsources = []",
+            "[[font]]\nname = \"a\"\nsources = [\"a.ttf\"]\nsubset = { flags = [\"fast\"] }",
+            "[[font]]\nname = \"a\"\nsources = [\"a.ttf\"]\n[[font]]\nname = \"a\"\nsources = [\"b.ttf\"]",
+            "[[output]]\ncss = \"a.css\"\nfonts = [\"missing\"]",
+            "[[output]]\ncss = \"a.css\"\nhash_names = true",
+        ];
+        for text in invalid {
+            assert!(Config::parse(text, Path::new("")).is_err(), "{text}");
+        }
+        assert!(Config::parse("", Path::new("")).is_ok());
+    }
+
+    #[test]
+    fn test_cli_overrides_config() {
+        #[derive(clap::Parser)]
+        struct Cli {
+            #[command(flatten)]
+            crawl: CrawlOptions,
+            #[command(flatten)]
+            capture: CaptureOptions,
+            #[command(flatten)]
+            page: PageOptions,
+            #[command(flatten)]
+            browser: BrowserOptions,
+            #[command(flatten)]
+            cache: CacheOptions,
+        }
+
+        let matches = Cli::command().get_matches_from([
+            "fontcull",
+            "--concurrency",
+            "8",
+            "--locale",
+            "fr-FR",
+            "--viewport",
+            "1280x800",
+            "--cache-dir",
+            "/tmp/cache",
+        ]);
+        let cli = Cli::from_arg_matches(&matches).unwrap();
+
+        let mut config = Config::parse(EXAMPLE, Path::new("")).unwrap();
+        config.override_from_cli(
+            &matches,
+            &cli.crawl,
+            &cli.capture,
+            &cli.page,
+            &cli.browser,
+            &cli.cache,
+        );
+        assert_eq!(config.crawl.concurrency, 8);
+        assert_eq!(config.page.defaults.locale.as_deref(), Some("fr-FR"));
+        assert_eq!(config.capture.viewports.len(), 1);
+        assert_eq!(config.capture.viewports[0].width, 1280);
+        assert_eq!(config.cache.cache_dir, Some(PathBuf::from("/tmp/cache")));
+        // Not given on the command line, so the config file wins over defaults
+        assert_eq!(config.crawl.max_depth, Some(2));
+        assert!(config.capture.scroll);
+        assert_eq!(config.browser.chrome_args, vec!["--lang=de"]);
+    }
+}
@@ -0,0 +1,1015 @@
+use std::{
+    collections::{BTreeSet, HashMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use chromiumoxide::{Browser, Page};
+use color_eyre::eyre::{Context, Result, bail};
+use futures::{StreamExt, stream::FuturesUnordered};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+    cache::PageCache,
+    capture::{self, CaptureOptions},
+    font_faces::{self, FontFaceRule},
+    glyph_script::CharCounts,
+    page_settings::PageRules,
+};
+
+/// How often the crawl state is written to disk while crawling
+const STATE_SAVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// How deep to follow nested sitemap indexes
+const MAX_SITEMAP_NESTING: usize = 3;
+
+/// Options controlling which pages are visited, and how
+#[derive(clap::Args, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CrawlOptions {
+    /// Maximum number of pages to spider (0 = don't follow links unless --max-depth is set)
+    #[arg(long, default_value = "0")]
+    pub spider_limit: usize,
+
+    /// Follow links at most this many hops away from the starting URLs
+    #[arg(long, value_name = "N")]
+    pub max_depth: Option<usize>,
+
+    /// Number of pages to load concurrently, each in its own tab
+    #[arg(long, short = 'j', default_value = "4", value_name = "N")]
+    pub concurrency: usize,
+
+    /// Also crawl URLs listed in sitemaps (from robots.txt, or /sitemap.xml)
+    #[arg(long)]
+    pub sitemap: bool,
+
+    /// Skip URLs disallowed by robots.txt
+    #[arg(long)]
+    pub respect_robots: bool,
+
+    /// Only crawl URLs matching this pattern: a glob on the path (or full URL if it
+    /// contains `://`), or a regex with a `re:` prefix (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    pub include: Vec<UrlPattern>,
+
+    /// Never crawl URLs matching this pattern, same syntax as --include (repeatable)
+    #[arg(long, value_name = "PATTERN")]
+    pub exclude: Vec<UrlPattern>,
+
+    /// Give up on a page after this many seconds
+    #[arg(long, default_value = "60", value_name = "SECS")]
+    pub page_timeout: u64,
+
+    /// Number of times to retry a page that failed to load
+    #[arg(long, default_value = "2", value_name = "N")]
+    pub retries: usize,
+
+    /// Save crawl progress to this file, and resume from it if it exists
+    #[arg(long, value_name = "FILE")]
+    pub state: Option<PathBuf>,
+}
+
+impl Default for CrawlOptions {
+    fn default() -> Self {
+        Self {
+            spider_limit: 0,
+            max_depth: None,
+            concurrency: 4,
+            sitemap: false,
+            respect_robots: false,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            page_timeout: 60,
+            retries: 2,
+            state: None,
+        }
+    }
+}
+
+impl CrawlOptions {
+    fn follows_links(&self) -> bool {
+        self.spider_limit > 0 || self.max_depth.is_some()
+    }
+
+    fn allows_depth(&self, depth: usize) -> bool {
+        self.follows_links() && self.max_depth.is_none_or(|max| depth <= max)
+    }
+
+    fn allows_url(&self, url: &Url) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|p| p.matches(url)))
+            && !self.exclude.iter().any(|p| p.matches(url))
+    }
+}
+
+/// A URL filter given on the command line
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "String")]
+pub enum UrlPattern {
+    /// Matched against the path, or the full URL if the pattern contains `://`
+    Glob(glob::Pattern),
+    /// Searched for anywhere in the full URL
+    Regex(regex::Regex),
+}
+
+impl FromStr for UrlPattern {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("re:") {
+            Some(re) => regex::Regex::new(re)
+                .map(UrlPattern::Regex)
+                .map_err(|e| format!("invalid regex {re:?}: {e}")),
+            None => glob::Pattern::new(s)
+                .map(UrlPattern::Glob)
+                .map_err(|e| format!("invalid glob {s:?}: {e}")),
+        }
+    }
+}
+
+impl TryFrom<String> for UrlPattern {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl UrlPattern {
+    pub fn matches(&self, url: &Url) -> bool {
+        match self {
+            UrlPattern::Glob(pattern) if pattern.as_str().contains("://") => {
+                pattern.matches(url.as_str())
+            }
+            UrlPattern::Glob(pattern) => pattern.matches(url.path()),
+            UrlPattern::Regex(re) => re.is_match(url.as_str()),
+        }
+    }
+}
+
+/// Glyphs captured on one page
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CrawledPage {
+    /// The page's canonical URL, or its final URL after redirects
+    pub url: String,
+    /// One glyph set per capture pass
+    pub glyphs: Vec<HashMap<String, Vec<u32>>>,
+    /// `@font-face` rules declared by the page's stylesheets
+    #[serde(default)]
+    pub font_faces: Vec<FontFaceRule>,
+    /// How often each character occurs in the first capture pass, as the page
+    /// is first shown
+    #[serde(default)]
+    pub char_counts: CharCounts,
+    /// Distinct texts per font-family, over every capture pass
+    #[serde(default)]
+    pub texts: HashMap<String, Vec<String>>,
+}
+
+/// A URL waiting to be crawled
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+struct QueuedUrl {
+    url: String,
+    depth: usize,
+}
+
+/// Everything needed to resume an interrupted crawl
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct CrawlState {
+    /// URLs still to visit, in breadth-first order
+    queue: VecDeque<QueuedUrl>,
+    /// Every URL that was ever queued, to avoid queueing it twice
+    seen: HashSet<String>,
+    /// Pages crawled so far
+    pages: Vec<CrawledPage>,
+    /// URLs that failed after all retries
+    failed: Vec<String>,
+}
+
+impl CrawlState {
+    fn load(path: &Path) -> Result<Self> {
+        let data = std::fs::read(path)
+            .wrap_err_with(|| format!("Failed to read crawl state {}", path.display()))?;
+        serde_json::from_slice(&data)
+            .wrap_err_with(|| format!("Failed to parse crawl state {}", path.display()))
+    }
+
+    /// Write the state atomically, putting in-flight URLs back at the front of the queue
+    fn save(&self, path: &Path, in_flight: &[QueuedUrl]) -> Result<()> {
+        let mut queue: VecDeque<QueuedUrl> = in_flight.iter().cloned().collect();
+        queue.extend(self.queue.iter().cloned());
+        let snapshot = CrawlStateRef {
+            queue: &queue,
+            seen: &self.seen,
+            pages: &self.pages,
+            failed: &self.failed,
+        };
+
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec(&snapshot)?)
+            .wrap_err_with(|| format!("Failed to write crawl state {}", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .wrap_err_with(|| format!("Failed to write crawl state {}", path.display()))?;
+        Ok(())
+    }
+
+    /// Queue a URL unless it was seen before; returns whether it was queued
+    fn enqueue(&mut self, url: String, depth: usize) -> bool {
+        if !self.seen.insert(url.clone()) {
+            return false;
+        }
+        self.queue.push_back(QueuedUrl { url, depth });
+        true
+    }
+
+    fn pages_started(&self, in_flight: usize) -> usize {
+        self.pages.len() + self.failed.len() + in_flight
+    }
+}
+
+/// Borrowed view of [`CrawlState`] used for saving without cloning every page
+#[derive(Serialize)]
+struct CrawlStateRef<'a> {
+    queue: &'a VecDeque<QueuedUrl>,
+    seen: &'a HashSet<String>,
+    pages: &'a [CrawledPage],
+    failed: &'a [String],
+}
+
+/// A captured page as stored in the page cache
+#[derive(Serialize, Deserialize)]
+struct CachedVisit {
+    page: CrawledPage,
+    links: Vec<String>,
+}
+
+/// Result of visiting one page
+enum Visit {
+    Captured {
+        page: CrawledPage,
+        links: Vec<String>,
+    },
+    /// The page's canonical URL was already captured
+    Duplicate { canonical: String },
+}
+
+/// Crawl breadth-first from `seeds`, capturing glyphs on every page
+///
+/// With a `page_cache`, pages that haven't changed since they were cached are
+/// taken from it instead of being captured again.
+pub async fn crawl(
+    browser: &Browser,
+    seeds: &[String],
+    options: &CrawlOptions,
+    capture_options: &CaptureOptions,
+    page_rules: &PageRules,
+    page_cache: Option<&PageCache<'_>>,
+) -> Result<Vec<CrawledPage>> {
+    let mut state = match &options.state {
+        Some(path) if path.exists() => {
+            let state = CrawlState::load(path)?;
+            tracing::info!(
+                pages = state.pages.len(),
+                queued = state.queue.len(),
+                "Resuming crawl from {}",
+                path.display()
+            );
+            state
+        }
+        _ => CrawlState::default(),
+    };
+
+    let origins: HashSet<String> = seeds
+        .iter()
+        .filter_map(|s| Url::parse(s).ok())
+        .map(|u| u.origin().ascii_serialization())
+        .collect();
+
+    // Starting URLs bypass --include/--exclude: they were asked for explicitly
+    for seed in seeds {
+        let url = normalize_url(seed).ok_or_else(|| {
+            color_eyre::eyre::eyre!("Invalid URL {seed:?}, expected an http(s) URL")
+        })?;
+        state.enqueue(url, 0);
+    }
+
+    let client = reqwest::Client::new();
+    let mut robots: HashMap<String, Robots> = HashMap::new();
+    if options.respect_robots || options.sitemap {
+        for origin in &origins {
+            robots.insert(
+                origin.clone(),
+                fetch_robots(&client, page_rules, origin).await,
+            );
+        }
+    }
+
+    let allowed = |url: &Url| {
+        options.allows_url(url)
+            && origins.contains(&url.origin().ascii_serialization())
+            && (!options.respect_robots
+                || robots
+                    .get(&url.origin().ascii_serialization())
+                    .is_none_or(|r| r.is_allowed(url.path())))
+    };
+
+    if options.sitemap {
+        for origin in &origins {
+            let sitemaps = match robots.get(origin) {
+                Some(r) if !r.sitemaps.is_empty() => r.sitemaps.clone(),
+                _ => vec![format!("{origin}/sitemap.xml")],
+            };
+            let urls = fetch_sitemap_urls(&client, page_rules, sitemaps).await;
+            let mut added = 0;
+            for url in urls {
+                if let Some(url) = normalize_url(&url)
+                    && allowed(&Url::parse(&url).expect("normalized URLs parse"))
+                    && state.enqueue(url, 0)
+                {
+                    added += 1;
+                }
+            }
+            tracing::info!("Queued {added} URLs from sitemaps of {origin}");
+        }
+    }
+
+    let claimed: Mutex<HashSet<String>> =
+        Mutex::new(state.pages.iter().map(|p| p.url.clone()).collect());
+    let concurrency = options.concurrency.max(1);
+    let limit = if options.follows_links() {
+        options.spider_limit
+    } else {
+        0
+    };
+
+    let mut in_flight = FuturesUnordered::new();
+    let mut in_flight_urls: Vec<QueuedUrl> = Vec::new();
+    let mut last_save = Instant::now();
+
+    loop {
+        while in_flight.len() < concurrency
+            && (limit == 0 || state.pages_started(in_flight.len()) < limit)
+        {
+            let Some(item) = state.queue.pop_front() else {
+                break;
+            };
+            in_flight_urls.push(item.clone());
+            in_flight.push(visit(
+                browser,
+                item,
+                options,
+                capture_options,
+                page_rules,
+                &claimed,
+                page_cache,
+            ));
+        }
+
+        if in_flight.is_empty() {
+            break;
+        }
+
+        let (item, result) = tokio::select! {
+            Some(done) = in_flight.next() => done,
+            _ = tokio::signal::ctrl_c() => {
+                if let Some(path) = &options.state {
+                    state.save(path, &in_flight_urls)?;
+                    bail!("Interrupted, crawl state saved to {}", path.display());
+                }
+                bail!("Interrupted");
+            }
+        };
+        in_flight_urls.retain(|u| u != &item);
+
+        match result {
+            Ok(Visit::Captured { page, links }) => {
+                tracing::info!(depth = item.depth, "Captured {}", page.url);
+                state.seen.insert(page.url.clone());
+                state.pages.push(page);
+
+                if options.allows_depth(item.depth + 1) {
+                    for link in links {
+                        let Some(link) = normalize_url(&link) else {
+                            continue;
+                        };
+                        if allowed(&Url::parse(&link).expect("normalized URLs parse")) {
+                            state.enqueue(link, item.depth + 1);
+                        }
+                    }
+                }
+            }
+            Ok(Visit::Duplicate { canonical }) => {
+                tracing::debug!("Skipping {}, duplicate of {canonical}", item.url);
+            }
+            Err(e) => {
+                tracing::warn!("Failed to crawl {}: {e:?}", item.url);
+                state.failed.push(item.url);
+            }
+        }
+
+        if let Some(path) = &options.state
+            && last_save.elapsed() >= STATE_SAVE_INTERVAL
+        {
+            state.save(path, &in_flight_urls)?;
+            last_save = Instant::now();
+        }
+    }
+
+    if let Some(path) = &options.state {
+        state.save(path, &[])?;
+    }
+    if let Some(page_cache) = page_cache
+        && page_cache.hits() > 0
+    {
+        tracing::info!(
+            "Reused {} unchanged pages from the cache",
+            page_cache.hits()
+        );
+    }
+
+    if !state.failed.is_empty() {
+        tracing::warn!(
+            "{} pages failed to load: {}",
+            state.failed.len(),
+            state.failed.join(", ")
+        );
+        if state.pages.is_empty() {
+            bail!("Every page failed to load");
+        }
+    }
+
+    Ok(state.pages)
+}
+
+/// Visit a page, retrying on failure or timeout
+async fn visit(
+    browser: &Browser,
+    item: QueuedUrl,
+    options: &CrawlOptions,
+    capture_options: &CaptureOptions,
+    page_rules: &PageRules,
+    claimed: &Mutex<HashSet<String>>,
+    page_cache: Option<&PageCache<'_>>,
+) -> (QueuedUrl, Result<Visit>) {
+    let settings = page_rules.settings_for(&item.url);
+    let timeout = Duration::from_secs(options.page_timeout);
+    let mut last_err = None;
+
+    let cache_key = match page_cache {
+        Some(page_cache) => page_cache.key(&item.url, &settings, capture_options).await,
+        None => None,
+    };
+    if let (Some(page_cache), Some(key)) = (page_cache, &cache_key)
+        && let Some(CachedVisit { page, links }) = page_cache.load(&item.url, key)
+    {
+        if !claimed.lock().unwrap().insert(page.url.clone()) {
+            return (
+                item,
+                Ok(Visit::Duplicate {
+                    canonical: page.url,
+                }),
+            );
+        }
+        return (item, Ok(Visit::Captured { page, links }));
+    }
+
+    for attempt in 0..=options.retries {
+        if attempt > 0 {
+            tracing::info!(attempt, "Retrying {}", item.url);
+            tokio::time::sleep(Duration::from_millis(500 * (1 << attempt.min(4)))).await;
+        }
+
+        let page = match browser.new_page("about:blank").await {
+            Ok(page) => page,
+            Err(e) => {
+                last_err = Some(color_eyre::eyre::Report::new(e).wrap_err("Failed to open tab"));
+                continue;
+            }
+        };
+        if let Err(e) = settings.apply(&page).await {
+            page.close().await.ok();
+            last_err = Some(e);
+            continue;
+        }
+
+        let result = tokio::time::timeout(
+            timeout,
+            visit_once(&page, &item.url, capture_options, claimed),
+        )
+        .await;
+        page.close().await.ok();
+
+        match result {
+            Ok(Ok(Visit::Captured { page, links })) => {
+                let entry = CachedVisit { page, links };
+                if let (Some(page_cache), Some(key)) = (page_cache, &cache_key) {
+                    page_cache.store(&item.url, key, &entry);
+                }
+                let visit = Visit::Captured {
+                    page: entry.page,
+                    links: entry.links,
+                };
+                return (item, Ok(visit));
+            }
+            Ok(Ok(visit)) => return (item, Ok(visit)),
+            Ok(Err(e)) => last_err = Some(e),
+            Err(_) => {
+                last_err = Some(color_eyre::eyre::eyre!(
+                    "Timed out after {}s",
+                    options.page_timeout
+                ))
+            }
+        }
+    }
+
+    (item, Err(last_err.expect("at least one attempt was made")))
+}
+
+async fn visit_once(
+    page: &Page,
+    url: &str,
+    capture_options: &CaptureOptions,
+    claimed: &Mutex<HashSet<String>>,
+) -> Result<Visit> {
+    page.goto(url)
+        .await
+        .wrap_err_with(|| format!("Failed to navigate to {url}"))?;
+    page.wait_for_navigation().await.ok();
+
+    let (links, canonical) = page_links(page).await?;
+    let canonical = canonical
+        .as_deref()
+        .and_then(normalize_url)
+        .unwrap_or_else(|| url.to_string());
+
+    if !claimed.lock().unwrap().insert(canonical.clone()) {
+        return Ok(Visit::Duplicate { canonical });
+    }
+
+    let captures = capture::capture_page(page, capture_options).await?;
+    let char_counts = captures
+        .first()
+        .map(|c| c.counts.clone())
+        .unwrap_or_default();
+    let mut texts: HashMap<String, BTreeSet<String>> = HashMap::new();
+    for capture in &captures {
+        for (family, family_texts) in &capture.texts {
+            texts
+                .entry(family.clone())
+                .or_default()
+                .extend(family_texts.iter().cloned());
+        }
+    }
+    let texts = texts
+        .into_iter()
+        .map(|(family, texts)| (family, texts.into_iter().collect()))
+        .collect();
+    let glyphs = captures.into_iter().map(|c| c.glyphs).collect();
+    let font_faces = font_faces::extract_font_faces(page).await?;
+    Ok(Visit::Captured {
+        page: CrawledPage {
+            url: canonical,
+            glyphs,
+            font_faces,
+            char_counts,
+            texts,
+        },
+        links,
+    })
+}
+
+/// All link targets on the page, plus its canonical (or final) URL
+async fn page_links(page: &Page) -> Result<(Vec<String>, Option<String>)> {
+    let script = r#"
+        (() => {
+            const canonical = document.querySelector('link[rel~="canonical"][href]');
+            return {
+                links: Array.from(document.querySelectorAll('a[href], area[href]'), a => a.href),
+                canonical: canonical ? canonical.href : window.location.href,
+            };
+        })()
+    "#;
+
+    let result: serde_json::Value = page
+        .evaluate(script)
+        .await
+        .wrap_err("Failed to execute spider script")?
+        .into_value()
+        .wrap_err("Failed to get spider result")?;
+
+    let links = result["links"]
+        .as_array()
+        .map(|arr| {
+            arr.iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect()
+        })
+        .unwrap_or_default();
+    let canonical = result["canonical"].as_str().map(String::from);
+
+    Ok((links, canonical))
+}
+
+/// Normalize a URL for deduplication
+///
+/// Drops the fragment and any trailing slash (except for the root), and sorts
+/// query parameters. Returns `None` for anything that isn't http(s).
+pub fn normalize_url(url: &str) -> Option<String> {
+    let mut parsed = Url::parse(url).ok()?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return None;
+    }
+
+    parsed.set_fragment(None);
+
+    let mut pairs: Vec<(String, String)> = parsed.query_pairs().into_owned().collect();
+    if pairs.is_empty() {
+        parsed.set_query(None);
+    } else {
+        pairs.sort();
+        parsed.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+
+    let path = parsed.path().to_string();
+    if path != "/" && path.ends_with('/') {
+        parsed.set_path(path.trim_end_matches('/'));
+    }
+
+    Some(parsed.to_string())
+}
+
+/// The parts of a robots.txt that apply to us
+#[derive(Debug, Default)]
+struct Robots {
+    /// `(allow, pattern)` rules from the `*` group
+    rules: Vec<(bool, String)>,
+    /// `Sitemap:` entries, which apply regardless of group
+    sitemaps: Vec<String>,
+}
+
+impl Robots {
+    fn parse(text: &str) -> Self {
+        let mut robots = Robots::default();
+        // Whether the current group applies to every user agent
+        let mut in_wildcard_group = false;
+        // Consecutive User-agent lines share a group
+        let mut reading_agents = false;
+
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let Some((key, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+
+            match key.trim().to_ascii_lowercase().as_str() {
+                "user-agent" => {
+                    if !reading_agents {
+                        in_wildcard_group = false;
+                    }
+                    reading_agents = true;
+                    in_wildcard_group |= value == "*";
+                }
+                "allow" | "disallow" => {
+                    reading_agents = false;
+                    if in_wildcard_group && !value.is_empty() {
+                        let allow = key.trim().eq_ignore_ascii_case("allow");
+                        robots.rules.push((allow, value.to_string()));
+                    }
+                }
+                "sitemap" => robots.sitemaps.push(value.to_string()),
+                _ => reading_agents = false,
+            }
+        }
+
+        robots
+    }
+
+    /// The longest matching rule wins, and `Allow` wins ties
+    fn is_allowed(&self, path: &str) -> bool {
+        let mut best: Option<(usize, bool)> = None;
+        for (allow, pattern) in &self.rules {
+            if robots_pattern_matches(pattern, path) {
+                let len = pattern.len();
+                match best {
+                    Some((best_len, best_allow))
+                        if best_len > len || (best_len == len && best_allow) => {}
+                    _ => best = Some((len, *allow)),
+                }
+            }
+        }
+        best.is_none_or(|(_, allow)| allow)
+    }
+}
+
+/// Match a robots.txt path pattern, supporting `*` wildcards and a `$` end anchor
+fn robots_pattern_matches(pattern: &str, path: &str) -> bool {
+    let (pattern, anchored) = match pattern.strip_suffix('$') {
+        Some(p) => (p, true),
+        None => (pattern, false),
+    };
+
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = path.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<&str> = parts.collect();
+    for (i, part) in parts.iter().enumerate() {
+        let is_last = i == parts.len() - 1;
+        if is_last && anchored {
+            return rest.ends_with(part);
+        }
+        match rest.find(part) {
+            Some(idx) => rest = &rest[idx + part.len()..],
+            None => return false,
+        }
+    }
+
+    !anchored || rest.is_empty()
+}
+
+async fn fetch_text(client: &reqwest::Client, page_rules: &PageRules, url: &str) -> Option<String> {
+    let response = page_rules
+        .settings_for(url)
+        .http_request(client, url)
+        .send()
+        .await
+        .ok()?;
+    if !response.status().is_success() {
+        tracing::debug!(status = %response.status(), "Could not fetch {url}");
+        return None;
+    }
+    response.text().await.ok()
+}
+
+async fn fetch_robots(client: &reqwest::Client, page_rules: &PageRules, origin: &str) -> Robots {
+    match fetch_text(client, page_rules, &format!("{origin}/robots.txt")).await {
+        Some(text) => Robots::parse(&text),
+        None => Robots::default(),
+    }
+}
+
+/// Fetch sitemaps, following sitemap indexes, and return every page URL listed
+async fn fetch_sitemap_urls(
+    client: &reqwest::Client,
+    page_rules: &PageRules,
+    sitemaps: Vec<String>,
+) -> Vec<String> {
+    let mut urls = Vec::new();
+    let mut pending: Vec<(String, usize)> = sitemaps.into_iter().map(|s| (s, 0)).collect();
+    let mut fetched = HashSet::new();
+
+    while let Some((sitemap, nesting)) = pending.pop() {
+        if !fetched.insert(sitemap.clone()) {
+            continue;
+        }
+        let Some(xml) = fetch_text(client, page_rules, &sitemap).await else {
+            tracing::warn!("Could not fetch sitemap {sitemap}");
+            continue;
+        };
+
+        let (is_index, locs) = parse_sitemap(&xml);
+        if is_index {
+            if nesting < MAX_SITEMAP_NESTING {
+                pending.extend(locs.into_iter().map(|loc| (loc, nesting + 1)));
+            }
+        } else {
+            urls.extend(locs);
+        }
+    }
+
+    urls
+}
+
+/// Extract `<loc>` entries from a sitemap, and whether it's a sitemap index
+fn parse_sitemap(xml: &str) -> (bool, Vec<String>) {
+    let is_index = xml.contains("<sitemapindex");
+    let loc = regex::Regex::new(r"(?s)<loc>\s*(.*?)\s*</loc>").unwrap();
+    let locs = loc
+        .captures_iter(xml)
+        .map(|c| unescape_xml(c[1].trim_start_matches("<![CDATA[").trim_end_matches("]]>")))
+        .collect();
+    (is_index, locs)
+}
+
+fn unescape_xml(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{TestBrowser, has};
+
+    #[test]
+    fn test_normalize_url() {
+        assert_eq!(
+            normalize_url("https://Example.com/docs/?b=2&a=1#intro").as_deref(),
+            Some("https://example.com/docs?a=1&b=2")
+        );
+        assert_eq!(
+            normalize_url("https://example.com/").as_deref(),
+            Some("https://example.com/")
+        );
+        assert_eq!(
+            normalize_url("https://example.com:443/a").as_deref(),
+            Some("https://example.com/a")
+        );
+        assert_eq!(normalize_url("mailto:hi@example.com"), None);
+        assert_eq!(normalize_url("not a url"), None);
+    }
+
+    #[test]
+    fn test_url_patterns() {
+        let url = Url::parse("https://example.com/docs/guide/intro?x=1").unwrap();
+        assert!("/docs/**".parse::<UrlPattern>().unwrap().matches(&url));
+        assert!(!"/blog/*".parse::<UrlPattern>().unwrap().matches(&url));
+        assert!(
+            "https://example.com/docs/*"
+                .parse::<UrlPattern>()
+                .unwrap()
+                .matches(&url)
+        );
+        assert!(
+            r"re:/guide/\w+"
+                .parse::<UrlPattern>()
+                .unwrap()
+                .matches(&url)
+        );
+        assert!(!"re:^/guide".parse::<UrlPattern>().unwrap().matches(&url));
+        assert!("re:(".parse::<UrlPattern>().is_err());
+    }
+
+    #[test]
+    fn test_include_exclude() {
+        let options = CrawlOptions {
+            include: vec!["/docs/**".parse().unwrap()],
+            exclude: vec!["re:/docs/archive/".parse().unwrap()],
+            ..Default::default()
+        };
+        let allows = |s: &str| options.allows_url(&Url::parse(s).unwrap());
+        assert!(allows("https://example.com/docs/a"));
+        assert!(!allows("https://example.com/docs/archive/a"));
+        assert!(!allows("https://example.com/blog/a"));
+    }
+
+    #[test]
+    fn test_allows_depth() {
+        let no_spider = CrawlOptions::default();
+        assert!(!no_spider.allows_depth(1));
+
+        let limited = CrawlOptions {
+            spider_limit: 10,
+            ..Default::default()
+        };
+        assert!(limited.allows_depth(100));
+
+        let deep = CrawlOptions {
+            max_depth: Some(2),
+            ..Default::default()
+        };
+        assert!(deep.allows_depth(2));
+        assert!(!deep.allows_depth(3));
+    }
+
+    #[test]
+    fn test_robots_parse() {
+        let robots = Robots::parse(
+            "User-agent: Googlebot\n\
+             Disallow: /\n\
+             \n\
+             User-agent: Bingbot\n\
+             User-agent: *\n\
+             Disallow: /private/  # secret\n\
+             Allow: /private/public\n\
+             Disallow: /*.pdf$\n\
+             Disallow:\n\
+             \n\
+             Sitemap: https://example.com/sitemap-index.xml\n",
+        );
+        assert_eq!(
+            robots.sitemaps,
+            vec!["https://example.com/sitemap-index.xml"]
+        );
+        assert!(robots.is_allowed("/"));
+        assert!(robots.is_allowed("/docs"));
+        assert!(!robots.is_allowed("/private/notes"));
+        assert!(robots.is_allowed("/private/public/page"));
+        assert!(!robots.is_allowed("/files/report.pdf"));
+        assert!(robots.is_allowed("/files/report.pdf.html"));
+    }
+
+    #[test]
+    fn test_robots_pattern_matches() {
+        assert!(robots_pattern_matches("/a", "/abc"));
+        assert!(robots_pattern_matches("/a*c", "/abbbc"));
+        assert!(robots_pattern_matches("/a$", "/a"));
+        assert!(!robots_pattern_matches("/a$", "/ab"));
+        assert!(!robots_pattern_matches("/b", "/abc"));
+    }
+
+    #[test]
+    fn test_parse_sitemap() {
+        let (is_index, locs) = parse_sitemap(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+            <urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+              <url><loc>https://example.com/</loc></url>
+              <url><loc>
+                https://example.com/search?q=a&amp;page=2
+              </loc></url>
+              <url><loc><![CDATA[https://example.com/cdata]]></loc></url>
+            </urlset>"#,
+        );
+        assert!(!is_index);
+        assert_eq!(
+            locs,
+            vec![
+                "https://example.com/",
+                "https://example.com/search?q=a&page=2",
+                "https://example.com/cdata"
+            ]
+        );
+
+        let (is_index, locs) = parse_sitemap(
+            r#"<sitemapindex><sitemap><loc>https://example.com/a.xml</loc></sitemap></sitemapindex>"#,
+        );
+        assert!(is_index);
+        assert_eq!(locs, vec!["https://example.com/a.xml"]);
+    }
+
+    #[test]
+    fn test_state_enqueue_and_save() {
+        let dir = std::env::temp_dir().join(format!("fontcull-crawl-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state.json");
+
+        let mut state = CrawlState::default();
+        assert!(state.enqueue("https://example.com/".into(), 0));
+        assert!(!state.enqueue("https://example.com/".into(), 1));
+        assert!(state.enqueue("https://example.com/b".into(), 1));
+        let in_flight = state.queue.pop_front().unwrap();
+        state.pages.push(CrawledPage {
+            url: "https://example.com/a".into(),
+            glyphs: vec![HashMap::from([("*".to_string(), vec![65])])],
+            font_faces: Vec::new(),
+            char_counts: Default::default(),
+            texts: Default::default(),
+        });
+        state.save(&path, std::slice::from_ref(&in_flight)).unwrap();
+
+        let loaded = CrawlState::load(&path).unwrap();
+        // In-flight pages go back to the front of the queue
+        assert_eq!(loaded.queue.front(), Some(&in_flight));
+        assert_eq!(loaded.queue.len(), 2);
+        assert_eq!(loaded.pages.len(), 1);
+        assert!(loaded.seen.contains("https://example.com/b"));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Chrome, run with --ignored"]
+    async fn test_crawl_fixture_site() {
+        let browser = TestBrowser::launch().await;
+        let options = CrawlOptions {
+            max_depth: Some(1),
+            concurrency: 2,
+            retries: 0,
+            ..Default::default()
+        };
+        let seeds = vec![browser.url("site/index.html")];
+        let pages = crawl(
+            browser.browser(),
+            &seeds,
+            &options,
+            &Default::default(),
+            &Default::default(),
+            None,
+        )
+        .await
+        .unwrap();
+        browser.close().await;
+
+        let mut urls: Vec<&str> = pages.iter().map(|p| p.url.as_str()).collect();
+        urls.sort();
+        // b.html is canonically a.html, and deep.html is two hops away
+        assert_eq!(urls.len(), 2, "{urls:?}");
+        assert!(urls[0].ends_with("/site/a.html"));
+        assert!(urls[1].ends_with("/site/index.html"));
+        assert!(
+            !pages
+                .iter()
+                .flat_map(|p| &p.glyphs)
+                .any(|g| has(g, "*", "Ђ"))
+        );
+    }
+}
@@ -0,0 +1,251 @@
+//! `fontcull diff`: compare two fonts, e.g. a subset with its original or a
+//! previous release
+
+use std::{fmt::Write as _, path::PathBuf};
+
+use color_eyre::eyre::{Context, Result, bail};
+use fontcull::{FontDiff, GlyphAlignment, LayoutDiff};
+
+use crate::to_unicode_range;
+
+/// How many added, removed or changed glyphs are listed
+const MAX_GLYPHS_SHOWN: usize = 20;
+
+/// Longest list of code point ranges shown, in characters
+const MAX_RANGES_LEN: usize = 200;
+
+/// Compare the tables, metrics, cmap, glyphs and layout features of two fonts
+#[derive(clap::Args, Debug)]
+pub struct DiffArgs {
+    /// The old font (TTF, OTF, WOFF or WOFF2)
+    pub old: PathBuf,
+
+    /// The new font
+    pub new: PathBuf,
+
+    /// Print JSON instead of text
+    #[arg(long)]
+    pub json: bool,
+
+    /// Fail if the fonts differ, for CI
+    #[arg(long)]
+    pub exit_code: bool,
+}
+
+pub fn run(args: &DiffArgs) -> Result<()> {
+    let read = |path: &PathBuf| -> Result<Vec<u8>> {
+        let data = std::fs::read(path)
+            .wrap_err_with(|| format!("Failed to read font file: {}", path.display()))?;
+        fontcull::decompress_font(&data).map_err(|e| color_eyre::eyre::eyre!("{}", e))
+    };
+    let diff = fontcull::diff_fonts(&read(&args.old)?, &read(&args.new)?)
+        .map_err(|e| color_eyre::eyre::eyre!("{}", e))
+        .wrap_err_with(|| {
+            format!(
+                "Failed to compare {} with {}",
+                args.old.display(),
+                args.new.display()
+            )
+        })?;
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&diff)?);
+    } else {
+        print!("{}", describe(args, &diff));
+    }
+    if args.exit_code && !diff.is_empty() {
+        bail!("The fonts differ");
+    }
+    Ok(())
+}
+
+fn describe(args: &DiffArgs, diff: &FontDiff) -> String {
+    let mut out = format!("--- {}\n+++ {}\n", args.old.display(), args.new.display());
+    if diff.is_empty() {
+        out.push_str("No differences\n");
+        return out;
+    }
+
+    if !diff.tables.is_empty() {
+        out.push_str("Tables:\n");
+        for table in &diff.tables {
+            let _ = match (table.old_length, table.new_length) {
+                (None, Some(new)) => writeln!(out, "  + {}  {new} bytes", table.tag),
+                (Some(old), None) => writeln!(out, "  - {}  {old} bytes", table.tag),
+                (Some(old), Some(new)) if old == new => {
+                    writeln!(out, "  ~ {}  {old} bytes, contents differ", table.tag)
+                }
+                (old, new) => writeln!(
+                    out,
+                    "  ~ {}  {} -> {} bytes",
+                    table.tag,
+                    old.unwrap_or_default(),
+                    new.unwrap_or_default()
+                ),
+            };
+        }
+    }
+
+    if !diff.metrics.is_empty() {
+        out.push_str("Metrics:\n");
+        let value = |v: Option<f32>| v.map_or_else(|| "none".to_string(), |v| v.to_string());
+        for metric in &diff.metrics {
+            let _ = writeln!(
+                out,
+                "  ~ {}  {} -> {}",
+                metric.name,
+                value(metric.old),
+                value(metric.new)
+            );
+        }
+    }
+
+    let cmap = &diff.cmap;
+    if !cmap.added.is_empty() || !cmap.removed.is_empty() || !cmap.remapped.is_empty() {
+        out.push_str("cmap:\n");
+        for (sign, codepoints) in [("+", &cmap.added), ("-", &cmap.removed)] {
+            if !codepoints.is_empty() {
+                let _ = writeln!(
+                    out,
+                    "  {sign} {} code points: {}",
+                    codepoints.len(),
+                    ranges(codepoints)
+                );
+            }
+        }
+        if !cmap.remapped.is_empty() {
+            let _ = writeln!(
+                out,
+                "  ~ {} code points remapped: {}",
+                cmap.remapped.len(),
+                ranges(&cmap.remapped)
+            );
+        }
+    }
+
+    let glyphs = &diff.glyphs;
+    let _ = writeln!(
+        out,
+        "Glyphs (matched by {}): {} added, {} removed, {} changed, {} unchanged",
+        match glyphs.aligned_by {
+            GlyphAlignment::Name => "name",
+            GlyphAlignment::Codepoint => "code point",
+        },
+        glyphs.added.len(),
+        glyphs.removed.len(),
+        glyphs.changed.len(),
+        glyphs.unchanged
+    );
+    let changed: Vec<String> = glyphs
+        .changed
+        .iter()
+        .map(|change| {
+            let mut parts = Vec::new();
+            if change.outline {
+                parts.push("outline".to_string());
+            }
+            if let Some((old, new)) = change.advance {
+                parts.push(format!("advance {old} -> {new}"));
+            }
+            format!("{}  {}", change.glyph, parts.join(", "))
+        })
+        .collect();
+    for (sign, list) in [
+        ("+", &glyphs.added),
+        ("-", &glyphs.removed),
+        ("~", &changed),
+    ] {
+        for glyph in list.iter().take(MAX_GLYPHS_SHOWN) {
+            let _ = writeln!(out, "  {sign} {glyph}");
+        }
+        if list.len() > MAX_GLYPHS_SHOWN {
+            let _ = writeln!(out, "  {sign} … and {} more", list.len() - MAX_GLYPHS_SHOWN);
+        }
+    }
+
+    for (tag, layout) in [("GSUB", &diff.gsub), ("GPOS", &diff.gpos)] {
+        if let Some(layout) = layout {
+            out.push_str(&describe_layout(tag, layout));
+        }
+    }
+    out
+}
+
+/// Code points as `unicode-range` syntax, cut short after a few lines
+fn ranges(codepoints: &[u32]) -> String {
+    let ranges = to_unicode_range(codepoints.to_vec());
+    if ranges.len() <= MAX_RANGES_LEN {
+        return ranges;
+    }
+    let end = ranges[..MAX_RANGES_LEN]
+        .rfind(',')
+        .unwrap_or(MAX_RANGES_LEN);
+    format!("{},…", &ranges[..end])
+}
+
+fn describe_layout(tag: &str, layout: &LayoutDiff) -> String {
+    let mut out = format!("{tag}:\n");
+    for (sign, what, tags) in [
+        ("+", "scripts", &layout.scripts_added),
+        ("-", "scripts", &layout.scripts_removed),
+        ("+", "features", &layout.features_added),
+        ("-", "features", &layout.features_removed),
+    ] {
+        if !tags.is_empty() {
+            let _ = writeln!(out, "  {sign} {what} {}", tags.join(", "));
+        }
+    }
+    let (old, new) = layout.lookups;
+    if old != new {
+        let _ = writeln!(out, "  ~ lookups {old} -> {new}");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_describe_diff() {
+        let path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/fonts/Roboto-Regular.abc.ttf");
+        let font = std::fs::read(&path).unwrap();
+        let options = fontcull::SubsetOptions {
+            drop_tables: "GPOS".to_string(),
+            ..Default::default()
+        };
+        let subset =
+            fontcull::subset_font_data_with_options(&font, &['a' as u32, 'c' as u32], &options)
+                .unwrap();
+        let args = DiffArgs {
+            old: PathBuf::from("a.ttf"),
+            new: PathBuf::from("b.ttf"),
+            json: false,
+            exit_code: false,
+        };
+
+        let text = describe(&args, &fontcull::diff_fonts(&font, &subset).unwrap());
+        assert!(text.starts_with("--- a.ttf\n+++ b.ttf\nTables:\n"));
+        assert!(text.contains("  - GPOS  148 bytes\n"));
+        assert!(text.contains("  ~ glyph_count  4 -> 3\n"));
+        assert!(text.contains("cmap:\n  - 1 code points: U+62\n"));
+        assert!(text.contains(
+            "Glyphs (matched by code point): 0 added, 1 removed, 0 changed, 3 unchanged\n  - U+0062\n"
+        ));
+        assert!(
+            text.ends_with("  - features kern\n  ~ lookups 1 -> 0\n"),
+            "{text}"
+        );
+
+        let many: Vec<u32> = (0..1000).step_by(2).collect();
+        let shown = ranges(&many);
+        assert!(shown.starts_with("U+0,U+2,U+4,"));
+        assert!(shown.ends_with(",…") && shown.len() <= MAX_RANGES_LEN + 4);
+
+        let same = describe(&args, &fontcull::diff_fonts(&font, &font).unwrap());
+        assert_eq!(same, "--- a.ttf\n+++ b.ttf\nNo differences\n");
+    }
+}
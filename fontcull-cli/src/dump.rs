@@ -0,0 +1,145 @@
+//! `fontcull dump` and `fontcull compile`: fonts as text and back, in place of
+//! fonttools' `ttx`
+
+use std::path::PathBuf;
+
+use color_eyre::eyre::{Context, Result};
+
+use crate::klippa_backend::OutputFormat;
+
+/// Print the tables of a font field by field, or as JSON that `fontcull
+/// compile` turns back into a font
+#[derive(clap::Args, Debug)]
+pub struct DumpArgs {
+    /// The font (TTF, OTF, WOFF, WOFF2 or TTC)
+    pub font: PathBuf,
+
+    /// Only dump these tables, e.g. `head,cmap`
+    #[arg(long, short = 't', value_delimiter = ',')]
+    pub tables: Vec<String>,
+
+    /// Print JSON that `fontcull compile` turns back into a font
+    #[arg(long)]
+    pub json: bool,
+
+    /// Write to this file instead of stdout
+    #[arg(long, short = 'o', value_name = "FILE")]
+    pub output_file: Option<PathBuf>,
+}
+
+/// Compile the JSON from `fontcull dump --json` back into a font
+#[derive(clap::Args, Debug)]
+pub struct CompileArgs {
+    /// JSON from `fontcull dump --json`
+    pub json: PathBuf,
+
+    /// The font to write
+    #[arg(long, short = 'o', value_name = "FILE")]
+    pub output_file: PathBuf,
+
+    /// Output format [default: from the extension of --output-file, else ttf]
+    #[arg(long, value_enum)]
+    pub format: Option<OutputFormat>,
+}
+
+pub fn run_dump(args: &DumpArgs) -> Result<()> {
+    let data = std::fs::read(&args.font)
+        .wrap_err_with(|| format!("Failed to read font file: {}", args.font.display()))?;
+    let sfnt = fontcull::decompress_font(&data).map_err(|e| color_eyre::eyre::eyre!("{}", e))?;
+    let tables: Vec<&str> = args.tables.iter().map(String::as_str).collect();
+    let text = if args.json {
+        let mut json = fontcull::font_to_json(&sfnt);
+        if !tables.is_empty() {
+            json = json.and_then(|json| only_tables(&json, &tables));
+        }
+        json.map(|json| json + "\n")
+    } else {
+        fontcull::dump_font(&sfnt, &tables)
+    }
+    .map_err(|e| color_eyre::eyre::eyre!("{}", e))
+    .wrap_err_with(|| format!("Failed to dump {}", args.font.display()))?;
+
+    match &args.output_file {
+        Some(path) => std::fs::write(path, text)
+            .wrap_err_with(|| format!("Failed to write: {}", path.display())),
+        None => {
+            print!("{text}");
+            Ok(())
+        }
+    }
+}
+
+/// The JSON of just some tables, which compiles to a partial font
+fn only_tables(json: &str, tables: &[&str]) -> Result<String, fontcull::SubsetError> {
+    let parse_error = |e: serde_json::Error| fontcull::SubsetError::FontParse(e.to_string());
+    let mut all: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(json).map_err(parse_error)?;
+    all.retain(|tag, _| tables.contains(&tag.as_str()));
+    serde_json::to_string_pretty(&all).map_err(parse_error)
+}
+
+pub fn run_compile(args: &CompileArgs) -> Result<()> {
+    let json = std::fs::read_to_string(&args.json)
+        .wrap_err_with(|| format!("Failed to read JSON file: {}", args.json.display()))?;
+    let sfnt = fontcull::compile_font_json(&json)
+        .map_err(|e| color_eyre::eyre::eyre!("{}", e))
+        .wrap_err_with(|| format!("Failed to compile {}", args.json.display()))?;
+    let format = args
+        .format
+        .or_else(|| {
+            let extension = args.output_file.extension()?.to_str()?.to_lowercase();
+            clap::ValueEnum::from_str(&extension, true).ok()
+        })
+        .unwrap_or(OutputFormat::Ttf);
+    let data = format.encode(sfnt)?;
+    std::fs::write(&args.output_file, &data)
+        .wrap_err_with(|| format!("Failed to write: {}", args.output_file.display()))?;
+    tracing::info!(
+        "Created: {} ({} bytes)",
+        args.output_file.display(),
+        data.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_dump_and_compile() {
+        let dir = std::env::temp_dir().join(format!("fontcull-dump-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let font =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/fonts/Roboto-Regular.abc.ttf");
+        let json = dir.join("roboto.json");
+
+        run_dump(&DumpArgs {
+            font: font.clone(),
+            tables: Vec::new(),
+            json: true,
+            output_file: Some(json.clone()),
+        })
+        .unwrap();
+        run_compile(&CompileArgs {
+            json: json.clone(),
+            output_file: dir.join("roboto.woff"),
+            format: None,
+        })
+        .unwrap();
+        let woff = std::fs::read(dir.join("roboto.woff")).unwrap();
+        let sfnt = fontcull::decompress_font(&woff).unwrap();
+        assert_eq!(
+            fontcull::dump_font(&sfnt, &["cmap", "hmtx"]).unwrap(),
+            fontcull::dump_font(&std::fs::read(&font).unwrap(), &["cmap", "hmtx"]).unwrap()
+        );
+
+        let json = std::fs::read_to_string(&json).unwrap();
+        let head = only_tables(&json, &["head"]).unwrap();
+        assert!(head.contains("\"units_per_em\": 2048"));
+        assert!(!head.contains("\"maxp\""));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
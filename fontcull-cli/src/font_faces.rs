@@ -0,0 +1,126 @@
+use chromiumoxide::Page;
+use color_eyre::eyre::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// JavaScript code that lists the `@font-face` rules of every readable stylesheet,
+/// including `@import`ed sheets and rules nested in `@media`/`@supports`/`@layer`.
+/// Source URLs are resolved against the stylesheet they appear in.
+const FONT_FACE_SCRIPT: &str = r#"
+(() => {
+    const faces = [];
+
+    function unquote(value) {
+        return value ? value.trim().replace(/^["']|["']$/g, '') : '';
+    }
+
+    function optional(style, name) {
+        const value = style.getPropertyValue(name);
+        return value ? value.trim() : null;
+    }
+
+    function sources(src, base) {
+        const urls = [];
+        const re = /url\(\s*(['"]?)(.*?)\1\s*\)/g;
+        let m;
+        while ((m = re.exec(src)) !== null) {
+            try {
+                urls.push(new URL(m[2], base).href);
+            } catch (e) {}
+        }
+        return urls;
+    }
+
+    function walkRules(rules, base) {
+        for (const rule of rules) {
+            if (rule instanceof CSSFontFaceRule) {
+                const style = rule.style;
+                faces.push({
+                    family: unquote(style.getPropertyValue('font-family')),
+                    sources: sources(style.getPropertyValue('src'), base),
+                    weight: optional(style, 'font-weight'),
+                    style: optional(style, 'font-style'),
+                    stretch: optional(style, 'font-stretch'),
+                    unicode_range: optional(style, 'unicode-range'),
+                    display: optional(style, 'font-display'),
+                });
+            } else if (rule instanceof CSSImportRule) {
+                if (rule.styleSheet) walkSheet(rule.styleSheet);
+            } else if (rule.cssRules) {
+                walkRules(rule.cssRules, base);
+            }
+        }
+    }
+
+    function walkSheet(sheet) {
+        let rules;
+        try {
+            rules = sheet.cssRules;
+        } catch (e) {
+            // Cross-origin stylesheets without CORS can't be read
+            return;
+        }
+        walkRules(rules, sheet.href || document.baseURI);
+    }
+
+    for (const sheet of document.styleSheets) {
+        walkSheet(sheet);
+    }
+    for (const sheet of document.adoptedStyleSheets || []) {
+        walkSheet(sheet);
+    }
+
+    return faces.filter(face => face.family && face.sources.length > 0);
+})()
+"#;
+
+/// An `@font-face` rule found on a page
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FontFaceRule {
+    /// The declared `font-family`, unquoted
+    pub family: String,
+    /// Absolute `url()` sources, in order of preference
+    pub sources: Vec<String>,
+    pub weight: Option<String>,
+    pub style: Option<String>,
+    pub stretch: Option<String>,
+    pub unicode_range: Option<String>,
+    pub display: Option<String>,
+}
+
+/// List the `@font-face` rules that apply to a loaded page
+pub async fn extract_font_faces(page: &Page) -> Result<Vec<FontFaceRule>> {
+    let result: serde_json::Value = page
+        .evaluate(FONT_FACE_SCRIPT)
+        .await
+        .wrap_err("Failed to execute font-face script")?
+        .into_value()
+        .wrap_err("Failed to get font-face result")?;
+
+    serde_json::from_value(result).wrap_err("Unexpected font-face script result")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::TestBrowser;
+
+    #[tokio::test]
+    #[ignore = "needs Chrome, run with --ignored"]
+    async fn test_extract_font_faces() {
+        let browser = TestBrowser::launch().await;
+        let page = browser.open("site/fonts.html").await;
+        let faces = extract_font_faces(&page).await.unwrap();
+        browser.close().await;
+
+        let body = faces.iter().find(|f| f.family == "Body Font").unwrap();
+        assert!(body.sources[0].ends_with("/site/fonts/body.woff2"));
+        assert!(body.sources[1].ends_with("/site/fonts/body.ttf"));
+        assert_eq!(body.weight.as_deref(), Some("100 900"));
+        assert_eq!(body.display.as_deref(), Some("swap"));
+
+        // Nested in @media, and resolved relative to an imported stylesheet
+        let imported = faces.iter().find(|f| f.family == "Imported Font").unwrap();
+        assert!(imported.sources[0].ends_with("/site/css/fonts/imported.woff2"));
+    }
+}
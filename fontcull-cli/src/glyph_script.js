@@ -0,0 +1,274 @@
+// Runs in the browser and returns the code points used per font-family, with
+// how often each one occurs, and the distinct texts each font-family renders.
+// Started from glyphhanger's glyphhanger-script.js.
+(() => {
+    const fontFamilyCounts = {};
+    const fontFamilyTexts = {};
+
+    // Elements whose contents never render as text
+    const SKIPPED_TAGS = new Set([
+        'script', 'style', 'noscript', 'template', 'head', 'title', 'meta', 'link',
+    ]);
+
+    // SVG elements whose text content is rendered
+    const SVG_TEXT_TAGS = new Set(['text', 'tspan', 'textpath']);
+
+    // <input> types that display their value (or placeholder) as text
+    const TEXT_INPUT_TYPES = new Set([
+        'text', 'search', 'url', 'tel', 'email', 'number',
+        'button', 'submit', 'reset', 'date', 'datetime-local',
+        'month', 'time', 'week',
+    ]);
+
+    // Default labels for buttons without a value attribute
+    const DEFAULT_INPUT_LABELS = { submit: 'Submit', reset: 'Reset' };
+
+    // Markers used for common list-style-type values
+    const LIST_MARKERS = {
+        disc: '• ',
+        circle: '◦ ',
+        square: '▪ ',
+        'disclosure-open': '▾ ',
+        'disclosure-closed': '▸ ',
+    };
+
+    function addCode(familyKey, code) {
+        if (!fontFamilyCounts[familyKey]) {
+            fontFamilyCounts[familyKey] = new Map();
+        }
+        const counts = fontFamilyCounts[familyKey];
+        counts.set(code, (counts.get(code) || 0) + 1);
+    }
+
+    function saveGlyph(char, fontFamily) {
+        const code = char.codePointAt(0);
+        // Lone surrogates are not Unicode scalar values and never map to a glyph
+        if (!code || isNaN(code) || (code >= 0xD800 && code <= 0xDFFF)) return;
+
+        // Add to specific family set, and to the universal set
+        const family = fontFamily || '*';
+        addCode(family, code);
+        if (family !== '*') addCode('*', code);
+    }
+
+    function addText(familyKey, text) {
+        if (!fontFamilyTexts[familyKey]) {
+            fontFamilyTexts[familyKey] = new Set();
+        }
+        fontFamilyTexts[familyKey].add(text);
+    }
+
+    function saveGlyphs(text, fontFamily) {
+        const family = fontFamily || '*';
+        addText(family, text);
+        if (family !== '*') addText('*', text);
+
+        // Iterating a string yields code points, so astral characters stay whole
+        for (const char of text) {
+            saveGlyph(char, fontFamily);
+        }
+    }
+
+    function getStyle(node, pseudo) {
+        try {
+            return node.ownerDocument.defaultView.getComputedStyle(node, pseudo || null);
+        } catch (e) {
+            return null;
+        }
+    }
+
+    function getFontFamily(style) {
+        if (!style) return '*';
+        let family = style.getPropertyValue('font-family');
+        // Take first font in the stack
+        if (family) {
+            family = family.split(',')[0].trim().replace(/['"]/g, '');
+        }
+        return family || '*';
+    }
+
+    function processText(text, style, fontFamily) {
+        const transform = style ? style.getPropertyValue('text-transform') : 'none';
+        const variant = style ? style.getPropertyValue('font-variant') : 'normal';
+
+        // Handle text-transform
+        if (transform === 'uppercase') {
+            text = text.toUpperCase();
+        } else if (transform === 'lowercase') {
+            text = text.toLowerCase();
+        } else if (transform === 'capitalize') {
+            // Include both cases for capitalize
+            saveGlyphs(text.toLowerCase(), fontFamily);
+            saveGlyphs(text.toUpperCase(), fontFamily);
+            return;
+        }
+
+        // Handle small-caps: include both upper and lower
+        if (variant && variant.includes('small-caps')) {
+            saveGlyphs(text.toLowerCase(), fontFamily);
+            saveGlyphs(text.toUpperCase(), fontFamily);
+            return;
+        }
+
+        saveGlyphs(text, fontFamily);
+    }
+
+    function processStyledText(text, node, pseudo) {
+        if (!text) return;
+        const style = getStyle(node, pseudo);
+        processText(text, style, getFontFamily(style));
+    }
+
+    // Decode the body of a CSS string token, honoring backslash escapes
+    function unescapeCssString(body) {
+        return body.replace(/\\([0-9a-fA-F]{1,6})\s?|\\(\n)|\\(.)/g, (_, hex, newline, other) => {
+            if (hex) {
+                const code = parseInt(hex, 16);
+                if (code === 0 || code > 0x10FFFF || (code >= 0xD800 && code <= 0xDFFF)) {
+                    return '�';
+                }
+                return String.fromCodePoint(code);
+            }
+            if (newline) return '';
+            return other;
+        });
+    }
+
+    // Open and close quotes from the computed `quotes` property
+    function getQuotes(style) {
+        const value = style ? style.getPropertyValue('quotes') : 'auto';
+        const quotes = [];
+        const re = /"((?:[^"\\]|\\.)*)"|'((?:[^'\\]|\\.)*)'/g;
+        let m;
+        while ((m = re.exec(value)) !== null) {
+            quotes.push(unescapeCssString(m[1] !== undefined ? m[1] : m[2]));
+        }
+        return quotes.length ? quotes : ['“', '”', '‘', '’'];
+    }
+
+    // Resolve a computed `content` value to the text it renders
+    function resolveContent(content, node, style) {
+        let text = '';
+        const re = /"((?:[^"\\]|\\.)*)"|'((?:[^'\\]|\\.)*)'|attr\(\s*([^\s,)]+)[^)]*\)|(counters?)\([^)]*\)|(open-quote|close-quote)|url\([^)]*\)/g;
+        let m;
+        while ((m = re.exec(content)) !== null) {
+            if (m[1] !== undefined || m[2] !== undefined) {
+                text += unescapeCssString(m[1] !== undefined ? m[1] : m[2]);
+            } else if (m[3] !== undefined) {
+                // attr(name) reads from the originating element
+                text += node.getAttribute(m[3]) || '';
+            } else if (m[4] !== undefined) {
+                // Counter values are only known at layout time, assume decimal
+                text += '0123456789';
+            } else if (m[5] !== undefined) {
+                text += getQuotes(style).join('');
+            }
+        }
+        return text;
+    }
+
+    function getPseudoContent(node, pseudo) {
+        const style = getStyle(node, pseudo);
+        if (!style) return '';
+        const content = style.getPropertyValue('content');
+        if (!content || content === 'none' || content === 'normal') return '';
+        return resolveContent(content, node, style);
+    }
+
+    // Text rendered by the ::marker of list items
+    function getMarkerText(node, style) {
+        if (!style || style.getPropertyValue('display') !== 'list-item') return '';
+        const markerContent = getPseudoContent(node, '::marker');
+        if (markerContent) return markerContent;
+
+        const type = style.getPropertyValue('list-style-type');
+        if (!type || type === 'none') return '';
+        if (LIST_MARKERS[type]) return LIST_MARKERS[type];
+        if (/^["']/.test(type)) return resolveContent(type, node, style);
+        if (type.startsWith('decimal')) return '0123456789. ';
+        if (type === 'lower-alpha' || type === 'lower-latin') return 'abcdefghijklmnopqrstuvwxyz. ';
+        if (type === 'upper-alpha' || type === 'upper-latin') return 'ABCDEFGHIJKLMNOPQRSTUVWXYZ. ';
+        if (type === 'lower-roman') return 'ivxlcdm. ';
+        if (type === 'upper-roman') return 'IVXLCDM. ';
+        return '';
+    }
+
+    function processFormControl(node, tagName) {
+        if (tagName === 'input') {
+            const type = (node.getAttribute('type') || 'text').toLowerCase();
+            if (!TEXT_INPUT_TYPES.has(type)) return;
+            processStyledText(node.value || DEFAULT_INPUT_LABELS[type] || '', node);
+            processStyledText(node.getAttribute('placeholder'), node, '::placeholder');
+        } else if (tagName === 'textarea') {
+            // The live value may differ from the element's text content
+            processStyledText(node.value, node);
+            processStyledText(node.getAttribute('placeholder'), node, '::placeholder');
+        } else if (tagName === 'option' || tagName === 'optgroup') {
+            processStyledText(node.getAttribute('label'), node);
+        }
+    }
+
+    function processElement(node, inSvg) {
+        const tagName = node.localName.toLowerCase();
+        if (SKIPPED_TAGS.has(tagName)) return;
+
+        const isSvgRoot = tagName === 'svg';
+        inSvg = inSvg || isSvgRoot;
+        const style = getStyle(node);
+
+        // Inside SVG only text content elements render their text
+        const rendersText = !inSvg || SVG_TEXT_TAGS.has(tagName);
+
+        if (rendersText && tagName !== 'textarea') {
+            for (const child of node.childNodes) {
+                if (child.nodeType === Node.TEXT_NODE && child.nodeValue && child.nodeValue.trim()) {
+                    processText(child.nodeValue, style, getFontFamily(style));
+                }
+            }
+        }
+
+        processFormControl(node, tagName);
+
+        if (!inSvg) {
+            processStyledText(getPseudoContent(node, '::before'), node, '::before');
+            processStyledText(getPseudoContent(node, '::after'), node, '::after');
+            processStyledText(getMarkerText(node, style), node, '::marker');
+        }
+
+        // Open shadow roots render instead of (or around) the light DOM children
+        if (node.shadowRoot) {
+            walk(node.shadowRoot, inSvg);
+        }
+
+        // Same-origin iframes expose their document; cross-origin ones throw
+        if (tagName === 'iframe' || tagName === 'frame') {
+            let doc = null;
+            try {
+                doc = node.contentDocument;
+            } catch (e) {}
+            if (doc && doc.documentElement) {
+                walk(doc, false);
+            }
+        }
+
+        walk(node, inSvg);
+    }
+
+    function walk(root, inSvg) {
+        for (const child of root.children) {
+            processElement(child, inSvg);
+        }
+    }
+
+    walk(document, false);
+
+    const glyphs = {};
+    const counts = {};
+    const texts = {};
+    for (const [family, codeCounts] of Object.entries(fontFamilyCounts)) {
+        glyphs[family] = Array.from(codeCounts.keys());
+        counts[family] = Object.fromEntries(codeCounts);
+        texts[family] = Array.from(fontFamilyTexts[family] || []);
+    }
+    return { glyphs, counts, texts };
+})()
@@ -1,160 +1,234 @@
-/// JavaScript code that runs in the browser to extract glyphs per font-family
-/// This is based on glyphhanger's glyphhanger-script.js
-pub const GLYPH_SCRIPT: &str = r#"
-(() => {
-    const fontFamilySets = {};
-
-    function saveGlyph(char, fontFamily) {
-        const code = char.charCodeAt(0);
-        if (code === 0 || isNaN(code)) return;
-
-        // Add to specific family set
-        const familyKey = fontFamily || '*';
-        if (!fontFamilySets[familyKey]) {
-            fontFamilySets[familyKey] = [];
-        }
-        if (!fontFamilySets[familyKey].includes(code)) {
-            fontFamilySets[familyKey].push(code);
-        }
+use std::collections::HashMap;
 
-        // Also add to universal set
-        if (!fontFamilySets['*']) {
-            fontFamilySets['*'] = [];
-        }
-        if (!fontFamilySets['*'].includes(code)) {
-            fontFamilySets['*'].push(code);
-        }
-    }
+use chromiumoxide::Page;
+use color_eyre::eyre::{Context, Result};
 
-    function saveGlyphs(text, fontFamily) {
-        for (const char of text) {
-            saveGlyph(char, fontFamily);
+/// JavaScript code that runs in the browser to extract glyphs per font-family,
+/// count how often each one occurs and collect the texts each family renders
+///
+/// Walks the document, open shadow roots and same-origin iframes, and collects
+/// text nodes, form control values and placeholders, `<option>` labels, SVG text
+/// content, list markers and `::before`/`::after` content (including `attr()`).
+/// This is based on glyphhanger's glyphhanger-script.js
+pub const GLYPH_SCRIPT: &str = include_str!("glyph_script.js");
+
+/// How often each code point occurs on a page, per font-family
+pub type CharCounts = HashMap<String, HashMap<u32, u64>>;
+
+/// The text found on a page by [`GLYPH_SCRIPT`]
+#[derive(Debug, Default)]
+pub struct PageGlyphs {
+    /// Code points per font-family, plus a universal "*" set
+    pub glyphs: HashMap<String, Vec<u32>>,
+    pub counts: CharCounts,
+    /// Distinct texts per font-family, plus a universal "*" set
+    pub texts: HashMap<String, Vec<String>>,
+}
+
+/// Run [`GLYPH_SCRIPT`] on a loaded page and return the code points per font-family
+pub async fn extract_glyphs(page: &Page) -> Result<PageGlyphs> {
+    let result: serde_json::Value = page
+        .evaluate(GLYPH_SCRIPT)
+        .await
+        .wrap_err("Failed to execute glyph extraction script")?
+        .into_value()
+        .wrap_err("Failed to get script result")?;
+
+    Ok(PageGlyphs {
+        glyphs: parse_glyph_sets(&result["glyphs"]),
+        counts: parse_char_counts(&result["counts"]),
+        texts: parse_texts(&result["texts"]),
+    })
+}
+
+/// Convert the script's `{ family: [codepoint, ...] }` result into glyph sets
+///
+/// Anything that isn't a Unicode scalar value (such as a lone surrogate) is dropped.
+fn parse_glyph_sets(result: &serde_json::Value) -> HashMap<String, Vec<u32>> {
+    let mut sets: HashMap<String, Vec<u32>> = HashMap::new();
+
+    if let Some(obj) = result.as_object() {
+        for (family, chars) in obj {
+            if let Some(arr) = chars.as_array() {
+                let codes: Vec<u32> = arr
+                    .iter()
+                    .filter_map(|v| v.as_u64())
+                    .filter_map(|n| u32::try_from(n).ok())
+                    .filter(|&n| char::from_u32(n).is_some())
+                    .collect();
+                sets.insert(family.clone(), codes);
+            }
         }
     }
 
-    function getFontFamily(node, pseudo) {
-        try {
-            const style = window.getComputedStyle(node, pseudo || null);
-            let family = style.getPropertyValue('font-family');
-            // Take first font in the stack
-            if (family) {
-                family = family.split(',')[0].trim().replace(/['"]/g, '');
+    sets
+}
+
+/// Convert the script's `{ family: { codepoint: count } }` result, dropping
+/// anything that isn't a Unicode scalar value
+fn parse_char_counts(result: &serde_json::Value) -> CharCounts {
+    let mut counts = CharCounts::new();
+
+    if let Some(obj) = result.as_object() {
+        for (family, chars) in obj {
+            if let Some(chars) = chars.as_object() {
+                let family_counts = chars
+                    .iter()
+                    .filter_map(|(code, count)| Some((code.parse::<u32>().ok()?, count.as_u64()?)))
+                    .filter(|&(code, _)| char::from_u32(code).is_some())
+                    .collect();
+                counts.insert(family.clone(), family_counts);
             }
-            return family || '*';
-        } catch (e) {
-            return '*';
         }
     }
 
-    function getTextTransform(node) {
-        try {
-            return window.getComputedStyle(node).getPropertyValue('text-transform');
-        } catch (e) {
-            return 'none';
+    counts
+}
+
+/// Convert the script's `{ family: [text, ...] }` result, dropping anything
+/// that isn't a string
+fn parse_texts(result: &serde_json::Value) -> HashMap<String, Vec<String>> {
+    let mut texts = HashMap::new();
+
+    if let Some(obj) = result.as_object() {
+        for (family, family_texts) in obj {
+            if let Some(arr) = family_texts.as_array() {
+                let family_texts = arr
+                    .iter()
+                    .filter_map(|v| v.as_str())
+                    .map(str::to_string)
+                    .collect();
+                texts.insert(family.clone(), family_texts);
+            }
         }
     }
 
-    function getFontVariant(node) {
-        try {
-            return window.getComputedStyle(node).getPropertyValue('font-variant');
-        } catch (e) {
-            return 'normal';
-        }
-    }
+    texts
+}
 
-    function processText(text, node, fontFamily) {
-        const transform = getTextTransform(node);
-        const variant = getFontVariant(node);
-
-        // Handle text-transform
-        if (transform === 'uppercase') {
-            text = text.toUpperCase();
-        } else if (transform === 'lowercase') {
-            text = text.toLowerCase();
-        } else if (transform === 'capitalize') {
-            // Include both cases for capitalize
-            saveGlyphs(text.toLowerCase(), fontFamily);
-            saveGlyphs(text.toUpperCase(), fontFamily);
-            return;
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        // Handle small-caps: include both upper and lower
-        if (variant && variant.includes('small-caps')) {
-            saveGlyphs(text.toLowerCase(), fontFamily);
-            saveGlyphs(text.toUpperCase(), fontFamily);
-            return;
-        }
+    use crate::testing::{self, has};
 
-        saveGlyphs(text, fontFamily);
+    /// Load a fixture page and run the glyph script on it
+    async fn capture(page_name: &str) -> HashMap<String, Vec<u32>> {
+        let browser = testing::TestBrowser::launch().await;
+        let page = browser.open(page_name).await;
+        let glyphs = extract_glyphs(&page).await.unwrap();
+        browser.close().await;
+        glyphs.glyphs
     }
 
-    function getPseudoContent(node, pseudo) {
-        try {
-            const style = window.getComputedStyle(node, pseudo);
-            let content = style.getPropertyValue('content');
-            if (content && content !== 'none' && content !== 'normal') {
-                // Remove quotes
-                content = content.replace(/^["']|["']$/g, '');
-                // Handle attr() - just skip for now
-                if (content.startsWith('attr(')) {
-                    return '';
-                }
-                return content;
-            }
-        } catch (e) {}
-        return '';
+    #[test]
+    fn test_parse_glyph_sets_drops_non_scalar_values() {
+        let value = serde_json::json!({
+            "*": [65, 0xD83D, 0x1F600, 0x110000, -1, "x"],
+            "Inter": [66],
+        });
+        let sets = parse_glyph_sets(&value);
+        assert_eq!(sets["*"], vec![65, 0x1F600]);
+        assert_eq!(sets["Inter"], vec![66]);
     }
 
-    function getTextNodes(node) {
-        const textNodes = [];
-        const walker = document.createTreeWalker(
-            node,
-            NodeFilter.SHOW_TEXT,
-            null,
-            false
-        );
-        let textNode;
-        while (textNode = walker.nextNode()) {
-            textNodes.push(textNode);
-        }
-        return textNodes;
+    #[test]
+    fn test_parse_char_counts() {
+        let value = serde_json::json!({
+            "*": { "65": 3, "55357": 1, "x": 2 },
+            "Inter": { "66": 7 },
+        });
+        let counts = parse_char_counts(&value);
+        assert_eq!(counts["*"], HashMap::from([(65, 3)]));
+        assert_eq!(counts["Inter"], HashMap::from([(66, 7)]));
     }
 
-    // Process all elements
-    const allElements = document.querySelectorAll('*');
+    #[test]
+    fn test_parse_texts() {
+        let value = serde_json::json!({
+            "*": ["Hello", 3, "fi"],
+            "Inter": ["fi"],
+        });
+        let texts = parse_texts(&value);
+        assert_eq!(texts["*"], vec!["Hello", "fi"]);
+        assert_eq!(texts["Inter"], vec!["fi"]);
+        assert!(parse_texts(&serde_json::json!(null)).is_empty());
+    }
 
-    for (const node of allElements) {
-        // Skip script and style tags
-        const tagName = node.tagName.toLowerCase();
-        if (tagName === 'script' || tagName === 'style' || tagName === 'noscript') {
-            continue;
-        }
+    #[test]
+    fn test_parse_glyph_sets_ignores_non_object() {
+        assert!(parse_glyph_sets(&serde_json::json!(null)).is_empty());
+        assert!(parse_glyph_sets(&serde_json::json!([1, 2, 3])).is_empty());
+    }
 
-        // Process text nodes
-        const textNodes = getTextNodes(node);
-        for (const textNode of textNodes) {
-            const text = textNode.nodeValue;
-            if (text && text.trim()) {
-                const fontFamily = getFontFamily(textNode.parentElement);
-                processText(text, textNode.parentElement, fontFamily);
-            }
-        }
+    #[tokio::test]
+    #[ignore = "needs Chrome, run with --ignored"]
+    async fn test_shadow_dom_text() {
+        let glyphs = capture("shadow.html").await;
+        assert!(has(&glyphs, "Shadow Font", "ШЩ"));
+        // Slotted light DOM content keeps the host's font
+        assert!(has(&glyphs, "Light Font", "Ы"));
+        // Nested shadow roots are walked too
+        assert!(has(&glyphs, "Nested Font", "Э"));
+    }
 
-        // Process ::before pseudo-element
-        const beforeContent = getPseudoContent(node, ':before');
-        if (beforeContent) {
-            const fontFamily = getFontFamily(node, ':before');
-            processText(beforeContent, node, fontFamily);
-        }
+    #[tokio::test]
+    #[ignore = "needs Chrome, run with --ignored"]
+    async fn test_same_origin_iframe_text() {
+        let glyphs = capture("iframe.html").await;
+        assert!(has(&glyphs, "Frame Font", "Ж"));
+        assert!(has(&glyphs, "*", "Ж"));
+    }
 
-        // Process ::after pseudo-element
-        const afterContent = getPseudoContent(node, ':after');
-        if (afterContent) {
-            const fontFamily = getFontFamily(node, ':after');
-            processText(afterContent, node, fontFamily);
-        }
+    #[tokio::test]
+    #[ignore = "needs Chrome, run with --ignored"]
+    async fn test_form_controls() {
+        let glyphs = capture("forms.html").await;
+        assert!(has(&glyphs, "Form Font", "Ω"), "input value");
+        assert!(has(&glyphs, "Placeholder Font", "Ψ"), "input placeholder");
+        assert!(has(&glyphs, "Form Font", "Ξ"), "textarea value");
+        assert!(has(&glyphs, "Form Font", "Π"), "value set from script");
+        assert!(has(&glyphs, "Form Font", "Ђ"), "option text");
+        assert!(has(&glyphs, "Form Font", "Љ"), "option label");
+        assert!(has(&glyphs, "Form Font", "Submit"), "default button label");
+        // Hidden inputs never render
+        assert!(!has(&glyphs, "*", "Ӂ"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Chrome, run with --ignored"]
+    async fn test_svg_text() {
+        let glyphs = capture("svg.html").await;
+        assert!(has(&glyphs, "Svg Font", "Ѣ"));
+        assert!(has(&glyphs, "Span Font", "Ѳ"));
+        // <title> and <desc> are not rendered
+        assert!(!has(&glyphs, "*", "Ѵ"));
+        assert!(!has(&glyphs, "*", "Ѹ"));
     }
 
-    return fontFamilySets;
-})()
-"#;
+    #[tokio::test]
+    #[ignore = "needs Chrome, run with --ignored"]
+    async fn test_pseudo_content() {
+        let glyphs = capture("pseudo.html").await;
+        assert!(has(&glyphs, "Before Font", "Ю"), "string content");
+        assert!(has(&glyphs, "Attr Font", "Я[]"), "attr() content");
+        assert!(has(&glyphs, "After Font", "\u{2192}"), "escaped content");
+        assert!(has(&glyphs, "Quote Font", "«»"), "open-quote/close-quote");
+        assert!(has(&glyphs, "Marker Font", "•"), "list marker");
+        // Script contents are not text
+        assert!(!has(&glyphs, "*", "Ф"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Chrome, run with --ignored"]
+    async fn test_astral_code_points() {
+        let glyphs = capture("emoji.html").await;
+        assert!(has(&glyphs, "Emoji Font", "😀𝔸"));
+        assert!(
+            glyphs
+                .values()
+                .flatten()
+                .all(|&c| !(0xD800..=0xDFFF).contains(&c))
+        );
+    }
+}
@@ -0,0 +1,309 @@
+//! `fontcull inspect`: print what's in a font, to choose subsetting options
+
+use std::{fmt::Write as _, path::PathBuf};
+
+use color_eyre::eyre::{Context, Result};
+use fontcull::{FontFormat, FontReport, Hinting, LayoutInfo};
+use serde::Serialize;
+
+/// Longest name value shown in full; licenses and descriptions are cut short
+const MAX_NAME_LEN: usize = 60;
+
+/// Print the tables, names, variations, layout features and coverage of fonts
+#[derive(clap::Args, Debug)]
+pub struct InspectArgs {
+    /// Font files (TTF, OTF, WOFF, WOFF2 or TTC)
+    #[arg(required = true, value_name = "FONT")]
+    pub fonts: Vec<PathBuf>,
+
+    /// Print JSON instead of text
+    #[arg(long)]
+    pub json: bool,
+}
+
+/// One inspected file; a collection has several fonts
+#[derive(Serialize, Debug)]
+struct FileReport {
+    path: PathBuf,
+    format: &'static str,
+    size: usize,
+    fonts: Vec<FontReport>,
+}
+
+pub fn run(args: &InspectArgs) -> Result<()> {
+    let mut files = Vec::new();
+    for path in &args.fonts {
+        let data = std::fs::read(path)
+            .wrap_err_with(|| format!("Failed to read font file: {}", path.display()))?;
+        let sfnt =
+            fontcull::decompress_font(&data).map_err(|e| color_eyre::eyre::eyre!("{}", e))?;
+        let fonts = fontcull::inspect_font(&sfnt)
+            .map_err(|e| color_eyre::eyre::eyre!("{}", e))
+            .wrap_err_with(|| format!("Failed to inspect {}", path.display()))?;
+        files.push(FileReport {
+            path: path.clone(),
+            format: format_name(&data),
+            size: data.len(),
+            fonts,
+        });
+    }
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&files)?);
+    } else {
+        let text: Vec<String> = files.iter().map(describe_file).collect();
+        print!("{}", text.join("\n"));
+    }
+    Ok(())
+}
+
+fn format_name(data: &[u8]) -> &'static str {
+    if data.starts_with(b"ttcf") {
+        return "TTC";
+    }
+    match FontFormat::detect(data) {
+        FontFormat::Ttf => "TTF",
+        FontFormat::Otf => "OTF",
+        FontFormat::Woff => "WOFF",
+        FontFormat::Woff2 => "WOFF2",
+        FontFormat::Unknown => "unknown",
+    }
+}
+
+/// What a name ID is for, per the OpenType spec
+fn name_id_label(name_id: u16) -> &'static str {
+    match name_id {
+        0 => "Copyright",
+        1 => "Family",
+        2 => "Subfamily",
+        3 => "Unique ID",
+        4 => "Full name",
+        5 => "Version",
+        6 => "PostScript name",
+        7 => "Trademark",
+        8 => "Manufacturer",
+        9 => "Designer",
+        10 => "Description",
+        11 => "Vendor URL",
+        12 => "Designer URL",
+        13 => "License",
+        14 => "License URL",
+        16 => "Typographic family",
+        17 => "Typographic subfamily",
+        18 => "Compatible full name",
+        19 => "Sample text",
+        20 => "PostScript CID name",
+        21 => "WWS family",
+        22 => "WWS subfamily",
+        23 => "Light palette",
+        24 => "Dark palette",
+        25 => "Variations PostScript prefix",
+        _ => "",
+    }
+}
+
+fn describe_file(file: &FileReport) -> String {
+    let mut out = format!(
+        "{} ({}, {} bytes)\n",
+        file.path.display(),
+        file.format,
+        file.size
+    );
+    for (index, font) in file.fonts.iter().enumerate() {
+        if file.fonts.len() > 1 {
+            let _ = writeln!(out, "\nFont {} of {}:", index + 1, file.fonts.len());
+        }
+        out.push_str(&describe_font(font));
+    }
+    out
+}
+
+fn describe_font(font: &FontReport) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "  Glyphs: {}, units per em: {}",
+        font.glyph_count, font.units_per_em
+    );
+
+    let total: u64 = font.tables.iter().map(|t| u64::from(t.length)).sum();
+    let _ = writeln!(out, "  Tables ({}, {total} bytes):", font.tables.len());
+    for table in &font.tables {
+        let _ = writeln!(out, "    {:<4}  {:>9}", table.tag, table.length);
+    }
+
+    if !font.names.is_empty() {
+        let _ = writeln!(out, "  Names:");
+        for name in &font.names {
+            // One line per record, even for multi-paragraph licenses
+            let full = name.value.split_whitespace().collect::<Vec<_>>().join(" ");
+            let mut value: String = full.chars().take(MAX_NAME_LEN).collect();
+            if full.chars().count() > MAX_NAME_LEN {
+                value.push('…');
+            }
+            let _ = writeln!(
+                out,
+                "    {:>3}  {:<22}  {:<10}  {value}",
+                name.name_id,
+                name_id_label(name.name_id),
+                format!(
+                    "{}/{}/{:#x}",
+                    name.platform_id, name.encoding_id, name.language_id
+                ),
+            );
+        }
+    }
+
+    if !font.axes.is_empty() {
+        let _ = writeln!(out, "  Axes:");
+        for axis in &font.axes {
+            let _ = writeln!(
+                out,
+                "    {}  {:<12}  {} to {}, default {}{}",
+                axis.tag,
+                axis.name.as_deref().unwrap_or(""),
+                axis.min,
+                axis.max,
+                axis.default,
+                if axis.hidden { " (hidden)" } else { "" }
+            );
+        }
+    }
+    if !font.instances.is_empty() {
+        let _ = writeln!(out, "  Named instances:");
+        for instance in &font.instances {
+            let _ = writeln!(
+                out,
+                "    {:<20}  {}",
+                instance.name.as_deref().unwrap_or("?"),
+                location(&instance.coordinates)
+            );
+        }
+    }
+
+    if let Some(stat) = &font.stat {
+        let axes: Vec<&str> = stat.design_axes.iter().map(|a| a.tag.as_str()).collect();
+        let _ = writeln!(out, "  STAT: axes {}", axes.join(", "));
+        for value in &stat.values {
+            let _ = writeln!(
+                out,
+                "    {:<20}  {}{}",
+                value.name.as_deref().unwrap_or("?"),
+                location(&value.location),
+                if value.elidable { " (elidable)" } else { "" }
+            );
+        }
+        if let Some(name) = &stat.elided_fallback_name {
+            let _ = writeln!(out, "    Elided fallback name: {name}");
+        }
+    }
+
+    for (tag, layout) in [("GSUB", &font.gsub), ("GPOS", &font.gpos)] {
+        if let Some(layout) = layout {
+            out.push_str(&describe_layout(tag, layout));
+        }
+    }
+
+    let _ = writeln!(out, "  Coverage: {} code points", font.codepoint_count);
+    for block in &font.coverage {
+        match block.size {
+            0 => {
+                let _ = writeln!(out, "    {:<40}  {}", block.block, block.count);
+            }
+            size => {
+                let _ = writeln!(out, "    {:<40}  {}/{size}", block.block, block.count);
+            }
+        }
+    }
+
+    let color = if font.color.is_empty() {
+        "none".to_string()
+    } else {
+        font.color.join(", ")
+    };
+    let _ = writeln!(out, "  Color: {color}");
+    let _ = writeln!(out, "  Hinting: {}", describe_hinting(&font.hinting));
+    out
+}
+
+fn location(coordinates: &[(String, f32)]) -> String {
+    let parts: Vec<String> = coordinates
+        .iter()
+        .map(|(tag, value)| format!("{tag}={value}"))
+        .collect();
+    parts.join(" ")
+}
+
+fn describe_layout(tag: &str, layout: &LayoutInfo) -> String {
+    let features = match layout.features.as_slice() {
+        [] => "no features".to_string(),
+        features => format!("features {}", features.join(", ")),
+    };
+    let mut out = format!("  {tag}: {} lookups, {features}\n", layout.lookup_count);
+    for script in &layout.scripts {
+        for language in &script.languages {
+            let _ = writeln!(
+                out,
+                "    {:<4} {:<4}  {}",
+                script.tag,
+                language.tag,
+                language.features.join(", ")
+            );
+        }
+    }
+    out
+}
+
+fn describe_hinting(hinting: &Hinting) -> String {
+    match hinting {
+        Hinting::None => "none".to_string(),
+        Hinting::TrueType {
+            instructed_glyphs,
+            tables,
+            autohinted,
+        } => {
+            let mut text = format!("TrueType instructions in {instructed_glyphs} glyph(s)");
+            if !tables.is_empty() {
+                let tables: Vec<&str> = tables.iter().map(|tag| tag.trim_end()).collect();
+                let _ = write!(text, ", {}", tables.join(", "));
+            }
+            if *autohinted {
+                text.push_str(" (ttfautohint)");
+            }
+            text
+        }
+        Hinting::PostScript => "PostScript hints in the CFF charstrings".to_string(),
+        Hinting::NoOutlines => "no outlines".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    #[test]
+    fn test_describe_file() {
+        let path =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/fonts/Roboto-Regular.abc.ttf");
+        let data = std::fs::read(&path).unwrap();
+        let file = FileReport {
+            path: PathBuf::from("Roboto-Regular.abc.ttf"),
+            format: format_name(&data),
+            size: data.len(),
+            fonts: fontcull::inspect_font(&data).unwrap(),
+        };
+
+        let text = describe_file(&file);
+        assert!(text.starts_with("Roboto-Regular.abc.ttf (TTF, "));
+        assert!(text.contains("  Glyphs: 4, units per em: 2048\n"));
+        assert!(text.contains("      1  Family                  3/1/0x409   Roboto\n"));
+        assert!(text.contains("  Coverage: 3 code points\n    Basic Latin"));
+
+        let json = serde_json::to_value(&file).unwrap();
+        assert_eq!(json["format"], "TTF");
+        assert_eq!(json["fonts"][0]["glyph_count"], 4);
+        assert!(json["fonts"][0]["hinting"]["kind"].is_string());
+    }
+}
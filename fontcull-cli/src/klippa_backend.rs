@@ -1,32 +1,263 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use color_eyre::eyre::{Context, Result};
+use color_eyre::eyre::{Context, Result, bail};
+use fontcull::{SubsetFlags, SubsetOptions};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-/// Subset a font using klippa (pure Rust, no external dependencies)
-pub fn subset_with_klippa(
-    font_path: &str,
-    unicodes: &[u32],
-    output_dir: Option<&PathBuf>,
-) -> Result<PathBuf> {
-    let path = PathBuf::from(font_path);
-    let stem = path.file_stem().unwrap().to_str().unwrap();
+use crate::cache::{self, Cache};
 
-    let output_path = match output_dir {
-        Some(dir) => dir.join(format!("{}-subset.woff2", stem)),
-        None => path.with_file_name(format!("{}-subset.woff2", stem)),
-    };
+/// File format for subset fonts
+#[derive(clap::ValueEnum, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Woff2,
+    Woff,
+    Ttf,
+    Otf,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Woff2 => "woff2",
+            OutputFormat::Woff => "woff",
+            OutputFormat::Ttf => "ttf",
+            OutputFormat::Otf => "otf",
+        }
+    }
+
+    /// Name used in the `format()` of an `@font-face` source
+    pub fn css_format(self) -> &'static str {
+        match self {
+            OutputFormat::Woff2 => "woff2",
+            OutputFormat::Woff => "woff",
+            OutputFormat::Ttf => "truetype",
+            OutputFormat::Otf => "opentype",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            OutputFormat::Woff2 => "font/woff2",
+            OutputFormat::Woff => "font/woff",
+            OutputFormat::Ttf => "font/ttf",
+            OutputFormat::Otf => "font/otf",
+        }
+    }
+
+    /// Encode an uncompressed subset font in this format
+    pub fn encode(self, sfnt: Vec<u8>) -> Result<Vec<u8>> {
+        match self {
+            OutputFormat::Woff2 => {
+                fontcull::compress_to_woff2(&sfnt).map_err(|e| color_eyre::eyre::eyre!("{}", e))
+            }
+            OutputFormat::Woff => {
+                fontcull::compress_to_woff(&sfnt).map_err(|e| color_eyre::eyre::eyre!("{}", e))
+            }
+            // TrueType and CFF fonts are both plain sfnt files, only the
+            // conventional extension differs
+            OutputFormat::Ttf | OutputFormat::Otf => Ok(sfnt),
+        }
+    }
+}
+
+/// A klippa subsetting flag, named after the matching hb-subset option
+#[derive(clap::ValueEnum, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SubsetFlag {
+    NoHinting,
+    RetainGids,
+    Desubroutinize,
+    NameLegacy,
+    SetOverlapsFlag,
+    PassthroughTables,
+    NotdefOutline,
+    GlyphNames,
+    NoPruneUnicodeRanges,
+    NoLayoutClosure,
+    OptimizeIupDeltas,
+    /// Not an hb-subset option: move `kern` table pairs into a GPOS `kern` feature
+    KernToGpos,
+    /// Not an hb-subset option: gzip every document of the `SVG ` table
+    GzipSvg,
+}
 
+impl SubsetFlag {
+    fn flag(self) -> SubsetFlags {
+        match self {
+            SubsetFlag::NoHinting => SubsetFlags::SUBSET_FLAGS_NO_HINTING,
+            SubsetFlag::RetainGids => SubsetFlags::SUBSET_FLAGS_RETAIN_GIDS,
+            SubsetFlag::Desubroutinize => SubsetFlags::SUBSET_FLAGS_DESUBROUTINIZE,
+            SubsetFlag::NameLegacy => SubsetFlags::SUBSET_FLAGS_NAME_LEGACY,
+            SubsetFlag::SetOverlapsFlag => SubsetFlags::SUBSET_FLAGS_SET_OVERLAPS_FLAG,
+            SubsetFlag::PassthroughTables => SubsetFlags::SUBSET_FLAGS_PASSTHROUGH_UNRECOGNIZED,
+            SubsetFlag::NotdefOutline => SubsetFlags::SUBSET_FLAGS_NOTDEF_OUTLINE,
+            SubsetFlag::GlyphNames => SubsetFlags::SUBSET_FLAGS_GLYPH_NAMES,
+            SubsetFlag::NoPruneUnicodeRanges => SubsetFlags::SUBSET_FLAGS_NO_PRUNE_UNICODE_RANGES,
+            SubsetFlag::NoLayoutClosure => SubsetFlags::SUBSET_FLAGS_NO_LAYOUT_CLOSURE,
+            SubsetFlag::OptimizeIupDeltas => SubsetFlags::SUBSET_FLAGS_OPTIMIZE_IUP_DELTAS,
+            SubsetFlag::KernToGpos => SubsetFlags::SUBSET_FLAGS_KERN_TO_GPOS,
+            SubsetFlag::GzipSvg => SubsetFlags::SUBSET_FLAGS_GZIP_SVG,
+        }
+    }
+
+    /// Combine flags into the set klippa expects
+    pub fn combine(flags: &[SubsetFlag]) -> SubsetFlags {
+        flags
+            .iter()
+            .fold(SubsetFlags::default(), |all, flag| all | flag.flag())
+    }
+}
+
+/// Read a font file (TTF, OTF, WOFF or WOFF2) and subset it, returning an
+/// uncompressed font
+pub fn subset_font_file(
+    font_path: &Path,
+    unicodes: &[u32],
+    options: &SubsetOptions,
+) -> Result<Vec<u8>> {
     // Read the input font
     let font_data = std::fs::read(font_path)
-        .wrap_err_with(|| format!("Failed to read font file: {}", font_path))?;
+        .wrap_err_with(|| format!("Failed to read font file: {}", font_path.display()))?;
 
     // Decompress if WOFF/WOFF2
     let decompressed =
         fontcull::decompress_font(&font_data).map_err(|e| color_eyre::eyre::eyre!("{}", e))?;
 
-    // Subset and compress to WOFF2
-    let woff2_data = fontcull::subset_font_to_woff2_unicode(&decompressed, unicodes)
+    let dropped = fontcull::dropped_tables(&decompressed, options)
         .map_err(|e| color_eyre::eyre::eyre!("{}", e))?;
+    if !dropped.is_empty() {
+        tracing::warn!(
+            "Dropping tables that can't be subset from {}: {} (the passthrough-tables flag keeps them as they are)",
+            font_path.display(),
+            dropped.join(", ")
+        );
+    }
+
+    fontcull::subset_font_data_with_options(&decompressed, unicodes, options)
+        .map_err(|e| color_eyre::eyre::eyre!("{}", e))
+        .wrap_err_with(|| format!("Failed to subset {}", font_path.display()))
+}
+
+/// Subset a font file and encode the subset in each of `formats`
+///
+/// With a `cache`, an output is reused if the same font was subset to the same
+/// characters with the same options before.
+pub fn subset_to_formats(
+    font_path: &Path,
+    unicodes: &[u32],
+    options: &SubsetOptions,
+    formats: &[OutputFormat],
+    cache: Option<&Cache>,
+) -> Result<Vec<Vec<u8>>> {
+    let keys = match cache {
+        Some(_) => {
+            let font_data = std::fs::read(font_path)
+                .wrap_err_with(|| format!("Failed to read font file: {}", font_path.display()))?;
+            let unicodes: Vec<u8> = unicodes.iter().flat_map(|u| u.to_le_bytes()).collect();
+            let options = options_key(options);
+            formats
+                .iter()
+                .map(|format| {
+                    Some(cache::key(&[
+                        &font_data,
+                        &unicodes,
+                        options.as_bytes(),
+                        format.extension().as_bytes(),
+                    ]))
+                })
+                .collect()
+        }
+        None => vec![None; formats.len()],
+    };
+
+    // Subset at most once, and only if some format isn't cached
+    let mut sfnt = None;
+    let mut outputs = Vec::new();
+    for (&format, key) in formats.iter().zip(keys) {
+        if let (Some(cache), Some(key)) = (cache, &key)
+            && let Some(data) = cache.get("fonts", key)
+        {
+            tracing::debug!(
+                "Reusing cached {} subset of {}",
+                format.extension(),
+                font_path.display()
+            );
+            outputs.push(data);
+            continue;
+        }
+        let sfnt = match &sfnt {
+            Some(sfnt) => sfnt,
+            None => sfnt.insert(subset_font_file(font_path, unicodes, options)?),
+        };
+        let data = format.encode(sfnt.clone())?;
+        if let (Some(cache), Some(key)) = (cache, &key) {
+            cache.put("fonts", key, &data)?;
+        }
+        outputs.push(data);
+    }
+    Ok(outputs)
+}
+
+/// A cache key for `options`, built from each of its fields
+fn options_key(options: &SubsetOptions) -> String {
+    // Destructured so that a new option can't be left out of the key
+    let SubsetOptions {
+        flags,
+        gids,
+        glyphs,
+        drop_tables,
+        layout_scripts,
+        layout_features,
+        name_ids,
+        name_languages,
+        texts,
+    } = options;
+    let flags = u16::from(*flags).to_le_bytes();
+    let mut parts: Vec<&[u8]> = vec![
+        &flags,
+        gids.as_bytes(),
+        glyphs.as_bytes(),
+        drop_tables.as_bytes(),
+        layout_scripts.as_bytes(),
+        layout_features.as_bytes(),
+        name_ids.as_bytes(),
+        name_languages.as_bytes(),
+    ];
+    parts.extend(texts.iter().map(|text| text.as_bytes()));
+    cache::key(&parts)
+}
+
+/// Subset a font using klippa (pure Rust, no external dependencies)
+///
+/// With `hash_name`, a hash of the output is added to the file name so it can
+/// be served with long-lived cache headers.
+pub fn subset_with_klippa(
+    font_path: &str,
+    unicodes: &[u32],
+    options: &SubsetOptions,
+    output_dir: Option<&PathBuf>,
+    hash_name: bool,
+    cache: Option<&Cache>,
+) -> Result<PathBuf> {
+    let path = PathBuf::from(font_path);
+
+    // Subset and compress to WOFF2
+    let [woff2_data] = subset_to_formats(&path, unicodes, options, &[OutputFormat::Woff2], cache)?
+        .try_into()
+        .expect("one output per format");
+
+    let template = if hash_name {
+        "{stem}-subset.{hash}.{ext}"
+    } else {
+        "{stem}-subset.{ext}"
+    };
+    let file_name = output_file_name(template, &path, "", OutputFormat::Woff2, &woff2_data)?;
+    let output_path = match output_dir {
+        Some(dir) => dir.join(file_name),
+        None => path.with_file_name(file_name),
+    };
 
     // Write the woff2 file
     std::fs::write(&output_path, &woff2_data)
@@ -34,3 +265,198 @@ pub fn subset_with_klippa(
 
     Ok(output_path)
 }
+
+/// Fill in an output naming template such as `{dir}/{stem}-{group}.{hash}.{ext}`
+///
+/// `{dir}` is the source font's directory, `{stem}` its file name without
+/// extension, `{group}` the font group's name, `{hash}` the first 8 hex digits of
+/// the output's SHA-256 and `{ext}` the format's extension.
+pub fn output_file_name(
+    template: &str,
+    source: &Path,
+    group: &str,
+    format: OutputFormat,
+    data: &[u8],
+) -> Result<PathBuf> {
+    let name = render_template(template, source, group, format, &content_hash(data), |s| {
+        s.to_string()
+    })?;
+    Ok(PathBuf::from(name))
+}
+
+/// A glob pattern matching every file [`output_file_name`] could have written,
+/// whatever its content hash
+pub fn output_file_pattern(
+    template: &str,
+    source: &Path,
+    group: &str,
+    format: OutputFormat,
+) -> Result<String> {
+    render_template(
+        template,
+        source,
+        group,
+        format,
+        "????????",
+        glob::Pattern::escape,
+    )
+}
+
+/// Expand `template`, passing everything but the hash through `literal`
+fn render_template(
+    template: &str,
+    source: &Path,
+    group: &str,
+    format: OutputFormat,
+    hash: &str,
+    literal: impl Fn(&str) -> String,
+) -> Result<String> {
+    let mut name = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        name.push_str(&literal(&rest[..start]));
+        let Some(len) = rest[start..].find('}') else {
+            bail!("Unclosed `{{` in output template {template:?}");
+        };
+        let placeholder = &rest[start + 1..start + len];
+        match placeholder {
+            "dir" => {
+                let dir = source.parent().unwrap_or(Path::new(""));
+                name.push_str(&literal(&dir.to_string_lossy()));
+                // An empty directory would otherwise turn `{dir}/x` into `/x`
+                if dir.as_os_str().is_empty() && rest[start + len + 1..].starts_with('/') {
+                    name.push('.');
+                }
+            }
+            "stem" => name.push_str(&literal(
+                &source.file_stem().unwrap_or_default().to_string_lossy(),
+            )),
+            "group" => name.push_str(&literal(group)),
+            "hash" => name.push_str(hash),
+            "ext" => name.push_str(format.extension()),
+            _ => bail!("Unknown placeholder {{{placeholder}}} in output template {template:?}"),
+        }
+        rest = &rest[start + len + 1..];
+    }
+    name.push_str(&literal(rest));
+    Ok(name)
+}
+
+/// First 8 hex digits of the SHA-256 of `data`
+fn content_hash(data: &[u8]) -> String {
+    Sha256::digest(data)[..4]
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_file_name() {
+        let source = Path::new("fonts/Inter.ttf");
+        let name = |template| output_file_name(template, source, "body", OutputFormat::Ttf, b"x");
+
+        assert_eq!(
+            name("{dir}/{stem}-subset.{ext}").unwrap(),
+            PathBuf::from("fonts/Inter-subset.ttf")
+        );
+        assert_eq!(
+            name("dist/{group}/{stem}.{hash}.{ext}").unwrap(),
+            PathBuf::from("dist/body/Inter.2d711642.ttf")
+        );
+        assert_eq!(
+            output_file_name(
+                "{dir}/{stem}.{ext}",
+                Path::new("a.otf"),
+                "",
+                OutputFormat::Otf,
+                b""
+            )
+            .unwrap(),
+            PathBuf::from("./a.otf")
+        );
+        assert!(name("{stem}.{extension}").is_err());
+
+        let pattern = output_file_pattern(
+            "out[1]/{stem}.{hash}.{ext}",
+            source,
+            "body",
+            OutputFormat::Woff2,
+        )
+        .unwrap();
+        assert_eq!(pattern, "out[[]1[]]/Inter.????????.woff2");
+        let pattern = glob::Pattern::new(&pattern).unwrap();
+        assert!(pattern.matches("out[1]/Inter.2d711642.woff2"));
+        assert!(name("{stem").is_err());
+    }
+
+    #[test]
+    fn test_combine_flags() {
+        let flags = SubsetFlag::combine(&[SubsetFlag::NoHinting, SubsetFlag::RetainGids]);
+        assert_eq!(
+            flags,
+            SubsetFlags::SUBSET_FLAGS_NO_HINTING | SubsetFlags::SUBSET_FLAGS_RETAIN_GIDS
+        );
+        assert_eq!(SubsetFlag::combine(&[]), SubsetFlags::default());
+    }
+
+    #[test]
+    fn test_options_key() {
+        let texts = |texts: &[&str]| SubsetOptions {
+            texts: texts.iter().map(|text| text.to_string()).collect(),
+            ..Default::default()
+        };
+        assert_eq!(options_key(&texts(&["ab"])), options_key(&texts(&["ab"])));
+        assert_ne!(
+            options_key(&texts(&["ab"])),
+            options_key(&texts(&["a", "b"]))
+        );
+        assert_ne!(options_key(&texts(&[])), options_key(&texts(&[""])));
+
+        let flags = SubsetOptions {
+            flags: SubsetFlags::SUBSET_FLAGS_NO_HINTING,
+            ..Default::default()
+        };
+        assert_ne!(options_key(&flags), options_key(&SubsetOptions::default()));
+        let gids = SubsetOptions {
+            gids: "1".to_string(),
+            ..Default::default()
+        };
+        let glyphs = SubsetOptions {
+            glyphs: "1".to_string(),
+            ..Default::default()
+        };
+        assert_ne!(options_key(&gids), options_key(&glyphs));
+    }
+
+    #[test]
+    fn test_subset_to_formats_uses_cache() {
+        let dir =
+            std::env::temp_dir().join(format!("fontcull-subset-cache-{}", std::process::id()));
+        let cache = Cache::open(&dir).unwrap();
+        let font =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/fonts/Roboto-Regular.abc.ttf");
+        let formats = [OutputFormat::Ttf, OutputFormat::Woff2];
+        let options = SubsetOptions::default();
+
+        let fresh =
+            subset_to_formats(&font, &['a' as u32], &options, &formats, Some(&cache)).unwrap();
+        assert_eq!(std::fs::read_dir(dir.join("fonts")).unwrap().count(), 2);
+        let cached =
+            subset_to_formats(&font, &['a' as u32], &options, &formats, Some(&cache)).unwrap();
+        assert_eq!(fresh, cached);
+        assert_eq!(
+            fresh,
+            subset_to_formats(&font, &['a' as u32], &options, &formats, None).unwrap()
+        );
+
+        // Other characters are another entry
+        subset_to_formats(&font, &['b' as u32], &options, &formats[..1], Some(&cache)).unwrap();
+        assert_eq!(std::fs::read_dir(dir.join("fonts")).unwrap().count(), 3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,23 +1,65 @@
 #![doc = include_str!("../README.md")]
 
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    path::{Path, PathBuf},
+};
 
-use chromiumoxide::{Page, browser::Browser};
-use clap::Parser;
+use chromiumoxide::browser::Browser;
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
 use color_eyre::eyre::{Context, Result};
+use fontcull::SubsetOptions;
 use futures::StreamExt;
 
+mod build;
+mod cache;
+mod capture;
+mod charsets;
+mod check;
+mod config;
+mod crawl;
+mod diff;
+mod dump;
+mod font_faces;
 mod glyph_script;
+mod inspect;
 mod klippa_backend;
+mod output;
+mod page_settings;
+mod serve;
+mod subset;
+#[cfg(test)]
+mod testing;
+mod verify;
+mod watch;
 
 #[derive(Parser, Debug)]
 #[command(name = "fontcull")]
 #[command(about = "Subset fonts based on actual glyph usage from web pages")]
+#[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 struct Args {
-    /// URLs to scan for glyph usage
-    #[arg(required = true)]
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// URLs to scan for glyph usage (paths relative to the site with --serve-dir)
+    #[arg(required_unless_present = "serve_dir")]
     urls: Vec<String>,
 
+    /// Serve this local build directory and crawl it; without --subset, the fonts
+    /// it declares are subset next to the originals
+    #[arg(long, value_name = "DIR")]
+    serve_dir: Option<PathBuf>,
+
+    /// Keep running, and scan and subset again whenever a file in --serve-dir
+    /// or a font given to --subset changes
+    #[arg(long, requires = "serve_dir")]
+    watch: bool,
+
+    /// Load every page again with the subset fonts in place of the originals,
+    /// failing if any text falls back to another font
+    #[arg(long)]
+    verify: bool,
+
     /// Font files to subset (glob patterns supported)
     #[arg(long, short = 's')]
     subset: Vec<String>,
@@ -26,10 +68,6 @@ struct Args {
     #[arg(long, short = 'f')]
     family: Option<String>,
 
-    /// Maximum number of pages to spider (0 = no limit)
-    #[arg(long, default_value = "0")]
-    spider_limit: usize,
-
     /// Additional characters to always include (whitelist)
     #[arg(long, short = 'w')]
     whitelist: Option<String>,
@@ -37,21 +75,89 @@ struct Args {
     /// Output directory for subset fonts
     #[arg(long, short = 'o')]
     output: Option<PathBuf>,
+
+    /// Only add the glyphs GSUB substitutions can produce for the text the
+    /// pages render, e.g. a ligature only if its letters follow each other
+    #[arg(long)]
+    text_closure: bool,
+
+    #[command(flatten)]
+    outputs: output::OutputOptions,
+
+    #[command(flatten)]
+    crawl: crawl::CrawlOptions,
+
+    #[command(flatten)]
+    capture: capture::CaptureOptions,
+
+    #[command(flatten)]
+    page: page_settings::PageOptions,
+
+    #[command(flatten)]
+    browser: page_settings::BrowserOptions,
+
+    #[command(flatten)]
+    cache: cache::CacheOptions,
 }
 
-/// Character set per font-family, plus a universal "*" set
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Scan, subset and write stylesheets as described by a `fontcull.toml`
+    Build(build::BuildCommand),
+    /// Check that the subset fonts of a `fontcull.toml` still cover the site,
+    /// failing if characters are missing
+    Check(check::CheckArgs),
+    /// Subset local font files directly, with hb-subset's options
+    Subset(subset::SubsetArgs),
+    /// Print the tables, names, variations, layout features and coverage of fonts
+    Inspect(inspect::InspectArgs),
+    /// Compare the tables, metrics, cmap, glyphs and layout features of two fonts
+    Diff(diff::DiffArgs),
+    /// Print the tables of a font field by field, or as JSON to edit and compile
+    Dump(dump::DumpArgs),
+    /// Compile the JSON from `fontcull dump --json` back into a font
+    Compile(dump::CompileArgs),
+}
+
+/// Character set per font-family, plus a universal "*" set, and the texts
+/// each family renders
 #[derive(Debug, Default)]
 struct GlyphSets {
     sets: HashMap<String, Vec<u32>>,
+    texts: HashMap<String, BTreeSet<String>>,
 }
 
 impl GlyphSets {
-    fn new() -> Self {
-        Self {
-            sets: HashMap::new(),
+    fn merge_texts(&mut self, other: &HashMap<String, Vec<String>>) {
+        for (family, texts) in other {
+            let entry = self.texts.entry(family.clone()).or_default();
+            entry.extend(texts.iter().cloned());
         }
     }
 
+    /// Texts of the families matching `families` like in
+    /// [`get_for_families`](Self::get_for_families), sorted
+    fn get_texts_for_families(&self, families: Option<&str>) -> Vec<String> {
+        let mut result = BTreeSet::new();
+        match families {
+            Some(filter) => {
+                let filter_families: Vec<String> =
+                    filter.split(',').map(|s| s.trim().to_lowercase()).collect();
+                for (family, texts) in &self.texts {
+                    let family_lower = family.to_lowercase();
+                    if filter_families.iter().any(|f| family_lower.contains(f)) {
+                        result.extend(texts.iter().cloned());
+                    }
+                }
+            }
+            None => match self.texts.get("*") {
+                Some(universal) => result.extend(universal.iter().cloned()),
+                None => result.extend(self.texts.values().flatten().cloned()),
+            },
+        }
+        result.into_iter().collect()
+    }
+
     fn merge(&mut self, other: HashMap<String, Vec<u32>>) {
         for (family, chars) in other {
             let entry = self.sets.entry(family).or_default();
@@ -149,94 +255,106 @@ fn to_unicode_range(mut chars: Vec<u32>) -> String {
     ranges.join(",")
 }
 
-async fn extract_glyphs(page: &Page) -> Result<HashMap<String, Vec<u32>>> {
-    let script = glyph_script::GLYPH_SCRIPT;
+/// Text found on the scanned pages
+#[derive(Debug, Default)]
+struct ScanResult {
+    glyph_sets: GlyphSets,
+    char_counts: glyph_script::CharCounts,
+    /// Fonts declared by the served directories, with the families using them
+    local_fonts: BTreeMap<PathBuf, BTreeSet<String>>,
+    /// Glyphs of each page, by URL (or site path for served directories)
+    pages: Vec<(String, GlyphSets)>,
+    /// URL of each page in `pages`
+    urls: Vec<String>,
+    /// Every distinct `@font-face` rule of the pages
+    font_faces: Vec<font_faces::FontFaceRule>,
+}
 
-    let result: serde_json::Value = page
-        .evaluate(script)
+/// Launch Chrome, with a task driving its event handler
+async fn launch_browser(
+    browser_options: &page_settings::BrowserOptions,
+) -> Result<(Browser, tokio::task::JoinHandle<()>)> {
+    let (browser, mut handler) = Browser::launch(browser_options.config()?)
         .await
-        .wrap_err("Failed to execute glyph extraction script")?
-        .into_value()
-        .wrap_err("Failed to get script result")?;
-
-    let mut sets: HashMap<String, Vec<u32>> = HashMap::new();
+        .wrap_err("Failed to launch browser")?;
 
-    if let Some(obj) = result.as_object() {
-        for (family, chars) in obj {
-            if let Some(arr) = chars.as_array() {
-                let codes: Vec<u32> = arr
-                    .iter()
-                    .filter_map(|v| v.as_u64().map(|n| n as u32))
-                    .collect();
-                sets.insert(family.clone(), codes);
-            }
+    // Spawn handler task (errors are expected from chromiumoxide for unhandled CDP messages)
+    let handle = tokio::spawn(async move {
+        while let Some(_event) = handler.next().await {
+            // Silently handle events - errors here are usually just unhandled CDP messages
         }
-    }
-
-    Ok(sets)
+    });
+    Ok((browser, handle))
 }
 
-async fn spider_page(page: &Page, limit: usize) -> Result<Vec<String>> {
-    let script = r#"
-        (() => {
-            const links = Array.from(document.querySelectorAll('a[href]'));
-            const currentOrigin = window.location.origin;
-
-            // Normalize URL for deduplication
-            const normalizeUrl = (url) => {
-                try {
-                    const parsed = new URL(url);
-                    // Remove trailing slash from pathname (except for root)
-                    if (parsed.pathname !== '/' && parsed.pathname.endsWith('/')) {
-                        parsed.pathname = parsed.pathname.slice(0, -1);
-                    }
-                    // Remove fragment (hash) to avoid duplicate content crawling
-                    parsed.hash = '';
-                    // Sort search params for consistent ordering
-                    parsed.searchParams.sort();
-                    return parsed.toString();
-                } catch (e) {
-                    return url;
-                }
-            };
-
-            const normalizedUrls = new Set();
-            return links
-                .map(a => a.href)
-                .filter(href => href.startsWith(currentOrigin))
-                .filter(href => {
-                    const normalized = normalizeUrl(href);
-                    if (normalizedUrls.has(normalized)) {
-                        return false;
-                    }
-                    normalizedUrls.add(normalized);
-                    return true;
-                })
-                .map(href => normalizeUrl(href));
-        })()
-    "#;
-
-    let result: serde_json::Value = page
-        .evaluate(script)
-        .await
-        .wrap_err("Failed to execute spider script")?
-        .into_value()
-        .wrap_err("Failed to get spider result")?;
-
-    let mut urls: Vec<String> = result
-        .as_array()
-        .map(|arr| {
-            arr.iter()
-                .filter_map(|v| v.as_str().map(String::from))
-                .collect()
-        })
-        .unwrap_or_default();
-
-    if limit > 0 && urls.len() > limit {
-        urls.truncate(limit);
-    }
+/// Crawl from `seeds` in a fresh browser, collecting the text of every page
+///
+/// With a `cache`, pages that haven't changed since an earlier scan are reused
+/// rather than loaded again.
+async fn scan(
+    seeds: &[String],
+    servers: &[serve::StaticServer],
+    crawl_options: &crawl::CrawlOptions,
+    capture_options: &capture::CaptureOptions,
+    page_rules: &page_settings::PageRules,
+    browser_options: &page_settings::BrowserOptions,
+    cache: Option<&cache::Cache>,
+) -> Result<ScanResult> {
+    let page_cache = cache
+        .map(|cache| cache::PageCache::new(cache, servers))
+        .transpose()?;
+
+    let (mut browser, handle) = launch_browser(browser_options).await?;
+
+    let pages = crawl::crawl(
+        &browser,
+        seeds,
+        crawl_options,
+        capture_options,
+        page_rules,
+        page_cache.as_ref(),
+    )
+    .await;
+
+    // Cleanup
+    browser.close().await.ok();
+    handle.abort();
+
+    let pages = pages?;
+    tracing::info!("Crawled {} pages", pages.len());
 
-    Ok(urls)
+    let mut result = ScanResult::default();
+    for server in servers {
+        result
+            .local_fonts
+            .extend(server.font_files(pages.iter().flat_map(|p| &p.font_faces)));
+    }
+    for page in pages {
+        for face in page.font_faces {
+            if !result.font_faces.contains(&face) {
+                result.font_faces.push(face);
+            }
+        }
+        result.glyph_sets.merge_texts(&page.texts);
+        let mut page_glyphs = GlyphSets::default();
+        for glyphs in page.glyphs {
+            result.glyph_sets.merge(glyphs.clone());
+            page_glyphs.merge(glyphs);
+        }
+        let name = servers
+            .iter()
+            .find_map(|server| server.site_path(&page.url))
+            .unwrap_or_else(|| page.url.clone());
+        result.pages.push((name, page_glyphs));
+        result.urls.push(page.url);
+        for (family, counts) in page.char_counts {
+            let entry = result.char_counts.entry(family).or_default();
+            for (c, n) in counts {
+                *entry.entry(c).or_default() += n;
+            }
+        }
+    }
+    Ok(result)
 }
 
 #[tokio::main]
@@ -251,63 +369,103 @@ async fn main() -> Result<()> {
         )
         .init();
 
-    let args = Args::parse();
+    // Parsed by hand so `build` can tell flags given on the command line from defaults
+    let matches = Args::command().get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
     tracing::info!(?args, "Starting fontcull");
 
-    // Launch browser
-    let (mut browser, mut handler) = Browser::launch(
-        chromiumoxide::BrowserConfig::builder()
-            .build()
-            .map_err(|e| color_eyre::eyre::eyre!("Failed to build browser config: {}", e))?,
-    )
-    .await
-    .wrap_err("Failed to launch browser")?;
-
-    // Spawn handler task (errors are expected from chromiumoxide for unhandled CDP messages)
-    let handle = tokio::spawn(async move {
-        while let Some(_event) = handler.next().await {
-            // Silently handle events - errors here are usually just unhandled CDP messages
+    match &args.command {
+        Some(Command::Build(build_args)) => {
+            let matches = matches.subcommand_matches("build").unwrap();
+            return build::run(build_args, matches).await;
         }
-    });
-
-    let mut glyph_sets = GlyphSets::new();
-    let mut visited_urls = std::collections::HashSet::new();
-    let mut urls_to_visit: Vec<String> = args.urls.clone();
-
-    // Process all URLs
-    while let Some(url) = urls_to_visit.pop() {
-        if visited_urls.contains(&url) {
-            continue;
+        Some(Command::Check(check_args)) => {
+            let matches = matches.subcommand_matches("check").unwrap();
+            return check::run(check_args, matches).await;
         }
-        visited_urls.insert(url.clone());
-
-        tracing::info!("Processing URL: {}", url);
-
-        let page = browser
-            .new_page(&url)
-            .await
-            .wrap_err_with(|| format!("Failed to navigate to {}", url))?;
-
-        // Wait for page to load
-        page.wait_for_navigation().await.ok();
+        Some(Command::Subset(subset_args)) => return subset::run(subset_args),
+        Some(Command::Inspect(inspect_args)) => return inspect::run(inspect_args),
+        Some(Command::Diff(diff_args)) => return diff::run(diff_args),
+        Some(Command::Dump(dump_args)) => return dump::run_dump(dump_args),
+        Some(Command::Compile(compile_args)) => return dump::run_compile(compile_args),
+        None => {}
+    }
 
-        // Extract glyphs
-        let glyphs = extract_glyphs(&page).await?;
-        tracing::info!("Found {} font families with glyphs", glyphs.len());
-        glyph_sets.merge(glyphs);
+    let cache = cache::Cache::from_options(&args.cache)?;
+    let Some(dir) = args.serve_dir.as_ref().filter(|_| args.watch) else {
+        return run(&args, cache.as_ref()).await;
+    };
 
-        // Spider for more URLs if requested
-        if args.spider_limit > 0 && visited_urls.len() < args.spider_limit {
-            let new_urls = spider_page(&page, args.spider_limit - visited_urls.len()).await?;
-            for new_url in new_urls {
-                if !visited_urls.contains(&new_url) {
-                    urls_to_visit.push(new_url);
-                }
-            }
+    let mut paths = vec![dir.clone()];
+    for pattern in &args.subset {
+        for entry in glob::glob(pattern).wrap_err("Invalid glob pattern")? {
+            paths.push(entry.wrap_err("Glob error")?);
+        }
+    }
+    // Without a cache directory, unchanged pages are still reused between runs,
+    // in a temporary one that is removed when watching stops
+    let mut temp_dir = None;
+    let cache = match cache {
+        Some(cache) => cache,
+        None => {
+            let dir = temp_dir.insert(
+                tempfile::Builder::new()
+                    .prefix("fontcull-watch-")
+                    .tempdir()
+                    .wrap_err("Failed to create a temporary cache directory")?,
+            );
+            cache::Cache::open(dir.path())?
         }
+    };
+    watch::watch(&paths, || run(&args, Some(&cache))).await
+}
 
-        page.close().await.ok();
+/// The options for subsetting a font of `families`, whose GSUB closure is
+/// limited to the texts they render with `--text-closure`
+fn text_options(args: &Args, glyph_sets: &GlyphSets, families: Option<&str>) -> SubsetOptions {
+    if !args.text_closure {
+        return SubsetOptions::default();
+    }
+    let texts = glyph_sets.get_texts_for_families(families);
+    tracing::info!("Limiting GSUB closure to {} texts", texts.len());
+    SubsetOptions {
+        texts,
+        ..Default::default()
     }
+}
+
+/// Scan the given URLs or served directory, then subset fonts or print the
+/// characters found
+async fn run(args: &Args, cache: Option<&cache::Cache>) -> Result<()> {
+    let page_rules = page_settings::PageRules::load(&args.page)?;
+
+    // Serve a local directory if requested, crawling every page in it by default
+    let server = match &args.serve_dir {
+        Some(dir) => Some(serve::StaticServer::start(dir).await?),
+        None => None,
+    };
+    let seeds = match &server {
+        Some(server) if args.urls.is_empty() => server.html_pages()?,
+        Some(server) => args.urls.iter().map(|u| server.url_for(u)).collect(),
+        None => args.urls.clone(),
+    };
+
+    let mut scan_result = scan(
+        &seeds,
+        server.as_slice(),
+        &args.crawl,
+        &args.capture,
+        &page_rules,
+        &args.browser,
+        cache,
+    )
+    .await?;
+    let ScanResult {
+        glyph_sets,
+        char_counts,
+        local_fonts,
+        ..
+    } = &mut scan_result;
 
     // Add whitelist characters
     if let Some(ref whitelist) = args.whitelist {
@@ -325,6 +483,7 @@ async fn main() -> Result<()> {
     );
 
     // Subset fonts if requested
+    let mut subset_fonts = Vec::new();
     if !args.subset.is_empty() {
         let mut font_files = Vec::new();
         for pattern in &args.subset {
@@ -336,19 +495,92 @@ async fn main() -> Result<()> {
         for font_file in font_files {
             tracing::info!("Subsetting font: {}", font_file);
 
-            let output =
-                klippa_backend::subset_with_klippa(&font_file, &chars, args.output.as_ref())?;
+            let options = text_options(args, glyph_sets, args.family.as_deref());
+            let output = klippa_backend::subset_with_klippa(
+                &font_file,
+                &chars,
+                &options,
+                args.output.as_ref(),
+                args.outputs.hash_names,
+                cache,
+            )?;
 
             tracing::info!("Created: {}", output.display());
+            subset_fonts.push(output::SubsetFont::describe(
+                Path::new(&font_file),
+                &[(output, klippa_backend::OutputFormat::Woff2)],
+                Vec::new(),
+                &chars,
+            )?);
+        }
+    } else if server.is_some() {
+        // Subset the fonts the site declares, each with the glyphs of its own families
+        let filter: Option<Vec<String>> = args
+            .family
+            .as_deref()
+            .map(|f| f.split(',').map(|s| s.trim().to_lowercase()).collect());
+
+        for (font_file, families) in local_fonts {
+            if let Some(filter) = &filter
+                && !families
+                    .iter()
+                    .any(|family| filter.iter().any(|f| family.to_lowercase().contains(f)))
+            {
+                continue;
+            }
+
+            let families: Vec<String> = families.iter().cloned().collect();
+            let mut chars = glyph_sets.get_for_families(Some(&families.join(",")));
+            if chars.is_empty() {
+                tracing::info!(
+                    "Skipping {}: {} is not used on any page",
+                    font_file.display(),
+                    families.join(", ")
+                );
+                continue;
+            }
+            if let Some(ref whitelist) = args.whitelist {
+                chars.extend(whitelist.chars().map(|c| c as u32));
+            }
+
+            tracing::info!(
+                "Subsetting font: {} ({})",
+                font_file.display(),
+                families.join(", ")
+            );
+            let options = text_options(args, glyph_sets, Some(&families.join(",")));
+            let output = klippa_backend::subset_with_klippa(
+                &font_file.display().to_string(),
+                &chars,
+                &options,
+                args.output.as_ref(),
+                args.outputs.hash_names,
+                cache,
+            )?;
+            tracing::info!("Created: {}", output.display());
+            subset_fonts.push(output::SubsetFont::describe(
+                font_file,
+                &[(output, klippa_backend::OutputFormat::Woff2)],
+                families,
+                &chars,
+            )?);
         }
     } else {
         // Just print the unicode range
         println!("{}", unicode_range);
     }
-
-    // Cleanup
-    browser.close().await.ok();
-    handle.abort();
-
+    output::write_all(&mut subset_fonts, &args.outputs, char_counts)?;
+
+    if args.verify {
+        verify::run(
+            &scan_result,
+            server.as_slice(),
+            &subset_fonts,
+            &args.capture,
+            &page_rules,
+            &args.browser,
+        )
+        .await?;
+    }
     Ok(())
 }
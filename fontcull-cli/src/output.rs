@@ -0,0 +1,568 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Write as _,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use color_eyre::eyre::{Context, Result};
+use fontcull::{FontMetrics, MetricOverrides};
+use serde::{Deserialize, Serialize};
+
+use crate::{glyph_script::CharCounts, klippa_backend::OutputFormat, to_unicode_range};
+
+/// Options for the files written alongside the subset fonts
+#[derive(clap::Args, Debug, Clone)]
+pub struct OutputOptions {
+    /// Write an `@font-face` rule for every subset font to this stylesheet
+    #[arg(long, value_name = "FILE")]
+    pub css: Option<PathBuf>,
+
+    /// Write `<link rel="preload">` tags for the subset fonts to this HTML snippet
+    #[arg(long, value_name = "FILE")]
+    pub preload: Option<PathBuf>,
+
+    /// Write a JSON manifest mapping each source font to its outputs and code points
+    #[arg(long, value_name = "FILE")]
+    pub manifest: Option<PathBuf>,
+
+    /// Add a content hash to subset file names, e.g. `inter-subset.3f2a9c1b.woff2`
+    #[arg(long)]
+    pub hash_names: bool,
+
+    /// URL prefix for fonts in the stylesheet and preload tags (default: the path
+    /// relative to the stylesheet)
+    #[arg(long, value_name = "URL")]
+    pub font_url_prefix: Option<String>,
+
+    /// `font-display` value for generated `@font-face` rules
+    #[arg(long, default_value = "swap", value_name = "VALUE")]
+    pub font_display: String,
+
+    /// Add a size-adjusted `<family> Fallback` face per font to the stylesheet, so
+    /// text doesn't shift when the web font loads: `arial`, `times-new-roman`, or
+    /// a local font file
+    #[arg(long, value_name = "FONT")]
+    pub fallback_font: Option<FallbackFont>,
+}
+
+impl Default for OutputOptions {
+    fn default() -> Self {
+        Self {
+            css: None,
+            preload: None,
+            manifest: None,
+            hash_names: false,
+            font_url_prefix: None,
+            font_display: "swap".to_string(),
+            fallback_font: None,
+        }
+    }
+}
+
+/// A locally installed font that text is shown in until the web font loads
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum FallbackFont {
+    Arial,
+    TimesNewRoman,
+    /// A font file, installed on visitors' machines under its family name
+    File(PathBuf),
+}
+
+impl FromStr for FallbackFont {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "arial" => Ok(FallbackFont::Arial),
+            "times-new-roman" | "times" => Ok(FallbackFont::TimesNewRoman),
+            // Whether the file exists is checked when it's loaded
+            _ if Path::new(s).extension().is_some() || Path::new(s).is_file() => {
+                Ok(FallbackFont::File(PathBuf::from(s)))
+            }
+            _ => Err(format!(
+                "expected `arial`, `times-new-roman` or a font file, got {s:?}"
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for FallbackFont {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl FallbackFont {
+    /// `local()` names to try, and the font's metrics
+    fn load(&self) -> Result<(Vec<String>, FontMetrics)> {
+        match self {
+            // Liberation fonts are metric-compatible stand-ins found on Linux
+            FallbackFont::Arial => Ok((
+                vec!["Arial".to_string(), "Liberation Sans".to_string()],
+                FontMetrics::arial(),
+            )),
+            FallbackFont::TimesNewRoman => Ok((
+                vec![
+                    "Times New Roman".to_string(),
+                    "Liberation Serif".to_string(),
+                ],
+                FontMetrics::times_new_roman(),
+            )),
+            FallbackFont::File(path) => {
+                let data = read_font(path)?;
+                let info =
+                    fontcull::font_info(&data).map_err(|e| color_eyre::eyre::eyre!("{}", e))?;
+                let metrics =
+                    FontMetrics::from_font(&data).map_err(|e| color_eyre::eyre::eyre!("{}", e))?;
+                Ok((info.family.into_iter().collect(), metrics))
+            }
+        }
+    }
+}
+
+/// A fallback `@font-face` sized to match a web font
+#[derive(Debug, Clone, Serialize)]
+pub struct FallbackFace {
+    pub family: String,
+    /// Names to try in `local()` sources
+    pub local: Vec<String>,
+    /// Percentages, rounded to two decimals
+    pub size_adjust: f32,
+    pub ascent_override: f32,
+    pub descent_override: f32,
+    pub line_gap_override: f32,
+}
+
+impl FallbackFace {
+    fn new(family: &str, local: Vec<String>, overrides: MetricOverrides) -> Self {
+        let percent = |ratio: f32| (ratio * 10000.0).round() / 100.0;
+        Self {
+            family: format!("{family} Fallback"),
+            local,
+            size_adjust: percent(overrides.size_adjust),
+            ascent_override: percent(overrides.ascent_override),
+            descent_override: percent(overrides.descent_override),
+            line_gap_override: percent(overrides.line_gap_override),
+        }
+    }
+}
+
+/// A source font and the subset files written for it
+#[derive(Debug, Clone, Serialize)]
+pub struct SubsetFont {
+    pub source: PathBuf,
+    /// Family names to declare: the page's `@font-face` names, or the font's own name
+    pub families: Vec<String>,
+    /// CSS `font-weight` value, a range for variable fonts
+    pub weight: String,
+    /// CSS `font-style` value
+    pub style: String,
+    /// CSS `font-stretch` value, if the font isn't normal width
+    pub stretch: Option<String>,
+    pub outputs: Vec<OutputFile>,
+    /// Code points that were requested and that the font actually has
+    pub codepoints: Vec<u32>,
+    pub unicode_range: String,
+    /// Size-adjusted fallback faces, one per family, with `--fallback-font`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub fallbacks: Vec<FallbackFace>,
+}
+
+/// One file written for a [`SubsetFont`]
+#[derive(Debug, Clone, Serialize)]
+pub struct OutputFile {
+    pub file: PathBuf,
+    /// URL used in the stylesheet and preload tags
+    pub url: String,
+    pub format: OutputFormat,
+    pub size: u64,
+}
+
+impl SubsetFont {
+    /// Describe a subset font from its source file, given the files written for
+    /// it, the code points it was subset to and the family names it's used under
+    /// (if known)
+    pub fn describe(
+        source: &Path,
+        outputs: &[(PathBuf, OutputFormat)],
+        families: Vec<String>,
+        requested: &[u32],
+    ) -> Result<Self> {
+        let decompressed = read_font(source)?;
+        let info = fontcull::font_info(&decompressed)
+            .map_err(|e| color_eyre::eyre::eyre!("{}", e))
+            .wrap_err_with(|| format!("Failed to read font file: {}", source.display()))?;
+
+        let requested: BTreeSet<u32> = requested.iter().copied().collect();
+        let codepoints: Vec<u32> = info
+            .codepoints
+            .iter()
+            .copied()
+            .filter(|c| requested.contains(c))
+            .collect();
+
+        let families = if families.is_empty() {
+            info.family.into_iter().collect()
+        } else {
+            families
+        };
+
+        let outputs = outputs
+            .iter()
+            .map(|(file, format)| {
+                let size = std::fs::metadata(file)
+                    .wrap_err_with(|| format!("Failed to read {}", file.display()))?
+                    .len();
+                Ok(OutputFile {
+                    file: file.clone(),
+                    url: String::new(),
+                    format: *format,
+                    size,
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            source: source.to_path_buf(),
+            families,
+            weight: css_range(info.weight, ""),
+            style: if info.italic { "italic" } else { "normal" }.to_string(),
+            stretch: (info.stretch != (100.0, 100.0)).then(|| css_range(info.stretch, "%")),
+            outputs,
+            unicode_range: to_unicode_range(codepoints.clone()),
+            codepoints,
+            fallbacks: Vec::new(),
+        })
+    }
+
+    /// Size fallback faces for this font's families, weighting characters by how
+    /// often they appear in text using those families
+    fn add_fallbacks(
+        &mut self,
+        local: &[String],
+        fallback: &FontMetrics,
+        char_counts: &CharCounts,
+    ) -> Result<()> {
+        let data = read_font(&self.source)?;
+        let metrics =
+            FontMetrics::from_font(&data).map_err(|e| color_eyre::eyre::eyre!("{}", e))?;
+
+        let mut frequencies: HashMap<u32, u64> = HashMap::new();
+        for (family, counts) in char_counts {
+            if self.families.iter().any(|f| f.eq_ignore_ascii_case(family)) {
+                for (&c, &n) in counts {
+                    *frequencies.entry(c).or_default() += n;
+                }
+            }
+        }
+        // The font's own name may not be what pages call it
+        if frequencies.is_empty()
+            && let Some(all) = char_counts.get("*")
+        {
+            frequencies = all.clone();
+        }
+
+        let overrides = metrics.overrides_for_fallback(fallback, &frequencies);
+        self.fallbacks = self
+            .families
+            .iter()
+            .map(|family| FallbackFace::new(family, local.to_vec(), overrides))
+            .collect();
+        Ok(())
+    }
+}
+
+/// Read a font file, decompressing WOFF2
+pub fn read_font(path: &Path) -> Result<Vec<u8>> {
+    let data = std::fs::read(path)
+        .wrap_err_with(|| format!("Failed to read font file: {}", path.display()))?;
+    fontcull::decompress_font(&data).map_err(|e| color_eyre::eyre::eyre!("{}", e))
+}
+
+/// `400` for a single value, `100 900` for a variable font's range
+fn css_range((min, max): (f32, f32), unit: &str) -> String {
+    if min == max {
+        format!("{min}{unit}")
+    } else {
+        format!("{min}{unit} {max}{unit}")
+    }
+}
+
+/// Fill in output URLs and fallback faces, then write the stylesheet, preload
+/// tags and manifest that were asked for
+pub fn write_all(
+    fonts: &mut [SubsetFont],
+    options: &OutputOptions,
+    char_counts: &CharCounts,
+) -> Result<()> {
+    fonts.sort_by(|a, b| a.source.cmp(&b.source));
+    for font in fonts.iter_mut() {
+        for output in &mut font.outputs {
+            output.url = font_url(&output.file, options);
+        }
+    }
+
+    if let Some(fallback_font) = &options.fallback_font {
+        let (local, fallback) = fallback_font.load()?;
+        for font in fonts.iter_mut() {
+            font.add_fallbacks(&local, &fallback, char_counts)?;
+        }
+    }
+
+    if let Some(path) = &options.css {
+        write_file(path, &stylesheet(fonts, &options.font_display))?;
+        tracing::info!("Wrote stylesheet: {}", path.display());
+    }
+    if let Some(path) = &options.preload {
+        write_file(path, &preload_tags(fonts))?;
+        tracing::info!("Wrote preload tags: {}", path.display());
+    }
+    if let Some(path) = &options.manifest {
+        let manifest = serde_json::json!({ "fonts": fonts });
+        write_file(path, &serde_json::to_string_pretty(&manifest)?)?;
+        tracing::info!("Wrote manifest: {}", path.display());
+    }
+
+    Ok(())
+}
+
+fn write_file(path: &Path, contents: &str) -> Result<()> {
+    std::fs::write(path, contents).wrap_err_with(|| format!("Failed to write {}", path.display()))
+}
+
+fn font_url(file: &Path, options: &OutputOptions) -> String {
+    let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+    match (&options.font_url_prefix, &options.css) {
+        (Some(prefix), _) => format!("{}/{}", prefix.trim_end_matches('/'), file_name),
+        (None, Some(css)) => relative_url(css.parent().unwrap_or(Path::new("")), file),
+        (None, None) => path_to_url(file),
+    }
+}
+
+/// The URL path from files in `from_dir` to `to`
+fn relative_url(from_dir: &Path, to: &Path) -> String {
+    let from = std::path::absolute(from_dir).unwrap_or_else(|_| from_dir.to_path_buf());
+    let to = std::path::absolute(to).unwrap_or_else(|_| to.to_path_buf());
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    let mut parts: Vec<String> = vec!["..".to_string(); from.len() - common];
+    parts.extend(
+        to[common..]
+            .iter()
+            .map(|c| c.as_os_str().to_string_lossy().into_owned()),
+    );
+    parts.join("/")
+}
+
+fn path_to_url(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+/// Quote a string for use in CSS
+fn css_string(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn stylesheet(fonts: &[SubsetFont], font_display: &str) -> String {
+    let mut css = String::from("/* Generated by fontcull */\n");
+    for font in fonts {
+        let src = font
+            .outputs
+            .iter()
+            .map(|o| {
+                format!(
+                    "url({}) format({})",
+                    css_string(&o.url),
+                    css_string(o.format.css_format())
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        for family in &font.families {
+            css.push('\n');
+            css.push_str("@font-face {\n");
+            writeln!(css, "  font-family: {};", css_string(family)).unwrap();
+            writeln!(css, "  src: {src};").unwrap();
+            writeln!(css, "  font-weight: {};", font.weight).unwrap();
+            writeln!(css, "  font-style: {};", font.style).unwrap();
+            if let Some(stretch) = &font.stretch {
+                writeln!(css, "  font-stretch: {stretch};").unwrap();
+            }
+            writeln!(css, "  font-display: {font_display};").unwrap();
+            if !font.unicode_range.is_empty() {
+                writeln!(css, "  unicode-range: {};", font.unicode_range).unwrap();
+            }
+            css.push_str("}\n");
+        }
+
+        for fallback in &font.fallbacks {
+            let local = fallback
+                .local
+                .iter()
+                .map(|name| format!("local({})", css_string(name)))
+                .collect::<Vec<_>>()
+                .join(", ");
+            css.push('\n');
+            css.push_str("@font-face {\n");
+            writeln!(css, "  font-family: {};", css_string(&fallback.family)).unwrap();
+            writeln!(css, "  src: {local};").unwrap();
+            writeln!(css, "  font-weight: {};", font.weight).unwrap();
+            writeln!(css, "  font-style: {};", font.style).unwrap();
+            writeln!(css, "  size-adjust: {}%;", fallback.size_adjust).unwrap();
+            writeln!(css, "  ascent-override: {}%;", fallback.ascent_override).unwrap();
+            writeln!(css, "  descent-override: {}%;", fallback.descent_override).unwrap();
+            writeln!(css, "  line-gap-override: {}%;", fallback.line_gap_override).unwrap();
+            css.push_str("}\n");
+        }
+    }
+    css
+}
+
+fn preload_tags(fonts: &[SubsetFont]) -> String {
+    let mut html = String::new();
+    for output in fonts.iter().flat_map(|f| &f.outputs) {
+        writeln!(
+            html,
+            r#"<link rel="preload" href="{}" as="font" type="{}" crossorigin>"#,
+            output.url.replace('&', "&amp;").replace('"', "&quot;"),
+            output.format.mime_type()
+        )
+        .unwrap();
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/fonts/Roboto-Regular.abc.ttf")
+    }
+
+    fn font(url: &str) -> SubsetFont {
+        SubsetFont {
+            source: PathBuf::from("fonts/inter.ttf"),
+            families: vec!["Inter".to_string(), "Body \"Font\"".to_string()],
+            weight: "100 900".to_string(),
+            style: "normal".to_string(),
+            stretch: None,
+            outputs: vec![OutputFile {
+                file: PathBuf::from("dist/inter-subset.woff2"),
+                url: url.to_string(),
+                format: OutputFormat::Woff2,
+                size: 1234,
+            }],
+            codepoints: vec![0x41, 0x42],
+            unicode_range: "U+41-42".to_string(),
+            fallbacks: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_describe_intersects_coverage() {
+        let source = fixture();
+        let outputs = [(source.clone(), OutputFormat::Ttf)];
+        let info = SubsetFont::describe(&source, &outputs, vec![], &[0x61, 0x63, 0x64]).unwrap();
+        assert_eq!(info.families, vec!["Roboto"]);
+        assert_eq!(info.weight, "400");
+        assert_eq!(info.style, "normal");
+        assert_eq!(info.stretch, None);
+        // `d` is not in the font, so it must not end up in unicode-range
+        assert_eq!(info.codepoints, vec![0x61, 0x63]);
+        assert_eq!(info.unicode_range, "U+61,U+63");
+
+        assert_eq!(info.outputs[0].format, OutputFormat::Ttf);
+        assert!(info.outputs[0].size > 0);
+
+        let info =
+            SubsetFont::describe(&source, &outputs, vec!["Body".to_string()], &[0x61]).unwrap();
+        assert_eq!(info.families, vec!["Body"]);
+    }
+
+    #[test]
+    fn test_css_range() {
+        assert_eq!(css_range((400.0, 400.0), ""), "400");
+        assert_eq!(css_range((100.0, 900.0), ""), "100 900");
+        assert_eq!(css_range((75.0, 125.0), "%"), "75% 125%");
+    }
+
+    #[test]
+    fn test_stylesheet() {
+        let css = stylesheet(&[font("/fonts/inter-subset.woff2")], "optional");
+        assert_eq!(css.matches("@font-face").count(), 2);
+        assert!(css.contains(r#"font-family: "Body \"Font\"";"#));
+        assert!(css.contains(r#"src: url("/fonts/inter-subset.woff2") format("woff2");"#));
+        assert!(css.contains("font-weight: 100 900;"));
+        assert!(css.contains("font-display: optional;"));
+        assert!(css.contains("unicode-range: U+41-42;"));
+        assert!(!css.contains("font-stretch"));
+    }
+
+    #[test]
+    fn test_fallback_faces() {
+        let outputs = [(fixture(), OutputFormat::Ttf)];
+        let mut font = SubsetFont::describe(&fixture(), &outputs, vec![], &[0x61]).unwrap();
+        let (local, arial) = FallbackFont::Arial.load().unwrap();
+        let counts = CharCounts::from([("Roboto".to_string(), HashMap::from([(0x61, 10)]))]);
+        font.add_fallbacks(&local, &arial, &counts).unwrap();
+
+        let fallback = &font.fallbacks[0];
+        assert_eq!(fallback.family, "Roboto Fallback");
+        assert!(fallback.size_adjust > 90.0 && fallback.size_adjust < 110.0);
+
+        let css = stylesheet(&[font], "swap");
+        assert!(css.contains(r#"font-family: "Roboto Fallback";"#));
+        assert!(css.contains(r#"src: local("Arial"), local("Liberation Sans");"#));
+        assert!(css.contains("size-adjust: "));
+        assert!(css.contains("line-gap-override: "));
+    }
+
+    #[test]
+    fn test_parse_fallback_font() {
+        assert!(matches!("Arial".parse(), Ok(FallbackFont::Arial)));
+        assert!(matches!(
+            "times-new-roman".parse(),
+            Ok(FallbackFont::TimesNewRoman)
+        ));
+        assert!(matches!(
+            fixture().to_str().unwrap().parse(),
+            Ok(FallbackFont::File(_))
+        ));
+        assert!("comic-sans".parse::<FallbackFont>().is_err());
+        let missing = FallbackFont::File(PathBuf::from("missing.ttf"));
+        assert_eq!("missing.ttf".parse(), Ok(missing.clone()));
+        assert!(missing.load().is_err());
+    }
+
+    #[test]
+    fn test_preload_tags() {
+        assert_eq!(
+            preload_tags(&[font("/fonts/a.woff2?v=1&x=2")]),
+            "<link rel=\"preload\" href=\"/fonts/a.woff2?v=1&amp;x=2\" as=\"font\" type=\"font/woff2\" crossorigin>\n"
+        );
+    }
+
+    #[test]
+    fn test_font_url() {
+        let file = Path::new("dist/fonts/inter-subset.woff2");
+        let mut options = OutputOptions::default();
+        assert_eq!(font_url(file, &options), "dist/fonts/inter-subset.woff2");
+
+        options.css = Some(PathBuf::from("dist/css/fonts.css"));
+        assert_eq!(font_url(file, &options), "../fonts/inter-subset.woff2");
+
+        options.font_url_prefix = Some("https://cdn.example.com/f/".to_string());
+        assert_eq!(
+            font_url(file, &options),
+            "https://cdn.example.com/f/inter-subset.woff2"
+        );
+    }
+}
@@ -0,0 +1,676 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use chromiumoxide::{
+    BrowserConfig, Page,
+    auth::Credentials,
+    cdp::browser_protocol::{
+        emulation::SetLocaleOverrideParams,
+        network::{
+            CookieParam, Headers, SetCookiesParams, SetExtraHttpHeadersParams,
+            SetUserAgentOverrideParams, TimeSinceEpoch,
+        },
+    },
+};
+use color_eyre::eyre::{Context, Result, bail, eyre};
+use serde::Deserialize;
+use url::Url;
+
+use crate::crawl::UrlPattern;
+
+/// Options for launching the browser
+#[derive(clap::Args, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct BrowserOptions {
+    /// Path to the Chrome or Chromium executable (detected automatically by default)
+    #[arg(long, value_name = "PATH")]
+    pub chrome: Option<PathBuf>,
+
+    /// Extra command-line argument for Chrome, e.g. `--chrome-arg=--lang=de` (repeatable)
+    #[arg(long = "chrome-arg", value_name = "ARG", allow_hyphen_values = true)]
+    pub chrome_args: Vec<String>,
+}
+
+impl BrowserOptions {
+    pub fn config(&self) -> Result<BrowserConfig> {
+        let mut builder = BrowserConfig::builder().args(&self.chrome_args);
+        if let Some(chrome) = &self.chrome {
+            builder = builder.chrome_executable(chrome);
+        }
+        builder
+            .build()
+            .map_err(|e| eyre!("Failed to build browser config: {}", e))
+    }
+}
+
+/// How pages are requested: headers, credentials and emulated locale
+///
+/// Given on the command line these apply to every page; `--page-rules` can
+/// override them for URLs matching a pattern.
+#[derive(clap::Args, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct PageSettings {
+    /// Send this extra HTTP header with every request: `NAME: VALUE` (repeatable)
+    #[arg(long = "header", value_name = "NAME: VALUE")]
+    pub headers: Vec<Header>,
+
+    /// Load cookies from a Netscape-format cookie file, as exported by curl or browser extensions
+    #[arg(long, value_name = "FILE")]
+    pub cookies: Option<PathBuf>,
+
+    /// Answer HTTP basic auth challenges with these credentials: `USER:PASSWORD`
+    #[arg(long, value_name = "USER:PASSWORD")]
+    pub basic_auth: Option<BasicAuth>,
+
+    /// Override the browser's user agent
+    #[arg(long, value_name = "UA")]
+    pub user_agent: Option<String>,
+
+    /// Emulate this locale, e.g. `de-DE`; also sets `Accept-Language`
+    #[arg(long, value_name = "LOCALE")]
+    pub locale: Option<String>,
+
+    /// Emulate this IANA timezone, e.g. `Europe/Berlin`
+    #[arg(long, value_name = "TZ")]
+    pub timezone: Option<String>,
+
+    /// Run this JavaScript file in every document before the page's own scripts (repeatable)
+    #[arg(long = "init-script", value_name = "FILE")]
+    pub init_scripts: Vec<PathBuf>,
+}
+
+impl PageSettings {
+    /// Layer `other` on top: headers and scripts are added, everything else is replaced
+    fn merge(&mut self, other: &PageSettings) {
+        self.headers.extend(other.headers.iter().cloned());
+        self.init_scripts.extend(other.init_scripts.iter().cloned());
+        if other.cookies.is_some() {
+            self.cookies = other.cookies.clone();
+        }
+        if other.basic_auth.is_some() {
+            self.basic_auth = other.basic_auth.clone();
+        }
+        if other.user_agent.is_some() {
+            self.user_agent = other.user_agent.clone();
+        }
+        if other.locale.is_some() {
+            self.locale = other.locale.clone();
+        }
+        if other.timezone.is_some() {
+            self.timezone = other.timezone.clone();
+        }
+    }
+
+    /// Make relative file paths relative to `dir` instead of the working directory
+    pub fn resolve_paths(&mut self, dir: &Path) {
+        if let Some(cookies) = &mut self.cookies {
+            *cookies = dir.join(&*cookies);
+        }
+        for script in &mut self.init_scripts {
+            *script = dir.join(&*script);
+        }
+    }
+
+    /// Read every referenced file once, so mistakes are reported before crawling
+    fn validate(&self) -> Result<()> {
+        if let Some(path) = &self.cookies {
+            read_cookies(path)?;
+        }
+        for path in &self.init_scripts {
+            read_script(path)?;
+        }
+        Ok(())
+    }
+
+    /// Headers to send, where a later header replaces an earlier one of the same name
+    fn header_map(&self) -> Vec<(&str, &str)> {
+        let mut headers: Vec<(&str, &str)> = Vec::new();
+        for header in &self.headers {
+            headers.retain(|(name, _)| !name.eq_ignore_ascii_case(&header.name));
+            headers.push((&header.name, &header.value));
+        }
+        headers
+    }
+
+    /// Configure a freshly opened tab; must be called before navigating
+    pub async fn apply(&self, page: &Page) -> Result<()> {
+        let headers = self.header_map();
+        if !headers.is_empty() {
+            let headers: serde_json::Map<String, serde_json::Value> = headers
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value.into()))
+                .collect();
+            page.execute(SetExtraHttpHeadersParams::new(Headers::new(headers)))
+                .await
+                .wrap_err("Failed to set extra HTTP headers")?;
+        }
+
+        if let Some(auth) = &self.basic_auth {
+            page.authenticate(Credentials {
+                username: auth.username.clone(),
+                password: auth.password.clone(),
+            })
+            .await
+            .wrap_err("Failed to set basic auth credentials")?;
+        }
+
+        if self.user_agent.is_some() || self.locale.is_some() {
+            let user_agent = match &self.user_agent {
+                Some(user_agent) => user_agent.clone(),
+                None => page
+                    .user_agent()
+                    .await
+                    .wrap_err("Failed to get user agent")?,
+            };
+            let mut params = SetUserAgentOverrideParams::new(user_agent);
+            params.accept_language = self.locale.clone();
+            page.set_user_agent(params)
+                .await
+                .wrap_err("Failed to override user agent")?;
+        }
+
+        if let Some(locale) = &self.locale {
+            page.emulate_locale(SetLocaleOverrideParams {
+                // ICU style, e.g. `de_DE`
+                locale: Some(locale.replace('-', "_")),
+            })
+            .await
+            .wrap_err_with(|| format!("Failed to emulate locale {locale}"))?;
+        }
+
+        if let Some(timezone) = &self.timezone {
+            page.emulate_timezone(timezone.as_str())
+                .await
+                .wrap_err_with(|| format!("Failed to emulate timezone {timezone}"))?;
+        }
+
+        if let Some(path) = &self.cookies {
+            let cookies = read_cookies(path)?;
+            if !cookies.is_empty() {
+                page.execute(SetCookiesParams::new(
+                    cookies.iter().map(Cookie::to_param).collect(),
+                ))
+                .await
+                .wrap_err("Failed to set cookies")?;
+            }
+        }
+
+        for path in &self.init_scripts {
+            page.evaluate_on_new_document(read_script(path)?)
+                .await
+                .wrap_err_with(|| format!("Failed to add init script {}", path.display()))?;
+        }
+
+        Ok(())
+    }
+
+    /// Build a plain HTTP request (for robots.txt and sitemaps) with the same
+    /// headers, credentials and cookies the browser would send
+    pub fn http_request(&self, client: &reqwest::Client, url: &str) -> reqwest::RequestBuilder {
+        let mut request = client.get(url);
+        for (name, value) in self.header_map() {
+            request = request.header(name, value);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            request = request.header(reqwest::header::USER_AGENT, user_agent);
+        }
+        if let Some(locale) = &self.locale {
+            request = request.header(reqwest::header::ACCEPT_LANGUAGE, locale);
+        }
+        if let Some(auth) = &self.basic_auth {
+            request = request.basic_auth(&auth.username, Some(&auth.password));
+        }
+        if let Some(path) = &self.cookies
+            && let Ok(cookies) = read_cookies(path)
+            && let Ok(parsed) = Url::parse(url)
+        {
+            let header: Vec<String> = cookies
+                .iter()
+                .filter(|c| c.matches(&parsed))
+                .map(|c| format!("{}={}", c.name, c.value))
+                .collect();
+            if !header.is_empty() {
+                request = request.header(reqwest::header::COOKIE, header.join("; "));
+            }
+        }
+        request
+    }
+}
+
+/// Command-line page settings, plus the rules file that refines them per URL
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct PageOptions {
+    #[command(flatten)]
+    pub defaults: PageSettings,
+
+    /// TOML file with `[[rule]]` tables that override page settings for URLs
+    /// matching a `match` pattern (same syntax as --include)
+    #[arg(long, value_name = "FILE")]
+    pub page_rules: Option<PathBuf>,
+}
+
+/// One `[[rule]]` table of a page rules file
+#[derive(Deserialize, Debug)]
+struct PageRule {
+    #[serde(rename = "match")]
+    pattern: UrlPattern,
+    #[serde(flatten)]
+    settings: PageSettings,
+    /// Keys that aren't page settings, reported as errors
+    #[serde(flatten)]
+    unknown: HashMap<String, toml::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+struct PageRulesFile {
+    #[serde(default)]
+    rule: Vec<PageRule>,
+}
+
+/// Page settings for every URL: the command-line defaults, overridden by each
+/// matching rule in file order
+#[derive(Debug, Default)]
+pub struct PageRules {
+    defaults: PageSettings,
+    rules: Vec<PageRule>,
+}
+
+impl PageRules {
+    pub fn load(options: &PageOptions) -> Result<Self> {
+        let rules = match &options.page_rules {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .wrap_err_with(|| format!("Failed to read page rules {}", path.display()))?;
+                let dir = path.parent().unwrap_or(Path::new("."));
+                Self::parse(&text, dir)
+                    .wrap_err_with(|| format!("Invalid page rules {}", path.display()))?
+            }
+            None => Vec::new(),
+        };
+
+        options.defaults.validate()?;
+        for rule in &rules {
+            rule.settings.validate()?;
+        }
+
+        Ok(Self {
+            defaults: options.defaults.clone(),
+            rules,
+        })
+    }
+
+    fn parse(text: &str, dir: &Path) -> Result<Vec<PageRule>> {
+        let file: PageRulesFile = toml::from_str(text)?;
+        let mut rules = file.rule;
+        for rule in &mut rules {
+            if let Some(key) = rule.unknown.keys().next() {
+                bail!("Unknown key {key:?} in rule for {:?}", rule.pattern);
+            }
+            rule.settings.resolve_paths(dir);
+        }
+        Ok(rules)
+    }
+
+    pub fn settings_for(&self, url: &str) -> PageSettings {
+        let mut settings = self.defaults.clone();
+        if let Ok(url) = Url::parse(url) {
+            for rule in self.rules.iter().filter(|r| r.pattern.matches(&url)) {
+                settings.merge(&rule.settings);
+            }
+        }
+        settings
+    }
+}
+
+/// An extra HTTP header given as `Name: value`
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(try_from = "String")]
+pub struct Header {
+    pub name: String,
+    pub value: String,
+}
+
+impl FromStr for Header {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once(':')
+            .ok_or_else(|| format!("expected `NAME: VALUE`, got {s:?}"))?;
+        let (name, value) = (name.trim(), value.trim());
+        reqwest::header::HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| format!("invalid header name {name:?}"))?;
+        reqwest::header::HeaderValue::from_str(value)
+            .map_err(|_| format!("invalid value for header {name}"))?;
+        Ok(Header {
+            name: name.to_string(),
+            value: value.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for Header {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// Credentials given as `user:password`
+#[derive(Deserialize, Clone, PartialEq)]
+#[serde(try_from = "String")]
+pub struct BasicAuth {
+    pub username: String,
+    pub password: String,
+}
+
+// Keep passwords out of the startup log
+impl std::fmt::Debug for BasicAuth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BasicAuth")
+            .field("username", &self.username)
+            .finish_non_exhaustive()
+    }
+}
+
+impl FromStr for BasicAuth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (username, password) = s
+            .split_once(':')
+            .ok_or_else(|| "expected `USER:PASSWORD`".to_string())?;
+        Ok(BasicAuth {
+            username: username.to_string(),
+            password: password.to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for BasicAuth {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+/// A cookie from a Netscape cookie file
+#[derive(Debug, Clone, PartialEq)]
+struct Cookie {
+    /// Without the leading dot
+    domain: String,
+    include_subdomains: bool,
+    path: String,
+    secure: bool,
+    http_only: bool,
+    /// Seconds since the epoch, `None` for session cookies
+    expires: Option<f64>,
+    name: String,
+    value: String,
+}
+
+impl Cookie {
+    fn to_param(&self) -> CookieParam {
+        let mut param = CookieParam::new(self.name.clone(), self.value.clone());
+        if self.include_subdomains {
+            param.domain = Some(format!(".{}", self.domain));
+            param.path = Some(self.path.clone());
+        } else {
+            // Passing a URL instead of a domain keeps the cookie host-only
+            let scheme = if self.secure { "https" } else { "http" };
+            param.url = Some(format!("{scheme}://{}{}", self.domain, self.path));
+        }
+        param.secure = Some(self.secure);
+        param.http_only = Some(self.http_only);
+        param.expires = self.expires.map(TimeSinceEpoch::new);
+        param
+    }
+
+    fn matches(&self, url: &Url) -> bool {
+        let Some(host) = url.host_str() else {
+            return false;
+        };
+        let domain_matches = host.eq_ignore_ascii_case(&self.domain)
+            || (self.include_subdomains
+                && host
+                    .to_ascii_lowercase()
+                    .ends_with(&format!(".{}", self.domain.to_ascii_lowercase())));
+        domain_matches
+            && url.path().starts_with(&self.path)
+            && (!self.secure || url.scheme() == "https")
+    }
+}
+
+fn read_cookies(path: &Path) -> Result<Vec<Cookie>> {
+    let text = std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read cookie file {}", path.display()))?;
+    parse_cookies(&text).wrap_err_with(|| format!("Invalid cookie file {}", path.display()))
+}
+
+fn read_script(path: &Path) -> Result<String> {
+    std::fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read init script {}", path.display()))
+}
+
+/// Parse the tab-separated Netscape cookie format:
+/// `domain  include_subdomains  path  secure  expires  name  value`
+fn parse_cookies(text: &str) -> Result<Vec<Cookie>> {
+    let mut cookies = Vec::new();
+
+    for (i, line) in text.lines().enumerate() {
+        // curl marks httpOnly cookies with a prefix that otherwise looks like a comment
+        let (line, http_only) = match line.strip_prefix("#HttpOnly_") {
+            Some(rest) => (rest, true),
+            None => (line, false),
+        };
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() < 6 {
+            bail!("Line {}: expected 7 tab-separated fields", i + 1);
+        }
+        let flag = |s: &str| s.eq_ignore_ascii_case("TRUE");
+        let expires: u64 = fields[4]
+            .trim()
+            .parse()
+            .map_err(|_| eyre!("Line {}: invalid expiry {:?}", i + 1, fields[4]))?;
+        let domain = fields[0].trim();
+
+        cookies.push(Cookie {
+            domain: domain.trim_start_matches('.').to_string(),
+            include_subdomains: flag(fields[1]) || domain.starts_with('.'),
+            path: fields[2].to_string(),
+            secure: flag(fields[3]),
+            http_only,
+            expires: (expires > 0).then_some(expires as f64),
+            name: fields[5].to_string(),
+            // A cookie may legitimately have an empty value, and no trailing tab
+            value: fields.get(6).copied().unwrap_or("").to_string(),
+        });
+    }
+
+    Ok(cookies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::{TestBrowser, pages_dir};
+
+    #[test]
+    fn test_parse_header() {
+        let header: Header = "X-Preview:  yes please ".parse().unwrap();
+        assert_eq!(header.name, "X-Preview");
+        assert_eq!(header.value, "yes please");
+
+        assert!("no colon".parse::<Header>().is_err());
+        assert!("Bad Name: x".parse::<Header>().is_err());
+    }
+
+    #[test]
+    fn test_parse_basic_auth() {
+        let auth: BasicAuth = "admin:pa:ss".parse().unwrap();
+        assert_eq!(auth.username, "admin");
+        assert_eq!(auth.password, "pa:ss");
+        assert!("admin".parse::<BasicAuth>().is_err());
+        assert!(!format!("{auth:?}").contains("pa:ss"));
+    }
+
+    #[test]
+    fn test_parse_cookies() {
+        let text = "# Netscape HTTP Cookie File\n\
+                    \n\
+                    .example.com\tTRUE\t/\tFALSE\t0\tsession\tabc\n\
+                    #HttpOnly_staging.example.com\tFALSE\t/app\tTRUE\t2000000000\ttoken\tx=y\n\
+                    example.com\tFALSE\t/\tFALSE\t0\tempty\n";
+        let cookies = parse_cookies(text).unwrap();
+        assert_eq!(cookies.len(), 3);
+
+        assert_eq!(cookies[0].domain, "example.com");
+        assert!(cookies[0].include_subdomains);
+        assert_eq!(cookies[0].expires, None);
+
+        assert_eq!(cookies[1].domain, "staging.example.com");
+        assert!(cookies[1].http_only);
+        assert!(cookies[1].secure);
+        assert_eq!(cookies[1].value, "x=y");
+        assert_eq!(cookies[1].expires, Some(2000000000.0));
+
+        assert_eq!(cookies[2].value, "");
+
+        assert!(parse_cookies("example.com\tFALSE\t/\n").is_err());
+        assert!(parse_cookies("example.com\tFALSE\t/\tFALSE\tsoon\ta\tb\n").is_err());
+    }
+
+    #[test]
+    fn test_cookie_matches() {
+        let cookies = parse_cookies(
+            ".example.com\tTRUE\t/\tFALSE\t0\twide\t1\n\
+             example.com\tFALSE\t/docs\tTRUE\t0\tnarrow\t2\n",
+        )
+        .unwrap();
+        let url = |s: &str| Url::parse(s).unwrap();
+
+        assert!(cookies[0].matches(&url("http://www.example.com/")));
+        assert!(cookies[0].matches(&url("http://example.com/")));
+        assert!(!cookies[0].matches(&url("http://notexample.com/")));
+
+        assert!(cookies[1].matches(&url("https://example.com/docs/a")));
+        assert!(!cookies[1].matches(&url("http://example.com/docs/a")));
+        assert!(!cookies[1].matches(&url("https://www.example.com/docs")));
+        assert!(!cookies[1].matches(&url("https://example.com/blog")));
+    }
+
+    #[test]
+    fn test_header_map_later_wins() {
+        let settings = PageSettings {
+            headers: vec!["X-A: 1".parse().unwrap(), "x-a: 2".parse().unwrap()],
+            ..Default::default()
+        };
+        assert_eq!(settings.header_map(), vec![("x-a", "2")]);
+    }
+
+    #[test]
+    fn test_rules_override_defaults() {
+        let rules = PageRules::parse(
+            r#"
+            [[rule]]
+            match = "https://staging.example.com/**"
+            basic_auth = "admin:secret"
+            headers = ["X-Env: staging"]
+            cookies = "cookies.txt"
+
+            [[rule]]
+            match = "/de/**"
+            locale = "de-DE"
+            timezone = "Europe/Berlin"
+            init_scripts = ["consent.js"]
+            "#,
+            Path::new("config"),
+        )
+        .unwrap();
+        let rules = PageRules {
+            defaults: PageSettings {
+                headers: vec!["X-Tool: fontcull".parse().unwrap()],
+                locale: Some("en-US".to_string()),
+                ..Default::default()
+            },
+            rules,
+        };
+
+        let settings = rules.settings_for("https://example.com/");
+        assert_eq!(settings, rules.defaults);
+
+        let settings = rules.settings_for("https://staging.example.com/de/about");
+        assert_eq!(
+            settings.header_map(),
+            vec![("X-Tool", "fontcull"), ("X-Env", "staging")]
+        );
+        assert_eq!(settings.basic_auth.unwrap().username, "admin");
+        assert_eq!(
+            settings.cookies.as_deref(),
+            Some(Path::new("config/cookies.txt"))
+        );
+        assert_eq!(settings.locale.as_deref(), Some("de-DE"));
+        assert_eq!(settings.timezone.as_deref(), Some("Europe/Berlin"));
+        assert_eq!(
+            settings.init_scripts,
+            vec![PathBuf::from("config/consent.js")]
+        );
+    }
+
+    #[test]
+    fn test_rules_reject_mistakes() {
+        let dir = Path::new(".");
+        assert!(PageRules::parse("[[rule]]\nlocale = \"de\"\n", dir).is_err());
+        assert!(PageRules::parse("[[rule]]\nmatch = \"/\"\nlocal = \"de\"\n", dir).is_err());
+        assert!(PageRules::parse("[[rule]]\nmatch = \"/\"\nheaders = [\"nope\"]\n", dir).is_err());
+        assert!(PageRules::parse("", dir).unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs Chrome, run with --ignored"]
+    async fn test_apply_settings() {
+        let browser = TestBrowser::launch().await;
+        let settings = PageSettings {
+            user_agent: Some("fontcull-test".to_string()),
+            locale: Some("de-DE".to_string()),
+            timezone: Some("Asia/Tokyo".to_string()),
+            init_scripts: vec![pages_dir().join("init-script.js")],
+            ..Default::default()
+        };
+
+        let page = browser.browser().new_page("about:blank").await.unwrap();
+        settings.apply(&page).await.unwrap();
+        page.goto(browser.url("forms.html")).await.unwrap();
+        page.wait_for_navigation().await.unwrap();
+
+        let result: serde_json::Value = page
+            .evaluate(
+                "({
+                    userAgent: navigator.userAgent,
+                    language: navigator.language,
+                    timeZone: Intl.DateTimeFormat().resolvedOptions().timeZone,
+                    initialized: window.__fontcullInit,
+                })",
+            )
+            .await
+            .unwrap()
+            .into_value()
+            .unwrap();
+        browser.close().await;
+
+        assert_eq!(result["userAgent"], "fontcull-test");
+        assert_eq!(result["language"], "de-DE");
+        assert_eq!(result["timeZone"], "Asia/Tokyo");
+        assert_eq!(result["initialized"], true);
+    }
+}
@@ -0,0 +1,494 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    net::SocketAddr,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use color_eyre::eyre::{Context, Result};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+use url::Url;
+
+use crate::font_faces::FontFaceRule;
+
+/// A static file server for a local build directory, bound to an ephemeral
+/// localhost port. It stops when dropped.
+pub struct StaticServer {
+    root: Arc<PathBuf>,
+    base: Url,
+    handle: JoinHandle<()>,
+}
+
+impl StaticServer {
+    /// Start serving `root` on 127.0.0.1 with an OS-assigned port
+    pub async fn start(root: &Path) -> Result<Self> {
+        let root = root
+            .canonicalize()
+            .wrap_err_with(|| format!("Failed to open directory {}", root.display()))?;
+        if !root.is_dir() {
+            color_eyre::eyre::bail!("{} is not a directory", root.display());
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .wrap_err("Failed to bind local server")?;
+        let addr: SocketAddr = listener.local_addr()?;
+        let base = Url::parse(&format!("http://{addr}/")).expect("socket address is a valid host");
+
+        let root = Arc::new(root);
+        let server_root = root.clone();
+        let handle = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let root = server_root.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_connection(stream, &root).await {
+                        tracing::debug!("Local server connection error: {e}");
+                    }
+                });
+            }
+        });
+
+        tracing::info!("Serving {} at {base}", root.display());
+        Ok(Self { root, base, handle })
+    }
+
+    /// Resolve a site-relative path (or absolute path) against the server
+    pub fn url_for(&self, path: &str) -> String {
+        self.base
+            .join(path)
+            .map(String::from)
+            .unwrap_or_else(|_| self.base.to_string())
+    }
+
+    /// URLs of every HTML page in the served directory
+    ///
+    /// `index.html` files are addressed by their directory.
+    pub fn html_pages(&self) -> Result<Vec<String>> {
+        let mut files = Vec::new();
+        collect_files(&self.root, &mut files)?;
+
+        let mut urls: Vec<String> = files
+            .iter()
+            .filter(|f| {
+                f.extension().and_then(|e| e.to_str()).is_some_and(|e| {
+                    e.eq_ignore_ascii_case("html") || e.eq_ignore_ascii_case("htm")
+                })
+            })
+            .filter_map(|f| {
+                let relative = f.strip_prefix(self.root.as_path()).ok()?;
+                let mut path = relative
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                if path == "index.html" {
+                    path.clear();
+                } else if let Some(dir) = path.strip_suffix("/index.html") {
+                    path = format!("{dir}/");
+                }
+                self.base.join(&path).ok().map(String::from)
+            })
+            .collect();
+        urls.sort();
+        Ok(urls)
+    }
+
+    /// A hash of the stylesheets, scripts and other files pages may load text
+    /// or styles from; fonts, media and the HTML pages themselves are left out
+    pub fn asset_fingerprint(&self) -> Result<String> {
+        const SKIPPED: &[&str] = &[
+            "html", "htm", "woff", "woff2", "ttf", "otf", "eot", "png", "jpg", "jpeg", "gif",
+            "webp", "avif", "ico", "mp4", "webm", "mp3", "ogg", "wav", "pdf", "zip", "tmp",
+        ];
+
+        let mut files = Vec::new();
+        collect_files(&self.root, &mut files)?;
+        files.retain(|f| {
+            f.extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| !SKIPPED.contains(&e.to_ascii_lowercase().as_str()))
+        });
+        files.sort();
+
+        let mut parts = Vec::new();
+        for file in &files {
+            let relative = file.strip_prefix(self.root.as_path()).unwrap_or(file);
+            parts.push(relative.to_string_lossy().into_owned().into_bytes());
+            parts.push(
+                std::fs::read(file)
+                    .wrap_err_with(|| format!("Failed to read {}", file.display()))?,
+            );
+        }
+        let parts: Vec<&[u8]> = parts.iter().map(Vec::as_slice).collect();
+        Ok(crate::cache::key(&parts))
+    }
+
+    /// The path and query of a URL served by this server, e.g. `/about/`
+    pub fn site_path(&self, url: &str) -> Option<String> {
+        let url = Url::parse(url).ok()?;
+        if url.origin() != self.base.origin() {
+            return None;
+        }
+        Some(url[url::Position::BeforePath..].to_string())
+    }
+
+    /// Map a URL served by this server back to the file it was served from
+    pub fn file_for_url(&self, url: &str) -> Option<PathBuf> {
+        let url = Url::parse(url).ok()?;
+        if url.origin() != self.base.origin() {
+            return None;
+        }
+        match resolve(&self.root, url.path()) {
+            Resolved::File(path) => Some(path),
+            _ => None,
+        }
+    }
+
+    /// Local font files referenced by `@font-face` rules, with the families using them
+    ///
+    /// Only the first source of each rule that maps to a file is used: the others
+    /// are the same font in fallback formats.
+    pub fn font_files<'a>(
+        &self,
+        faces: impl IntoIterator<Item = &'a FontFaceRule>,
+    ) -> BTreeMap<PathBuf, BTreeSet<String>> {
+        let mut files: BTreeMap<PathBuf, BTreeSet<String>> = BTreeMap::new();
+        for face in faces {
+            if let Some(file) = face.sources.iter().find_map(|src| self.file_for_url(src)) {
+                files.entry(file).or_default().insert(face.family.clone());
+            }
+        }
+        files
+    }
+}
+
+impl Drop for StaticServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+fn collect_files(dir: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in
+        std::fs::read_dir(dir).wrap_err_with(|| format!("Failed to read {}", dir.display()))?
+    {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_files(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// What a request path maps to on disk
+#[derive(Debug, PartialEq)]
+enum Resolved {
+    File(PathBuf),
+    /// A directory requested without its trailing slash
+    Redirect(String),
+    NotFound,
+}
+
+/// Map a URL path to a file under `root`
+///
+/// Directories serve their `index.html` and clean URLs (`/about`) fall back to
+/// `about.html`. Paths escaping `root` are never resolved.
+fn resolve(root: &Path, url_path: &str) -> Resolved {
+    let decoded = percent_decode(url_path);
+    let mut path = root.to_path_buf();
+    for component in Path::new(decoded.trim_start_matches('/')).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {}
+            _ => return Resolved::NotFound,
+        }
+    }
+
+    if path.is_dir() {
+        if !url_path.ends_with('/') {
+            return Resolved::Redirect(format!("{url_path}/"));
+        }
+        let index = path.join("index.html");
+        return if index.is_file() {
+            Resolved::File(index)
+        } else {
+            Resolved::NotFound
+        };
+    }
+    if path.is_file() {
+        return Resolved::File(path);
+    }
+
+    let with_html = path.with_extension("html");
+    if path.extension().is_none() && with_html.is_file() {
+        return Resolved::File(with_html);
+    }
+
+    Resolved::NotFound
+}
+
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit()
+        {
+            let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).expect("hex digits are ASCII");
+            out.push(u8::from_str_radix(hex, 16).expect("checked hex digits"));
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Content type for a file, based on its extension
+pub fn mime_type(path: &Path) -> &'static str {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase())
+        .unwrap_or_default();
+    match ext.as_str() {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" | "mjs" => "text/javascript; charset=utf-8",
+        "json" | "map" => "application/json",
+        "xml" => "application/xml",
+        "txt" => "text/plain; charset=utf-8",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "avif" => "image/avif",
+        "ico" => "image/x-icon",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "wasm" => "application/wasm",
+        "pdf" => "application/pdf",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        _ => "application/octet-stream",
+    }
+}
+
+async fn handle_connection(stream: TcpStream, root: &Path) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    // Skip the headers, we don't need any of them
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line).await? == 0 || line == "\r\n" || line == "\n" {
+            break;
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("");
+    let target = parts.next().unwrap_or("/");
+    let path = target.split(['?', '#']).next().unwrap_or("/");
+    let mut stream = reader.into_inner();
+
+    if method != "GET" && method != "HEAD" {
+        return write_response(
+            &mut stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            &[],
+            &[],
+            true,
+        )
+        .await;
+    }
+    let include_body = method == "GET";
+
+    match resolve(root, path) {
+        Resolved::File(file) => {
+            let body = tokio::fs::read(&file).await?;
+            write_response(
+                &mut stream,
+                "200 OK",
+                mime_type(&file),
+                &[],
+                &body,
+                include_body,
+            )
+            .await
+        }
+        Resolved::Redirect(location) => {
+            write_response(
+                &mut stream,
+                "301 Moved Permanently",
+                "text/plain",
+                &[("Location", &location)],
+                &[],
+                include_body,
+            )
+            .await
+        }
+        Resolved::NotFound => {
+            let not_found = root.join("404.html");
+            let body = tokio::fs::read(&not_found).await.unwrap_or_default();
+            write_response(
+                &mut stream,
+                "404 Not Found",
+                "text/html; charset=utf-8",
+                &[],
+                &body,
+                include_body,
+            )
+            .await
+        }
+    }
+}
+
+async fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    headers: &[(&str, &str)],
+    body: &[u8],
+    include_body: bool,
+) -> std::io::Result<()> {
+    let mut head = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n",
+        body.len()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{name}: {value}\r\n"));
+    }
+    head.push_str("\r\n");
+
+    stream.write_all(head.as_bytes()).await?;
+    if include_body {
+        stream.write_all(body).await?;
+    }
+    stream.flush().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn site() -> PathBuf {
+        crate::testing::pages_dir()
+    }
+
+    #[test]
+    fn test_resolve() {
+        let root = site();
+        assert_eq!(
+            resolve(&root, "/forms.html"),
+            Resolved::File(root.join("forms.html"))
+        );
+        // Clean URLs
+        assert_eq!(
+            resolve(&root, "/forms"),
+            Resolved::File(root.join("forms.html"))
+        );
+        assert_eq!(
+            resolve(&root, "/site/"),
+            Resolved::File(root.join("site/index.html"))
+        );
+        assert_eq!(resolve(&root, "/site"), Resolved::Redirect("/site/".into()));
+        assert_eq!(resolve(&root, "/nope.html"), Resolved::NotFound);
+        assert_eq!(resolve(&root, "/../Cargo.toml"), Resolved::NotFound);
+        assert_eq!(
+            resolve(&root, "/site/%2E%2E/forms.html"),
+            Resolved::NotFound
+        );
+    }
+
+    #[test]
+    fn test_percent_decode() {
+        assert_eq!(percent_decode("/a%20b/%C3%A9"), "/a b/é");
+        assert_eq!(percent_decode("/100%"), "/100%");
+        assert_eq!(percent_decode("/%zz"), "/%zz");
+    }
+
+    #[test]
+    fn test_mime_type() {
+        assert_eq!(mime_type(Path::new("a/b.WOFF2")), "font/woff2");
+        assert_eq!(
+            mime_type(Path::new("index.html")),
+            "text/html; charset=utf-8"
+        );
+        assert_eq!(mime_type(Path::new("noext")), "application/octet-stream");
+    }
+
+    #[tokio::test]
+    async fn test_serve_and_map_back() {
+        let server = StaticServer::start(&site()).await.unwrap();
+        let client = reqwest::Client::new();
+
+        let response = client.get(server.url_for("/site")).send().await.unwrap();
+        // Redirected to /site/ and served its index
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.url().path(), "/site/");
+        assert!(
+            response
+                .text()
+                .await
+                .unwrap()
+                .contains("<title>Home</title>")
+        );
+
+        let response = client.get(server.url_for("/missing")).send().await.unwrap();
+        assert_eq!(response.status(), 404);
+
+        assert_eq!(
+            server.file_for_url(&server.url_for("/site/a.html?v=2")),
+            Some(site().canonicalize().unwrap().join("site/a.html"))
+        );
+        assert_eq!(server.file_for_url("https://example.com/site/a.html"), None);
+        assert_eq!(
+            server
+                .site_path(&server.url_for("/site/a.html?v=2"))
+                .as_deref(),
+            Some("/site/a.html?v=2")
+        );
+        assert_eq!(server.site_path("https://example.com/site/a.html"), None);
+
+        let face = |family: &str, sources: &[&str]| FontFaceRule {
+            family: family.to_string(),
+            sources: sources.iter().map(|s| server.url_for(s)).collect(),
+            weight: None,
+            style: None,
+            stretch: None,
+            unicode_range: None,
+            display: None,
+        };
+        let faces = [
+            face("A", &["/missing.woff2", "/site/a.html"]),
+            face("B", &["/site/a.html", "/forms.html"]),
+            face("C", &["/nope.woff2"]),
+        ];
+        let files = server.font_files(&faces);
+        assert_eq!(files.len(), 1);
+        assert_eq!(
+            files[&site().canonicalize().unwrap().join("site/a.html")],
+            BTreeSet::from(["A".to_string(), "B".to_string()])
+        );
+
+        let pages = server.html_pages().unwrap();
+        assert!(pages.contains(&server.url_for("/site/")));
+        assert!(pages.contains(&server.url_for("/forms.html")));
+    }
+}
@@ -0,0 +1,376 @@
+//! `fontcull subset`: subset local font files directly, with hb-subset's
+//! command line options
+
+use std::path::{Path, PathBuf};
+
+use color_eyre::eyre::{Context, Result, bail};
+use fontcull::SubsetOptions;
+
+use crate::klippa_backend::{self, OutputFormat, SubsetFlag};
+
+/// Subset font files to the given characters or glyphs, without a browser
+#[derive(clap::Args, Debug)]
+pub struct SubsetArgs {
+    /// Font files to subset (TTF, OTF, WOFF or WOFF2)
+    #[arg(required = true, value_name = "FONT")]
+    pub fonts: Vec<PathBuf>,
+
+    /// Output file, or a directory when subsetting several fonts [default:
+    /// `<stem>-subset.<ext>` next to each font]
+    #[arg(long, short = 'o', value_name = "PATH")]
+    pub output_file: Option<PathBuf>,
+
+    /// Output format [default: from the extension of --output-file, else ttf]
+    #[arg(long, value_enum)]
+    pub format: Option<OutputFormat>,
+
+    /// Code points to keep, e.g. `U+41-5A,61`, or `*` for all
+    #[arg(long, short = 'u')]
+    pub unicodes: Option<String>,
+
+    /// Characters to keep
+    #[arg(long, short = 't')]
+    pub text: Option<String>,
+
+    /// File with the characters to keep
+    #[arg(long, value_name = "FILE")]
+    pub text_file: Option<PathBuf>,
+
+    /// Glyph IDs to keep, e.g. `1,5-9`, or `*` for all
+    #[arg(long, default_value = "")]
+    pub gids: String,
+
+    /// Glyph names to keep, separated by commas or spaces
+    #[arg(long, default_value = "")]
+    pub glyphs: String,
+
+    /// Tables to drop, e.g. `DSIG,kern`
+    #[arg(long, default_value = "")]
+    pub drop_tables: String,
+
+    /// Layout scripts to keep, or `*` for all
+    #[arg(long, default_value = "*")]
+    pub layout_scripts: String,
+
+    /// Layout features to keep, or `*` for all [default: hb-subset's list]
+    #[arg(long)]
+    pub layout_features: Option<String>,
+
+    /// Name IDs to keep, or `*` for all
+    #[arg(long = "name-IDs", default_value = "0,1,2,3,4,5,6")]
+    pub name_ids: String,
+
+    /// Name language IDs to keep, or `*` for all
+    #[arg(long, default_value = "1033")]
+    pub name_languages: String,
+
+    /// Drop hinting instructions
+    #[arg(long)]
+    pub no_hinting: bool,
+
+    /// Keep glyph IDs, leaving empty glyphs in place of the dropped ones
+    #[arg(long)]
+    pub retain_gids: bool,
+
+    /// Remove CFF subroutines
+    #[arg(long)]
+    pub desubroutinize: bool,
+
+    /// Keep legacy (non-Unicode) name table entries
+    #[arg(long)]
+    pub name_legacy: bool,
+
+    /// Set the overlap flag on the first contour of each glyph
+    #[arg(long)]
+    pub set_overlaps_flag: bool,
+
+    /// Copy tables klippa doesn't know how to subset instead of dropping them
+    #[arg(long)]
+    pub passthrough_tables: bool,
+
+    /// Keep the outline of the .notdef glyph
+    #[arg(long)]
+    pub notdef_outline: bool,
+
+    /// Keep glyph names in the post table
+    #[arg(long)]
+    pub glyph_names: bool,
+
+    /// Don't change the OS/2 Unicode ranges to match the subset
+    #[arg(long)]
+    pub no_prune_unicode_ranges: bool,
+
+    /// Don't add glyphs reachable through GSUB substitutions
+    #[arg(long)]
+    pub no_layout_closure: bool,
+
+    /// Only add the glyphs GSUB substitutions can produce for the --text and
+    /// --text-file lines, e.g. a ligature only if its letters follow each other
+    #[arg(long)]
+    pub text_closure: bool,
+
+    /// Optimize IUP deltas in the gvar table
+    #[arg(long)]
+    pub optimize: bool,
+
+    /// Move the pairs of the legacy `kern` table into a GPOS `kern` feature,
+    /// for browsers that ignore `kern`
+    #[arg(long)]
+    pub kern_to_gpos: bool,
+
+    /// Gzip every SVG glyph document, not only the ones that were compressed
+    #[arg(long)]
+    pub gzip_svg: bool,
+}
+
+impl SubsetArgs {
+    fn flags(&self) -> Vec<SubsetFlag> {
+        [
+            (self.no_hinting, SubsetFlag::NoHinting),
+            (self.retain_gids, SubsetFlag::RetainGids),
+            (self.desubroutinize, SubsetFlag::Desubroutinize),
+            (self.name_legacy, SubsetFlag::NameLegacy),
+            (self.set_overlaps_flag, SubsetFlag::SetOverlapsFlag),
+            (self.passthrough_tables, SubsetFlag::PassthroughTables),
+            (self.notdef_outline, SubsetFlag::NotdefOutline),
+            (self.glyph_names, SubsetFlag::GlyphNames),
+            (
+                self.no_prune_unicode_ranges,
+                SubsetFlag::NoPruneUnicodeRanges,
+            ),
+            (self.no_layout_closure, SubsetFlag::NoLayoutClosure),
+            (self.optimize, SubsetFlag::OptimizeIupDeltas),
+            (self.kern_to_gpos, SubsetFlag::KernToGpos),
+            (self.gzip_svg, SubsetFlag::GzipSvg),
+        ]
+        .into_iter()
+        .filter_map(|(set, flag)| set.then_some(flag))
+        .collect()
+    }
+
+    /// `text_file` is the content of `--text-file`, see [`Self::read_text_file`]
+    fn options(&self, text_file: Option<&str>) -> Result<SubsetOptions> {
+        let defaults = SubsetOptions::hb_subset_defaults();
+        Ok(SubsetOptions {
+            flags: SubsetFlag::combine(&self.flags()),
+            gids: self.gids.clone(),
+            glyphs: self.glyphs.clone(),
+            drop_tables: self.drop_tables.clone(),
+            layout_scripts: self.layout_scripts.clone(),
+            layout_features: self
+                .layout_features
+                .clone()
+                .unwrap_or(defaults.layout_features),
+            name_ids: self.name_ids.clone(),
+            name_languages: self.name_languages.clone(),
+            texts: if self.text_closure {
+                self.texts(text_file)
+            } else {
+                Vec::new()
+            },
+        })
+    }
+
+    /// The content of `--text-file`, read once for both the code points and the texts
+    fn read_text_file(&self) -> Result<Option<String>> {
+        self.text_file
+            .as_ref()
+            .map(|path| {
+                std::fs::read_to_string(path)
+                    .wrap_err_with(|| format!("Failed to read text file: {}", path.display()))
+            })
+            .transpose()
+    }
+
+    /// The `--text` and each line of `--text-file`
+    fn texts(&self, text_file: Option<&str>) -> Vec<String> {
+        let mut texts: Vec<String> = self.text.iter().cloned().collect();
+        if let Some(text) = text_file {
+            texts.extend(text.lines().map(str::to_string));
+        }
+        texts
+    }
+
+    /// Code points from `--unicodes`, `--text` and `--text-file` together
+    fn unicodes(&self, text_file: Option<&str>) -> Result<Vec<u32>> {
+        let mut unicodes = match &self.unicodes {
+            Some(list) => {
+                fontcull::parse_unicodes(list).map_err(|e| color_eyre::eyre::eyre!("{}", e))?
+            }
+            None => Vec::new(),
+        };
+        if let Some(text) = &self.text {
+            unicodes.extend(text.chars().map(|c| c as u32));
+        }
+        if let Some(text) = text_file {
+            unicodes.extend(text.chars().map(|c| c as u32));
+        }
+        unicodes.sort_unstable();
+        unicodes.dedup();
+        Ok(unicodes)
+    }
+
+    fn format(&self) -> OutputFormat {
+        self.format
+            .or_else(|| {
+                let path = self
+                    .output_file
+                    .as_ref()
+                    .filter(|_| self.fonts.len() == 1)?;
+                let extension = path.extension()?.to_str()?.to_lowercase();
+                clap::ValueEnum::from_str(&extension, true).ok()
+            })
+            .unwrap_or(OutputFormat::Ttf)
+    }
+
+    /// Where the subset of `font` is written
+    fn output_path(&self, font: &Path, format: OutputFormat) -> PathBuf {
+        let file_name = format!(
+            "{}-subset.{}",
+            font.file_stem().unwrap_or_default().to_string_lossy(),
+            format.extension()
+        );
+        match &self.output_file {
+            Some(path) if self.fonts.len() == 1 => path.clone(),
+            Some(dir) => dir.join(file_name),
+            None => font.with_file_name(file_name),
+        }
+    }
+}
+
+/// Subset each font and write it out
+pub fn run(args: &SubsetArgs) -> Result<()> {
+    let text_file = args.read_text_file()?;
+    let unicodes = args.unicodes(text_file.as_deref())?;
+    if unicodes.is_empty() && args.gids.is_empty() && args.glyphs.is_empty() {
+        bail!("Nothing to keep: give --unicodes, --text, --text-file, --gids or --glyphs");
+    }
+    let options = args.options(text_file.as_deref())?;
+    let format = args.format();
+
+    if args.fonts.len() > 1
+        && let Some(dir) = &args.output_file
+    {
+        std::fs::create_dir_all(dir)
+            .wrap_err_with(|| format!("Failed to create directory: {}", dir.display()))?;
+    }
+    for font in &args.fonts {
+        let [data] = klippa_backend::subset_to_formats(font, &unicodes, &options, &[format], None)?
+            .try_into()
+            .expect("one output per format");
+        let output = args.output_path(font, format);
+        std::fs::write(&output, &data)
+            .wrap_err_with(|| format!("Failed to write: {}", output.display()))?;
+        tracing::info!("Created: {} ({} bytes)", output.display(), data.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::*;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        subset: SubsetArgs,
+    }
+
+    fn parse(args: &[&str]) -> SubsetArgs {
+        Cli::parse_from(std::iter::once("fontcull").chain(args.iter().copied())).subset
+    }
+
+    #[test]
+    fn test_subset_args() {
+        let args = parse(&[
+            "a.ttf",
+            "--unicodes=U+61,62",
+            "--text=cb",
+            "--no-hinting",
+            "--retain-gids",
+            "--name-IDs=0,1,2",
+            "--layout-scripts=grek,latn",
+            "-o",
+            "out.woff",
+        ]);
+        assert_eq!(args.unicodes(None).unwrap(), vec![0x61, 0x62, 0x63]);
+        assert_eq!(
+            args.flags(),
+            vec![SubsetFlag::NoHinting, SubsetFlag::RetainGids]
+        );
+        let options = args.options(None).unwrap();
+        assert_eq!(options.name_ids, "0,1,2");
+        assert_eq!(options.layout_scripts, "grek,latn");
+        assert!(options.layout_features.contains("kern"));
+        assert!(options.texts.is_empty());
+        let options = parse(&["a.ttf", "--text=cb", "--text-closure"])
+            .options(None)
+            .unwrap();
+        assert_eq!(options.texts, vec!["cb".to_string()]);
+        let closure = parse(&["a.ttf", "--text=cb", "--text-file=t.txt", "--text-closure"]);
+        assert_eq!(
+            closure.unicodes(Some("ab\nd")).unwrap(),
+            vec![0x0a, 0x61, 0x62, 0x63, 0x64]
+        );
+        assert_eq!(
+            closure.options(Some("ab\nd")).unwrap().texts,
+            vec!["cb", "ab", "d"]
+        );
+        assert_eq!(args.format(), OutputFormat::Woff);
+        assert_eq!(
+            args.output_path(Path::new("a.ttf"), OutputFormat::Woff),
+            PathBuf::from("out.woff")
+        );
+
+        let args = parse(&[
+            "fonts/a.woff2",
+            "fonts/b.otf",
+            "--format=woff2",
+            "-o",
+            "out",
+        ]);
+        assert_eq!(args.format(), OutputFormat::Woff2);
+        assert_eq!(
+            args.output_path(&args.fonts[1], OutputFormat::Woff2),
+            PathBuf::from("out/b-subset.woff2")
+        );
+        assert_eq!(parse(&["a.otf"]).format(), OutputFormat::Ttf);
+        assert_eq!(
+            parse(&["fonts/a.otf"]).output_path(Path::new("fonts/a.otf"), OutputFormat::Ttf),
+            PathBuf::from("fonts/a-subset.ttf")
+        );
+    }
+
+    #[test]
+    fn test_subset_writes_fonts() {
+        let dir = std::env::temp_dir().join(format!("fontcull-subset-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("test_data/fonts/Roboto-Regular.abc.ttf")
+            .display()
+            .to_string();
+        let output = dir.join("roboto.woff").display().to_string();
+
+        run(&parse(&[
+            &fixture,
+            "--text=ab",
+            "--drop-tables=GPOS",
+            "-o",
+            &output,
+        ]))
+        .unwrap();
+        let woff = std::fs::read(&output).unwrap();
+        assert_eq!(
+            fontcull::FontFormat::detect(&woff),
+            fontcull::FontFormat::Woff
+        );
+        let sfnt = fontcull::decompress_font(&woff).unwrap();
+        let info = fontcull::font_info(&sfnt).unwrap();
+        assert_eq!(info.codepoints, vec!['a' as u32, 'b' as u32]);
+
+        assert!(run(&parse(&[&fixture, "-o", &output])).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,72 @@
+//! Helpers for tests that drive a real browser against fixture pages
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use chromiumoxide::{Browser, BrowserConfig, Page};
+use futures::StreamExt;
+use tokio::task::JoinHandle;
+
+use crate::serve::StaticServer;
+
+pub fn pages_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/pages")
+}
+
+/// A headless Chrome instance plus a fixture server
+pub struct TestBrowser {
+    browser: Browser,
+    handle: JoinHandle<()>,
+    server: StaticServer,
+}
+
+impl TestBrowser {
+    /// Launch Chrome, panicking when no executable can be found. Tests that
+    /// call this are `#[ignore]`d so they only run when asked for with
+    /// `--ignored`, on machines that have a browser.
+    pub async fn launch() -> Self {
+        let config = BrowserConfig::builder()
+            .build()
+            .unwrap_or_else(|e| panic!("browser tests need Chrome or Chromium: {e}"));
+
+        let (browser, mut handler) = Browser::launch(config).await.unwrap();
+        let handle = tokio::spawn(async move { while handler.next().await.is_some() {} });
+        let server = StaticServer::start(&pages_dir()).await.unwrap();
+
+        Self {
+            browser,
+            handle,
+            server,
+        }
+    }
+
+    pub fn browser(&self) -> &Browser {
+        &self.browser
+    }
+
+    /// URL of a fixture page on the test server
+    pub fn url(&self, page_name: &str) -> String {
+        self.server.url_for(page_name)
+    }
+
+    /// Open a fixture page and wait for it to load
+    pub async fn open(&self, page_name: &str) -> Page {
+        let page = self.browser.new_page(self.url(page_name)).await.unwrap();
+        page.wait_for_navigation().await.unwrap();
+        page
+    }
+
+    pub async fn close(mut self) {
+        self.browser.close().await.ok();
+        self.handle.abort();
+    }
+}
+
+/// Whether every character of `text` was captured for `family`
+pub fn has(glyphs: &HashMap<String, Vec<u32>>, family: &str, text: &str) -> bool {
+    glyphs
+        .get(family)
+        .is_some_and(|codes| text.chars().all(|c| codes.contains(&(c as u32))))
+}
@@ -0,0 +1,574 @@
+//! `--verify`: load the pages again with the subset fonts served in place of the
+//! originals, and check that no text falls back to another font
+//!
+//! A subset can have every character of a page and still miss glyphs that
+//! shaping needs (ligatures, contextual forms, marks). Chrome reports which
+//! platform fonts rendered the text of each element, so every element is
+//! measured once with the original fonts and once with the subsets: a font that
+//! renders more glyphs with the subsets is one the text fell back to.
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use chromiumoxide::{
+    Browser, Page,
+    cdp::browser_protocol::{
+        css::GetPlatformFontsForNodeParams,
+        fetch::{
+            ContinueRequestParams, EnableParams, EventRequestPaused, FulfillRequestParams,
+            HeaderEntry, RequestPattern, RequestStage,
+        },
+        network::{ResourceType, SetCacheDisabledParams},
+    },
+};
+use color_eyre::eyre::{Context, Result, bail, eyre};
+use futures::StreamExt;
+use serde::Deserialize;
+use url::Url;
+
+use crate::{
+    ScanResult, capture,
+    capture::CaptureOptions,
+    font_faces::FontFaceRule,
+    output::SubsetFont,
+    page_settings::{BrowserOptions, PageRules, PageSettings},
+    serve::StaticServer,
+};
+
+/// JavaScript code that waits for fonts to load, then marks every element with
+/// text of its own and lists them with a selector-like description
+const MARK_SCRIPT: &str = r#"
+(async () => {
+    await document.fonts.ready;
+
+    function describe(el) {
+        const parts = [];
+        for (; el && el.nodeType === Node.ELEMENT_NODE; el = el.parentElement) {
+            let part = el.localName;
+            if (el.id) {
+                parts.unshift(part + '#' + el.id);
+                break;
+            }
+            const parent = el.parentElement;
+            if (parent) {
+                const same = Array.from(parent.children).filter(c => c.localName === el.localName);
+                if (same.length > 1) part += ':nth-of-type(' + (same.indexOf(el) + 1) + ')';
+            }
+            parts.unshift(part);
+            if (el.localName === 'body') break;
+        }
+        return parts.join(' > ');
+    }
+
+    const elements = [];
+    const root = document.body || document.documentElement;
+    const walker = document.createTreeWalker(root, NodeFilter.SHOW_ELEMENT);
+    for (let el = walker.currentNode; el; el = walker.nextNode()) {
+        if (['SCRIPT', 'STYLE', 'TEMPLATE', 'NOSCRIPT'].includes(el.tagName)) continue;
+        let text = '';
+        for (const child of el.childNodes) {
+            if (child.nodeType === Node.TEXT_NODE) text += child.data;
+        }
+        if (!text.trim()) continue;
+        el.setAttribute('data-fontcull-verify', String(elements.length));
+        elements.push({ element: describe(el), text });
+    }
+    return elements;
+})()
+"#;
+
+/// A subset font served in place of an original font URL
+#[derive(Debug)]
+pub struct Replacement {
+    pub data: Vec<u8>,
+    pub mime_type: &'static str,
+}
+
+/// An element with text of its own
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+struct TextElement {
+    element: String,
+    text: String,
+}
+
+/// Glyphs rendered per platform font family
+type FontUsage = BTreeMap<String, f64>;
+
+/// Text that renders in a different font with the subsets than with the originals
+#[derive(Debug, PartialEq)]
+pub struct Fallback {
+    pub page: String,
+    pub element: String,
+    /// Fonts that render more of the element's text with the subsets
+    pub fonts: Vec<String>,
+    /// The element's characters the subsets don't map, or all of its characters
+    /// if they map every one (then shaping needed glyphs that were dropped)
+    pub chars: Vec<char>,
+    pub all_mapped: bool,
+}
+
+/// Verify the scanned pages against the subset fonts, failing if any text falls
+/// back to another font
+pub async fn run(
+    scan: &ScanResult,
+    servers: &[StaticServer],
+    fonts: &[SubsetFont],
+    capture_options: &CaptureOptions,
+    page_rules: &PageRules,
+    browser_options: &BrowserOptions,
+) -> Result<()> {
+    let replacements = replacements(&scan.font_faces, servers, fonts)?;
+    if replacements.is_empty() {
+        tracing::warn!("The pages load none of the subset fonts, nothing to verify");
+        return Ok(());
+    }
+    let covered: BTreeSet<u32> = fonts
+        .iter()
+        .flat_map(|f| f.codepoints.iter().copied())
+        .collect();
+
+    let (mut browser, handle) = crate::launch_browser(browser_options).await?;
+    let mut fallbacks = Vec::new();
+    let mut result = Ok(());
+    for ((name, _), url) in scan.pages.iter().zip(&scan.urls) {
+        let settings = page_rules.settings_for(url);
+        if settings.basic_auth.is_some() {
+            // chromiumoxide answers intercepted requests itself to handle auth
+            tracing::warn!("Can't verify {name}: not supported with basic auth");
+            continue;
+        }
+        tracing::info!("Verifying {name}");
+        match verify_page(&browser, url, &settings, capture_options, &replacements).await {
+            Ok((original, subset)) => {
+                fallbacks.extend(compare(name, &original, &subset, &covered));
+            }
+            Err(e) => {
+                result = Err(e.wrap_err(format!("Failed to verify {name}")));
+                break;
+            }
+        }
+    }
+    browser.close().await.ok();
+    handle.abort();
+    result?;
+
+    if fallbacks.is_empty() {
+        println!(
+            "Verified {} pages: all text renders in the subset fonts",
+            scan.urls.len()
+        );
+        return Ok(());
+    }
+    print!("{}", report(&fallbacks));
+    bail!(
+        "{} element(s) fall back to other fonts with the subset fonts",
+        fallbacks.len()
+    );
+}
+
+/// Map the font URLs the pages load to the subset fonts made from them
+///
+/// A URL served from a local directory maps to the subset of that file; any
+/// other URL to the subset of a source font with the same file stem. The output
+/// in the URL's format is served if there is one.
+pub fn replacements(
+    font_faces: &[FontFaceRule],
+    servers: &[StaticServer],
+    fonts: &[SubsetFont],
+) -> Result<HashMap<String, Replacement>> {
+    let sources: Vec<Option<PathBuf>> =
+        fonts.iter().map(|f| f.source.canonicalize().ok()).collect();
+
+    let mut replacements = HashMap::new();
+    for url in font_faces.iter().flat_map(|face| &face.sources) {
+        if replacements.contains_key(url) {
+            continue;
+        }
+        let font = match servers.iter().find_map(|s| s.file_for_url(url)) {
+            Some(file) => {
+                let file = file.canonicalize().ok();
+                fonts
+                    .iter()
+                    .zip(&sources)
+                    .find(|(_, source)| file.is_some() && **source == file)
+                    .map(|(font, _)| font)
+            }
+            None => {
+                let name = Url::parse(url)
+                    .ok()
+                    .and_then(|u| u.path_segments()?.next_back().map(str::to_string))
+                    .unwrap_or_default();
+                let stem = Path::new(&name).file_stem();
+                fonts
+                    .iter()
+                    .find(|font| stem.is_some() && font.source.file_stem() == stem)
+            }
+        };
+        let Some(font) = font else {
+            continue;
+        };
+
+        let extension = Url::parse(url)
+            .ok()
+            .and_then(|u| Some(Path::new(u.path()).extension()?.to_str()?.to_lowercase()));
+        let Some(output) = font
+            .outputs
+            .iter()
+            .find(|o| Some(o.format.extension()) == extension.as_deref())
+            .or(font.outputs.first())
+        else {
+            continue;
+        };
+        let data = std::fs::read(&output.file)
+            .wrap_err_with(|| format!("Failed to read {}", output.file.display()))?;
+        replacements.insert(
+            url.clone(),
+            Replacement {
+                data,
+                mime_type: output.format.mime_type(),
+            },
+        );
+    }
+    Ok(replacements)
+}
+
+/// Measure a page with its original fonts, then with the subsets
+async fn verify_page(
+    browser: &Browser,
+    url: &str,
+    settings: &PageSettings,
+    capture_options: &CaptureOptions,
+    replacements: &HashMap<String, Replacement>,
+) -> Result<(Vec<(TextElement, FontUsage)>, Vec<(TextElement, FontUsage)>)> {
+    let original = measure(browser, url, settings, capture_options, None).await?;
+    let subset = measure(browser, url, settings, capture_options, Some(replacements)).await?;
+    Ok((original, subset))
+}
+
+/// Load a page in a new tab, serving `replacements` for font requests if given,
+/// and report which fonts render each element's text
+async fn measure(
+    browser: &Browser,
+    url: &str,
+    settings: &PageSettings,
+    capture_options: &CaptureOptions,
+    replacements: Option<&HashMap<String, Replacement>>,
+) -> Result<Vec<(TextElement, FontUsage)>> {
+    let page = browser
+        .new_page("about:blank")
+        .await
+        .wrap_err("Failed to open tab")?;
+    let result = measure_in(&page, url, settings, capture_options, replacements).await;
+    page.close().await.ok();
+    result
+}
+
+async fn measure_in(
+    page: &Page,
+    url: &str,
+    settings: &PageSettings,
+    capture_options: &CaptureOptions,
+    replacements: Option<&HashMap<String, Replacement>>,
+) -> Result<Vec<(TextElement, FontUsage)>> {
+    settings.apply(page).await?;
+    let Some(replacements) = replacements else {
+        return load_and_measure(page, url, capture_options).await;
+    };
+
+    // Cached originals would never reach the interception
+    page.execute(SetCacheDisabledParams::new(true))
+        .await
+        .wrap_err("Failed to disable the browser cache")?;
+    let mut paused = page
+        .event_listener::<EventRequestPaused>()
+        .await
+        .wrap_err("Failed to listen to intercepted requests")?;
+    page.execute(
+        EnableParams::builder()
+            .pattern(
+                RequestPattern::builder()
+                    .resource_type(ResourceType::Font)
+                    .request_stage(RequestStage::Request)
+                    .build(),
+            )
+            .build(),
+    )
+    .await
+    .wrap_err("Failed to intercept font requests")?;
+
+    let serve = async {
+        while let Some(event) = paused.next().await {
+            if let Err(e) = serve_font(page, &event, replacements).await {
+                tracing::warn!("Failed to answer {}: {e}", event.request.url);
+            }
+        }
+    };
+    tokio::select! {
+        result = load_and_measure(page, url, capture_options) => result,
+        () = serve => bail!("Stopped receiving intercepted requests"),
+    }
+}
+
+/// Answer an intercepted font request with its subset, or let it through
+async fn serve_font(
+    page: &Page,
+    event: &EventRequestPaused,
+    replacements: &HashMap<String, Replacement>,
+) -> Result<()> {
+    let Some(replacement) = replacements.get(&event.request.url) else {
+        page.execute(ContinueRequestParams::new(event.request_id.clone()))
+            .await?;
+        return Ok(());
+    };
+    let params = FulfillRequestParams::builder()
+        .request_id(event.request_id.clone())
+        .response_code(200)
+        .response_header(HeaderEntry::new("Content-Type", replacement.mime_type))
+        // Fonts from another origin need CORS
+        .response_header(HeaderEntry::new("Access-Control-Allow-Origin", "*"))
+        .body(BASE64.encode(&replacement.data))
+        .build()
+        .map_err(|e| eyre!("{e}"))?;
+    page.execute(params).await?;
+    Ok(())
+}
+
+async fn load_and_measure(
+    page: &Page,
+    url: &str,
+    capture_options: &CaptureOptions,
+) -> Result<Vec<(TextElement, FontUsage)>> {
+    page.goto(url)
+        .await
+        .wrap_err_with(|| format!("Failed to navigate to {url}"))?;
+    page.wait_for_navigation().await.ok();
+    capture::wait_until_ready(page, capture_options).await?;
+
+    let result = page
+        .evaluate(MARK_SCRIPT)
+        .await
+        .wrap_err("Failed to execute verify script")?
+        .into_value()
+        .wrap_err("Failed to get verify script result")?;
+    let elements: Vec<TextElement> =
+        serde_json::from_value(result).wrap_err("Unexpected verify script result")?;
+
+    page.enable_css()
+        .await
+        .wrap_err("Failed to enable CSS domain")?;
+    let nodes = page
+        .find_elements("[data-fontcull-verify]")
+        .await
+        .wrap_err("Failed to find the marked elements")?;
+    if nodes.len() != elements.len() {
+        bail!("The page changed while it was being measured");
+    }
+
+    let mut measured = Vec::new();
+    for (element, node) in elements.into_iter().zip(nodes) {
+        let fonts = page
+            .execute(GetPlatformFontsForNodeParams::new(node.node_id))
+            .await
+            .wrap_err_with(|| format!("Failed to get the fonts of {}", element.element))?;
+        let mut usage = FontUsage::new();
+        for font in &fonts.result.fonts {
+            *usage.entry(font.family_name.clone()).or_default() += font.glyph_count;
+        }
+        measured.push((element, usage));
+    }
+    Ok(measured)
+}
+
+/// Find the elements whose text renders in fonts it didn't render in before
+fn compare(
+    page: &str,
+    original: &[(TextElement, FontUsage)],
+    subset: &[(TextElement, FontUsage)],
+    covered: &BTreeSet<u32>,
+) -> Vec<Fallback> {
+    let mut before: HashMap<&TextElement, &FontUsage> = HashMap::new();
+    for (element, usage) in original {
+        before.entry(element).or_insert(usage);
+    }
+
+    let mut fallbacks = Vec::new();
+    for (element, usage) in subset {
+        // Content that differs between loads can't be compared
+        let Some(before) = before.get(element) else {
+            continue;
+        };
+        let fonts: Vec<String> = usage
+            .iter()
+            .filter(|&(family, &glyphs)| glyphs > before.get(family).copied().unwrap_or(0.0))
+            .map(|(family, _)| family.clone())
+            .collect();
+        if fonts.is_empty() {
+            continue;
+        }
+
+        let chars: BTreeSet<char> = element
+            .text
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .collect();
+        let unmapped: Vec<char> = chars
+            .iter()
+            .copied()
+            .filter(|&c| !covered.contains(&(c as u32)))
+            .collect();
+        let all_mapped = unmapped.is_empty();
+        fallbacks.push(Fallback {
+            page: page.to_string(),
+            element: element.element.clone(),
+            fonts,
+            chars: if all_mapped {
+                chars.into_iter().collect()
+            } else {
+                unmapped
+            },
+            all_mapped,
+        });
+    }
+    fallbacks
+}
+
+/// One line per element that falls back
+fn report(fallbacks: &[Fallback]) -> String {
+    let mut out = String::new();
+    for fallback in fallbacks {
+        let chars: Vec<String> = fallback
+            .chars
+            .iter()
+            .map(|&c| format!("U+{:04X} {c}", c as u32))
+            .collect();
+        write!(
+            out,
+            "{}: {} falls back to {} for {}",
+            fallback.page,
+            fallback.element,
+            fallback.fonts.join(", "),
+            chars.join(", ")
+        )
+        .unwrap();
+        if fallback.all_mapped {
+            out.push_str(" (all mapped, so glyphs needed by shaping are missing)");
+        }
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn element(element: &str, text: &str) -> TextElement {
+        TextElement {
+            element: element.to_string(),
+            text: text.to_string(),
+        }
+    }
+
+    fn usage(fonts: &[(&str, f64)]) -> FontUsage {
+        fonts.iter().map(|&(f, n)| (f.to_string(), n)).collect()
+    }
+
+    #[test]
+    fn test_compare() {
+        let original = [
+            (element("h1", "Café"), usage(&[("Inter", 4.0)])),
+            (element("p", "office"), usage(&[("Inter", 5.0)])),
+            (element("span", "😀"), usage(&[("Noto Color Emoji", 1.0)])),
+        ];
+        let subset = [
+            (
+                element("h1", "Café"),
+                usage(&[("Inter", 3.0), ("Arial", 1.0)]),
+            ),
+            (
+                element("p", "office"),
+                usage(&[("Inter", 4.0), ("Arial", 2.0)]),
+            ),
+            // Already rendered in a fallback font with the originals
+            (element("span", "😀"), usage(&[("Noto Color Emoji", 1.0)])),
+            (element("p.new", "x"), usage(&[("Arial", 1.0)])),
+        ];
+        let covered: BTreeSet<u32> = "Cafoice".chars().map(|c| c as u32).collect();
+
+        let fallbacks = compare("/", &original, &subset, &covered);
+        assert_eq!(
+            fallbacks,
+            vec![
+                Fallback {
+                    page: "/".to_string(),
+                    element: "h1".to_string(),
+                    fonts: vec!["Arial".to_string()],
+                    chars: vec!['é'],
+                    all_mapped: false,
+                },
+                Fallback {
+                    page: "/".to_string(),
+                    element: "p".to_string(),
+                    fonts: vec!["Arial".to_string()],
+                    chars: vec!['c', 'e', 'f', 'i', 'o'],
+                    all_mapped: true,
+                },
+            ]
+        );
+
+        let report = report(&fallbacks);
+        assert!(report.starts_with("/: h1 falls back to Arial for U+00E9 é\n"));
+        assert!(report.contains("(all mapped, so glyphs needed by shaping are missing)"));
+    }
+
+    #[tokio::test]
+    async fn test_replacements() {
+        let dir = std::env::temp_dir().join(format!("fontcull-verify-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("fonts")).unwrap();
+        let source = dir.join("fonts/Roboto.ttf");
+        std::fs::copy(
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/fonts/Roboto-Regular.abc.ttf"),
+            &source,
+        )
+        .unwrap();
+        let output = dir.join("Roboto-subset.woff2");
+        std::fs::write(&output, b"subset").unwrap();
+        let font = SubsetFont::describe(
+            &source,
+            &[(output, crate::klippa_backend::OutputFormat::Woff2)],
+            vec!["Roboto".to_string()],
+            &['a' as u32],
+        )
+        .unwrap();
+
+        let server = StaticServer::start(&dir).await.unwrap();
+        let face = |url: String| FontFaceRule {
+            family: "Roboto".to_string(),
+            sources: vec![url],
+            weight: None,
+            style: None,
+            stretch: None,
+            unicode_range: None,
+            display: None,
+        };
+        let local = server.url_for("/fonts/Roboto.ttf");
+        let faces = [
+            face(local.clone()),
+            face("https://cdn.example.com/Roboto.woff2?v=2".to_string()),
+            face("https://cdn.example.com/Other.woff2".to_string()),
+            face(server.url_for("/fonts/Missing.ttf")),
+        ];
+
+        let replacements = replacements(&faces, std::slice::from_ref(&server), &[font]).unwrap();
+        assert_eq!(replacements.len(), 2);
+        assert_eq!(replacements[&local].data, b"subset");
+        assert_eq!(replacements[&local].mime_type, "font/woff2");
+        assert!(replacements.contains_key("https://cdn.example.com/Roboto.woff2?v=2"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
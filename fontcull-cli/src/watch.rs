@@ -0,0 +1,126 @@
+//! `--watch`: run again whenever a watched file changes
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, SystemTime},
+};
+
+use color_eyre::eyre::Result;
+
+/// How often watched files are checked for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Size and modification time of every file under some paths
+#[derive(Debug, PartialEq, Eq)]
+struct Snapshot(BTreeMap<PathBuf, (u64, Option<SystemTime>)>);
+
+impl Snapshot {
+    fn take(paths: &[PathBuf]) -> Self {
+        let mut files = BTreeMap::new();
+        for path in paths {
+            add_files(path, &mut files);
+        }
+        Self(files)
+    }
+}
+
+fn add_files(path: &Path, files: &mut BTreeMap<PathBuf, (u64, Option<SystemTime>)>) {
+    let Ok(metadata) = std::fs::metadata(path) else {
+        return;
+    };
+    if metadata.is_dir() {
+        let Ok(entries) = std::fs::read_dir(path) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            add_files(&entry.path(), files);
+        }
+    } else {
+        files.insert(
+            path.to_path_buf(),
+            (metadata.len(), metadata.modified().ok()),
+        );
+    }
+}
+
+/// Call `run`, then again every time a file under `paths` changes, until Ctrl+C
+///
+/// Files written by `run` itself don't count as changes. A failed run is
+/// reported and waits for the next change, unless it was interrupted.
+pub async fn watch<F, Fut>(paths: &[PathBuf], mut run: F) -> Result<()>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    // Listen from the start, so a Ctrl+C that interrupts a run is seen here too
+    let interrupted = Arc::new(AtomicBool::new(false));
+    tokio::spawn({
+        let interrupted = interrupted.clone();
+        async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                interrupted.store(true, Ordering::Relaxed);
+            }
+        }
+    });
+
+    loop {
+        if let Err(e) = run().await {
+            if interrupted.load(Ordering::Relaxed) {
+                return Err(e);
+            }
+            tracing::error!("{e:?}");
+        }
+
+        let mut snapshot = Snapshot::take(paths);
+        tracing::info!("Watching for changes, press Ctrl+C to stop");
+        // Wait for a change, then for the files to settle, so that a build
+        // writing many files is seen once it's done
+        let mut changed = false;
+        loop {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            if interrupted.load(Ordering::Relaxed) {
+                return Ok(());
+            }
+            let current = Snapshot::take(paths);
+            if current != snapshot {
+                changed = true;
+                snapshot = current;
+            } else if changed {
+                break;
+            }
+        }
+        tracing::info!("Files changed, running again");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_sees_changes() {
+        let dir = std::env::temp_dir().join(format!("fontcull-watch-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("nested")).unwrap();
+        std::fs::write(dir.join("nested/a.html"), "a").unwrap();
+        let paths = [dir.clone(), dir.join("missing.ttf")];
+
+        let before = Snapshot::take(&paths);
+        assert_eq!(before.0.len(), 1);
+        assert_eq!(before, Snapshot::take(&paths));
+
+        std::fs::write(dir.join("nested/a.html"), "ab").unwrap();
+        let after = Snapshot::take(&paths);
+        assert_ne!(before, after);
+
+        std::fs::write(dir.join("b.css"), "").unwrap();
+        assert_ne!(after, Snapshot::take(&paths));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,2 @@
+// Runs before any of the page's own scripts
+window.__fontcullInit = true;
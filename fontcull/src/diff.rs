@@ -0,0 +1,406 @@
+//! Differences between two fonts, e.g. a subset and its original
+//!
+//! Glyph IDs change when a font is subset, so glyphs are matched by name when
+//! both fonts have glyph names, and by code point otherwise.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use fontcull_read_fonts::{FontRef, types::Tag};
+use fontcull_skrifa::{
+    GlyphId, GlyphNameSource, MetadataProvider,
+    instance::{LocationRef, Size},
+    outline::{DrawSettings, pen::PathElement},
+};
+
+use crate::{
+    SubsetError,
+    inspect::{LayoutInfo, gpos_info, gsub_info},
+};
+
+/// Everything [`diff_fonts`] found to differ
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FontDiff {
+    /// Tables added, removed or with different contents, by tag
+    pub tables: Vec<TableChange>,
+    /// Font-wide metrics that differ
+    pub metrics: Vec<MetricChange>,
+    pub cmap: CmapDiff,
+    pub glyphs: GlyphDiff,
+    pub gsub: Option<LayoutDiff>,
+    pub gpos: Option<LayoutDiff>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TableChange {
+    pub tag: String,
+    /// Length in the old font, `None` if the table was added
+    pub old_length: Option<u32>,
+    /// Length in the new font, `None` if the table was removed
+    pub new_length: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct MetricChange {
+    pub name: String,
+    pub old: Option<f32>,
+    pub new: Option<f32>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CmapDiff {
+    /// Code points only the new font maps
+    pub added: Vec<u32>,
+    /// Code points only the old font maps
+    pub removed: Vec<u32>,
+    /// Code points mapped to a glyph with another name; only known when
+    /// glyphs are matched by name
+    pub remapped: Vec<u32>,
+}
+
+/// How glyphs of the two fonts are matched
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum GlyphAlignment {
+    /// By glyph name, from `post` or `CFF`
+    Name,
+    /// By the lowest code point mapped to each glyph, as `U+0041`; `.notdef`
+    /// is matched too, other unmapped glyphs are left out
+    Codepoint,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct GlyphDiff {
+    pub aligned_by: GlyphAlignment,
+    /// Glyphs only in the new font
+    pub added: Vec<String>,
+    /// Glyphs only in the old font
+    pub removed: Vec<String>,
+    pub changed: Vec<GlyphChange>,
+    /// Number of glyphs in both fonts with the same outline and advance
+    pub unchanged: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct GlyphChange {
+    /// Glyph name, or code point as `U+0041`
+    pub glyph: String,
+    /// Whether the unhinted outline at the default location differs
+    pub outline: bool,
+    /// Old and new advance width, if it differs
+    pub advance: Option<(f32, f32)>,
+}
+
+/// Differences between the `GSUB` or `GPOS` tables of the two fonts
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LayoutDiff {
+    pub scripts_added: Vec<String>,
+    pub scripts_removed: Vec<String>,
+    pub features_added: Vec<String>,
+    pub features_removed: Vec<String>,
+    /// Old and new number of lookups
+    pub lookups: (u16, u16),
+}
+
+impl FontDiff {
+    /// Whether the fonts are the same as far as the diff looks
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+            && self.metrics.is_empty()
+            && self.cmap == CmapDiff::default()
+            && self.glyphs.added.is_empty()
+            && self.glyphs.removed.is_empty()
+            && self.glyphs.changed.is_empty()
+            && self.gsub.is_none()
+            && self.gpos.is_none()
+    }
+}
+
+/// Compare two TTF/OTF fonts
+///
+/// WOFF and WOFF2 fonts need decompressing first, see `decompress_font`.
+pub fn diff_fonts(old_data: &[u8], new_data: &[u8]) -> Result<FontDiff, SubsetError> {
+    let parse = |data| FontRef::new(data).map_err(|e| SubsetError::FontParse(format!("{e:?}")));
+    let (old, new) = (parse(old_data)?, parse(new_data)?);
+
+    Ok(FontDiff {
+        tables: diff_tables(&old, &new),
+        metrics: diff_metrics(&old, &new),
+        cmap: diff_cmap(&old, &new),
+        glyphs: diff_glyphs(&old, &new),
+        gsub: diff_layout(gsub_info(&old), gsub_info(&new)),
+        gpos: diff_layout(gpos_info(&old), gpos_info(&new)),
+    })
+}
+
+fn diff_tables(old: &FontRef, new: &FontRef) -> Vec<TableChange> {
+    let tags = |font: &FontRef| -> BTreeSet<Tag> {
+        font.table_directory()
+            .table_records()
+            .iter()
+            .map(|record| record.tag())
+            .collect()
+    };
+    tags(old)
+        .union(&tags(new))
+        .filter_map(|&tag| {
+            let (old_table, new_table) = (
+                old.table_data(tag).map(|data| data.as_bytes()),
+                new.table_data(tag).map(|data| data.as_bytes()),
+            );
+            (old_table != new_table).then(|| TableChange {
+                tag: tag.to_string(),
+                old_length: old_table.map(|t| t.len() as u32),
+                new_length: new_table.map(|t| t.len() as u32),
+            })
+        })
+        .collect()
+}
+
+fn diff_metrics(old: &FontRef, new: &FontRef) -> Vec<MetricChange> {
+    let fields = |font: &FontRef| {
+        let metrics = font.metrics(Size::unscaled(), LocationRef::default());
+        [
+            ("units_per_em", Some(f32::from(metrics.units_per_em))),
+            ("glyph_count", Some(f32::from(metrics.glyph_count))),
+            ("ascent", Some(metrics.ascent)),
+            ("descent", Some(metrics.descent)),
+            ("line_gap", Some(metrics.leading)),
+            ("cap_height", metrics.cap_height),
+            ("x_height", metrics.x_height),
+            ("italic_angle", Some(metrics.italic_angle)),
+            ("average_width", metrics.average_width),
+        ]
+    };
+    fields(old)
+        .into_iter()
+        .zip(fields(new))
+        .filter(|((_, old), (_, new))| old != new)
+        .map(|((name, old), (_, new))| MetricChange {
+            name: name.to_string(),
+            old,
+            new,
+        })
+        .collect()
+}
+
+fn diff_cmap(old: &FontRef, new: &FontRef) -> CmapDiff {
+    let mappings =
+        |font: &FontRef| -> BTreeMap<u32, GlyphId> { font.charmap().mappings().collect() };
+    let (old_map, new_map) = (mappings(old), mappings(new));
+
+    let mut remapped = Vec::new();
+    if alignment(old, new) == GlyphAlignment::Name {
+        let (old_names, new_names) = (old.glyph_names(), new.glyph_names());
+        for (cp, old_gid) in &old_map {
+            if let Some(new_gid) = new_map.get(cp)
+                && old_names
+                    .get(*old_gid)
+                    .map(|name| name.as_str().to_string())
+                    != new_names
+                        .get(*new_gid)
+                        .map(|name| name.as_str().to_string())
+            {
+                remapped.push(*cp);
+            }
+        }
+    }
+
+    CmapDiff {
+        added: new_map
+            .keys()
+            .filter(|cp| !old_map.contains_key(cp))
+            .copied()
+            .collect(),
+        removed: old_map
+            .keys()
+            .filter(|cp| !new_map.contains_key(cp))
+            .copied()
+            .collect(),
+        remapped,
+    }
+}
+
+fn alignment(old: &FontRef, new: &FontRef) -> GlyphAlignment {
+    let named = |font: &FontRef| font.glyph_names().source() != GlyphNameSource::Synthesized;
+    if named(old) && named(new) {
+        GlyphAlignment::Name
+    } else {
+        GlyphAlignment::Codepoint
+    }
+}
+
+/// The glyphs of a font by the key they're matched on
+fn glyph_keys(font: &FontRef, alignment: GlyphAlignment) -> BTreeMap<String, GlyphId> {
+    match alignment {
+        GlyphAlignment::Name => font
+            .glyph_names()
+            .iter()
+            .map(|(gid, name)| (name.as_str().to_string(), gid))
+            .collect(),
+        GlyphAlignment::Codepoint => {
+            // Mappings are in code point order, so the first one per glyph is the lowest
+            let mut lowest: BTreeMap<GlyphId, u32> = BTreeMap::new();
+            for (cp, gid) in font.charmap().mappings() {
+                lowest.entry(gid).or_insert(cp);
+            }
+            let mut keys: BTreeMap<String, GlyphId> = lowest
+                .into_iter()
+                .map(|(gid, cp)| (format!("U+{cp:04X}"), gid))
+                .collect();
+            keys.insert(".notdef".to_string(), GlyphId::NOTDEF);
+            keys
+        }
+    }
+}
+
+fn diff_glyphs(old: &FontRef, new: &FontRef) -> GlyphDiff {
+    let aligned_by = alignment(old, new);
+    let (old_keys, new_keys) = (glyph_keys(old, aligned_by), glyph_keys(new, aligned_by));
+    let (old_outlines, new_outlines) = (old.outline_glyphs(), new.outline_glyphs());
+    let (old_metrics, new_metrics) = (
+        old.glyph_metrics(Size::unscaled(), LocationRef::default()),
+        new.glyph_metrics(Size::unscaled(), LocationRef::default()),
+    );
+    let outline = |outlines: &fontcull_skrifa::OutlineGlyphCollection, gid| {
+        let glyph = outlines.get(gid)?;
+        let mut path: Vec<PathElement> = Vec::new();
+        glyph
+            .draw(
+                DrawSettings::unhinted(Size::unscaled(), LocationRef::default()),
+                &mut path,
+            )
+            .ok()?;
+        Some(path)
+    };
+
+    let mut changed = Vec::new();
+    let mut unchanged = 0;
+    for (key, &old_gid) in &old_keys {
+        let Some(&new_gid) = new_keys.get(key) else {
+            continue;
+        };
+        let outline_changed = outline(&old_outlines, old_gid) != outline(&new_outlines, new_gid);
+        let (old_advance, new_advance) = (
+            old_metrics.advance_width(old_gid).unwrap_or_default(),
+            new_metrics.advance_width(new_gid).unwrap_or_default(),
+        );
+        let advance = (old_advance != new_advance).then_some((old_advance, new_advance));
+        if outline_changed || advance.is_some() {
+            changed.push(GlyphChange {
+                glyph: key.clone(),
+                outline: outline_changed,
+                advance,
+            });
+        } else {
+            unchanged += 1;
+        }
+    }
+
+    GlyphDiff {
+        aligned_by,
+        added: new_keys
+            .keys()
+            .filter(|key| !old_keys.contains_key(*key))
+            .cloned()
+            .collect(),
+        removed: old_keys
+            .keys()
+            .filter(|key| !new_keys.contains_key(*key))
+            .cloned()
+            .collect(),
+        changed,
+        unchanged,
+    }
+}
+
+fn diff_layout(old: Option<LayoutInfo>, new: Option<LayoutInfo>) -> Option<LayoutDiff> {
+    let (old, new) = (old.unwrap_or(EMPTY_LAYOUT), new.unwrap_or(EMPTY_LAYOUT));
+    let scripts = |layout: &LayoutInfo| -> BTreeSet<String> {
+        layout.scripts.iter().map(|s| s.tag.clone()).collect()
+    };
+    let features =
+        |layout: &LayoutInfo| -> BTreeSet<String> { layout.features.iter().cloned().collect() };
+    let only = |a: &BTreeSet<String>, b: &BTreeSet<String>| -> Vec<String> {
+        a.difference(b).cloned().collect()
+    };
+
+    let diff = LayoutDiff {
+        scripts_added: only(&scripts(&new), &scripts(&old)),
+        scripts_removed: only(&scripts(&old), &scripts(&new)),
+        features_added: only(&features(&new), &features(&old)),
+        features_removed: only(&features(&old), &features(&new)),
+        lookups: (old.lookup_count, new.lookup_count),
+    };
+    let same = diff.scripts_added.is_empty()
+        && diff.scripts_removed.is_empty()
+        && diff.features_added.is_empty()
+        && diff.features_removed.is_empty()
+        && diff.lookups.0 == diff.lookups.1;
+    (!same).then_some(diff)
+}
+
+const EMPTY_LAYOUT: LayoutInfo = LayoutInfo {
+    scripts: Vec::new(),
+    features: Vec::new(),
+    lookup_count: 0,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> Vec<u8> {
+        std::fs::read("test_data/Roboto-Regular.abc.ttf").expect("failed to read TTF fixture")
+    }
+
+    #[test]
+    fn test_diff_same_font() {
+        let font = fixture();
+        let diff = diff_fonts(&font, &font).unwrap();
+        assert!(diff.is_empty());
+        assert_eq!(diff.glyphs.aligned_by, GlyphAlignment::Codepoint);
+        // .notdef, a, b and c
+        assert_eq!(diff.glyphs.unchanged, 4);
+    }
+
+    #[test]
+    fn test_diff_subset() {
+        let font = fixture();
+        let options = crate::SubsetOptions {
+            drop_tables: "GPOS".to_string(),
+            ..Default::default()
+        };
+        let subset =
+            crate::subset_font_data_with_options(&font, &['a' as u32, 'c' as u32], &options)
+                .unwrap();
+        let diff = diff_fonts(&font, &subset).unwrap();
+
+        assert!(!diff.is_empty());
+        assert_eq!(diff.cmap.removed, vec!['b' as u32]);
+        assert!(diff.cmap.added.is_empty());
+        assert_eq!(diff.glyphs.removed, vec!["U+0062".to_string()]);
+        assert!(diff.glyphs.changed.is_empty());
+        assert_eq!(diff.glyphs.unchanged, 3);
+        assert!(
+            diff.tables
+                .iter()
+                .any(|t| t.tag == "GPOS" && t.new_length.is_none())
+        );
+        assert!(
+            diff.metrics
+                .iter()
+                .any(|m| m.name == "glyph_count" && m.old == Some(4.0) && m.new == Some(3.0))
+        );
+        let gpos = diff.gpos.expect("GPOS was dropped");
+        assert_eq!(gpos.features_removed, vec!["kern".to_string()]);
+        assert_eq!(gpos.lookups, (1, 0));
+    }
+}
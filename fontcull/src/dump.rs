@@ -0,0 +1,306 @@
+//! Fonts as text, like `ttx`: a readable dump for debugging and diffing, and a
+//! JSON form that compiles back to a font
+//!
+//! The text dump comes from `fontcull-dump`, which the vendored subsetter's
+//! tests share. The JSON form is the serde representation of write-fonts'
+//! tables, so it covers whatever write-fonts can compile; other tables are kept
+//! as hex.
+
+use fontcull_dump::cmap4_glyph_ids_used;
+use fontcull_read_fonts::{FontRef, TableProvider, types::Tag};
+use fontcull_write_fonts::{FontBuilder, from_obj::ToOwnedTable, tables};
+
+use crate::SubsetError;
+
+/// Dump the tables of a font (TTF, OTF or TTC) as indented text, one field per
+/// line
+///
+/// See [`fontcull_dump::dump_font`], which this wraps.
+pub fn dump_font(font_data: &[u8], tables: &[&str]) -> Result<String, SubsetError> {
+    fontcull_dump::dump_font(font_data, tables)
+        .map_err(|e| SubsetError::FontParse(format!("{e:?}")))
+}
+
+/// A font as JSON, one key per table
+///
+/// Tables write-fonts can compile are written out field by field, in the shape
+/// of write-fonts' types. `glyf` is an array with each glyph as a hex string,
+/// and `loca` is left out since it's rebuilt from those. Other tables, such as
+/// `CFF ` and `gvar`, are hex strings. [`compile_font_json`] turns the result
+/// back into a font, so a table can be edited by hand without a font editor.
+pub fn font_to_json(font_data: &[u8]) -> Result<String, SubsetError> {
+    let parse_error = |e| SubsetError::FontParse(format!("{e:?}"));
+    let font = FontRef::new(font_data).map_err(parse_error)?;
+    let glyphs = glyph_data(&font);
+    let mut tables = serde_json::Map::new();
+    for record in font.table_directory().table_records() {
+        let tag = record.tag();
+        let json_error = |e: String| SubsetError::FontParse(format!("{tag}: {e}"));
+        let value = match (&tag.into_bytes(), &glyphs) {
+            (b"glyf", Some(glyphs)) => glyphs
+                .iter()
+                .map(|glyph| hex(glyph))
+                .collect::<Vec<_>>()
+                .into(),
+            (b"loca", Some(_)) => continue,
+            (b"cmap", _) => {
+                let mut cmap: tables::cmap::Cmap = font
+                    .cmap()
+                    .map_err(|e| json_error(e.to_string()))?
+                    .to_owned_table();
+                trim_glyph_id_arrays(&mut cmap);
+                serde_json::to_value(&cmap).map_err(|e| json_error(e.to_string()))?
+            }
+            _ => match owned_to_json(&font, tag) {
+                Some(value) => value.map_err(json_error)?,
+                None => hex(font.table_data(tag).unwrap_or_default().as_bytes()).into(),
+            },
+        };
+        // write-fonts picks the lowest OS/2 version with the fields present,
+        // which would turn version 3 into 4
+        let value = match (value, font.os2()) {
+            (serde_json::Value::Object(mut os2), Ok(table)) if tag == Tag::new(b"OS/2") => {
+                os2.insert("version".to_string(), table.version().into());
+                os2.into()
+            }
+            (value, _) => value,
+        };
+        tables.insert(tag.to_string(), value);
+    }
+    serde_json::to_string_pretty(&tables).map_err(|e| SubsetError::FontParse(e.to_string()))
+}
+
+/// The bytes of each glyph in `glyf`, if `loca` can be read
+fn glyph_data<'a>(font: &FontRef<'a>) -> Option<Vec<&'a [u8]>> {
+    let loca = font.loca(None).ok()?;
+    let glyf = font.table_data(Tag::new(b"glyf"))?.as_bytes();
+    (0..loca.len())
+        .map(|gid| {
+            let start = loca.get_raw(gid)? as usize;
+            let end = loca.get_raw(gid + 1)? as usize;
+            glyf.get(start..end)
+        })
+        .collect()
+}
+
+/// Compile the output of [`font_to_json`] back into a font
+///
+/// `loca`, and the `indexToLocFormat` of `head`, are rebuilt from the glyphs in
+/// `glyf`.
+pub fn compile_font_json(json: &str) -> Result<Vec<u8>, SubsetError> {
+    let compile_error = |e: String| SubsetError::Compile(e);
+    let mut tables: serde_json::Map<String, serde_json::Value> =
+        serde_json::from_str(json).map_err(|e| compile_error(e.to_string()))?;
+
+    let mut builder = FontBuilder::new();
+    if let Some(serde_json::Value::Array(glyphs)) = tables.get("glyf") {
+        let mut glyf = Vec::new();
+        let mut offsets = vec![0];
+        for glyph in glyphs {
+            let bytes = glyph
+                .as_str()
+                .ok_or_else(|| "expected a hex string per glyph".to_string())
+                .and_then(unhex)
+                .map_err(|e| compile_error(format!("glyf: {e}")))?;
+            glyf.extend(bytes);
+            // Short loca offsets are stored halved, so glyphs start at even offsets
+            if glyf.len() % 2 != 0 {
+                glyf.push(0);
+            }
+            offsets.push(glyf.len() as u32);
+        }
+        let loca = tables::loca::Loca::new(offsets);
+        if let Some(head) = tables.get_mut("head").and_then(|head| head.as_object_mut()) {
+            head.insert(
+                "index_to_loc_format".to_string(),
+                (loca.format() as i16).into(),
+            );
+        }
+        builder
+            .add_raw(Tag::new(b"glyf"), glyf)
+            .add_table(&loca)
+            .map_err(|e| compile_error(e.to_string()))?;
+    }
+
+    for (tag, value) in tables {
+        let tag = Tag::new_checked(tag.as_bytes())
+            .map_err(|e| compile_error(format!("bad table tag {tag:?}: {e}")))?;
+        if builder.contains(tag) {
+            continue;
+        }
+        match value {
+            serde_json::Value::String(hex) => {
+                let data = unhex(&hex).map_err(|e| compile_error(format!("{tag}: {e}")))?;
+                builder.add_raw(tag, data);
+            }
+            value => {
+                let version = value.get("version").and_then(|v| v.as_u64());
+                let mut data =
+                    json_to_owned(tag, value).map_err(|e| compile_error(format!("{tag}: {e}")))?;
+                if let (b"OS/2", Some(version)) = (&tag.into_bytes(), version) {
+                    data[..2].copy_from_slice(&(version as u16).to_be_bytes());
+                }
+                builder.add_raw(tag, data);
+            }
+        }
+    }
+    Ok(builder.build())
+}
+
+macro_rules! owned_tables {
+    ($($tag:literal => $method:ident: $table:ty),* $(,)?) => {
+        /// A table as write-fonts' owned type, serialized, or `None` if it
+        /// wouldn't compile back
+        fn owned_to_json(
+            font: &FontRef,
+            tag: Tag,
+        ) -> Option<Result<serde_json::Value, String>> {
+            match &tag.into_bytes() {
+                $($tag => {
+                    let table: $table = match font.$method() {
+                        Ok(table) => table.to_owned_table(),
+                        Err(e) => return Some(Err(e.to_string())),
+                    };
+                    let value = match serde_json::to_value(&table) {
+                        Ok(value) => value,
+                        Err(e) => return Some(Err(e.to_string())),
+                    };
+                    // Some fonts have tags that can't be read back, like
+                    // DejaVu's " RQD" feature; keep those tables as hex
+                    serde_json::from_value::<$table>(value.clone()).ok()?;
+                    Some(Ok(value))
+                })*
+                _ => None,
+            }
+        }
+
+        /// A serialized owned table, compiled
+        fn json_to_owned(tag: Tag, value: serde_json::Value) -> Result<Vec<u8>, String> {
+            match &tag.into_bytes() {
+                $($tag => {
+                    let table: $table = serde_json::from_value(value).map_err(|e| e.to_string())?;
+                    fontcull_write_fonts::dump_table(&table).map_err(|e| e.to_string())
+                })*
+                _ => Err("no compiler for this table, write it as a hex string".to_string()),
+            }
+        }
+    };
+}
+
+owned_tables!(
+    b"avar" => avar: tables::avar::Avar,
+    b"BASE" => base: tables::base::Base,
+    b"cmap" => cmap: tables::cmap::Cmap,
+    b"COLR" => colr: tables::colr::Colr,
+    b"CPAL" => cpal: tables::cpal::Cpal,
+    b"DSIG" => dsig: tables::dsig::Dsig,
+    b"fvar" => fvar: tables::fvar::Fvar,
+    b"gasp" => gasp: tables::gasp::Gasp,
+    b"GDEF" => gdef: tables::gdef::Gdef,
+    b"GPOS" => gpos: tables::gpos::Gpos,
+    b"GSUB" => gsub: tables::gsub::Gsub,
+    b"head" => head: tables::head::Head,
+    b"hhea" => hhea: tables::hhea::Hhea,
+    b"hmtx" => hmtx: tables::hmtx::Hmtx,
+    b"HVAR" => hvar: tables::hvar::Hvar,
+    b"maxp" => maxp: tables::maxp::Maxp,
+    b"meta" => meta: tables::meta::Meta,
+    b"MVAR" => mvar: tables::mvar::Mvar,
+    b"name" => name: tables::name::Name,
+    b"OS/2" => os2: tables::os2::Os2,
+    b"post" => post: tables::post::Post,
+    b"sbix" => sbix: tables::sbix::Sbix,
+    b"STAT" => stat: tables::stat::Stat,
+    b"vhea" => vhea: tables::vhea::Vhea,
+    b"vmtx" => vmtx: tables::vmtx::Vmtx,
+    b"VVAR" => vvar: tables::vvar::Vvar,
+);
+
+/// Drop the glyph ids read past the end of format 4 subtables
+fn trim_glyph_id_arrays(cmap: &mut tables::cmap::Cmap) {
+    for record in &mut cmap.encoding_records {
+        if let tables::cmap::CmapSubtable::Format4(subtable) = &mut *record.subtable {
+            let used = cmap4_glyph_ids_used(
+                &subtable.start_code,
+                &subtable.end_code,
+                &subtable.id_range_offsets,
+            );
+            subtable.glyph_id_array.truncate(used);
+        }
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(hex: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = hex.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+        return Err("odd number of hex digits".to_string());
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| format!("not hex: {}", String::from_utf8_lossy(pair)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> Vec<u8> {
+        std::fs::read("test_data/Roboto-Regular.abc.ttf").expect("failed to read TTF fixture")
+    }
+
+    /// The dump without the checksum, which depends on the table layout
+    fn dump(font: &[u8]) -> String {
+        dump_font(font, &[])
+            .unwrap()
+            .lines()
+            .filter(|line| !line.trim_start().starts_with("checksum_adjustment:"))
+            .map(|line| format!("{line}\n"))
+            .collect()
+    }
+
+    #[test]
+    fn test_dump_font() {
+        let text = dump_font(&fixture(), &[]).unwrap();
+        assert!(text.contains("head: Head\n  version: 1.0\n"), "{text}");
+        assert!(text.contains("maxp: Maxp\n  version: 1.0\n  num_glyphs: 4\n"));
+        assert!(text.contains("glyf: 4 glyphs\n"));
+        assert!(text.contains("    contour 0:\n"));
+        assert!(text.contains("        feature_tag: kern\n"));
+
+        let head = dump_font(&fixture(), &["head"]).unwrap();
+        assert!(head.starts_with("head: Head\n"));
+        assert!(!head.contains("maxp"));
+    }
+
+    #[test]
+    fn test_json_round_trip() {
+        let font = fixture();
+        let json = font_to_json(&font).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["maxp"]["num_glyphs"], 4);
+        assert_eq!(value["glyf"].as_array().unwrap().len(), 4);
+        assert!(value.get("loca").is_none());
+
+        let compiled = compile_font_json(&json).unwrap();
+        assert_eq!(dump(&compiled), dump(&font));
+
+        let edited = json.replace("\"num_glyphs\": 4", "\"num_glyphs\": 3");
+        let compiled = compile_font_json(&edited).unwrap();
+        assert!(
+            dump_font(&compiled, &["maxp"])
+                .unwrap()
+                .contains("num_glyphs: 3\n")
+        );
+        assert!(compile_font_json("{\"head\": \"0g\"}").is_err());
+    }
+}
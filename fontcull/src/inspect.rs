@@ -0,0 +1,510 @@
+//! What's in a font: tables, names, variations, layout features, coverage
+//!
+//! Meant for choosing subsetting options, so it reports what those options act
+//! on rather than every field of every table.
+
+use std::collections::BTreeSet;
+
+use fontcull_read_fonts::{
+    FileRef, FontRef, TableProvider,
+    tables::{glyf::Glyph, layout::FeatureList, layout::ScriptList},
+    types::Tag,
+};
+use fontcull_skrifa::{GlyphId, MetadataProvider, string::StringId};
+
+use crate::{SubsetError, unicode_blocks::UNICODE_BLOCKS};
+
+/// Everything [`inspect_font`] found in one font
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct FontReport {
+    /// Tables, in the order of the table directory
+    pub tables: Vec<TableSize>,
+    pub names: Vec<NameEntry>,
+    pub glyph_count: u16,
+    pub units_per_em: u16,
+    /// Variation axes from `fvar`
+    pub axes: Vec<VariationAxis>,
+    /// Named instances from `fvar`
+    pub instances: Vec<NamedInstance>,
+    pub stat: Option<StatInfo>,
+    pub gsub: Option<LayoutInfo>,
+    pub gpos: Option<LayoutInfo>,
+    /// Number of code points mapped in `cmap`
+    pub codepoint_count: usize,
+    /// The mapped code points per Unicode block, in code point order
+    pub coverage: Vec<BlockCoverage>,
+    /// Color formats, e.g. `COLRv1` or `sbix`
+    pub color: Vec<String>,
+    pub hinting: Hinting,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TableSize {
+    pub tag: String,
+    /// Length in bytes, without padding
+    pub length: u32,
+}
+
+/// A record of the `name` table
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct NameEntry {
+    pub name_id: u16,
+    pub platform_id: u16,
+    pub encoding_id: u16,
+    pub language_id: u16,
+    pub value: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct VariationAxis {
+    pub tag: String,
+    pub name: Option<String>,
+    pub min: f32,
+    pub default: f32,
+    pub max: f32,
+    pub hidden: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct NamedInstance {
+    pub name: Option<String>,
+    pub postscript_name: Option<String>,
+    /// User-space value per axis tag, in axis order
+    pub coordinates: Vec<(String, f32)>,
+}
+
+/// The style attributes table
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StatInfo {
+    pub design_axes: Vec<StatAxis>,
+    pub values: Vec<StatValue>,
+    pub elided_fallback_name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StatAxis {
+    pub tag: String,
+    pub name: Option<String>,
+    pub ordering: u16,
+}
+
+/// A named position on one or more design axes
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct StatValue {
+    pub name: Option<String>,
+    /// Axis tag and value, several for a format 4 combination
+    pub location: Vec<(String, f32)>,
+    /// Whether the name is left out of full style names, as "Regular" usually is
+    pub elidable: bool,
+}
+
+/// Scripts, languages and features of a `GSUB` or `GPOS` table
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LayoutInfo {
+    pub scripts: Vec<LayoutScript>,
+    /// Every feature tag, sorted and without duplicates
+    pub features: Vec<String>,
+    pub lookup_count: u16,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LayoutScript {
+    pub tag: String,
+    /// The default language system first, as `dflt`
+    pub languages: Vec<LayoutLanguage>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct LayoutLanguage {
+    pub tag: String,
+    pub features: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct BlockCoverage {
+    /// Block name, or `Other` for code points outside the known blocks
+    pub block: String,
+    /// Code points of the block the font maps
+    pub count: usize,
+    /// Code points in the block, 0 for `Other`
+    pub size: u32,
+}
+
+/// How the outlines are hinted
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[cfg_attr(feature = "serde", serde(tag = "kind", rename_all = "snake_case"))]
+pub enum Hinting {
+    /// TrueType outlines without instructions
+    None,
+    /// TrueType instructions
+    TrueType {
+        /// Glyphs with instructions of their own
+        instructed_glyphs: usize,
+        /// Tables shared by all glyphs: `fpgm`, `prep`, `cvt `
+        tables: Vec<String>,
+        /// Whether the version string names ttfautohint
+        autohinted: bool,
+    },
+    /// CFF or CFF2 outlines, whose hints are part of each glyph's charstring
+    PostScript,
+    /// No outlines, e.g. a bitmap-only font
+    NoOutlines,
+}
+
+/// Inspect a TTF/OTF font, or each font of a TrueType collection
+///
+/// WOFF and WOFF2 fonts need decompressing first, see `decompress_font`.
+pub fn inspect_font(font_data: &[u8]) -> Result<Vec<FontReport>, SubsetError> {
+    let parse_error = |e| SubsetError::FontParse(format!("{e:?}"));
+    let file = FileRef::new(font_data).map_err(parse_error)?;
+    file.fonts()
+        .map(|font| font.map_err(parse_error).and_then(|font| inspect(&font)))
+        .collect()
+}
+
+fn inspect(font: &FontRef) -> Result<FontReport, SubsetError> {
+    let parse_error = |e| SubsetError::FontParse(format!("{e:?}"));
+    let string = |id: StringId| {
+        font.localized_strings(id)
+            .english_or_first()
+            .map(|name| name.chars().collect::<String>())
+    };
+
+    let tables = font
+        .table_directory()
+        .table_records()
+        .iter()
+        .map(|record| TableSize {
+            tag: record.tag().to_string(),
+            length: record.length(),
+        })
+        .collect();
+
+    let names = match font.name() {
+        Ok(name) => name
+            .name_record()
+            .iter()
+            .map(|record| NameEntry {
+                name_id: record.name_id().to_u16(),
+                platform_id: record.platform_id(),
+                encoding_id: record.encoding_id(),
+                language_id: record.language_id(),
+                value: record
+                    .string(name.string_data())
+                    .map(|value| value.chars().collect())
+                    .unwrap_or_default(),
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    let axes: Vec<VariationAxis> = font
+        .axes()
+        .iter()
+        .map(|axis| VariationAxis {
+            tag: axis.tag().to_string(),
+            name: string(axis.name_id()),
+            min: axis.min_value(),
+            default: axis.default_value(),
+            max: axis.max_value(),
+            hidden: axis.is_hidden(),
+        })
+        .collect();
+    let instances = font
+        .named_instances()
+        .iter()
+        .map(|instance| NamedInstance {
+            name: string(instance.subfamily_name_id()),
+            postscript_name: instance.postscript_name_id().and_then(string),
+            coordinates: axes
+                .iter()
+                .map(|axis| axis.tag.clone())
+                .zip(instance.user_coords())
+                .collect(),
+        })
+        .collect();
+
+    let codepoints: Vec<u32> = font.charmap().mappings().map(|(cp, _)| cp).collect();
+
+    Ok(FontReport {
+        tables,
+        names,
+        glyph_count: font.maxp().map_err(parse_error)?.num_glyphs(),
+        units_per_em: font.head().map_err(parse_error)?.units_per_em(),
+        axes,
+        instances,
+        stat: stat(font, string),
+        gsub: gsub_info(font),
+        gpos: gpos_info(font),
+        codepoint_count: codepoints.len(),
+        coverage: coverage(&codepoints),
+        color: color(font),
+        hinting: hinting(font, string(StringId::VERSION_STRING)),
+    })
+}
+
+fn stat(font: &FontRef, string: impl Fn(StringId) -> Option<String>) -> Option<StatInfo> {
+    let stat = font.stat().ok()?;
+    let design_axes = stat.design_axes().ok()?;
+    let axis_tag = |index: u16| {
+        design_axes
+            .get(index as usize)
+            .map_or_else(|| format!("#{index}"), |axis| axis.axis_tag().to_string())
+    };
+
+    let mut values = Vec::new();
+    if let Some(Ok(array)) = stat.offset_to_axis_values() {
+        for value in array.axis_values().iter().flatten() {
+            let location = match &value {
+                fontcull_read_fonts::tables::stat::AxisValue::Format4(combination) => combination
+                    .axis_values()
+                    .iter()
+                    .map(|record| (axis_tag(record.axis_index()), record.value().to_f32()))
+                    .collect(),
+                _ => value
+                    .axis_index()
+                    .zip(value.value())
+                    .map(|(index, value)| (axis_tag(index), value.to_f32()))
+                    .into_iter()
+                    .collect(),
+            };
+            values.push(StatValue {
+                name: string(value.value_name_id()),
+                location,
+                elidable: value.flags().contains(
+                    fontcull_read_fonts::tables::stat::AxisValueTableFlags::ELIDABLE_AXIS_VALUE_NAME,
+                ),
+            });
+        }
+    }
+
+    Some(StatInfo {
+        design_axes: design_axes
+            .iter()
+            .map(|axis| StatAxis {
+                tag: axis.axis_tag().to_string(),
+                name: string(axis.axis_name_id()),
+                ordering: axis.axis_ordering(),
+            })
+            .collect(),
+        values,
+        elided_fallback_name: stat.elided_fallback_name_id().and_then(&string),
+    })
+}
+
+pub(crate) fn gsub_info(font: &FontRef) -> Option<LayoutInfo> {
+    let gsub = font.gsub().ok()?;
+    layout(
+        gsub.script_list().ok()?,
+        gsub.feature_list().ok()?,
+        gsub.lookup_list().ok()?.lookup_count(),
+    )
+}
+
+pub(crate) fn gpos_info(font: &FontRef) -> Option<LayoutInfo> {
+    let gpos = font.gpos().ok()?;
+    layout(
+        gpos.script_list().ok()?,
+        gpos.feature_list().ok()?,
+        gpos.lookup_list().ok()?.lookup_count(),
+    )
+}
+
+fn layout(scripts: ScriptList, features: FeatureList, lookup_count: u16) -> Option<LayoutInfo> {
+    let feature_tags: Vec<Tag> = features
+        .feature_records()
+        .iter()
+        .map(|record| record.feature_tag())
+        .collect();
+    let tags_of = |indices: &[fontcull_read_fonts::types::BigEndian<u16>]| {
+        let tags: BTreeSet<String> = indices
+            .iter()
+            .filter_map(|index| feature_tags.get(index.get() as usize))
+            .map(Tag::to_string)
+            .collect();
+        tags.into_iter().collect()
+    };
+
+    let mut layout_scripts = Vec::new();
+    for record in scripts.script_records() {
+        let script = record.script(scripts.offset_data()).ok()?;
+        let mut languages = Vec::new();
+        if let Some(Ok(lang_sys)) = script.default_lang_sys() {
+            languages.push(LayoutLanguage {
+                tag: "dflt".to_string(),
+                features: tags_of(lang_sys.feature_indices()),
+            });
+        }
+        for lang_record in script.lang_sys_records() {
+            let lang_sys = lang_record.lang_sys(script.offset_data()).ok()?;
+            languages.push(LayoutLanguage {
+                tag: lang_record.lang_sys_tag().to_string(),
+                features: tags_of(lang_sys.feature_indices()),
+            });
+        }
+        layout_scripts.push(LayoutScript {
+            tag: record.script_tag().to_string(),
+            languages,
+        });
+    }
+
+    let features: BTreeSet<String> = feature_tags.iter().map(Tag::to_string).collect();
+    Some(LayoutInfo {
+        scripts: layout_scripts,
+        features: features.into_iter().collect(),
+        lookup_count,
+    })
+}
+
+/// Count sorted code points per Unicode block
+fn coverage(codepoints: &[u32]) -> Vec<BlockCoverage> {
+    let mut blocks: Vec<BlockCoverage> = Vec::new();
+    let mut other = 0;
+    for &cp in codepoints {
+        let Some(&(first, last, name)) = UNICODE_BLOCKS
+            .iter()
+            .find(|(first, last, _)| (*first..=*last).contains(&cp))
+        else {
+            other += 1;
+            continue;
+        };
+        match blocks.last_mut() {
+            Some(block) if block.block == name => block.count += 1,
+            _ => blocks.push(BlockCoverage {
+                block: name.to_string(),
+                count: 1,
+                size: last - first + 1,
+            }),
+        }
+    }
+    if other > 0 {
+        blocks.push(BlockCoverage {
+            block: "Other".to_string(),
+            count: other,
+            size: 0,
+        });
+    }
+    blocks
+}
+
+fn color(font: &FontRef) -> Vec<String> {
+    let mut formats = Vec::new();
+    if let Ok(colr) = font.colr() {
+        formats.push(format!("COLRv{}", colr.version()));
+    }
+    if let Ok(cpal) = font.cpal() {
+        formats.push(format!("CPAL ({} palettes)", cpal.num_palettes()));
+    }
+    for tag in [b"sbix", b"CBDT", b"SVG "] {
+        if font.table_data(Tag::new(tag)).is_some() {
+            formats.push(Tag::new(tag).to_string().trim_end().to_string());
+        }
+    }
+    formats
+}
+
+fn hinting(font: &FontRef, version: Option<String>) -> Hinting {
+    if font.table_data(Tag::new(b"CFF ")).is_some() || font.table_data(Tag::new(b"CFF2")).is_some()
+    {
+        return Hinting::PostScript;
+    }
+    let (Ok(glyf), Ok(loca)) = (font.glyf(), font.loca(None)) else {
+        return Hinting::NoOutlines;
+    };
+
+    let instructed_glyphs = (0..loca.len().saturating_sub(1) as u32)
+        .filter(|&gid| {
+            let instructions = match loca.get_glyf(GlyphId::new(gid), &glyf) {
+                Ok(Some(Glyph::Simple(glyph))) => glyph.instructions(),
+                Ok(Some(Glyph::Composite(glyph))) => glyph.instructions().unwrap_or_default(),
+                _ => &[],
+            };
+            !instructions.is_empty()
+        })
+        .count();
+    let tables: Vec<String> = [b"fpgm", b"prep", b"cvt "]
+        .into_iter()
+        .filter(|tag| font.table_data(Tag::new(tag)).is_some())
+        .map(|tag| Tag::new(tag).to_string())
+        .collect();
+    if instructed_glyphs == 0 && tables.is_empty() {
+        return Hinting::None;
+    }
+    Hinting::TrueType {
+        instructed_glyphs,
+        tables,
+        autohinted: version.is_some_and(|version| version.contains("ttfautohint")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inspect_font() {
+        let font_data =
+            std::fs::read("test_data/Roboto-Regular.abc.ttf").expect("failed to read TTF fixture");
+        let [report] = inspect_font(&font_data)
+            .expect("failed to inspect")
+            .try_into()
+            .unwrap();
+
+        assert!(
+            report
+                .tables
+                .iter()
+                .any(|t| t.tag == "glyf" && t.length > 0)
+        );
+        assert!(
+            report
+                .names
+                .iter()
+                .any(|name| name.name_id == 1 && name.value == "Roboto")
+        );
+        assert_eq!(report.glyph_count, 4);
+        assert!(report.axes.is_empty());
+        assert_eq!(report.codepoint_count, 3);
+        assert_eq!(
+            report.coverage,
+            vec![BlockCoverage {
+                block: "Basic Latin".to_string(),
+                count: 3,
+                size: 128,
+            }]
+        );
+        assert!(report.color.is_empty());
+        assert!(inspect_font(b"not a font").is_err());
+    }
+
+    #[test]
+    fn test_coverage() {
+        let blocks = coverage(&[0x41, 0x42, 0xE9, 0x1F600, 0x10FFFF]);
+        let summary: Vec<(&str, usize)> =
+            blocks.iter().map(|b| (b.block.as_str(), b.count)).collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("Basic Latin", 2),
+                ("Latin-1 Supplement", 1),
+                ("Emoticons", 1),
+                ("Supplementary Private Use Area-B", 1),
+            ]
+        );
+        assert_eq!(coverage(&[0x0870]).len(), 1);
+        assert_eq!(coverage(&[0xE0080])[0].block, "Other");
+    }
+}
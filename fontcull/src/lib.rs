@@ -2,6 +2,23 @@
 
 use std::collections::HashSet;
 
+mod diff;
+mod inspect;
+mod metrics;
+mod unicode_blocks;
+mod woff;
+
+pub use diff::*;
+pub use inspect::*;
+pub use metrics::{FontMetrics, MetricOverrides};
+pub use woff::{compress_to_woff, decompress_woff};
+
+#[cfg(feature = "dump")]
+mod dump;
+
+#[cfg(feature = "dump")]
+pub use dump::*;
+
 #[cfg(feature = "static-analysis")]
 mod static_analysis;
 
@@ -19,6 +36,8 @@ pub enum SubsetError {
     Woff2(String),
     /// Failed to decompress WOFF font
     WoffDecompress(String),
+    /// Failed to compile a font from JSON
+    Compile(String),
 }
 
 impl std::fmt::Display for SubsetError {
@@ -28,6 +47,7 @@ impl std::fmt::Display for SubsetError {
             SubsetError::Subset(msg) => write!(f, "failed to subset font: {msg}"),
             SubsetError::Woff2(msg) => write!(f, "failed to compress to WOFF2: {msg}"),
             SubsetError::WoffDecompress(msg) => write!(f, "failed to decompress WOFF: {msg}"),
+            SubsetError::Compile(msg) => write!(f, "failed to compile font: {msg}"),
         }
     }
 }
@@ -78,10 +98,10 @@ impl FontFormat {
     }
 }
 
-/// Decompress a WOFF2 font to TTF/OTF
+/// Decompress a WOFF or WOFF2 font to TTF/OTF
 ///
 /// If the input is already TTF/OTF, returns a copy unchanged.
-/// If the input is WOFF2, decompresses it to TTF/OTF.
+/// If the input is WOFF or WOFF2, decompresses it to TTF/OTF.
 ///
 /// This is a separate operation that can be cached/salsified independently
 /// from subsetting.
@@ -92,9 +112,7 @@ pub fn decompress_font(font_data: &[u8]) -> Result<Vec<u8>, SubsetError> {
     match FontFormat::detect(font_data) {
         FontFormat::Woff2 => woofwoof::decompress(font_data)
             .ok_or_else(|| SubsetError::WoffDecompress("WOFF2 decompression failed".to_string())),
-        FontFormat::Woff => Err(SubsetError::WoffDecompress(
-            "WOFF1 decompression not supported, please convert to WOFF2 or TTF first".to_string(),
-        )),
+        FontFormat::Woff => decompress_woff(font_data),
         // Already TTF/OTF, return as-is
         _ => Ok(font_data.to_vec()),
     }
@@ -190,10 +208,80 @@ pub fn subset_font_data_unicode(
     font_data: &[u8],
     unicodes: &[u32],
 ) -> Result<Vec<u8>, SubsetError> {
-    use fontcull_klippa::{Plan, SubsetFlags, subset_font};
+    subset_font_data_with_options(font_data, unicodes, &SubsetOptions::default())
+}
+
+pub use fontcull_klippa::SubsetFlags;
+
+/// Options for [`subset_font_data_with_options`], following hb-subset's command line
+///
+/// The string fields use hb-subset's list syntax, e.g. `"kern,liga"`, `"1,5-9"`
+/// or `"*"` for everything. An empty string is an empty set, which is what the
+/// other subsetting functions use.
+#[derive(Debug, Clone, Default)]
+pub struct SubsetOptions {
+    pub flags: SubsetFlags,
+    /// Glyph IDs to keep in addition to those mapped from the code points
+    pub gids: String,
+    /// Glyph names to keep, separated by commas or whitespace
+    pub glyphs: String,
+    /// Tables to drop from the output
+    pub drop_tables: String,
+    /// Layout scripts to keep
+    pub layout_scripts: String,
+    /// Layout features to keep
+    pub layout_features: String,
+    /// Name IDs to keep
+    pub name_ids: String,
+    /// Name language IDs to keep
+    pub name_languages: String,
+    /// Strings the font renders. When not empty, GSUB substitutions only add
+    /// the glyphs they can produce for these strings, e.g. a ligature is kept
+    /// only if its letters follow each other in one of them. Their characters
+    /// are kept along with the code points.
+    pub texts: Vec<String>,
+}
+
+impl SubsetOptions {
+    /// The options hb-subset uses when none are given: its default layout
+    /// features in every script, and the English names with IDs 0 to 6
+    pub fn hb_subset_defaults() -> Self {
+        let layout_features: Vec<String> = fontcull_klippa::DEFAULT_LAYOUT_FEATURES
+            .iter()
+            .map(|tag| tag.to_string())
+            .collect();
+        Self {
+            layout_scripts: "*".to_string(),
+            layout_features: layout_features.join(","),
+            name_ids: "0,1,2,3,4,5,6".to_string(),
+            name_languages: "1033".to_string(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Parse code points in hb-subset's `--unicodes` syntax, e.g. `"U+41-5A,61"`,
+/// or `"*"` for all of Unicode
+pub fn parse_unicodes(text: &str) -> Result<Vec<u32>, SubsetError> {
+    if text.trim() == "*" {
+        return Ok((0..=0x10FFFF).collect());
+    }
+    fontcull_klippa::parse_unicodes(text)
+        .map(|set| set.iter().collect())
+        .map_err(|e| SubsetError::Subset(e.to_string()))
+}
+
+/// Subset a font to the given code points, with hb-subset style options
+pub fn subset_font_data_with_options(
+    font_data: &[u8],
+    unicodes: &[u32],
+    options: &SubsetOptions,
+) -> Result<Vec<u8>, SubsetError> {
+    use fontcull_klippa::{
+        Plan, parse_name_ids, parse_name_languages, parse_tag_list, populate_gids, subset_font,
+    };
     use fontcull_read_fonts::collections::IntSet;
-    use fontcull_skrifa::{FontRef, GlyphId, Tag};
-    use fontcull_write_fonts::types::NameId;
+    use fontcull_skrifa::FontRef;
 
     let font = FontRef::new(font_data).map_err(|e| SubsetError::FontParse(format!("{e:?}")))?;
 
@@ -202,21 +290,27 @@ pub fn subset_font_data_unicode(
         unicode_set.insert(u);
     }
 
-    let empty_gids: IntSet<GlyphId> = IntSet::empty();
-    let empty_tags: IntSet<Tag> = IntSet::empty();
-    let empty_name_ids: IntSet<NameId> = IntSet::empty();
-    let empty_langs: IntSet<u16> = IntSet::empty();
-
-    let plan = Plan::new(
-        &empty_gids,
+    let invalid = |e: fontcull_klippa::SubsetError| SubsetError::Subset(e.to_string());
+    let mut gids = populate_gids(&options.gids).map_err(invalid)?;
+    gids.extend(glyph_ids_for_names(&font, &options.glyphs)?);
+    let drop_tables = parse_tag_list(&options.drop_tables).map_err(invalid)?;
+    let layout_scripts = parse_tag_list(&options.layout_scripts).map_err(invalid)?;
+    let layout_features = parse_tag_list(&options.layout_features).map_err(invalid)?;
+    let name_ids = parse_name_ids(&options.name_ids).map_err(invalid)?;
+    let name_languages = parse_name_languages(&options.name_languages).map_err(invalid)?;
+
+    let texts: Vec<&str> = options.texts.iter().map(String::as_str).collect();
+    let plan = Plan::new_with_texts(
+        &gids,
         &unicode_set,
+        &texts,
         &font,
-        SubsetFlags::default(),
-        &empty_tags,
-        &empty_tags,
-        &empty_tags,
-        &empty_name_ids,
-        &empty_langs,
+        options.flags,
+        &drop_tables,
+        &layout_scripts,
+        &layout_features,
+        &name_ids,
+        &name_languages,
     );
 
     let subsetted = subset_font(&font, &plan).map_err(|e| SubsetError::Subset(format!("{e:?}")))?;
@@ -224,6 +318,58 @@ pub fn subset_font_data_unicode(
     Ok(subsetted)
 }
 
+/// Tables that [`subset_font_data_with_options`] drops from a font because it
+/// can't subset them: tables indexed by glyph ID (`mort`, `LTSH`, `JSTF`, ...)
+/// that would point at the wrong glyphs, and tables it doesn't recognize
+///
+/// Tables in `options.drop_tables` aren't listed. With
+/// `SubsetFlags::SUBSET_FLAGS_PASSTHROUGH_UNRECOGNIZED` they are copied as they
+/// are instead, and none are dropped.
+pub fn dropped_tables(
+    font_data: &[u8],
+    options: &SubsetOptions,
+) -> Result<Vec<String>, SubsetError> {
+    use fontcull_skrifa::FontRef;
+
+    let font = FontRef::new(font_data).map_err(|e| SubsetError::FontParse(format!("{e:?}")))?;
+    let drop_tables = fontcull_klippa::parse_tag_list(&options.drop_tables)
+        .map_err(|e| SubsetError::Subset(e.to_string()))?;
+    Ok(fontcull_klippa::dropped_tables(&font, options.flags)
+        .into_iter()
+        .filter(|tag| !drop_tables.contains(*tag))
+        .map(|tag| tag.to_string())
+        .collect())
+}
+
+/// Look up glyph names, separated by commas or whitespace, in the font's `post`
+/// or `CFF` table
+fn glyph_ids_for_names(
+    font: &fontcull_skrifa::FontRef,
+    names: &str,
+) -> Result<Vec<fontcull_skrifa::GlyphId>, SubsetError> {
+    use fontcull_skrifa::MetadataProvider;
+
+    let wanted: Vec<&str> = names
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|name| !name.is_empty())
+        .collect();
+    if wanted.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let glyph_names = font.glyph_names();
+    wanted
+        .into_iter()
+        .map(|name| {
+            glyph_names
+                .iter()
+                .find(|(_, glyph_name)| glyph_name == &name)
+                .map(|(gid, _)| gid)
+                .ok_or_else(|| SubsetError::Subset(format!("no glyph named {name:?}")))
+        })
+        .collect()
+}
+
 /// Subset a font to WOFF2 using unicode codepoints (u32)
 ///
 /// Requires the `woff2` feature (enabled by default).
@@ -240,6 +386,51 @@ pub fn subset_font_to_woff2_unicode(
     Ok(woff2)
 }
 
+/// What a font is, as needed to describe it in an `@font-face` rule
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontInfo {
+    /// Typographic family name, falling back to the legacy family name
+    pub family: Option<String>,
+    /// Weight, or the `wght` axis range of a variable font
+    pub weight: (f32, f32),
+    /// Width as a percentage of normal, or the `wdth` axis range of a variable font
+    pub stretch: (f32, f32),
+    /// Whether the font is italic or oblique
+    pub italic: bool,
+    /// Every code point the font maps to a glyph, in ascending order
+    pub codepoints: Vec<u32>,
+}
+
+/// Read the family name, style attributes and character coverage of a TTF/OTF font
+pub fn font_info(font_data: &[u8]) -> Result<FontInfo, SubsetError> {
+    use fontcull_skrifa::{FontRef, MetadataProvider, Tag, attribute::Style, string::StringId};
+
+    let font = FontRef::new(font_data).map_err(|e| SubsetError::FontParse(format!("{e:?}")))?;
+
+    let family = [StringId::TYPOGRAPHIC_FAMILY_NAME, StringId::FAMILY_NAME]
+        .into_iter()
+        .find_map(|id| font.localized_strings(id).english_or_first())
+        .map(|name| name.chars().collect());
+
+    let attributes = font.attributes();
+    let axis_range = |tag: &[u8; 4], default: f32| {
+        font.axes()
+            .iter()
+            .find(|axis| axis.tag() == Tag::new(tag))
+            .map_or((default, default), |axis| {
+                (axis.min_value(), axis.max_value())
+            })
+    };
+
+    Ok(FontInfo {
+        family,
+        weight: axis_range(b"wght", attributes.weight.value()),
+        stretch: axis_range(b"wdth", attributes.stretch.percentage()),
+        italic: attributes.style != Style::Normal,
+        codepoints: font.charmap().mappings().map(|(cp, _)| cp).collect(),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     #[allow(unused_imports)]
@@ -323,7 +514,7 @@ mod tests {
 
     #[test]
     #[cfg(feature = "woff2")]
-    fn test_decompress_woff1_not_supported() {
+    fn test_decompress_woff1_fixture() {
         // Read WOFF1 fixture file (created by fonttools)
         let woff1_data =
             std::fs::read("test_data/simple_glyf.woff").expect("failed to read WOFF1 fixture");
@@ -331,9 +522,11 @@ mod tests {
         // Verify it's actually WOFF1
         assert_eq!(FontFormat::detect(&woff1_data), FontFormat::Woff);
 
-        // WOFF1 decompression is not supported with woofwoof
-        let result = decompress_font(&woff1_data);
-        assert!(result.is_err());
+        // Decompressed with zlib, like a WOFF2 font with brotli
+        let decompressed = decompress_font(&woff1_data).expect("failed to decompress WOFF1");
+        assert_eq!(FontFormat::detect(&decompressed), FontFormat::Ttf);
+        let chars: HashSet<char> = ['a', 'b', 'c'].into_iter().collect();
+        let _subsetted = subset_font_data(&decompressed, &chars).expect("failed to subset");
     }
 
     #[test]
@@ -352,4 +545,132 @@ mod tests {
         // Verify output is WOFF2
         assert_eq!(FontFormat::detect(&woff2_output), FontFormat::Woff2);
     }
+
+    #[test]
+    fn test_subset_with_options() {
+        let font_data =
+            std::fs::read("test_data/Roboto-Regular.abc.ttf").expect("failed to read TTF fixture");
+
+        let options = SubsetOptions {
+            drop_tables: "GPOS, GSUB".to_string(),
+            ..Default::default()
+        };
+        let subsetted = subset_font_data_with_options(&font_data, &['a' as u32], &options)
+            .expect("failed to subset");
+        let info = font_info(&subsetted).expect("failed to read subset");
+        assert_eq!(info.codepoints, vec!['a' as u32]);
+
+        let options = SubsetOptions {
+            gids: "9-5".to_string(),
+            ..Default::default()
+        };
+        assert!(subset_font_data_with_options(&font_data, &['a' as u32], &options).is_err());
+
+        // The fixture has no glyph names in `post`, so they're synthesized
+        let num_glyphs = |options: &SubsetOptions| {
+            use fontcull_skrifa::{FontRef, raw::TableProvider};
+            let subsetted = subset_font_data_with_options(&font_data, &['a' as u32], options)
+                .expect("failed to subset");
+            FontRef::new(&subsetted)
+                .unwrap()
+                .maxp()
+                .unwrap()
+                .num_glyphs()
+        };
+        let options = SubsetOptions {
+            glyphs: "gid2, gid3".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            num_glyphs(&options),
+            num_glyphs(&SubsetOptions::default()) + 2
+        );
+
+        let options = SubsetOptions {
+            glyphs: "nosuchglyph".to_string(),
+            ..Default::default()
+        };
+        assert!(subset_font_data_with_options(&font_data, &['a' as u32], &options).is_err());
+    }
+
+    #[test]
+    fn test_dropped_tables() {
+        use fontcull_skrifa::{FontRef, Tag};
+
+        let font_data =
+            std::fs::read("test_data/Roboto-Regular.abc.ttf").expect("failed to read TTF fixture");
+        // A glyph-indexed table klippa can't subset, and one it doesn't know
+        let font_data = fontcull_write_fonts::FontBuilder::new()
+            .add_raw(Tag::new(b"LTSH"), vec![0, 0, 0, 4, 1, 1, 1, 1])
+            .add_raw(Tag::new(b"FFTM"), vec![0; 28])
+            .copy_missing_tables(FontRef::new(&font_data).unwrap())
+            .build();
+        let has_table = |data: &[u8], tag: &[u8; 4]| {
+            FontRef::new(data)
+                .unwrap()
+                .table_data(Tag::new(tag))
+                .is_some()
+        };
+
+        let options = SubsetOptions::default();
+        assert_eq!(
+            dropped_tables(&font_data, &options).unwrap(),
+            vec!["FFTM".to_string(), "LTSH".to_string()]
+        );
+        let subsetted = subset_font_data_with_options(&font_data, &['a' as u32], &options).unwrap();
+        assert!(!has_table(&subsetted, b"LTSH"));
+        assert!(!has_table(&subsetted, b"FFTM"));
+        assert!(has_table(&subsetted, b"gasp"));
+
+        let options = SubsetOptions {
+            drop_tables: "FFTM".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(
+            dropped_tables(&font_data, &options).unwrap(),
+            vec!["LTSH".to_string()]
+        );
+
+        let options = SubsetOptions {
+            flags: SubsetFlags::SUBSET_FLAGS_PASSTHROUGH_UNRECOGNIZED,
+            ..Default::default()
+        };
+        assert!(dropped_tables(&font_data, &options).unwrap().is_empty());
+        let subsetted = subset_font_data_with_options(&font_data, &['a' as u32], &options).unwrap();
+        assert!(has_table(&subsetted, b"LTSH"));
+        assert!(has_table(&subsetted, b"FFTM"));
+    }
+
+    #[test]
+    fn test_parse_unicodes() {
+        assert_eq!(
+            parse_unicodes("U+61-63, 7A").unwrap(),
+            vec![0x61, 0x62, 0x63, 0x7A]
+        );
+        assert_eq!(parse_unicodes("*").unwrap().len(), 0x110000);
+        assert!(parse_unicodes("U+zz").is_err());
+
+        let font_data =
+            std::fs::read("test_data/Roboto-Regular.abc.ttf").expect("failed to read TTF fixture");
+        let subsetted = subset_font_data_with_options(
+            &font_data,
+            &parse_unicodes("61").unwrap(),
+            &SubsetOptions::hb_subset_defaults(),
+        )
+        .expect("failed to subset with hb-subset's defaults");
+        assert_eq!(font_info(&subsetted).unwrap().codepoints, vec!['a' as u32]);
+    }
+
+    #[test]
+    fn test_font_info() {
+        let font_data =
+            std::fs::read("test_data/Roboto-Regular.abc.ttf").expect("failed to read TTF fixture");
+        let info = font_info(&font_data).expect("failed to read font info");
+
+        assert_eq!(info.family.as_deref(), Some("Roboto"));
+        assert_eq!(info.weight, (400.0, 400.0));
+        assert_eq!(info.stretch, (100.0, 100.0));
+        assert!(!info.italic);
+        assert_eq!(info.codepoints, vec!['a' as u32, 'b' as u32, 'c' as u32]);
+    }
 }
@@ -0,0 +1,249 @@
+//! Metric overrides for fallback fonts
+//!
+//! While a web font loads, text renders in a local fallback font, and the page
+//! shifts when the web font swaps in. An `@font-face` rule for the fallback with
+//! `size-adjust` and `*-override` descriptors makes it take up the same space.
+
+use std::collections::HashMap;
+
+use crate::SubsetError;
+
+/// Vertical metrics and advance widths of a font, in ems
+#[derive(Debug, Clone, PartialEq)]
+pub struct FontMetrics {
+    /// Distance above the baseline
+    pub ascent: f32,
+    /// Distance below the baseline, as a positive number
+    pub descent: f32,
+    pub line_gap: f32,
+    /// Advance width per code point
+    pub advances: HashMap<u32, f32>,
+}
+
+/// Descriptors for a fallback `@font-face` rule, as ratios (1.0 = 100%)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MetricOverrides {
+    pub size_adjust: f32,
+    pub ascent_override: f32,
+    pub descent_override: f32,
+    pub line_gap_override: f32,
+}
+
+/// Character weights used when no text was observed: lowercase letters and spaces
+/// in roughly the proportions of English prose
+const DEFAULT_FREQUENCIES: &[(char, u64)] = &[
+    (' ', 180),
+    ('e', 102),
+    ('t', 75),
+    ('a', 65),
+    ('o', 62),
+    ('i', 57),
+    ('n', 57),
+    ('s', 53),
+    ('r', 50),
+    ('h', 50),
+    ('l', 33),
+    ('d', 33),
+    ('c', 22),
+    ('u', 22),
+    ('m', 20),
+    ('f', 18),
+    ('w', 17),
+    ('g', 16),
+    ('y', 16),
+    ('p', 15),
+    ('b', 12),
+    ('v', 8),
+    ('k', 6),
+    ('x', 2),
+    ('j', 1),
+    ('q', 1),
+    ('z', 1),
+];
+
+impl FontMetrics {
+    /// Read the metrics of a TTF/OTF font, at its default location if variable
+    pub fn from_font(font_data: &[u8]) -> Result<Self, SubsetError> {
+        use fontcull_skrifa::{FontRef, MetadataProvider, instance::LocationRef, instance::Size};
+
+        let font = FontRef::new(font_data).map_err(|e| SubsetError::FontParse(format!("{e:?}")))?;
+        // At a size of 1, metrics come out in ems
+        let size = Size::new(1.0);
+        let metrics = font.metrics(size, LocationRef::default());
+        let glyph_metrics = font.glyph_metrics(size, LocationRef::default());
+
+        let advances = font
+            .charmap()
+            .mappings()
+            .filter_map(|(cp, gid)| Some((cp, glyph_metrics.advance_width(gid)?)))
+            .collect();
+
+        Ok(Self {
+            ascent: metrics.ascent,
+            descent: metrics.descent.abs(),
+            line_gap: metrics.leading,
+            advances,
+        })
+    }
+
+    /// Build metrics from vertical metrics in font units and AFM-style advance
+    /// widths (thousandths of an em) for printable ASCII
+    fn from_afm(
+        units_per_em: f32,
+        ascent: f32,
+        descent: f32,
+        line_gap: f32,
+        widths: &[u16; 95],
+    ) -> Self {
+        Self {
+            ascent: ascent / units_per_em,
+            descent: descent / units_per_em,
+            line_gap: line_gap / units_per_em,
+            advances: (' ' as u32..)
+                .zip(widths)
+                .map(|(cp, &w)| (cp, w as f32 / 1000.0))
+                .collect(),
+        }
+    }
+
+    /// Arial, the usual sans-serif fallback (printable ASCII only)
+    pub fn arial() -> Self {
+        // Arial is metric-compatible with Helvetica, whose AFM widths these are
+        const WIDTHS: [u16; 95] = [
+            278, 278, 355, 556, 556, 889, 667, 191, 333, 333, 389, 584, 278, 333, 278, 278, 556,
+            556, 556, 556, 556, 556, 556, 556, 556, 556, 278, 278, 584, 584, 584, 556, 1015, 667,
+            667, 722, 722, 667, 611, 778, 722, 278, 500, 667, 556, 833, 722, 778, 667, 778, 722,
+            667, 611, 722, 667, 944, 667, 667, 611, 278, 278, 278, 469, 556, 333, 556, 556, 500,
+            556, 556, 278, 556, 556, 222, 222, 500, 222, 833, 556, 556, 556, 556, 333, 500, 278,
+            556, 500, 722, 500, 500, 500, 334, 260, 334, 584,
+        ];
+        Self::from_afm(2048.0, 1854.0, 434.0, 67.0, &WIDTHS)
+    }
+
+    /// Times New Roman, the usual serif fallback (printable ASCII only)
+    pub fn times_new_roman() -> Self {
+        // Times New Roman is metric-compatible with Times, whose AFM widths these are
+        const WIDTHS: [u16; 95] = [
+            250, 333, 408, 500, 500, 833, 778, 180, 333, 333, 500, 564, 250, 333, 250, 278, 500,
+            500, 500, 500, 500, 500, 500, 500, 500, 500, 278, 278, 564, 564, 564, 444, 921, 722,
+            667, 667, 722, 611, 556, 722, 722, 333, 389, 722, 611, 889, 722, 722, 556, 722, 667,
+            556, 611, 722, 722, 944, 722, 722, 611, 333, 278, 333, 469, 500, 333, 444, 500, 444,
+            500, 444, 333, 500, 500, 278, 278, 500, 278, 778, 500, 500, 500, 500, 333, 389, 278,
+            500, 500, 722, 500, 500, 444, 480, 200, 480, 541,
+        ];
+        Self::from_afm(2048.0, 1825.0, 443.0, 87.0, &WIDTHS)
+    }
+
+    /// Average advance width, weighted by how often each character occurs.
+    /// Only characters both fonts have are counted, so the two averages compare
+    /// like with like.
+    fn average_advance(&self, other: &FontMetrics, frequencies: &HashMap<u32, u64>) -> Option<f32> {
+        let mut total = 0.0;
+        let mut weight = 0.0;
+        for (cp, &count) in frequencies {
+            if let (Some(advance), true) = (self.advances.get(cp), other.advances.contains_key(cp))
+            {
+                total += advance * count as f32;
+                weight += count as f32;
+            }
+        }
+        (weight > 0.0).then(|| total / weight)
+    }
+
+    /// Overrides that make `fallback` occupy the same space as this font for text
+    /// with the given character frequencies (code point → occurrences)
+    pub fn overrides_for_fallback(
+        &self,
+        fallback: &FontMetrics,
+        frequencies: &HashMap<u32, u64>,
+    ) -> MetricOverrides {
+        let default_frequencies: HashMap<u32, u64> = DEFAULT_FREQUENCIES
+            .iter()
+            .map(|&(c, n)| (c as u32, n))
+            .collect();
+
+        let averages = |frequencies: &HashMap<u32, u64>| {
+            Some((
+                self.average_advance(fallback, frequencies)?,
+                fallback.average_advance(self, frequencies)?,
+            ))
+        };
+        let size_adjust = averages(frequencies)
+            .or_else(|| averages(&default_frequencies))
+            .filter(|&(_, fallback_width)| fallback_width > 0.0)
+            .map_or(1.0, |(width, fallback_width)| width / fallback_width);
+
+        // The overrides apply to the fallback after it's scaled by size-adjust
+        MetricOverrides {
+            size_adjust,
+            ascent_override: self.ascent / size_adjust,
+            descent_override: self.descent / size_adjust,
+            line_gap_override: self.line_gap / size_adjust,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metrics(ascent: f32, descent: f32, advances: &[(char, f32)]) -> FontMetrics {
+        FontMetrics {
+            ascent,
+            descent,
+            line_gap: 0.0,
+            advances: advances.iter().map(|&(c, w)| (c as u32, w)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_overrides_weighted_by_frequency() {
+        let web = metrics(1.0, 0.25, &[('a', 0.6), ('i', 0.2)]);
+        let fallback = metrics(0.9, 0.2, &[('a', 0.5), ('i', 0.25)]);
+
+        // Mostly "a": the web font is 1.2× wider
+        let frequencies = HashMap::from([('a' as u32, 100), ('i' as u32, 0)]);
+        let overrides = web.overrides_for_fallback(&fallback, &frequencies);
+        assert!((overrides.size_adjust - 1.2).abs() < 1e-6);
+        assert!((overrides.ascent_override - 1.0 / 1.2).abs() < 1e-6);
+        assert!((overrides.descent_override - 0.25 / 1.2).abs() < 1e-6);
+
+        // Only "i": the web font is narrower
+        let frequencies = HashMap::from([('i' as u32, 5)]);
+        let overrides = web.overrides_for_fallback(&fallback, &frequencies);
+        assert!((overrides.size_adjust - 0.8).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_overrides_ignore_characters_missing_from_either_font() {
+        let web = metrics(1.0, 0.25, &[('a', 0.6), ('ж', 2.0)]);
+        let fallback = metrics(1.0, 0.25, &[('a', 0.6)]);
+        let frequencies = HashMap::from([('a' as u32, 1), ('ж' as u32, 1000)]);
+        let overrides = web.overrides_for_fallback(&fallback, &frequencies);
+        assert_eq!(overrides.size_adjust, 1.0);
+    }
+
+    #[test]
+    fn test_overrides_default_frequencies() {
+        let web = FontMetrics::arial();
+        let overrides = web.overrides_for_fallback(&FontMetrics::arial(), &HashMap::new());
+        assert_eq!(overrides.size_adjust, 1.0);
+        assert!((overrides.ascent_override - 0.905).abs() < 1e-3);
+        assert!((overrides.descent_override - 0.212).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_from_font() {
+        let font_data =
+            std::fs::read("test_data/Roboto-Regular.abc.ttf").expect("failed to read TTF fixture");
+        let metrics = FontMetrics::from_font(&font_data).unwrap();
+        assert_eq!(metrics.advances.len(), 3);
+        assert!(metrics.advances[&('a' as u32)] > 0.3 && metrics.advances[&('a' as u32)] < 0.8);
+        assert!(metrics.ascent > 0.5 && metrics.ascent < 1.5);
+        assert!(metrics.descent > 0.0 && metrics.descent < 0.5);
+
+        // Roboto is a little narrower than Arial
+        let overrides = metrics.overrides_for_fallback(&FontMetrics::arial(), &HashMap::new());
+        assert!(overrides.size_adjust > 0.9 && overrides.size_adjust < 1.05);
+    }
+}
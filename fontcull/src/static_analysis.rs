@@ -62,12 +62,17 @@ pub fn collect_chars_per_font(html: &str, css: &str) -> HashMap<String, HashSet<
     let all_elements = Selector::parse("*").unwrap();
 
     for element in document.select(&all_elements) {
+        // Code, not text
+        if matches!(element.value().name(), "script" | "style" | "template") {
+            continue;
+        }
+
         // Get direct text content (not from children)
         let text: String = element
-            .text()
-            .next()
-            .map(|s| s.to_string())
-            .unwrap_or_default();
+            .children()
+            .filter_map(|node| node.value().as_text())
+            .map(|text| &**text)
+            .collect();
 
         if text.trim().is_empty() {
             continue;
@@ -453,10 +458,33 @@ pub fn extract_css_from_html(html: &str) -> String {
     css
 }
 
+/// `href`s of the stylesheets an HTML document links to, in document order
+pub fn stylesheet_links(html: &str) -> Vec<String> {
+    let document = Html::parse_document(html);
+    let link_selector = Selector::parse("link[rel~=stylesheet][href]").unwrap();
+
+    document
+        .select(&link_selector)
+        .filter_map(|link| link.value().attr("href"))
+        .map(|href| href.to_string())
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_stylesheet_links() {
+        let html = r#"
+            <link rel="stylesheet" href="/css/site.css">
+            <link rel="preload stylesheet" href="fonts.css">
+            <link rel="icon" href="/favicon.ico">
+            <link rel="stylesheet">
+        "#;
+        assert_eq!(stylesheet_links(html), vec!["/css/site.css", "fonts.css"]);
+    }
+
     #[test]
     fn test_parse_font_family_rules() {
         let css = r#"
@@ -497,6 +525,19 @@ mod tests {
         assert!(test_font_chars.contains(&'e'));
         assert!(test_font_chars.contains(&'l'));
         assert!(test_font_chars.contains(&'o'));
+        // The stylesheet itself isn't text
+        assert!(chars.values().all(|chars| !chars.contains(&'{')));
+    }
+
+    #[test]
+    fn test_nested_text_uses_own_font() {
+        let html = r#"<p>Ab <code>xy</code> cd</p>"#;
+        let css = r#"p { font-family: "Body"; } code { font-family: "Mono"; }"#;
+        let chars = collect_chars_per_font(html, css);
+
+        assert!(chars["Body"].contains(&'A') && chars["Body"].contains(&'d'));
+        assert!(!chars["Body"].contains(&'x'));
+        assert!(chars["Mono"].contains(&'x'));
     }
 
     #[test]
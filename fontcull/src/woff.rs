@@ -0,0 +1,211 @@
+//! WOFF 1.0: an sfnt font with each table compressed by zlib
+//!
+//! See <https://www.w3.org/TR/WOFF/>. Metadata and private data blocks are
+//! neither written nor kept.
+
+use std::io::{Read, Write};
+
+use flate2::{Compression, read::ZlibDecoder, write::ZlibEncoder};
+
+use crate::SubsetError;
+
+const WOFF_SIGNATURE: u32 = 0x774F_4646;
+const WOFF_HEADER_LEN: usize = 44;
+const WOFF_ENTRY_LEN: usize = 20;
+const SFNT_HEADER_LEN: usize = 12;
+const SFNT_ENTRY_LEN: usize = 16;
+
+/// One table of a font, uncompressed
+struct Table<'a> {
+    tag: u32,
+    checksum: u32,
+    data: std::borrow::Cow<'a, [u8]>,
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn padded(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Compress a TTF/OTF font to WOFF 1.0
+///
+/// Tables are stored uncompressed where zlib doesn't make them smaller, as the
+/// format requires.
+pub fn compress_to_woff(font_data: &[u8]) -> Result<Vec<u8>, SubsetError> {
+    let invalid = || SubsetError::FontParse("truncated sfnt table directory".to_string());
+    let flavor = read_u32(font_data, 0).ok_or_else(invalid)?;
+    let num_tables = read_u16(font_data, 4).ok_or_else(invalid)?;
+
+    let mut tables = Vec::new();
+    for i in 0..num_tables as usize {
+        let record = SFNT_HEADER_LEN + i * SFNT_ENTRY_LEN;
+        let tag = read_u32(font_data, record).ok_or_else(invalid)?;
+        let checksum = read_u32(font_data, record + 4).ok_or_else(invalid)?;
+        let offset = read_u32(font_data, record + 8).ok_or_else(invalid)? as usize;
+        let length = read_u32(font_data, record + 12).ok_or_else(invalid)? as usize;
+        let data = font_data
+            .get(offset..offset + length)
+            .ok_or_else(|| SubsetError::FontParse("table extends past end of font".to_string()))?;
+        tables.push(Table {
+            tag,
+            checksum,
+            data: data.into(),
+        });
+    }
+    tables.sort_by_key(|t| t.tag);
+
+    let mut compressed = Vec::new();
+    for table in &tables {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        encoder
+            .write_all(&table.data)
+            .and_then(|()| encoder.finish())
+            .map(|data| compressed.push(data))
+            .map_err(|e| SubsetError::FontParse(format!("zlib compression failed: {e}")))?;
+    }
+
+    let total_sfnt_size = SFNT_HEADER_LEN
+        + tables.len() * SFNT_ENTRY_LEN
+        + tables.iter().map(|t| padded(t.data.len())).sum::<usize>();
+
+    let mut out = vec![0; WOFF_HEADER_LEN + tables.len() * WOFF_ENTRY_LEN];
+    let mut entries = Vec::new();
+    for (table, zlib) in tables.iter().zip(&compressed) {
+        let stored: &[u8] = if zlib.len() < table.data.len() {
+            zlib
+        } else {
+            &table.data
+        };
+        entries.push((table, out.len(), stored.len()));
+        out.extend_from_slice(stored);
+        out.resize(padded(out.len()), 0);
+    }
+
+    let mut header = Vec::with_capacity(WOFF_HEADER_LEN);
+    header.extend(WOFF_SIGNATURE.to_be_bytes());
+    header.extend(flavor.to_be_bytes());
+    header.extend((out.len() as u32).to_be_bytes());
+    header.extend(num_tables.to_be_bytes());
+    header.extend(0u16.to_be_bytes());
+    header.extend((total_sfnt_size as u32).to_be_bytes());
+    // Font version 1.0, no metadata, no private data
+    header.extend(1u16.to_be_bytes());
+    header.extend(0u16.to_be_bytes());
+    header.extend([0; 20]);
+    for (table, offset, stored_len) in entries {
+        header.extend(table.tag.to_be_bytes());
+        header.extend((offset as u32).to_be_bytes());
+        header.extend((stored_len as u32).to_be_bytes());
+        header.extend((table.data.len() as u32).to_be_bytes());
+        header.extend(table.checksum.to_be_bytes());
+    }
+    out[..header.len()].copy_from_slice(&header);
+    Ok(out)
+}
+
+/// Decompress a WOFF 1.0 font to TTF/OTF
+pub fn decompress_woff(woff_data: &[u8]) -> Result<Vec<u8>, SubsetError> {
+    let invalid = |msg: &str| SubsetError::WoffDecompress(msg.to_string());
+    if read_u32(woff_data, 0) != Some(WOFF_SIGNATURE) || woff_data.len() < WOFF_HEADER_LEN {
+        return Err(invalid("not a WOFF 1.0 font"));
+    }
+    let flavor = read_u32(woff_data, 4).ok_or_else(|| invalid("truncated header"))?;
+    let num_tables = read_u16(woff_data, 12).ok_or_else(|| invalid("truncated header"))?;
+
+    let mut tables = Vec::new();
+    for i in 0..num_tables as usize {
+        let entry = WOFF_HEADER_LEN + i * WOFF_ENTRY_LEN;
+        let field =
+            |n| read_u32(woff_data, entry + n).ok_or_else(|| invalid("truncated table directory"));
+        let (tag, offset, comp_length, orig_length, checksum) = (
+            field(0)?,
+            field(4)? as usize,
+            field(8)? as usize,
+            field(12)? as usize,
+            field(16)?,
+        );
+        let stored = woff_data
+            .get(offset..offset + comp_length)
+            .ok_or_else(|| invalid("table extends past end of font"))?;
+        let data = if comp_length < orig_length {
+            let mut data = Vec::with_capacity(orig_length);
+            ZlibDecoder::new(stored)
+                .read_to_end(&mut data)
+                .map_err(|e| SubsetError::WoffDecompress(format!("zlib: {e}")))?;
+            if data.len() != orig_length {
+                return Err(invalid("table has the wrong length once decompressed"));
+            }
+            data.into()
+        } else {
+            stored.into()
+        };
+        tables.push(Table {
+            tag,
+            checksum,
+            data,
+        });
+    }
+    tables.sort_by_key(|t| t.tag);
+
+    let num_tables = tables.len() as u16;
+    let entry_selector = num_tables.max(1).ilog2() as u16;
+    let search_range = (1u16 << entry_selector) * SFNT_ENTRY_LEN as u16;
+    let mut out = Vec::new();
+    out.extend(flavor.to_be_bytes());
+    out.extend(num_tables.to_be_bytes());
+    out.extend(search_range.to_be_bytes());
+    out.extend(entry_selector.to_be_bytes());
+    out.extend((num_tables * SFNT_ENTRY_LEN as u16 - search_range).to_be_bytes());
+
+    let mut offset = SFNT_HEADER_LEN + tables.len() * SFNT_ENTRY_LEN;
+    for table in &tables {
+        out.extend(table.tag.to_be_bytes());
+        out.extend(table.checksum.to_be_bytes());
+        out.extend((offset as u32).to_be_bytes());
+        out.extend((table.data.len() as u32).to_be_bytes());
+        offset += padded(table.data.len());
+    }
+    for table in &tables {
+        out.extend_from_slice(&table.data);
+        out.resize(padded(out.len()), 0);
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_woff_roundtrip() {
+        let ttf = std::fs::read("test_data/Roboto-Regular.abc.ttf").unwrap();
+        let woff = compress_to_woff(&ttf).unwrap();
+        assert_eq!(crate::FontFormat::detect(&woff), crate::FontFormat::Woff);
+        assert!(woff.len() < ttf.len());
+
+        let sfnt = decompress_woff(&woff).unwrap();
+        let info = crate::font_info(&sfnt).unwrap();
+        assert_eq!(info, crate::font_info(&ttf).unwrap());
+        // Table data is unchanged, so compressing again gives the same file
+        assert_eq!(compress_to_woff(&sfnt).unwrap(), woff);
+    }
+
+    #[test]
+    fn test_decompress_woff_fixture() {
+        let woff = std::fs::read("test_data/simple_glyf.woff").unwrap();
+        let sfnt = decompress_woff(&woff).unwrap();
+        assert_eq!(crate::FontFormat::detect(&sfnt), crate::FontFormat::Ttf);
+        assert!(decompress_woff(&woff[..30]).is_err());
+    }
+}
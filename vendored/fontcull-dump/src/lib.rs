@@ -0,0 +1,403 @@
+//! Fonts as indented text, like `ttx`, for debugging and for diffing two
+//! fonts in tests
+//!
+//! The dump walks every field of every table with read-fonts' generic
+//! traversal, so it covers whatever read-fonts can parse. Tables it can't
+//! parse are dumped as hex.
+
+use std::fmt::Write as _;
+
+use fontcull_read_fonts::{
+    FileRef, FontRef, ReadError, TableProvider,
+    tables::glyf::Glyph,
+    traversal::{
+        ArrayOffset, Field, FieldType, ResolvedOffset, SomeArray, SomeTable, StringOffset,
+    },
+    types::{GlyphId, Tag},
+};
+
+/// How deep offsets are followed, so a font with an offset cycle still ends
+const MAX_DEPTH: usize = 64;
+
+/// Numbers per line in long arrays
+const VALUES_PER_LINE: usize = 16;
+
+/// Bytes per line in hex dumps of tables read-fonts can't parse
+const BYTES_PER_LINE: usize = 32;
+
+/// Dump the tables of a font (TTF, OTF or TTC) as indented text, one field per
+/// line
+///
+/// `tables` limits the dump to these tags; empty means every table. Glyphs are
+/// listed with decoded points rather than packed bytes, and offsets are
+/// followed rather than printed, so two fonts with the same contents dump the
+/// same however their tables are laid out. Fields come straight from
+/// read-fonts, which names them after the OpenType spec in snake case.
+pub fn dump_font(font_data: &[u8], tables: &[&str]) -> Result<String, ReadError> {
+    let file = FileRef::new(font_data)?;
+    let fonts: Vec<FontRef> = file.fonts().collect::<Result<_, _>>()?;
+
+    let mut out = String::new();
+    for (index, font) in fonts.iter().enumerate() {
+        if fonts.len() > 1 {
+            let _ = writeln!(out, "# Font {} of {}", index + 1, fonts.len());
+        }
+        for record in font.table_directory().table_records() {
+            let tag = record.tag();
+            if tables.is_empty() || tables.contains(&tag.to_string().as_str()) {
+                dump_table(&mut out, font, tag);
+            }
+        }
+    }
+    Ok(out)
+}
+
+fn dump_table(out: &mut String, font: &FontRef, tag: Tag) {
+    let result = match &tag.into_bytes() {
+        b"glyf" => dump_glyf(out, font),
+        b"loca" => font.loca(None).map(|loca| {
+            let _ = writeln!(out, "loca: {} glyphs", loca.len());
+        }),
+        _ => match traverse(font, tag) {
+            Some(Ok(table)) => {
+                let _ = writeln!(out, "{tag}: {}", table.type_name());
+                dump_fields(out, &*table, 1);
+                Ok(())
+            }
+            Some(Err(e)) => Err(e),
+            None => {
+                dump_hex(
+                    out,
+                    tag,
+                    font.table_data(tag).unwrap_or_default().as_bytes(),
+                );
+                Ok(())
+            }
+        },
+    };
+    if let Err(e) = result {
+        let _ = writeln!(out, "{tag}: error: {e}");
+    }
+}
+
+/// The generic view of a table, if read-fonts can parse it
+fn traverse<'a>(
+    font: &FontRef<'a>,
+    tag: Tag,
+) -> Option<Result<Box<dyn SomeTable<'a> + 'a>, ReadError>> {
+    macro_rules! tables {
+        ($($tag:literal => $method:ident),* $(,)?) => {
+            match &tag.into_bytes() {
+                $($tag => Some(
+                    font.$method().map(|table| Box::new(table) as Box<dyn SomeTable<'a> + 'a>)
+                ),)*
+                _ => None,
+            }
+        };
+    }
+    tables!(
+        b"avar" => avar,
+        b"BASE" => base,
+        b"CBDT" => cbdt,
+        b"CBLC" => cblc,
+        b"cmap" => cmap,
+        b"COLR" => colr,
+        b"CPAL" => cpal,
+        b"cvar" => cvar,
+        b"DSIG" => dsig,
+        b"EBDT" => ebdt,
+        b"EBLC" => eblc,
+        b"feat" => feat,
+        b"fvar" => fvar,
+        b"gasp" => gasp,
+        b"GDEF" => gdef,
+        b"GPOS" => gpos,
+        b"GSUB" => gsub,
+        b"gvar" => gvar,
+        b"hdmx" => hdmx,
+        b"head" => head,
+        b"hhea" => hhea,
+        b"hmtx" => hmtx,
+        b"HVAR" => hvar,
+        b"kerx" => kerx,
+        b"ltag" => ltag,
+        b"maxp" => maxp,
+        b"meta" => meta,
+        b"morx" => morx,
+        b"MVAR" => mvar,
+        b"name" => name,
+        b"OS/2" => os2,
+        b"post" => post,
+        b"sbix" => sbix,
+        b"STAT" => stat,
+        b"SVG " => svg,
+        b"trak" => trak,
+        b"vhea" => vhea,
+        b"vmtx" => vmtx,
+        b"VORG" => vorg,
+        b"VVAR" => vvar,
+    )
+}
+
+/// Glyphs with their points, instead of the packed flags and coordinates
+fn dump_glyf(out: &mut String, font: &FontRef) -> Result<(), ReadError> {
+    let loca = font.loca(None)?;
+    let glyf = font.glyf()?;
+    let _ = writeln!(out, "glyf: {} glyphs", loca.len());
+    for gid in 0..loca.len() as u32 {
+        match loca.get_glyf(GlyphId::new(gid), &glyf) {
+            Ok(None) => {
+                let _ = writeln!(out, "  [{gid}]: empty");
+            }
+            Ok(Some(Glyph::Simple(glyph))) => {
+                let _ = writeln!(out, "  [{gid}]: SimpleGlyph");
+                // glyph_data is the packed flags and coordinates decoded below
+                for field in (&glyph as &dyn SomeTable).iter() {
+                    if field.name != "glyph_data" {
+                        dump_value(out, field.name, field.value, 2);
+                    }
+                }
+                if glyph.has_overlapping_contours() {
+                    let _ = writeln!(out, "    overlap_simple: true");
+                }
+                let mut points = glyph.points();
+                let mut start = 0;
+                for (contour, end) in glyph.end_pts_of_contours().iter().enumerate() {
+                    let _ = writeln!(out, "    contour {contour}:");
+                    for point in points.by_ref().take(usize::from(end.get()) + 1 - start) {
+                        let on = if point.on_curve { "on" } else { "off" };
+                        let _ = writeln!(out, "      {} {} {on}", point.x, point.y);
+                    }
+                    start = usize::from(end.get()) + 1;
+                }
+            }
+            Ok(Some(Glyph::Composite(glyph))) => {
+                let _ = writeln!(out, "  [{gid}]: CompositeGlyph");
+                for field in (&glyph as &dyn SomeTable).iter() {
+                    if field.name != "component_data" {
+                        dump_value(out, field.name, field.value, 2);
+                    }
+                }
+                for (index, component) in glyph.components().enumerate() {
+                    let _ = writeln!(out, "    component {index}:");
+                    dump_fields(out, &component, 3);
+                }
+                if let Some(instructions) = glyph.instructions() {
+                    dump_value(out, "instructions", instructions.into(), 2);
+                }
+            }
+            Err(e) => {
+                let _ = writeln!(out, "  [{gid}]: error: {e}");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn dump_hex(out: &mut String, tag: Tag, data: &[u8]) {
+    let _ = writeln!(out, "{tag}: {} bytes", data.len());
+    for (line, bytes) in data.chunks(BYTES_PER_LINE).enumerate() {
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{b:02x}")).collect();
+        let _ = writeln!(out, "  {:06x}: {}", line * BYTES_PER_LINE, hex.join(" "));
+    }
+}
+
+fn dump_fields<'a>(out: &mut String, table: &(dyn SomeTable<'a> + 'a), depth: usize) {
+    let fields: Vec<Field> = table.iter().collect();
+    let glyph_ids_used = (table.type_name() == "Cmap4").then(|| {
+        let numbers = |name: &str| -> Vec<u16> {
+            match fields
+                .iter()
+                .find(|field| field.name == name)
+                .map(|f| &f.value)
+            {
+                Some(FieldType::Array(array)) => array
+                    .iter()
+                    .filter_map(|item| match item {
+                        FieldType::U16(value) => Some(value),
+                        _ => None,
+                    })
+                    .collect(),
+                _ => Vec::new(),
+            }
+        };
+        cmap4_glyph_ids_used(
+            &numbers("start_code"),
+            &numbers("end_code"),
+            &numbers("id_range_offsets"),
+        )
+    });
+    for field in fields {
+        match (&field.value, glyph_ids_used) {
+            (FieldType::Array(array), Some(used)) if field.name == "glyph_id_array" => {
+                dump_array(out, field.name, array.as_ref(), Some(used), depth);
+            }
+            _ => dump_value(out, field.name, field.value, depth),
+        }
+    }
+}
+
+fn dump_value(out: &mut String, label: &str, value: FieldType, depth: usize) {
+    let indent = "  ".repeat(depth);
+    if let Some(scalar) = scalar(&value) {
+        let _ = writeln!(out, "{indent}{label}: {scalar}");
+        return;
+    }
+    if depth >= MAX_DEPTH {
+        let _ = writeln!(out, "{indent}{label}: too deeply nested");
+        return;
+    }
+    match value {
+        FieldType::BareOffset(offset) if offset.to_u32() == 0 => {
+            let _ = writeln!(out, "{indent}{label}: null");
+        }
+        FieldType::BareOffset(offset) => {
+            let _ = writeln!(out, "{indent}{label}: offset {:#x}", offset.to_u32());
+        }
+        FieldType::ResolvedOffset(ResolvedOffset {
+            target: Ok(table), ..
+        }) => {
+            let _ = writeln!(out, "{indent}{label}: {}", table.type_name());
+            dump_fields(out, &*table, depth + 1);
+        }
+        FieldType::StringOffset(StringOffset {
+            target: Ok(string), ..
+        }) => {
+            let string: String = string.iter_chars().collect();
+            let _ = writeln!(out, "{indent}{label}: {string:?}");
+        }
+        FieldType::ArrayOffset(ArrayOffset {
+            target: Ok(array), ..
+        }) => dump_array(out, label, &*array, None, depth),
+        FieldType::ResolvedOffset(ResolvedOffset { target: Err(e), .. })
+        | FieldType::StringOffset(StringOffset { target: Err(e), .. })
+        | FieldType::ArrayOffset(ArrayOffset { target: Err(e), .. }) => {
+            let _ = writeln!(out, "{indent}{label}: error: {e}");
+        }
+        FieldType::Record(record) => {
+            let _ = writeln!(out, "{indent}{label}: {}", record.type_name());
+            dump_fields(out, &record, depth + 1);
+        }
+        FieldType::Array(array) => dump_array(out, label, &*array, None, depth),
+        _ => {
+            let _ = writeln!(out, "{indent}{label}: unknown");
+        }
+    }
+}
+
+/// Arrays of numbers are packed several to a line, anything else gets a line
+/// (or more) per item
+fn dump_array<'a>(
+    out: &mut String,
+    label: &str,
+    array: &(dyn SomeArray<'a> + 'a),
+    limit: Option<usize>,
+    depth: usize,
+) {
+    let indent = "  ".repeat(depth);
+    let items: Vec<FieldType> = array.iter().take(limit.unwrap_or(usize::MAX)).collect();
+    let scalars: Option<Vec<String>> = items.iter().map(scalar).collect();
+    match scalars {
+        _ if items.is_empty() => {
+            let _ = writeln!(out, "{indent}{label}: []");
+        }
+        Some(values) if values.len() <= VALUES_PER_LINE => {
+            let _ = writeln!(out, "{indent}{label}: [{}]", values.join(", "));
+        }
+        Some(values) => {
+            let _ = writeln!(out, "{indent}{label}: {} items", values.len());
+            for (line, values) in values.chunks(VALUES_PER_LINE).enumerate() {
+                let _ = writeln!(
+                    out,
+                    "{indent}  [{}]: {}",
+                    line * VALUES_PER_LINE,
+                    values.join(", ")
+                );
+            }
+        }
+        None => {
+            let _ = writeln!(out, "{indent}{label}: {} items", items.len());
+            for (index, item) in items.into_iter().enumerate() {
+                dump_value(out, &format!("[{index}]"), item, depth + 1);
+            }
+        }
+    }
+}
+
+fn scalar(value: &FieldType) -> Option<String> {
+    Some(match value {
+        FieldType::I8(v) => v.to_string(),
+        FieldType::U8(v) => v.to_string(),
+        FieldType::I16(v) => v.to_string(),
+        FieldType::U16(v) => v.to_string(),
+        FieldType::I32(v) => v.to_string(),
+        FieldType::U32(v) => v.to_string(),
+        FieldType::I24(v) => v.to_i32().to_string(),
+        FieldType::U24(v) => v.to_u32().to_string(),
+        FieldType::Tag(v) => v.to_string(),
+        FieldType::FWord(v) => v.to_i16().to_string(),
+        FieldType::UfWord(v) => v.to_u16().to_string(),
+        FieldType::MajorMinor(v) => v.to_string(),
+        FieldType::Version16Dot16(v) => v.to_string(),
+        FieldType::F2Dot14(v) => v.to_string(),
+        FieldType::Fixed(v) => v.to_string(),
+        FieldType::LongDateTime(v) => v.as_secs().to_string(),
+        FieldType::GlyphId16(v) => format!("gid{}", v.to_u16()),
+        FieldType::NameId(v) => v.to_u16().to_string(),
+        _ => return None,
+    })
+}
+
+/// How many entries of a format 4 cmap subtable's glyph id array its segments
+/// use
+///
+/// read-fonts reads the array up to the end of the cmap, taking in whatever
+/// subtables follow, which would otherwise show up in dumps and compile into
+/// the font.
+pub fn cmap4_glyph_ids_used(
+    start_code: &[u16],
+    end_code: &[u16],
+    id_range_offsets: &[u16],
+) -> usize {
+    let seg_count = end_code.len();
+    (0..seg_count)
+        .filter_map(|i| {
+            let id_range_offset = usize::from(*id_range_offsets.get(i)?);
+            let span = usize::from(end_code[i].saturating_sub(*start_code.get(i)?));
+            // The offset counts from the segment's own id_range_offsets entry
+            (id_range_offset != 0)
+                .then(|| (id_range_offset / 2 + span + i + 1).saturating_sub(seg_count))
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dump_font() {
+        let text = dump_font(fontcull_font_test_data::GLYF_COMPONENTS, &[]).unwrap();
+        assert!(text.contains("head: Head\n  version: 1.0\n"), "{text}");
+        assert!(text.contains("glyf: "));
+        assert!(text.contains("    contour 0:\n"));
+        assert!(text.contains(": CompositeGlyph\n"));
+        assert!(text.contains("    component 0:\n"));
+
+        let head = dump_font(fontcull_font_test_data::GLYF_COMPONENTS, &["head"]).unwrap();
+        assert!(head.starts_with("head: Head\n"));
+        assert!(!head.contains("maxp"));
+    }
+
+    #[test]
+    fn test_cmap4_glyph_ids_used() {
+        // the second segment maps through the glyph id array, starting at its
+        // first entry and using 3 of them
+        assert_eq!(
+            cmap4_glyph_ids_used(&[0x20, 0x41, 0xFFFF], &[0x20, 0x43, 0xFFFF], &[0, 4, 0]),
+            3
+        );
+        assert_eq!(cmap4_glyph_ids_used(&[0xFFFF], &[0xFFFF], &[0]), 0);
+    }
+}
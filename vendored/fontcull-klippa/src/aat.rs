@@ -0,0 +1,468 @@
+//! Lookup tables and extended state tables shared by the AAT tables
+//!
+//! reference: <https://developer.apple.com/fonts/TrueType-Reference-Manual/RM06/Chap6Tables.html>
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::serialize::{SerializeErrorFlags, Serializer};
+use crate::Plan;
+use fontcull_write_fonts::{
+    read::{
+        collections::IntSet,
+        tables::aat::{Lookup, LookupU16, LookupValue},
+        FontData, FontRead, ReadError,
+    },
+    types::GlyphId,
+};
+
+/// Class of the glyphs a class table doesn't list
+const CLASS_OUT_OF_BOUNDS: u16 = 1;
+const CLASS_DELETED_GLYPH: u16 = 2;
+// end of text, out of bounds, deleted glyph and end of line
+const NUM_PREDEFINED_CLASSES: u16 = 4;
+
+/// Glyph ID AAT tables use for deleted glyphs, it isn't remapped
+pub(crate) const DELETED_GLYPH: u16 = 0xFFFF;
+
+/// (glyph, value) for each glyph a lookup table maps, in glyph order
+///
+/// Format 0 tables have a value for each of the font's `num_glyphs` glyphs.
+pub(crate) fn lookup_entries<T: LookupValue + Into<u32>>(
+    lookup: &Lookup,
+    num_glyphs: usize,
+) -> Result<Vec<(u16, u32)>, ReadError> {
+    let mut entries = Vec::new();
+    match lookup {
+        Lookup::Format0(lookup) => entries.extend(
+            (0..=u16::MAX)
+                .zip(lookup.values::<T>()?.iter().take(num_glyphs))
+                .map(|(gid, value)| (gid, value.get().into())),
+        ),
+        Lookup::Format2(lookup) => {
+            for segment in lookup.segments::<T>()? {
+                let first = segment.first_glyph.get();
+                // skip the binary search terminator
+                if first == DELETED_GLYPH {
+                    continue;
+                }
+                let value = segment.value;
+                let value = value.get().into();
+                entries.extend((first..=segment.last_glyph.get()).map(|gid| (gid, value)));
+            }
+        }
+        Lookup::Format4(lookup) => {
+            for (i, segment) in lookup.segments().iter().enumerate() {
+                if segment.first_glyph() == DELETED_GLYPH {
+                    continue;
+                }
+                let values = lookup.segment_values::<T>(i)?;
+                entries.extend(
+                    (segment.first_glyph()..=segment.last_glyph())
+                        .zip(values)
+                        .map(|(gid, value)| (gid, value.get().into())),
+                );
+            }
+        }
+        Lookup::Format6(lookup) => {
+            for entry in lookup.entries::<T>()? {
+                let gid = entry.glyph.get();
+                if gid == DELETED_GLYPH {
+                    continue;
+                }
+                let value = entry.value;
+                entries.push((gid, value.get().into()));
+            }
+        }
+        Lookup::Format8(lookup) => entries.extend(
+            (lookup.first_glyph()..=u16::MAX)
+                .zip(lookup.value_array())
+                .map(|(gid, value)| (gid, T::from_u16(value.get()).into())),
+        ),
+        Lookup::Format10(lookup) => {
+            let first = lookup.first_glyph();
+            for gid in (first..=u16::MAX).take(lookup.glyph_count() as usize) {
+                entries.push((gid, lookup.value::<T>(gid)?.into()));
+            }
+        }
+    }
+    entries.sort_unstable_by_key(|(gid, _)| *gid);
+    entries.dedup_by_key(|(gid, _)| *gid);
+    Ok(entries)
+}
+
+/// Write a lookup table mapping glyphs to 16-bit values, or 32-bit ones with
+/// `long_values`, in whichever of the segment (2) and single (6) formats is
+/// smaller
+pub(crate) fn serialize_lookup(
+    s: &mut Serializer,
+    entries: &BTreeMap<u16, u32>,
+    long_values: bool,
+) -> Result<(), SerializeErrorFlags> {
+    serialize_lookup_with_gaps(s, entries, long_values, None)
+}
+
+/// Write a class table, which can also use the trimmed array format (8) since
+/// glyphs it doesn't list are out of bounds anyway
+pub(crate) fn serialize_class_table(
+    s: &mut Serializer,
+    classes: &BTreeMap<u16, u32>,
+) -> Result<(), SerializeErrorFlags> {
+    serialize_lookup_with_gaps(s, classes, false, Some(CLASS_OUT_OF_BOUNDS))
+}
+
+fn serialize_lookup_with_gaps(
+    s: &mut Serializer,
+    entries: &BTreeMap<u16, u32>,
+    long_values: bool,
+    gap_value: Option<u16>,
+) -> Result<(), SerializeErrorFlags> {
+    let value_size = if long_values { 4 } else { 2 };
+    let segments = lookup_segments(entries);
+    // both binary search formats end with a terminator unit
+    let segment_size = 12 + (segments.len() + 1) * (4 + value_size);
+    let single_size = 12 + (entries.len() + 1) * (2 + value_size);
+    let span = match (entries.keys().next(), entries.keys().next_back()) {
+        (Some(first), Some(last)) => (last - first) as usize + 1,
+        _ => 0,
+    };
+    let trimmed_size = 6 + 2 * span;
+
+    if let Some(gap_value) = gap_value.filter(|_| span > 0 && trimmed_size < single_size) {
+        if trimmed_size < segment_size {
+            let first = *entries.keys().next().unwrap();
+            s.embed(8_u16)?;
+            s.embed(first)?;
+            s.embed(span as u16)?;
+            for gid in (first..=u16::MAX).take(span) {
+                let value = entries.get(&gid).map_or(gap_value, |value| *value as u16);
+                s.embed(value)?;
+            }
+            return Ok(());
+        }
+    }
+
+    let embed_value = |s: &mut Serializer, value: u32| {
+        if long_values {
+            s.embed(value)
+        } else {
+            s.embed(value as u16)
+        }
+    };
+    if segment_size <= single_size {
+        s.embed(2_u16)?;
+        embed_bin_search_header(s, 4 + value_size, segments.len() + 1)?;
+        for (first, last, value) in segments {
+            s.embed(last)?;
+            s.embed(first)?;
+            embed_value(s, value)?;
+        }
+        s.embed(DELETED_GLYPH)?;
+        s.embed(DELETED_GLYPH)?;
+    } else {
+        s.embed(6_u16)?;
+        embed_bin_search_header(s, 2 + value_size, entries.len() + 1)?;
+        for (gid, value) in entries {
+            s.embed(*gid)?;
+            embed_value(s, *value)?;
+        }
+        s.embed(DELETED_GLYPH)?;
+    }
+    embed_value(s, 0)?;
+    Ok(())
+}
+
+/// (first glyph, last glyph, value) for each run of consecutive glyphs with
+/// the same value
+fn lookup_segments(entries: &BTreeMap<u16, u32>) -> Vec<(u16, u16, u32)> {
+    let mut segments: Vec<(u16, u16, u32)> = Vec::new();
+    for (gid, value) in entries {
+        match segments.last_mut() {
+            Some((_, last, last_value)) if *last + 1 == *gid && last_value == value => *last = *gid,
+            _ => segments.push((*gid, *gid, *value)),
+        }
+    }
+    segments
+}
+
+// The unit count includes the terminator, like harfbuzz expects
+fn embed_bin_search_header(
+    s: &mut Serializer,
+    unit_size: usize,
+    num_units: usize,
+) -> Result<(), SerializeErrorFlags> {
+    let num_units: u16 = num_units
+        .try_into()
+        .map_err(|_| s.set_err(SerializeErrorFlags::SERIALIZE_ERROR_INT_OVERFLOW))?;
+    let entry_selector = num_units.ilog2();
+    let search_range = unit_size << entry_selector;
+    s.embed(unit_size as u16)?;
+    s.embed(num_units)?;
+    s.embed(search_range.min(u16::MAX as usize) as u16)?;
+    s.embed(entry_selector as u16)?;
+    s.embed((unit_size * num_units as usize - search_range).min(u16::MAX as usize) as u16)?;
+    Ok(())
+}
+
+/// Entry of an extended state table
+#[derive(Clone, Copy)]
+pub(crate) struct Entry<'a> {
+    pub(crate) flags: u16,
+    data: FontData<'a>,
+}
+
+impl Entry<'_> {
+    /// The 16-bit payload field at `index`, which depends on the table
+    pub(crate) fn payload(&self, index: usize) -> u16 {
+        self.data.read_at::<u16>(4 + 2 * index).unwrap_or(0)
+    }
+}
+
+/// An extended state table (STXHeader) with the states and entries the state
+/// machine can use
+///
+/// The state array has no length, so it's cut after the states reachable from
+/// the two predefined states, and the entry table after the entries those
+/// states use.
+pub(crate) struct StateMachine<'a> {
+    n_classes: u32,
+    class_table: LookupU16<'a>,
+    states: &'a [u8],
+    entries: FontData<'a>,
+    entry_size: usize,
+}
+
+impl<'a> StateMachine<'a> {
+    pub(crate) const HEADER_LEN: usize = 16;
+
+    /// Read the state table at the start of `data`, with `payload_len` bytes
+    /// of table specific data in each entry
+    pub(crate) fn read(data: FontData<'a>, payload_len: usize) -> Result<Self, ReadError> {
+        let subtable_data = |offset_pos: usize| {
+            let offset = data.read_at::<u32>(offset_pos)? as usize;
+            data.split_off(offset).ok_or(ReadError::OutOfBounds)
+        };
+        let n_classes = data.read_at::<u32>(0)?;
+        if n_classes == 0 {
+            return Err(ReadError::MalformedData("empty AAT state table"));
+        }
+        let class_table = LookupU16::read(subtable_data(4)?)?;
+        let state_array = subtable_data(8)?;
+        let entry_table = subtable_data(12)?;
+        let entry_size = 4 + payload_len;
+
+        // every state reachable so far, and every entry those states use
+        let (mut num_states, mut num_entries) = (2, 0);
+        let (mut states_read, mut entries_read) = (0, 0);
+        while states_read < num_states {
+            let row_indices = states_read * n_classes as usize..num_states * n_classes as usize;
+            for i in row_indices {
+                let entry = state_array.read_at::<u16>(2 * i)? as usize;
+                num_entries = num_entries.max(entry + 1);
+            }
+            states_read = num_states;
+            for entry in entries_read..num_entries {
+                let new_state = entry_table.read_at::<u16>(entry * entry_size)? as usize;
+                num_states = num_states.max(new_state + 1);
+            }
+            entries_read = num_entries;
+        }
+
+        let states = state_array
+            .slice(..num_states * n_classes as usize * 2)
+            .ok_or(ReadError::OutOfBounds)?;
+        let entries = entry_table
+            .slice(..num_entries * entry_size)
+            .ok_or(ReadError::OutOfBounds)?;
+        Ok(Self {
+            n_classes,
+            class_table,
+            states: states.as_bytes(),
+            entries,
+            entry_size,
+        })
+    }
+
+    pub(crate) fn num_entries(&self) -> usize {
+        self.entries.len() / self.entry_size
+    }
+
+    pub(crate) fn entry(&self, index: usize) -> Option<Entry<'a>> {
+        let data = self.entries.slice(index * self.entry_size..)?;
+        Some(Entry {
+            flags: data.read_at::<u16>(2).ok()?,
+            data,
+        })
+    }
+
+    pub(crate) fn entries(&self) -> impl Iterator<Item = Entry<'a>> + '_ {
+        (0..self.num_entries()).filter_map(|index| self.entry(index))
+    }
+
+    fn class(&self, gid: GlyphId) -> u16 {
+        match u16::try_from(gid.to_u32()) {
+            Ok(DELETED_GLYPH) | Err(_) => CLASS_DELETED_GLYPH,
+            Ok(gid) => self.class_table.value(gid).unwrap_or(CLASS_OUT_OF_BOUNDS),
+        }
+    }
+
+    /// The entries the state machine can get to from the predefined states on
+    /// a text made of `glyphs`
+    pub(crate) fn reachable_entries(&self, glyphs: &IntSet<GlyphId>) -> Vec<Entry<'a>> {
+        let mut classes: BTreeSet<u16> = (0..NUM_PREDEFINED_CLASSES).collect();
+        classes.extend(glyphs.iter().map(|gid| self.class(gid)));
+        let classes: BTreeSet<usize> = classes
+            .into_iter()
+            .map(|class| match class as u32 {
+                class if class < self.n_classes => class as usize,
+                _ => CLASS_OUT_OF_BOUNDS as usize,
+            })
+            .collect();
+
+        let mut seen_states = BTreeSet::from([0, 1]);
+        let mut queue = vec![0, 1];
+        let mut reachable = BTreeSet::new();
+        while let Some(state) = queue.pop() {
+            for class in &classes {
+                let pos = 2 * (state * self.n_classes as usize + class);
+                let Some(index) = self.states.get(pos..pos + 2) else {
+                    continue;
+                };
+                let index = u16::from_be_bytes([index[0], index[1]]) as usize;
+                if !reachable.insert(index) {
+                    continue;
+                }
+                let Some(entry) = self.entry(index) else {
+                    continue;
+                };
+                let new_state = entry.data.read_at::<u16>(0).unwrap_or(0) as usize;
+                if seen_states.insert(new_state) {
+                    queue.push(new_state);
+                }
+            }
+        }
+        reachable
+            .into_iter()
+            .filter_map(|index| self.entry(index))
+            .collect()
+    }
+
+    /// Write the header, followed by `extra_fields` 32-bit fields left zero for
+    /// the caller, then the class table for the new glyph IDs, the state array
+    /// and the entry table. Offsets are from where the header starts.
+    pub(crate) fn serialize(
+        &self,
+        s: &mut Serializer,
+        plan: &Plan,
+        extra_fields: usize,
+    ) -> Result<(), SerializeErrorFlags> {
+        let start = s.head();
+        s.embed(self.n_classes)?;
+        let offsets_pos = s.embed(0_u32)?;
+        s.embed(0_u32)?;
+        s.embed(0_u32)?;
+        for _ in 0..extra_fields {
+            s.embed(0_u32)?;
+        }
+
+        let classes: BTreeMap<u16, u32> = plan
+            .new_to_old_gid_list
+            .iter()
+            .filter_map(|(new_gid, old_gid)| {
+                let class = self.class_table.value(old_gid.to_u32() as u16).ok()?;
+                (class != CLASS_OUT_OF_BOUNDS).then_some((new_gid.to_u32() as u16, class as u32))
+            })
+            .collect();
+        s.copy_assign(offsets_pos, (s.head() - start) as u32);
+        serialize_class_table(s, &classes)?;
+        s.copy_assign(offsets_pos + 4, (s.head() - start) as u32);
+        s.embed_bytes(self.states)?;
+        s.copy_assign(offsets_pos + 8, (s.head() - start) as u32);
+        s.embed_bytes(self.entries.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// The new glyph ID of an AAT glyph value, deleted glyphs stay deleted
+pub(crate) fn new_gid(plan: &Plan, gid: u16) -> Option<u16> {
+    if gid == DELETED_GLYPH {
+        return Some(DELETED_GLYPH);
+    }
+    plan.glyph_map
+        .get(&GlyphId::from(gid))
+        .map(|new_gid| new_gid.to_u32() as u16)
+}
+
+/// A glyph lookup remapped to the new glyph IDs, dropping glyphs that aren't
+/// kept on either side
+pub(crate) fn remap_glyph_lookup(
+    lookup: &Lookup,
+    plan: &Plan,
+) -> Result<BTreeMap<u16, u32>, ReadError> {
+    Ok(lookup_entries::<u16>(lookup, plan.font_num_glyphs)?
+        .into_iter()
+        .filter_map(|(gid, value)| {
+            let gid = new_gid(plan, gid).filter(|gid| *gid != DELETED_GLYPH)?;
+            Some((gid, new_gid(plan, value as u16)? as u32))
+        })
+        .collect())
+}
+
+/// A lookup with the values of the kept glyphs, keyed by new glyph ID
+pub(crate) fn remap_lookup<T: LookupValue + Into<u32>>(
+    lookup: &Lookup,
+    plan: &Plan,
+) -> Result<BTreeMap<u16, u32>, ReadError> {
+    Ok(lookup_entries::<T>(lookup, plan.font_num_glyphs)?
+        .into_iter()
+        .filter_map(|(gid, value)| Some((new_gid(plan, gid)?, value)))
+        .filter(|(gid, _)| *gid != DELETED_GLYPH)
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn round_trip(entries: &BTreeMap<u16, u32>, class_table: bool) -> (u16, Vec<(u16, u32)>) {
+        let mut s = Serializer::new(1024);
+        assert_eq!(s.start_serialize(), Ok(()));
+        if class_table {
+            serialize_class_table(&mut s, entries).unwrap();
+        } else {
+            serialize_lookup(&mut s, entries, false).unwrap();
+        }
+        s.end_serialize();
+        let data = s.copy_bytes();
+        let lookup = Lookup::read(FontData::new(&data)).unwrap();
+        let format = lookup.format();
+        let mut read = lookup_entries::<u16>(&lookup, 0xFFFF).unwrap();
+        if class_table {
+            read.retain(|(_, class)| *class != CLASS_OUT_OF_BOUNDS as u32);
+        }
+        (format, read)
+    }
+
+    #[test]
+    fn test_serialize_lookup() {
+        // runs of the same value are segments
+        let runs: BTreeMap<u16, u32> = (10..20).map(|gid| (gid, 4)).chain([(30, 5)]).collect();
+        assert_eq!(
+            round_trip(&runs, false),
+            (2, runs.clone().into_iter().collect())
+        );
+
+        // different values are single entries
+        let singles: BTreeMap<u16, u32> = [(3, 7), (4, 8), (100, 9)].into();
+        assert_eq!(
+            round_trip(&singles, false),
+            (6, singles.clone().into_iter().collect())
+        );
+
+        // dense classes are a trimmed array, gaps are out of bounds
+        let classes: BTreeMap<u16, u32> = [(5, 4), (6, 5), (8, 6), (9, 4)].into();
+        assert_eq!(
+            round_trip(&classes, true),
+            (8, classes.clone().into_iter().collect())
+        );
+    }
+}
@@ -0,0 +1,149 @@
+//! impl subset() for ankr
+
+use std::collections::BTreeMap;
+
+use crate::aat::serialize_lookup;
+use crate::serialize::{SerializeErrorFlags, Serializer};
+use crate::{Plan, Subset, SubsetError, ANKR};
+use fontcull_write_fonts::{
+    read::{tables::ankr::Ankr, FontRef},
+    FontBuilder,
+};
+
+// numPoints, then an (x, y) pair for each anchor point
+const GLYPH_DATA_HEADER_LEN: usize = 4;
+const ANCHOR_POINT_LEN: usize = 4;
+
+// reference: the ankr table in the Apple spec
+// <https://developer.apple.com/fonts/TrueType-Reference-Manual/RM06/Chap6ankr.html>
+impl Subset for Ankr<'_> {
+    fn subset(
+        &self,
+        plan: &Plan,
+        _font: &FontRef,
+        s: &mut Serializer,
+        _builder: &mut FontBuilder,
+    ) -> Result<(), SubsetError> {
+        let lookup = self
+            .lookup_table()
+            .map_err(|_| SubsetError::SubsetTableError(ANKR))?;
+        let glyph_data = self
+            .offset_data()
+            .split_off(self.glyph_data_table_offset() as usize)
+            .ok_or(SubsetError::SubsetTableError(ANKR))?;
+
+        // glyphs can share anchor points, so each entry is copied once
+        let mut new_offsets: BTreeMap<u16, u16> = BTreeMap::new();
+        let mut offsets = BTreeMap::new();
+        let mut entries = Vec::new();
+        for (new_gid, old_gid) in &plan.new_to_old_gid_list {
+            let Ok(old_offset) = lookup.value(old_gid.to_u32() as u16) else {
+                continue;
+            };
+            let new_offset = match new_offsets.get(&old_offset) {
+                Some(new_offset) => *new_offset,
+                None => {
+                    let Ok(num_points) = glyph_data.read_at::<u32>(old_offset as usize) else {
+                        continue;
+                    };
+                    let start = old_offset as usize;
+                    let end =
+                        start + GLYPH_DATA_HEADER_LEN + num_points as usize * ANCHOR_POINT_LEN;
+                    let Some(entry) = glyph_data.slice(start..end) else {
+                        continue;
+                    };
+                    let new_offset = u16::try_from(entries.len())
+                        .map_err(|_| SubsetError::SubsetTableError(ANKR))?;
+                    entries.extend_from_slice(entry.as_bytes());
+                    new_offsets.insert(old_offset, new_offset);
+                    new_offset
+                }
+            };
+            offsets.insert(new_gid.to_u32() as u16, new_offset as u32);
+        }
+        if offsets.is_empty() {
+            return Err(SubsetError::SubsetTableError(ANKR));
+        }
+
+        serialize_ankr(s, self.version(), self.flags(), &offsets, &entries)
+            .map_err(|_| SubsetError::SubsetTableError(ANKR))
+    }
+}
+
+fn serialize_ankr(
+    s: &mut Serializer,
+    version: u16,
+    flags: u16,
+    offsets: &BTreeMap<u16, u32>,
+    entries: &[u8],
+) -> Result<(), SerializeErrorFlags> {
+    let start = s.head();
+    s.embed(version)?;
+    s.embed(flags)?;
+    let lookup_offset_pos = s.embed(0_u32)?;
+    let glyph_data_offset_pos = s.embed(0_u32)?;
+    s.copy_assign(lookup_offset_pos, (s.head() - start) as u32);
+    serialize_lookup(s, offsets, false)?;
+    // glyph data entries start with a 32-bit count
+    s.pad((4 - (s.head() - start) % 4) % 4)?;
+    s.copy_assign(glyph_data_offset_pos, (s.head() - start) as u32);
+    s.embed_bytes(entries)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fontcull_font_test_data::bebuffer::BeBuffer;
+    use fontcull_write_fonts::{
+        read::{FontData, FontRead},
+        types::GlyphId,
+    };
+
+    #[test]
+    fn test_subset_ankr() {
+        #[rustfmt::skip]
+        let buf = BeBuffer::new()
+            .extend([0_u16, 0]) // version, flags
+            .extend([12_u32, 26]) // lookup and glyph data offsets
+            .extend([0_u16, 0, 8, 0, 16, 8, 0_u16]) // format 0: glyphs 0-5
+            .extend([1_u32]).extend([-20_i16, 20]) // offset 0
+            .extend([1_u32]).extend([42_i16, -10]) // offset 8
+            .extend([2_u32]).extend([0_i16, 4, 64, -64]); // offset 16
+        let ankr = Ankr::read(FontData::new(buf.data())).unwrap();
+
+        // old 3 -> new 1, old 4 -> new 2, old 5 -> new 3
+        let mut plan = Plan::default();
+        for (new_gid, old_gid) in [(0, 0), (1, 3), (2, 4), (3, 5)] {
+            let (new_gid, old_gid) = (GlyphId::new(new_gid), GlyphId::new(old_gid));
+            plan.glyph_map.insert(old_gid, new_gid);
+            plan.new_to_old_gid_list.push((new_gid, old_gid));
+        }
+
+        let mut s = Serializer::new(1024);
+        assert_eq!(s.start_serialize(), Ok(()));
+        let font = FontRef::new(fontcull_font_test_data::GLYF_COMPONENTS).unwrap();
+        let mut builder = FontBuilder::default();
+        ankr.subset(&plan, &font, &mut s, &mut builder).unwrap();
+        s.end_serialize();
+        let subsetted = s.copy_bytes();
+        let subsetted = Ankr::read(FontData::new(&subsetted)).unwrap();
+
+        let points = |gid| {
+            subsetted
+                .anchor_points(GlyphId::new(gid))
+                .unwrap()
+                .iter()
+                .map(|point| (point.x(), point.y()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(points(0), [(-20, 20)]);
+        assert_eq!(points(1), [(0, 4), (64, -64)]);
+        assert_eq!(points(2), [(42, -10)]);
+        assert_eq!(points(3), [(-20, 20)]);
+        // the entry glyphs 0 and 5 share is written once
+        let glyph_data_len =
+            subsetted.offset_data().len() - subsetted.glyph_data_table_offset() as usize;
+        assert_eq!(glyph_data_len, 8 + 12 + 8);
+    }
+}
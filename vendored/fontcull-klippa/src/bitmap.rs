@@ -0,0 +1,861 @@
+//! impl subset() for the bitmap location tables shared by CBLC and EBLC
+use std::ops::Range;
+
+use crate::{
+    serialize::{OffsetWhence, SerializeErrorFlags, Serializer},
+    Plan, SubsetTable,
+};
+use fontcull_write_fonts::{
+    read::{
+        collections::IntSet,
+        tables::bitmap::{
+            BigGlyphMetrics, BitmapSize, IndexSubtable, IndexSubtable1, IndexSubtable3,
+            IndexSubtable4, IndexSubtableList, IndexSubtableRecord,
+        },
+        FontData,
+    },
+    types::{FixedSize, GlyphId, Offset32},
+};
+
+// indexFormat, imageFormat, imageDataOffset and imageSize precede the
+// bigMetrics of index subtable formats 2 and 5
+const BIG_METRICS_OFFSET: usize = 12;
+
+/// Subsets the bitmap size records of a CBLC or EBLC table, starting at its
+/// numSizes field, and appends the retained glyph images to `data_out`.
+///
+/// `location_data` and `image_data` are the offset data of the source
+/// location (CBLC/EBLC) and image data (CBDT/EBDT) tables.
+pub(crate) fn subset_bitmap_sizes(
+    plan: &Plan,
+    s: &mut Serializer,
+    location_data: FontData,
+    bitmap_sizes: &[BitmapSize],
+    bitmap_sizes_bytes: &[u8],
+    image_data: FontData,
+    data_out: &mut Vec<u8>,
+) -> Result<(), SerializeErrorFlags> {
+    let mut num_sizes: u32 = 0;
+    let num_sizes_pos = s.embed(num_sizes)?;
+
+    for (idx, bitmap_size_table) in bitmap_sizes.iter().enumerate() {
+        let start = idx * BitmapSize::RAW_BYTE_LEN;
+        let Some(src_bytes) = bitmap_sizes_bytes.get(start..start + BitmapSize::RAW_BYTE_LEN)
+        else {
+            return Err(s.set_err(SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR));
+        };
+        match bitmap_size_table.subset(plan, s, (location_data, image_data, src_bytes, data_out)) {
+            Ok(()) => num_sizes += 1,
+            Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    s.copy_assign(num_sizes_pos, num_sizes);
+    Ok(())
+}
+
+impl<'a> SubsetTable<'a> for BitmapSize {
+    // (CBLC/EBLC offset data, CBDT/EBDT offset data, src_bitmapsize_bytes, data_out)
+    type ArgsForSubset = (FontData<'a>, FontData<'a>, &'a [u8], &'a mut Vec<u8>);
+    type Output = ();
+    fn subset(
+        &self,
+        plan: &Plan,
+        s: &mut Serializer,
+        args: Self::ArgsForSubset,
+    ) -> Result<(), SerializeErrorFlags> {
+        let (location_data, image_data, src_bytes, data_out) = args;
+
+        if self.start_glyph_index() > plan.glyphset.last().unwrap()
+            || self.end_glyph_index() < plan.glyphset.first().unwrap()
+        {
+            return Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY);
+        }
+
+        let snap = s.snapshot();
+        let offset_pos = s.embed_bytes(src_bytes)?;
+        let Ok(index_subtable_list) = self.index_subtable_list(location_data) else {
+            return Err(s.set_err(SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR));
+        };
+
+        s.push()?;
+        match index_subtable_list.subset(plan, s, (image_data, data_out)) {
+            Ok((min_gid, max_gid, table_list_size, num_subtables)) => {
+                let Some(obj_idx) = s.pop_pack(true) else {
+                    return Err(s.error());
+                };
+                let _ = s.add_link(
+                    offset_pos..offset_pos + Offset32::RAW_BYTE_LEN,
+                    obj_idx,
+                    OffsetWhence::Head,
+                    0,
+                    false,
+                );
+
+                //update table list size, byte pos = 4
+                s.copy_assign(offset_pos + 4, table_list_size as u32);
+                // update number of index subtable, byte_pos = 8
+                s.copy_assign(offset_pos + 8, num_subtables as u32);
+
+                // startGlyphIndex, byte_pos = 40
+                s.copy_assign(offset_pos + 40, min_gid.to_u32() as u16);
+
+                // endGlyphIndex, byte_pos = 42
+                s.copy_assign(offset_pos + 42, max_gid.to_u32() as u16);
+            }
+            Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY) => {
+                s.pop_discard();
+                s.revert_snapshot(snap);
+                return Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY);
+            }
+            Err(e) => {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> SubsetTable<'a> for IndexSubtableList<'a> {
+    // (CBDT/EBDT offset data, data_out)
+    type ArgsForSubset = (FontData<'a>, &'a mut Vec<u8>);
+    // min_gid(new), max_gid(new), indexSubtableListSize, numberOfIndexSubtables
+    type Output = (GlyphId, GlyphId, usize, usize);
+
+    fn subset(
+        &self,
+        plan: &Plan,
+        s: &mut Serializer,
+        args: Self::ArgsForSubset,
+    ) -> Result<Self::Output, SerializeErrorFlags> {
+        let records = self.index_subtable_records();
+        let src_num_records = records.len();
+
+        let mut obj_idxes = Vec::with_capacity(src_num_records);
+        let mut table_list_size = 0;
+        let init_len = s.length();
+        // serialize subtables in reverse order
+        for idx in 0..src_num_records {
+            let record = records[src_num_records - 1 - idx];
+            let Ok(subtable) = record.index_subtable(self.offset_data()) else {
+                return Err(s.set_err(SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR));
+            };
+            s.push()?;
+            match subset_index_subtable(&subtable, plan, s, &record, args.0, args.1) {
+                Ok((start_gid, end_gid, table_size)) => {
+                    let Some(obj_idx) = s.pop_pack(true) else {
+                        return Err(s.error());
+                    };
+                    obj_idxes.push((idx, obj_idx, start_gid, end_gid));
+                    table_list_size += table_size;
+                }
+                Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY) => {
+                    s.pop_discard();
+                    continue;
+                }
+                Err(e) => {
+                    return Err(e);
+                }
+            }
+        }
+
+        if obj_idxes.is_empty() {
+            return Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY);
+        }
+
+        let mut min_start_gid = GlyphId::from(u16::MAX);
+        let mut max_end_gid = GlyphId::NOTDEF;
+        for (record_idx, objidx, start_gid, end_gid) in obj_idxes.iter().rev() {
+            let record = records[*record_idx];
+            record.subset(plan, s, (*objidx, *start_gid, *end_gid))?;
+
+            min_start_gid = min_start_gid.min(*start_gid);
+            max_end_gid = max_end_gid.max(*end_gid);
+        }
+
+        table_list_size += s.length() - init_len;
+        Ok((min_start_gid, max_end_gid, table_list_size, obj_idxes.len()))
+    }
+}
+
+impl SubsetTable<'_> for IndexSubtableRecord {
+    // (obj_idx, first_glyph_id(new), last_glyph_id(new))
+    type ArgsForSubset = (usize, GlyphId, GlyphId);
+    type Output = ();
+    fn subset(
+        &self,
+        _plan: &Plan,
+        s: &mut Serializer,
+        args: Self::ArgsForSubset,
+    ) -> Result<(), SerializeErrorFlags> {
+        s.embed(args.1.to_u32() as u16)?;
+        s.embed(args.2.to_u32() as u16)?;
+
+        let offset_pos = s.embed(0_u32)?;
+        let _ = s.add_link(
+            offset_pos..offset_pos + 4,
+            args.0,
+            OffsetWhence::Head,
+            0,
+            false,
+        );
+        Ok(())
+    }
+}
+
+fn subset_index_subtable(
+    table: &IndexSubtable,
+    plan: &Plan,
+    s: &mut Serializer,
+    index_subtable_record: &IndexSubtableRecord,
+    src_data: FontData,
+    data_out: &mut Vec<u8>,
+) -> Result<(GlyphId, GlyphId, usize), SerializeErrorFlags> {
+    match table {
+        IndexSubtable::Format1(item) => {
+            item.subset(plan, s, (index_subtable_record, src_data, data_out))
+        }
+        IndexSubtable::Format2(item) => {
+            let first = index_subtable_record.first_glyph_index().to_u32();
+            let last = index_subtable_record.last_glyph_index().to_u32();
+            subset_constant_size_subtable(
+                table,
+                plan,
+                s,
+                item.image_size(),
+                (first..=last).map(GlyphId::new),
+                src_data,
+                data_out,
+            )
+        }
+        IndexSubtable::Format3(item) => {
+            item.subset(plan, s, (index_subtable_record, src_data, data_out))
+        }
+        IndexSubtable::Format4(item) => {
+            item.subset(plan, s, (index_subtable_record, src_data, data_out))
+        }
+        IndexSubtable::Format5(item) => subset_constant_size_subtable(
+            table,
+            plan,
+            s,
+            item.image_size(),
+            item.glyph_array()
+                .iter()
+                .map(|gid| GlyphId::from(gid.get())),
+            src_data,
+            data_out,
+        ),
+    }
+}
+
+/// Copies the image data of a glyph into `data_out`.
+///
+/// EBDT image formats 8 and 9 are composites of other bitmap glyphs, so the
+/// component glyph ids are remapped on the way. Returns false if the source
+/// data can't be read.
+fn copy_glyph_data(
+    plan: &Plan,
+    image_format: u16,
+    src_data: FontData,
+    range: Range<usize>,
+    data_out: &mut Vec<u8>,
+) -> bool {
+    let Some(glyph_data) = src_data.as_bytes().get(range) else {
+        return false;
+    };
+    let start = data_out.len();
+    data_out.extend_from_slice(glyph_data);
+
+    // numComponents follows the small metrics and a pad byte in format 8, and
+    // the big metrics in format 9
+    let num_components_pos = match image_format {
+        8 => 6,
+        9 => 8,
+        _ => return true,
+    };
+    let Some(num_components) = glyph_data
+        .get(num_components_pos..num_components_pos + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+    else {
+        return false;
+    };
+    for idx in 0..num_components as usize {
+        // each component starts with its glyph id
+        let pos = start + num_components_pos + 2 + idx * 4;
+        let Some(bytes) = data_out.get_mut(pos..pos + 2) else {
+            return false;
+        };
+        let old_gid = GlyphId::from(u16::from_be_bytes([bytes[0], bytes[1]]));
+        let new_gid = plan
+            .glyph_map
+            .get(&old_gid)
+            .copied()
+            .unwrap_or(GlyphId::NOTDEF);
+        bytes.copy_from_slice(&(new_gid.to_u32() as u16).to_be_bytes());
+    }
+    true
+}
+
+impl<'a> SubsetTable<'a> for IndexSubtable1<'a> {
+    // (src record, src image data, data_out)
+    type ArgsForSubset = (&'a IndexSubtableRecord, FontData<'a>, &'a mut Vec<u8>);
+
+    // output:(first_gid, end_gid, size of subsetted table)
+    type Output = (GlyphId, GlyphId, usize);
+
+    fn subset(
+        &self,
+        plan: &Plan,
+        s: &mut Serializer,
+        args: Self::ArgsForSubset,
+    ) -> Result<(GlyphId, GlyphId, usize), SerializeErrorFlags> {
+        let src_record = args.0;
+        let src_gid_min = src_record.first_glyph_index();
+        let src_gid_max = src_record.last_glyph_index();
+
+        let mut retained_glyphs = IntSet::empty();
+        retained_glyphs.insert_range(GlyphId::from(src_gid_min)..=GlyphId::from(src_gid_max));
+        retained_glyphs.intersect(&plan.glyphset);
+
+        if retained_glyphs.is_empty() {
+            return Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY);
+        }
+
+        let src_offsets = self.sbit_offsets();
+        let src_gid_min = src_gid_min.to_u32() as usize;
+        // find the last glyph that has image_data
+        let mut end_glyph = None;
+        for gid in retained_glyphs.iter().rev() {
+            let idx = gid.to_u32() as usize - src_gid_min;
+            let offset_start = src_offsets[idx].get();
+            let offset_end = src_offsets[idx + 1].get();
+
+            if offset_end > offset_start {
+                end_glyph = Some(gid);
+                break;
+            }
+        }
+
+        if end_glyph.is_none() {
+            return Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY);
+        }
+
+        let init_len = s.length();
+        s.embed(self.index_format())?;
+        s.embed(self.image_format())?;
+
+        let data_out = args.2;
+        let image_offset = data_out.len() as u32;
+        s.embed(image_offset)?;
+
+        let mut start_glyph = None;
+        let src_data = args.1;
+
+        let mut cur_offset = 0_u32;
+        let mut last = 0;
+
+        for (new_gid, old_gid) in plan
+            .new_to_old_gid_list
+            .iter()
+            .filter(|&(_, g)| retained_glyphs.contains(*g))
+        {
+            let idx = old_gid.to_u32() as usize - src_gid_min;
+            let offset_start = src_offsets[idx].get();
+            let offset_end = src_offsets[idx + 1].get();
+
+            // for retain-gids
+            if start_glyph.is_some() {
+                while last < new_gid.to_u32() {
+                    s.embed(cur_offset)?;
+                    last += 1;
+                }
+            }
+
+            if offset_end <= offset_start {
+                if start_glyph.is_none() {
+                    continue;
+                } else {
+                    // add skip glyph that has no image data
+                    s.embed(cur_offset)?;
+                }
+            } else {
+                if start_glyph.is_none() {
+                    start_glyph = Some(*new_gid);
+                    last = new_gid.to_u32();
+                }
+                //copy glyph image data into data_out
+                let src_glyph_offset = (self.image_data_offset() + offset_start) as usize;
+                let len = offset_end - offset_start;
+                if !copy_glyph_data(
+                    plan,
+                    self.image_format(),
+                    src_data,
+                    src_glyph_offset..src_glyph_offset + len as usize,
+                    data_out,
+                ) {
+                    return Err(s.set_err(SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR));
+                }
+                // add offset for glyph
+                s.embed(cur_offset)?;
+                cur_offset += len;
+            }
+            // Skip over gid
+            last += 1;
+
+            if *old_gid == end_glyph.unwrap() {
+                end_glyph = Some(*new_gid);
+                s.embed(cur_offset)?;
+                break;
+            }
+        }
+
+        let len = s.length() - init_len;
+        Ok((start_glyph.unwrap(), end_glyph.unwrap(), len))
+    }
+}
+
+impl<'a> SubsetTable<'a> for IndexSubtable3<'a> {
+    // (src record, src image data, data_out)
+    type ArgsForSubset = (&'a IndexSubtableRecord, FontData<'a>, &'a mut Vec<u8>);
+
+    // output:(first_gid, end_gid, size of subsetted table)
+    type Output = (GlyphId, GlyphId, usize);
+    fn subset(
+        &self,
+        plan: &Plan,
+        s: &mut Serializer,
+        args: Self::ArgsForSubset,
+    ) -> Result<(GlyphId, GlyphId, usize), SerializeErrorFlags> {
+        let src_record = args.0;
+        let src_gid_min = src_record.first_glyph_index();
+        let src_gid_max = src_record.last_glyph_index();
+
+        let mut retained_glyphs = IntSet::empty();
+        retained_glyphs.insert_range(GlyphId::from(src_gid_min)..=GlyphId::from(src_gid_max));
+        retained_glyphs.intersect(&plan.glyphset);
+
+        if retained_glyphs.is_empty() {
+            return Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY);
+        }
+
+        let src_offsets = self.sbit_offsets();
+        let src_gid_min = src_gid_min.to_u32() as usize;
+        // find the last glyph that has image_data
+        let mut end_glyph = None;
+        for gid in retained_glyphs.iter().rev() {
+            let idx = gid.to_u32() as usize - src_gid_min;
+            let offset_start = src_offsets[idx].get();
+            let offset_end = src_offsets[idx + 1].get();
+
+            if offset_end > offset_start {
+                end_glyph = Some(gid);
+                break;
+            }
+        }
+
+        if end_glyph.is_none() {
+            return Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY);
+        }
+
+        let init_len = s.length();
+        s.embed(self.index_format())?;
+        s.embed(self.image_format())?;
+
+        let data_out = args.2;
+        let image_offset = data_out.len() as u32;
+        s.embed(image_offset)?;
+
+        let mut start_glyph = None;
+        let src_data = args.1;
+
+        let mut cur_offset = 0_u16;
+        let mut num_offsets: u32 = 0;
+        let mut last = 0;
+
+        for (new_gid, old_gid) in plan
+            .new_to_old_gid_list
+            .iter()
+            .filter(|&(_, g)| retained_glyphs.contains(*g))
+        {
+            let idx = old_gid.to_u32() as usize - src_gid_min;
+            let offset_start = src_offsets[idx].get();
+            let offset_end = src_offsets[idx + 1].get();
+
+            // for retain-gids
+            if start_glyph.is_some() {
+                while last < new_gid.to_u32() {
+                    s.embed(cur_offset)?;
+                    num_offsets += 1;
+                    last += 1;
+                }
+            }
+
+            if offset_end <= offset_start {
+                if start_glyph.is_none() {
+                    continue;
+                } else {
+                    // add skip glyph that has no image data
+                    s.embed(cur_offset)?;
+                    num_offsets += 1;
+                }
+            } else {
+                if start_glyph.is_none() {
+                    start_glyph = Some(*new_gid);
+                    last = new_gid.to_u32();
+                }
+                //copy glyph image data into data_out
+                let src_glyph_offset = self.image_data_offset() as usize + offset_start as usize;
+                let len = offset_end - offset_start;
+                if !copy_glyph_data(
+                    plan,
+                    self.image_format(),
+                    src_data,
+                    src_glyph_offset..src_glyph_offset + len as usize,
+                    data_out,
+                ) {
+                    return Err(s.set_err(SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR));
+                }
+                // add offset for glyph
+                s.embed(cur_offset)?;
+                cur_offset += len;
+                num_offsets += 1;
+            }
+
+            // Skip over gid
+            last += 1;
+
+            if *old_gid == end_glyph.unwrap() {
+                end_glyph = Some(*new_gid);
+                s.embed(cur_offset)?;
+                num_offsets += 1;
+                break;
+            }
+        }
+        //pad for 32-bit alignment if needed
+        if num_offsets % 2 == 1 {
+            s.embed(0_u16)?;
+        }
+
+        let len = s.length() - init_len;
+        Ok((start_glyph.unwrap(), end_glyph.unwrap(), len))
+    }
+}
+
+impl<'a> SubsetTable<'a> for IndexSubtable4<'a> {
+    // (src record, src image data, data_out)
+    type ArgsForSubset = (&'a IndexSubtableRecord, FontData<'a>, &'a mut Vec<u8>);
+
+    // output:(first_gid, end_gid, size of subsetted table)
+    type Output = (GlyphId, GlyphId, usize);
+    fn subset(
+        &self,
+        plan: &Plan,
+        s: &mut Serializer,
+        args: Self::ArgsForSubset,
+    ) -> Result<(GlyphId, GlyphId, usize), SerializeErrorFlags> {
+        let (_, src_data, data_out) = args;
+
+        // (new gid, src offset range), glyphs without image data are left out
+        let mut glyphs = Vec::new();
+        for pair in self.glyph_array().windows(2) {
+            let Some(new_gid) = plan.glyph_map.get(&GlyphId::from(pair[0].glyph_id())) else {
+                continue;
+            };
+            let offset_start = pair[0].sbit_offset();
+            let offset_end = pair[1].sbit_offset();
+            if offset_end > offset_start {
+                glyphs.push((*new_gid, offset_start, offset_end));
+            }
+        }
+
+        let (Some(first), Some(last)) = (glyphs.first(), glyphs.last()) else {
+            return Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY);
+        };
+        let (start_glyph, end_glyph) = (first.0, last.0);
+
+        let init_len = s.length();
+        s.embed(self.index_format())?;
+        s.embed(self.image_format())?;
+        s.embed(data_out.len() as u32)?;
+        s.embed(glyphs.len() as u32)?;
+
+        // the retained images are a subset of the source images, so the
+        // offsets stay within 16 bits
+        let mut cur_offset = 0_u16;
+        for (new_gid, offset_start, offset_end) in glyphs {
+            s.embed(new_gid.to_u32() as u16)?;
+            s.embed(cur_offset)?;
+
+            let src_glyph_offset = self.image_data_offset() as usize + offset_start as usize;
+            let len = offset_end - offset_start;
+            if !copy_glyph_data(
+                plan,
+                self.image_format(),
+                src_data,
+                src_glyph_offset..src_glyph_offset + len as usize,
+                data_out,
+            ) {
+                return Err(s.set_err(SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR));
+            }
+            cur_offset += len;
+        }
+        // the extra pair holds the end offset of the last glyph
+        s.embed(0_u16)?;
+        s.embed(cur_offset)?;
+
+        let len = s.length() - init_len;
+        Ok((start_glyph, end_glyph, len))
+    }
+}
+
+// Formats 2 and 5 share a single image size and metrics for all glyphs. The
+// retained glyphs are written as format 2 when their new glyph ids are
+// consecutive, and as format 5 otherwise.
+fn subset_constant_size_subtable(
+    table: &IndexSubtable,
+    plan: &Plan,
+    s: &mut Serializer,
+    image_size: u32,
+    src_gids: impl Iterator<Item = GlyphId>,
+    src_data: FontData,
+    data_out: &mut Vec<u8>,
+) -> Result<(GlyphId, GlyphId, usize), SerializeErrorFlags> {
+    // (new gid, index of the image in the src subtable)
+    let glyphs: Vec<(GlyphId, usize)> = src_gids
+        .enumerate()
+        .filter_map(|(idx, old_gid)| plan.glyph_map.get(&old_gid).map(|new_gid| (*new_gid, idx)))
+        .collect();
+
+    let (Some(first), Some(last)) = (glyphs.first(), glyphs.last()) else {
+        return Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY);
+    };
+    let (start_glyph, end_glyph) = (first.0, last.0);
+    let consecutive = end_glyph.to_u32() - start_glyph.to_u32() + 1 == glyphs.len() as u32;
+
+    let Some(big_metrics) = table
+        .offset_data()
+        .as_bytes()
+        .get(BIG_METRICS_OFFSET..BIG_METRICS_OFFSET + BigGlyphMetrics::RAW_BYTE_LEN)
+    else {
+        return Err(s.set_err(SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR));
+    };
+
+    let init_len = s.length();
+    s.embed(if consecutive { 2_u16 } else { 5_u16 })?;
+    s.embed(table.image_format())?;
+    s.embed(data_out.len() as u32)?;
+    s.embed(image_size)?;
+    s.embed_bytes(big_metrics)?;
+
+    if !consecutive {
+        s.embed(glyphs.len() as u32)?;
+        for (new_gid, _) in &glyphs {
+            s.embed(new_gid.to_u32() as u16)?;
+        }
+        //pad for 32-bit alignment if needed
+        if glyphs.len() % 2 == 1 {
+            s.embed(0_u16)?;
+        }
+    }
+
+    let image_size = image_size as usize;
+    for (_, idx) in glyphs {
+        let src_glyph_offset = table.image_data_offset() as usize + idx * image_size;
+        if !copy_glyph_data(
+            plan,
+            table.image_format(),
+            src_data,
+            src_glyph_offset..src_glyph_offset + image_size,
+            data_out,
+        ) {
+            return Err(s.set_err(SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR));
+        }
+    }
+
+    let len = s.length() - init_len;
+    Ok((start_glyph, end_glyph, len))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fontcull_font_test_data::bebuffer::BeBuffer;
+    use fontcull_write_fonts::read::tables::bitmap::IndexSubtable5;
+    use fontcull_write_fonts::read::FontRead;
+
+    fn plan_with_map(map: &[(u32, u32)]) -> Plan {
+        let mut plan = Plan::default();
+        for (old_gid, new_gid) in map {
+            let (old_gid, new_gid) = (GlyphId::new(*old_gid), GlyphId::new(*new_gid));
+            plan.glyphset.insert(old_gid);
+            plan.glyph_map.insert(old_gid, new_gid);
+            plan.new_to_old_gid_list.push((new_gid, old_gid));
+        }
+        plan
+    }
+
+    // subsets the only subtable of an IndexSubtableList, returns the subsetted
+    // subtable bytes and the retained image data
+    fn subset_list(
+        plan: &Plan,
+        list: &BeBuffer,
+        image_data: &[u8],
+    ) -> Result<(GlyphId, GlyphId, Vec<u8>, Vec<u8>), SerializeErrorFlags> {
+        let list = IndexSubtableList::read(FontData::new(list.data()), 1).unwrap();
+        let record = list.index_subtable_records()[0];
+        let subtable = record.index_subtable(list.offset_data()).unwrap();
+
+        let mut s = Serializer::new(1024);
+        assert_eq!(s.start_serialize(), Ok(()));
+        let mut data_out = Vec::new();
+        let (first, last, len) = subset_index_subtable(
+            &subtable,
+            plan,
+            &mut s,
+            &record,
+            FontData::new(image_data),
+            &mut data_out,
+        )?;
+        s.end_serialize();
+        let out = s.copy_bytes();
+        assert_eq!(out.len(), len);
+        Ok((first, last, out, data_out))
+    }
+
+    #[test]
+    fn test_subset_index_format2() {
+        #[rustfmt::skip]
+        let list = BeBuffer::new()
+            .extend([10_u16, 13]).extend([8_u32]) // record: glyphs 10-13
+            .extend([2_u16, 5]).extend([4_u32, 2]) // format, image format, offset, size
+            .extend([1_u8, 2, 3, 4, 5, 6, 7, 8]); // big metrics
+        let image_data = [
+            0_u8, 0, 0, 0, 0xA0, 0xA1, 0xB0, 0xB1, 0xC0, 0xC1, 0xD0, 0xD1,
+        ];
+
+        // consecutive new glyph ids stay format 2
+        let plan = plan_with_map(&[(0, 0), (12, 1), (13, 2)]);
+        let (first, last, out, data_out) = subset_list(&plan, &list, &image_data).unwrap();
+        assert_eq!((first.to_u32(), last.to_u32()), (1, 2));
+        #[rustfmt::skip]
+        let expected = BeBuffer::new()
+            .extend([2_u16, 5]).extend([0_u32, 2])
+            .extend([1_u8, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(out, expected.data());
+        assert_eq!(data_out, [0xC0, 0xC1, 0xD0, 0xD1]);
+
+        // a gap in the new glyph ids needs format 5
+        let plan = plan_with_map(&[(0, 0), (10, 10), (12, 12)]);
+        let (first, last, out, data_out) = subset_list(&plan, &list, &image_data).unwrap();
+        assert_eq!((first.to_u32(), last.to_u32()), (10, 12));
+        let subtable = IndexSubtable5::read(FontData::new(&out)).unwrap();
+        assert_eq!(subtable.image_size(), 2);
+        assert_eq!(subtable.big_metrics()[0].vert_advance(), 8);
+        let glyphs: Vec<_> = subtable
+            .glyph_array()
+            .iter()
+            .map(|gid| gid.get().to_u32())
+            .collect();
+        assert_eq!(glyphs, [10, 12]);
+        assert_eq!(data_out, [0xA0, 0xA1, 0xC0, 0xC1]);
+
+        let plan = plan_with_map(&[(0, 0)]);
+        assert_eq!(
+            subset_list(&plan, &list, &image_data).unwrap_err(),
+            SerializeErrorFlags::SERIALIZE_ERROR_EMPTY
+        );
+    }
+
+    #[test]
+    fn test_subset_index_format4() {
+        #[rustfmt::skip]
+        let list = BeBuffer::new()
+            .extend([3_u16, 9]).extend([8_u32]) // record: glyphs 3-9
+            .extend([4_u16, 1]).extend([2_u32, 3]) // format, image format, offset, numGlyphs
+            .extend([3_u16, 0, 7, 3, 9, 3, 0, 5]); // glyph 7 has no image data
+        let image_data = [0_u8, 0, 0x30, 0x31, 0x32, 0x90, 0x91];
+
+        let plan = plan_with_map(&[(0, 0), (3, 1), (7, 2), (9, 3)]);
+        let (first, last, out, data_out) = subset_list(&plan, &list, &image_data).unwrap();
+        assert_eq!((first.to_u32(), last.to_u32()), (1, 3));
+        #[rustfmt::skip]
+        let expected = BeBuffer::new()
+            .extend([4_u16, 1]).extend([0_u32, 2])
+            .extend([1_u16, 0, 3, 3, 0, 5]);
+        assert_eq!(out, expected.data());
+        assert_eq!(data_out, [0x30, 0x31, 0x32, 0x90, 0x91]);
+
+        let plan = plan_with_map(&[(0, 0), (9, 1)]);
+        let (first, last, out, data_out) = subset_list(&plan, &list, &image_data).unwrap();
+        assert_eq!((first.to_u32(), last.to_u32()), (1, 1));
+        #[rustfmt::skip]
+        let expected = BeBuffer::new()
+            .extend([4_u16, 1]).extend([0_u32, 1])
+            .extend([1_u16, 0, 0, 2]);
+        assert_eq!(out, expected.data());
+        assert_eq!(data_out, [0x90, 0x91]);
+    }
+
+    #[test]
+    fn test_subset_index_format5() {
+        #[rustfmt::skip]
+        let list = BeBuffer::new()
+            .extend([2_u16, 8]).extend([8_u32]) // record: glyphs 2-8
+            .extend([5_u16, 5]).extend([0_u32, 1]) // format, image format, offset, size
+            .extend([1_u8, 2, 3, 4, 5, 6, 7, 8]) // big metrics
+            .extend([3_u32]).extend([2_u16, 5, 8, 0]); // glyphs, pad
+        let image_data = [0x20_u8, 0x50, 0x80];
+
+        // one glyph left, written as format 2
+        let plan = plan_with_map(&[(0, 0), (5, 1)]);
+        let (first, last, out, data_out) = subset_list(&plan, &list, &image_data).unwrap();
+        assert_eq!((first.to_u32(), last.to_u32()), (1, 1));
+        assert_eq!(out.len(), 20);
+        assert_eq!(out[..2], [0, 2]);
+        assert_eq!(data_out, [0x50]);
+
+        let plan = plan_with_map(&[(0, 0), (2, 2), (5, 5), (8, 8)]);
+        let (first, last, out, data_out) = subset_list(&plan, &list, &image_data).unwrap();
+        assert_eq!((first.to_u32(), last.to_u32()), (2, 8));
+        // 3 glyph ids and a pad
+        assert_eq!(out.len(), 32);
+        assert_eq!(out, list.data()[8..]);
+        assert_eq!(data_out, image_data);
+    }
+
+    #[test]
+    fn test_copy_composite_glyph_data() {
+        #[rustfmt::skip]
+        let glyph = BeBuffer::new()
+            .extend([8_u8, 8, 0, 8, 8, 0]) // small metrics, pad
+            .extend([2_u16]) // numComponents
+            .extend([5_u16]).extend([0_i8, 0])
+            .extend([9_u16]).extend([4_i8, 0]);
+        let plan = plan_with_map(&[(0, 0), (5, 1), (9, 2)]);
+
+        let mut data_out = vec![0xFF];
+        let src_data = FontData::new(glyph.data());
+        assert!(copy_glyph_data(
+            &plan,
+            8,
+            src_data,
+            0..glyph.data().len(),
+            &mut data_out
+        ));
+        assert_eq!(data_out[1..9], glyph.data()[..8]);
+        assert_eq!(data_out[9..], [0, 1, 0, 0, 0, 2, 4, 0]);
+
+        // other image formats are copied as is
+        let mut data_out = Vec::new();
+        assert!(copy_glyph_data(
+            &plan,
+            1,
+            src_data,
+            0..glyph.data().len(),
+            &mut data_out
+        ));
+        assert_eq!(data_out, glyph.data());
+        assert!(!copy_glyph_data(&plan, 1, src_data, 0..100, &mut data_out));
+    }
+}
@@ -0,0 +1,135 @@
+//! impl subset() for EBLC
+use crate::{bitmap::subset_bitmap_sizes, serialize::Serializer, Plan, Subset, SubsetError};
+use fontcull_write_fonts::{
+    read::{
+        collections::IntSet,
+        tables::{
+            bitmap::{BitmapContent, BitmapData},
+            ebdt::Ebdt,
+            eblc::Eblc,
+        },
+        FontRef, TableProvider, TopLevelTable,
+    },
+    types::GlyphId,
+    FontBuilder,
+};
+
+// EBLC shares its layout with CBLC, see cblc.rs
+impl Subset for Eblc<'_> {
+    fn subset(
+        &self,
+        plan: &Plan,
+        font: &FontRef,
+        s: &mut Serializer,
+        builder: &mut FontBuilder,
+    ) -> Result<(), SubsetError> {
+        let ebdt = font
+            .ebdt()
+            .or(Err(SubsetError::SubsetTableError(Ebdt::TAG)))?;
+
+        s.embed(self.major_version())
+            .map_err(|_| SubsetError::SubsetTableError(Eblc::TAG))?;
+        s.embed(self.minor_version())
+            .map_err(|_| SubsetError::SubsetTableError(Eblc::TAG))?;
+
+        let bitmapsize_bytes = self
+            .offset_data()
+            .as_bytes()
+            .get(self.shape().bitmap_sizes_byte_range())
+            .ok_or(SubsetError::SubsetTableError(Eblc::TAG))?;
+
+        let mut ebdt_out = Vec::with_capacity(ebdt.offset_data().len());
+        ebdt_out.extend_from_slice(ebdt.min_table_bytes());
+
+        subset_bitmap_sizes(
+            plan,
+            s,
+            self.offset_data(),
+            self.bitmap_sizes(),
+            bitmapsize_bytes,
+            ebdt.offset_data(),
+            &mut ebdt_out,
+        )
+        .map_err(|_| SubsetError::SubsetTableError(Eblc::TAG))?;
+
+        builder.add_raw(Ebdt::TAG, ebdt_out);
+        Ok(())
+    }
+}
+
+/// Adds the components of composite bitmaps (EBDT image formats 8 and 9) in
+/// any strike to `gids`.
+pub(crate) fn closure_glyphs(eblc: &Eblc, ebdt: &Ebdt, gids: &mut IntSet<GlyphId>) {
+    let mut queue: Vec<GlyphId> = gids.iter().collect();
+    while let Some(gid) = queue.pop() {
+        for bitmap_size in eblc.bitmap_sizes() {
+            let Ok(location) = bitmap_size.location(eblc.offset_data(), gid) else {
+                continue;
+            };
+            let Ok(BitmapData {
+                content: BitmapContent::Composite(components),
+                ..
+            }) = ebdt.data(&location)
+            else {
+                continue;
+            };
+            for component in components {
+                let component_gid = GlyphId::from(component.glyph_id());
+                if gids.insert(component_gid) {
+                    queue.push(component_gid);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fontcull_write_fonts::read::{tables::bitmap::BitmapLocation, FontData, FontRead};
+
+    #[test]
+    fn test_subset_eblc() {
+        let font = FontRef::new(fontcull_font_test_data::EMBEDDED_BITMAPS).unwrap();
+        let eblc = font.eblc().unwrap();
+        let ebdt = font.ebdt().unwrap();
+        let mut builder = FontBuilder::new();
+
+        // old 2 -> new 1 (index format 3), old 3 -> new 2 (index format 2)
+        let mut plan = Plan::default();
+        for (new_gid, old_gid) in [(0_u32, 0_u32), (1, 2), (2, 3)] {
+            let (new_gid, old_gid) = (GlyphId::new(new_gid), GlyphId::new(old_gid));
+            plan.glyphset.insert(old_gid);
+            plan.glyph_map.insert(old_gid, new_gid);
+            plan.new_to_old_gid_list.push((new_gid, old_gid));
+        }
+
+        let mut s = Serializer::new(1024);
+        assert_eq!(s.start_serialize(), Ok(()));
+        eblc.subset(&plan, &font, &mut s, &mut builder).unwrap();
+        s.end_serialize();
+        let eblc_out = s.copy_bytes();
+        let eblc_out = Eblc::read(FontData::new(&eblc_out)).unwrap();
+        let font_out = builder.build();
+        let font_out = FontRef::new(&font_out).unwrap();
+        let ebdt_out = font_out.ebdt().unwrap();
+
+        let image = |eblc: &Eblc, ebdt: &Ebdt, size: usize, gid: u32| {
+            let location: BitmapLocation = eblc.bitmap_sizes()[size]
+                .location(eblc.offset_data(), GlyphId::new(gid))
+                .unwrap();
+            let start = location.data_offset;
+            ebdt.offset_data().as_bytes()[start..start + location.data_size].to_vec()
+        };
+
+        assert_eq!(eblc_out.bitmap_sizes().len(), 2);
+        assert_eq!(image(&eblc_out, &ebdt_out, 0, 1), image(&eblc, &ebdt, 0, 2));
+        assert_eq!(image(&eblc_out, &ebdt_out, 1, 2), image(&eblc, &ebdt, 1, 3));
+        assert!(eblc_out.bitmap_sizes()[0]
+            .location(eblc_out.offset_data(), GlyphId::new(2))
+            .is_err());
+        // the second strike only covers old glyph 3
+        assert_eq!(eblc_out.bitmap_sizes()[1].start_glyph_index().to_u32(), 2);
+        assert_eq!(ebdt_out.major_version(), 2);
+    }
+}
@@ -0,0 +1,19 @@
+//! impl NameIdClosure for feat table
+
+use crate::{NameIdClosure, Plan};
+use fontcull_write_fonts::read::tables::feat::Feat;
+
+// feat has no glyph IDs and is passed through, but the names of its features
+// and settings must survive name table subsetting
+impl NameIdClosure for Feat<'_> {
+    fn collect_name_ids(&self, plan: &mut Plan) {
+        for feature in self.names() {
+            plan.name_ids.insert(feature.name_index());
+            let Ok(settings) = feature.setting_table(self.offset_data()) else {
+                continue;
+            };
+            plan.name_ids
+                .extend_unsorted(settings.settings().iter().map(|x| x.name_index()));
+        }
+    }
+}
@@ -239,7 +239,6 @@ impl<'a> SubsetTable<'a> for PositionLookup<'_> {
     }
 }
 
-// TODO: support extension lookup type
 impl<'a> SubsetTable<'a> for PositionSubtables<'a> {
     type ArgsForSubset = (&'a SubsetState, &'a FontRef<'a>, &'a FnvHashMap<u16, u16>);
     type Output = u16;
@@ -250,14 +249,14 @@ impl<'a> SubsetTable<'a> for PositionSubtables<'a> {
         args: Self::ArgsForSubset,
     ) -> Result<u16, SerializeErrorFlags> {
         match self {
-            PositionSubtables::Single(subtables) => subtables.subset(plan, s, args),
-            PositionSubtables::Pair(subtables) => subtables.subset(plan, s, args),
-            PositionSubtables::Cursive(subtables) => subtables.subset(plan, s, args),
-            PositionSubtables::MarkToBase(subtables) => subtables.subset(plan, s, args),
-            PositionSubtables::MarkToLig(subtables) => subtables.subset(plan, s, args),
-            PositionSubtables::MarkToMark(subtables) => subtables.subset(plan, s, args),
-            PositionSubtables::Contextual(subtables) => subtables.subset(plan, s, args),
-            PositionSubtables::ChainContextual(subtables) => subtables.subset(plan, s, args),
+            PositionSubtables::Single(subtables) => subtables.subset(plan, s, (args, 1)),
+            PositionSubtables::Pair(subtables) => subtables.subset(plan, s, (args, 2)),
+            PositionSubtables::Cursive(subtables) => subtables.subset(plan, s, (args, 3)),
+            PositionSubtables::MarkToBase(subtables) => subtables.subset(plan, s, (args, 4)),
+            PositionSubtables::MarkToLig(subtables) => subtables.subset(plan, s, (args, 5)),
+            PositionSubtables::MarkToMark(subtables) => subtables.subset(plan, s, (args, 6)),
+            PositionSubtables::Contextual(subtables) => subtables.subset(plan, s, (args, 7)),
+            PositionSubtables::ChainContextual(subtables) => subtables.subset(plan, s, (args, 8)),
         }
     }
 }
@@ -282,7 +281,6 @@ impl CollectVariationIndices for Gpos<'_> {
     }
 }
 
-//TODO: support all lookup types
 impl CollectVariationIndices for PositionSubtables<'_> {
     fn collect_variation_indices(&self, plan: &Plan, varidx_set: &mut IntSet<u32>) {
         match self {
@@ -304,7 +302,9 @@ impl CollectVariationIndices for PositionSubtables<'_> {
             PositionSubtables::MarkToMark(subtables) => {
                 subtables.collect_variation_indices(plan, varidx_set)
             }
-            _ => (),
+            // contextual lookups have no device tables of their own, the lookups
+            // they reference are in plan.gpos_lookups and visited separately
+            PositionSubtables::Contextual(_) | PositionSubtables::ChainContextual(_) => (),
         }
     }
 }
@@ -416,4 +416,114 @@ mod test {
         assert_eq!(feature_index_map.get(&3), Some(&3));
         assert_eq!(feature_index_map.get(&4), Some(&4));
     }
+
+    // Roboto with a kern lookup of two PairPosFormat1 subtables sharing 50 of
+    // their 100 PairSets: the font packs because the shared PairSets are
+    // reachable from both subtables, but each subtable alone reaches more
+    // than 64K of PairSets. The x advance of a pair is its PairSet's index
+    fn font_with_shared_pair_sets() -> Vec<u8> {
+        use fontcull_write_fonts::{
+            tables::{
+                gpos::{PairPos, PairSet, PairValueRecord, PositionLookupList, ValueRecord},
+                layout::{
+                    Feature, FeatureList, FeatureRecord, LangSys, Lookup, Script, ScriptList,
+                    ScriptRecord,
+                },
+            },
+            types::GlyphId16,
+            FontBuilder,
+        };
+
+        let pair_set = |index: u16| {
+            PairSet::new(
+                (0..163)
+                    .map(|second| {
+                        PairValueRecord::new(
+                            GlyphId16::new(second),
+                            ValueRecord::new().with_x_advance(index as i16),
+                            ValueRecord::default(),
+                        )
+                    })
+                    .collect(),
+            )
+        };
+        let pair_pos = |first: u16, pair_sets: std::ops::Range<u16>| {
+            let coverage: Vec<_> = (first..first + 100).map(GlyphId16::new).collect();
+            PairPos::format_1(coverage.into(), pair_sets.map(pair_set).collect())
+        };
+        let gpos = fontcull_write_fonts::tables::gpos::Gpos::new(
+            ScriptList::new(vec![ScriptRecord::new(
+                Tag::new(b"DFLT"),
+                Script::new(Some(LangSys::new(vec![0])), Vec::new()),
+            )]),
+            FeatureList::new(vec![FeatureRecord::new(
+                Tag::new(b"kern"),
+                Feature::new(None, vec![0]),
+            )]),
+            PositionLookupList::new(vec![
+                fontcull_write_fonts::tables::gpos::PositionLookup::Pair(Lookup::new(
+                    LookupFlag::empty(),
+                    vec![pair_pos(0, 0..100), pair_pos(100, 50..150)],
+                )),
+            ]),
+        );
+
+        let font = FontRef::new(include_bytes!("../test-data/fonts/Roboto-Regular.ttf")).unwrap();
+        FontBuilder::new()
+            .add_table(&gpos)
+            .unwrap()
+            .copy_missing_tables(font)
+            .build()
+    }
+
+    #[test]
+    fn test_subset_gpos_splits_large_pair_pos() {
+        use crate::{subset_font, SubsetFlags};
+        use fontcull_write_fonts::{read::tables::gpos::PairPos, types::GlyphId};
+
+        let font_data = font_with_shared_pair_sets();
+        let font = FontRef::new(&font_data).unwrap();
+        let mut gids = IntSet::empty();
+        gids.insert_range(GlyphId::NOTDEF..=GlyphId::new(199));
+        let plan = Plan::new(
+            &gids,
+            &IntSet::empty(),
+            &font,
+            SubsetFlags::default(),
+            &IntSet::empty(),
+            &IntSet::all(),
+            &IntSet::all(),
+            &IntSet::empty(),
+            &IntSet::empty(),
+        );
+        let subset = subset_font(&font, &plan).unwrap();
+        let subset = FontRef::new(&subset).unwrap();
+
+        let gpos = subset.gpos().unwrap();
+        let lookup = gpos.lookup_list().unwrap().lookups().get(0).unwrap();
+        let PositionSubtables::Pair(subtables) = lookup.subtables().unwrap() else {
+            panic!("not a PairPos lookup");
+        };
+        assert!(subtables.len() > 2);
+
+        let mut num_pairs = 0;
+        for subtable in subtables.iter() {
+            let PairPos::Format1(subtable) = subtable.unwrap() else {
+                panic!("not a PairPosFormat1 subtable");
+            };
+            let coverage = subtable.coverage().unwrap();
+            assert_eq!(coverage.iter().count(), subtable.pair_set_count() as usize);
+            for (first, pair_set) in coverage.iter().zip(subtable.pair_sets().iter()) {
+                let first = first.to_u32() as i16;
+                let index = if first < 100 { first } else { first - 50 };
+                for (second, record) in pair_set.unwrap().pair_value_records().iter().enumerate() {
+                    let record = record.unwrap();
+                    assert_eq!(record.second_glyph().to_u32(), second as u32);
+                    assert_eq!(record.value_record1().x_advance(), Some(index));
+                    num_pairs += 1;
+                }
+            }
+        }
+        assert_eq!(num_pairs, 200 * 163);
+    }
 }
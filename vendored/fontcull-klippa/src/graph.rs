@@ -1,6 +1,10 @@
 //! Define a graph struct that represents a serialized table
 //! Implement methods to modify and reorder the graph
 
+mod markbasepos;
+mod pairpos;
+mod split;
+
 use crate::{
     priority_queue::PriorityQueue,
     serialize::{Link, LinkWidth, ObjIdx, Object, OffsetWhence, SerializeErrorFlags, Serializer},
@@ -20,9 +24,7 @@ impl RepackErrorFlags {
     #[allow(dead_code)]
     pub(crate) const GRAPH_ERROR_INVALID_ROOT: Self = Self(0x0010);
     pub(crate) const REPACK_ERROR_SERIALIZE: Self = Self(0x0020);
-    #[allow(dead_code)]
     pub(crate) const REPACK_ERROR_SPLIT_SUBTABLE: Self = Self(0x0040);
-    #[allow(dead_code)]
     pub(crate) const REPACK_ERROR_EXT_PROMOTION: Self = Self(0x0080);
     #[allow(dead_code)]
     pub(crate) const REPACK_ERROR_NO_RESOLUTION: Self = Self(0x0100);
@@ -217,7 +219,6 @@ impl Clone for Vertex {
     }
 }
 
-//TODO: add support for space assignment and splitting
 #[derive(Default, Debug)]
 pub(crate) struct Graph {
     vertices: Vec<Vertex>,
@@ -906,6 +907,187 @@ impl Graph {
         }
     }
 
+    // Returns the lookups in the LookupList of a GSUB/GPOS table, or nothing if
+    // the root doesn't look like a GSUB/GPOS header.
+    fn lookup_indices(&self) -> Vec<ObjIdx> {
+        let root_bytes = self.vertex_bytes(self.root_idx());
+        if root_bytes.len() < 10 || root_bytes[0..2] != [0, 1] {
+            return Vec::new();
+        }
+        // the LookupList offset is at the same position in GSUB and GPOS headers
+        let Some(lookup_list_idx) = self.link_at(self.root_idx(), 8) else {
+            return Vec::new();
+        };
+
+        let mut lookup_idxes: Vec<ObjIdx> = self.vertices[lookup_list_idx]
+            .real_links
+            .values()
+            .map(|l| l.obj_idx())
+            .collect();
+        lookup_idxes.sort_unstable();
+        lookup_idxes.dedup();
+        lookup_idxes
+    }
+
+    fn vertex_bytes(&self, obj_idx: ObjIdx) -> &[u8] {
+        let v = &self.vertices[obj_idx];
+        &self.data[v.head..v.tail]
+    }
+
+    // returns the obj_idx of the object linked at pos of obj_idx
+    fn link_at(&self, obj_idx: ObjIdx, pos: u32) -> Option<ObjIdx> {
+        self.vertices[obj_idx]
+            .real_links
+            .get(&pos)
+            .map(|l| l.obj_idx())
+    }
+
+    // Turns lookups in the LookupList of a GSUB/GPOS table into extension
+    // lookups, one at a time and those with the largest subtables first, until
+    // the graph packs without overflows: each subtable of a promoted lookup is
+    // moved behind an extension subtable with a 32-bit offset, so that it can
+    // be packed in its own space.
+    // Returns false if there was no lookup left to promote.
+    pub(crate) fn promote_extension_lookups(
+        &mut self,
+        extension_type: u16,
+    ) -> Result<bool, RepackErrorFlags> {
+        let mut lookups: Vec<(usize, ObjIdx)> = self
+            .lookup_indices()
+            .into_iter()
+            .map(|lookup_idx| (self.subtable_size(lookup_idx), lookup_idx))
+            .collect();
+        lookups.sort_by_key(|(size, lookup_idx)| (std::cmp::Reverse(*size), *lookup_idx));
+
+        let mut promoted = false;
+        for (_, lookup_idx) in lookups {
+            if !self.promote_lookup(lookup_idx, extension_type)? {
+                continue;
+            }
+            promoted = true;
+
+            // priorities raised while resolving the unpromoted graph would
+            // keep subtables next to their (former) parents
+            for v in self.vertices.iter_mut() {
+                v.priority = 0;
+            }
+            self.parents_invalid = true;
+            self.distance_invalid = true;
+            self.positions_invalid = true;
+
+            self.sort_shortest_distance()?;
+            if self.assign_spaces()? {
+                self.sort_shortest_distance()?;
+            }
+            if !self.has_overflows() {
+                break;
+            }
+        }
+        Ok(promoted)
+    }
+
+    // Average size of the subtables of a lookup, including everything they
+    // link to
+    fn subtable_size(&self, lookup_idx: ObjIdx) -> usize {
+        let subtable_count = self
+            .vertex_bytes(lookup_idx)
+            .get(4..6)
+            .map_or(0, |b| u16::from_be_bytes([b[0], b[1]]) as usize);
+        if subtable_count == 0 {
+            return 0;
+        }
+
+        let mut subgraph = IntSet::empty();
+        self.find_subgraph_nodes(lookup_idx, &mut subgraph);
+        subgraph.remove(lookup_idx as u32);
+        let size: usize = subgraph
+            .iter()
+            .map(|idx| self.vertices[idx as usize].table_size())
+            .sum();
+        size / subtable_count
+    }
+
+    fn promote_lookup(
+        &mut self,
+        lookup_idx: ObjIdx,
+        extension_type: u16,
+    ) -> Result<bool, RepackErrorFlags> {
+        let v = &self.vertices[lookup_idx];
+        let Some(lookup_bytes) = self.data.get(v.head..v.tail).filter(|b| b.len() >= 6) else {
+            return Err(self.set_err(RepackErrorFlags::REPACK_ERROR_EXT_PROMOTION));
+        };
+        let lookup_type = u16::from_be_bytes([lookup_bytes[0], lookup_bytes[1]]);
+        if lookup_type == extension_type {
+            return Ok(false);
+        }
+        let subtable_count = u16::from_be_bytes([lookup_bytes[4], lookup_bytes[5]]) as u32;
+
+        let mut new_bytes = lookup_bytes.to_vec();
+        new_bytes[0..2].copy_from_slice(&extension_type.to_be_bytes());
+        self.replace_vertex_bytes(lookup_idx, &new_bytes);
+
+        for i in 0..subtable_count {
+            let link_pos = 6 + 2 * i;
+            let Some(subtable_idx) = self.link_at(lookup_idx, link_pos) else {
+                continue;
+            };
+
+            let ext_idx = self.add_extension_subtable(lookup_type, subtable_idx);
+            if let Some(link) = self.vertices[lookup_idx].real_links.get_mut(&link_pos) {
+                link.update_obj_idx(ext_idx);
+            }
+        }
+        Ok(true)
+    }
+
+    // Adds an ExtensionSubstFormat1/ExtensionPosFormat1 object pointing to
+    // subtable_idx and returns the new obj_idx.
+    fn add_extension_subtable(&mut self, lookup_type: u16, subtable_idx: ObjIdx) -> ObjIdx {
+        // format 1, extensionLookupType and a 32-bit offset to the subtable
+        let mut bytes = [0_u8; 8];
+        bytes[0..2].copy_from_slice(&1_u16.to_be_bytes());
+        bytes[2..4].copy_from_slice(&lookup_type.to_be_bytes());
+
+        let mut links = FnvHashMap::default();
+        links.insert(
+            4,
+            Link::new(LinkWidth::Four, OffsetWhence::Head, 4, subtable_idx),
+        );
+        self.add_vertex(&bytes, links)
+    }
+
+    // Adds a new object with the given bytes and links, returns the new obj_idx.
+    fn add_vertex(&mut self, bytes: &[u8], real_links: FnvHashMap<u32, Link>) -> ObjIdx {
+        let head = self.data.len();
+        self.data.extend_from_slice(bytes);
+        let v = Vertex {
+            head,
+            tail: self.data.len(),
+            real_links,
+            ..Default::default()
+        };
+
+        let obj_idx = self.vertices.len();
+        self.vertices.push(v);
+        self.ordering.push(obj_idx);
+        obj_idx
+    }
+
+    // Replaces the bytes of obj_idx. New bytes are always appended to the graph
+    // data, the old ones may be shared with a duplicate of this object.
+    fn replace_vertex_bytes(&mut self, obj_idx: ObjIdx, bytes: &[u8]) {
+        let head = self.data.len();
+        self.data.extend_from_slice(bytes);
+        let v = &mut self.vertices[obj_idx];
+        v.head = head;
+        v.tail = self.data.len();
+    }
+
+    fn replace_vertex(&mut self, obj_idx: ObjIdx, bytes: &[u8], real_links: FnvHashMap<u32, Link>) {
+        self.replace_vertex_bytes(obj_idx, bytes);
+        self.vertices[obj_idx].real_links = real_links;
+    }
+
     // Creates a copy of the specified obj and returns the new obj_idx.
     fn duplicate_obj(&mut self, obj_idx: ObjIdx) -> ObjIdx {
         self.positions_invalid = true;
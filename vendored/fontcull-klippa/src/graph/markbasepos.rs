@@ -0,0 +1,193 @@
+//! Split MarkBasePos subtables
+//! ref: <https://github.com/harfbuzz/harfbuzz/blob/main/src/graph/markbasepos-graph.hh>
+
+use super::{
+    split::{coverage_bytes, offset16, read_u16, MAX_TABLE_SIZE},
+    Graph, RepackErrorFlags,
+};
+use crate::serialize::{Link, ObjIdx};
+use fnv::FnvHashMap;
+use fontcull_write_fonts::read::collections::IntSet;
+
+// format, markCoverage, baseCoverage, markClassCount, markArray and baseArray
+const HEADER_LEN: usize = 12;
+const MARK_RECORD_LEN: usize = 4;
+
+impl Graph {
+    // Splits the mark classes into several subtables, each with its own mark
+    // coverage, MarkArray and BaseArray. The base coverage is shared by all of
+    // them.
+    pub(super) fn split_mark_base_pos(
+        &mut self,
+        subtable_idx: ObjIdx,
+    ) -> Result<Vec<ObjIdx>, RepackErrorFlags> {
+        let bytes = self.vertex_bytes(subtable_idx);
+        if bytes.len() < HEADER_LEN || bytes[0..2] != [0, 1] {
+            return Ok(Vec::new());
+        }
+        let class_count = read_u16(bytes, 6) as usize;
+        if class_count < 2 {
+            return Ok(Vec::new());
+        }
+
+        let (
+            Some(mark_coverage_idx),
+            Some(base_coverage_idx),
+            Some(mark_array_idx),
+            Some(base_array_idx),
+        ) = (
+            self.link_at(subtable_idx, 2),
+            self.link_at(subtable_idx, 4),
+            self.link_at(subtable_idx, 8),
+            self.link_at(subtable_idx, 10),
+        )
+        else {
+            return Ok(Vec::new());
+        };
+
+        let mark_glyphs = self.coverage_glyphs(mark_coverage_idx)?;
+        let mark_array = self.vertex_bytes(mark_array_idx);
+        let mark_count = mark_array
+            .get(0..2)
+            .map_or(0, |_| read_u16(mark_array, 0) as usize);
+        if mark_count != mark_glyphs.len() || mark_array.len() < 2 + mark_count * MARK_RECORD_LEN {
+            return Ok(Vec::new());
+        }
+        // (glyph, class, anchor) of each mark in coverage order
+        let mut marks = Vec::with_capacity(mark_count);
+        for (i, glyph) in mark_glyphs.into_iter().enumerate() {
+            let record_pos = 2 + i * MARK_RECORD_LEN;
+            let class = read_u16(mark_array, record_pos);
+            if class as usize >= class_count {
+                return Ok(Vec::new());
+            }
+            let anchor = self.link_at(mark_array_idx, (record_pos + 2) as u32);
+            marks.push((glyph, class, anchor));
+        }
+
+        let base_array = self.vertex_bytes(base_array_idx);
+        let base_count = base_array
+            .get(0..2)
+            .map_or(0, |_| read_u16(base_array, 0) as usize);
+        if base_array.len() < 2 + 2 * base_count * class_count {
+            return Ok(Vec::new());
+        }
+        let base_anchor = |base: usize, class: usize| {
+            self.link_at(
+                base_array_idx,
+                (2 + 2 * (base * class_count + class)) as u32,
+            )
+        };
+
+        // the size of the mark and base records and the anchors of a class
+        let class_size = |class: usize, visited: &mut IntSet<u32>| {
+            let mut size = 0;
+            for (_, _, anchor) in marks.iter().filter(|m| m.1 as usize == class) {
+                size += 2 + MARK_RECORD_LEN + anchor.map_or(0, |a| self.subgraph_size(a, visited));
+            }
+            for base in 0..base_count {
+                size += 2 + base_anchor(base, class).map_or(0, |a| self.subgraph_size(a, visited));
+            }
+            size
+        };
+
+        // header, the mark coverage, MarkArray and BaseArray counts and the base coverage
+        let base_size = HEADER_LEN + 4 + 2 + 2 + self.vertices[base_coverage_idx].table_size();
+        let mut split_points = Vec::new();
+        let mut visited = IntSet::empty();
+        let mut accumulated = base_size;
+        for class in 0..class_count {
+            let accumulated_delta = class_size(class, &mut visited);
+            accumulated += accumulated_delta;
+            let last_split = split_points.last().copied().unwrap_or(0);
+            if accumulated > MAX_TABLE_SIZE && class > last_split {
+                split_points.push(class);
+                // this class didn't fit, it starts the next split
+                visited.clear();
+                accumulated = base_size + class_size(class, &mut visited);
+            }
+        }
+
+        if split_points.is_empty() {
+            return Ok(Vec::new());
+        }
+        split_points.push(class_count);
+
+        let base_anchors: Vec<Vec<Option<ObjIdx>>> = (0..base_count)
+            .map(|base| (0..class_count).map(|c| base_anchor(base, c)).collect())
+            .collect();
+        let header = self.vertex_bytes(subtable_idx)[0..HEADER_LEN].to_vec();
+        let mut new_subtables = Vec::with_capacity(split_points.len() - 1);
+        let mut start = 0;
+        for end in split_points {
+            let split_class_count = end - start;
+            let split_marks: Vec<_> = marks
+                .iter()
+                .filter(|(_, class, _)| (start..end).contains(&(*class as usize)))
+                .collect();
+
+            let split_glyphs: Vec<_> = split_marks.iter().map(|(g, _, _)| *g).collect();
+            let mark_coverage_bytes = coverage_bytes(&split_glyphs)?;
+
+            let mut mark_array_bytes = Vec::with_capacity(2 + split_marks.len() * MARK_RECORD_LEN);
+            mark_array_bytes.extend_from_slice(&(split_marks.len() as u16).to_be_bytes());
+            let mut mark_array_links: FnvHashMap<u32, Link> = FnvHashMap::default();
+            for (i, (_, class, anchor)) in split_marks.iter().enumerate() {
+                mark_array_bytes.extend_from_slice(&(class - start as u16).to_be_bytes());
+                mark_array_bytes.extend_from_slice(&[0, 0]);
+                if let Some(anchor) = anchor {
+                    let pos = 2 + i * MARK_RECORD_LEN + 2;
+                    mark_array_links.extend([offset16(pos as u32, *anchor)]);
+                }
+            }
+
+            let mut base_array_bytes = vec![0; 2 + 2 * base_count * split_class_count];
+            base_array_bytes[0..2].copy_from_slice(&(base_count as u16).to_be_bytes());
+            let mut base_array_links: FnvHashMap<u32, Link> = FnvHashMap::default();
+            for (base, anchors) in base_anchors.iter().enumerate() {
+                for (i, anchor) in anchors[start..end].iter().enumerate() {
+                    if let Some(anchor) = anchor {
+                        let pos = 2 + 2 * (base * split_class_count + i);
+                        base_array_links.extend([offset16(pos as u32, *anchor)]);
+                    }
+                }
+            }
+
+            let (new_mark_coverage_idx, new_mark_array_idx, new_base_array_idx) = if start == 0 {
+                (
+                    self.replace_or_add_vertex(
+                        mark_coverage_idx,
+                        &mark_coverage_bytes,
+                        FnvHashMap::default(),
+                    ),
+                    self.replace_or_add_vertex(mark_array_idx, &mark_array_bytes, mark_array_links),
+                    self.replace_or_add_vertex(base_array_idx, &base_array_bytes, base_array_links),
+                )
+            } else {
+                (
+                    self.add_vertex(&mark_coverage_bytes, FnvHashMap::default()),
+                    self.add_vertex(&mark_array_bytes, mark_array_links),
+                    self.add_vertex(&base_array_bytes, base_array_links),
+                )
+            };
+
+            let mut bytes = header.clone();
+            bytes[6..8].copy_from_slice(&(split_class_count as u16).to_be_bytes());
+            let mut links: FnvHashMap<u32, Link> = FnvHashMap::default();
+            links.extend([
+                offset16(2, new_mark_coverage_idx),
+                offset16(4, base_coverage_idx),
+                offset16(8, new_mark_array_idx),
+                offset16(10, new_base_array_idx),
+            ]);
+
+            if start == 0 {
+                self.replace_vertex(subtable_idx, &bytes, links);
+            } else {
+                new_subtables.push(self.add_vertex(&bytes, links));
+            }
+            start = end;
+        }
+        Ok(new_subtables)
+    }
+}
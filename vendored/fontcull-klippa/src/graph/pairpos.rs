@@ -0,0 +1,198 @@
+//! Split PairPos subtables
+//! ref: <https://github.com/harfbuzz/harfbuzz/blob/main/src/graph/pairpos-graph.hh>
+
+use super::{
+    split::{coverage_bytes, offset16, read_u16, serialize_to_bytes, MAX_TABLE_SIZE},
+    Graph, RepackErrorFlags,
+};
+use crate::serialize::{Link, ObjIdx};
+use fnv::FnvHashMap;
+use fontcull_write_fonts::read::{
+    collections::IntSet, tables::layout::ClassDef, FontData, FontRead,
+};
+
+const FORMAT1_HEADER_LEN: usize = 10;
+// format, coverage, valueFormat1, valueFormat2, classDef1, classDef2,
+// class1Count and class2Count
+const FORMAT2_HEADER_LEN: usize = 16;
+
+impl Graph {
+    pub(super) fn split_pair_pos(
+        &mut self,
+        subtable_idx: ObjIdx,
+    ) -> Result<Vec<ObjIdx>, RepackErrorFlags> {
+        match self.vertex_bytes(subtable_idx).get(0..2) {
+            Some([0, 1]) => self.split_coverage_indexed_subtable(subtable_idx, FORMAT1_HEADER_LEN),
+            Some([0, 2]) => self.split_pair_pos_format2(subtable_idx),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    // Splits the Class1Records into several subtables, each with its own
+    // coverage and ClassDef1. ClassDef2 is shared by all of them.
+    fn split_pair_pos_format2(
+        &mut self,
+        subtable_idx: ObjIdx,
+    ) -> Result<Vec<ObjIdx>, RepackErrorFlags> {
+        let bytes = self.vertex_bytes(subtable_idx);
+        if bytes.len() < FORMAT2_HEADER_LEN {
+            return Err(RepackErrorFlags::REPACK_ERROR_SPLIT_SUBTABLE);
+        }
+        let value_record_len = |value_format: u16| 2 * (value_format & 0xFF).count_ones() as usize;
+        let class1_count = read_u16(bytes, 12) as usize;
+        let class2_count = read_u16(bytes, 14) as usize;
+        let class1_record_len = class2_count
+            * (value_record_len(read_u16(bytes, 4)) + value_record_len(read_u16(bytes, 6)));
+        if class1_record_len == 0 {
+            return Ok(Vec::new());
+        }
+        if bytes.len() < FORMAT2_HEADER_LEN + class1_count * class1_record_len {
+            return Err(RepackErrorFlags::REPACK_ERROR_SPLIT_SUBTABLE);
+        }
+
+        let (Some(coverage_idx), Some(class_def1_idx), Some(class_def2_idx)) = (
+            self.link_at(subtable_idx, 2),
+            self.link_at(subtable_idx, 8),
+            self.link_at(subtable_idx, 10),
+        ) else {
+            return Ok(Vec::new());
+        };
+
+        let glyphs = self.coverage_glyphs(coverage_idx)?;
+        let class_def1 = ClassDef::read(FontData::new(self.vertex_bytes(class_def1_idx)))
+            .map_err(|_| RepackErrorFlags::REPACK_ERROR_SPLIT_SUBTABLE)?;
+        let glyph_classes: Vec<(u16, u16)> = glyphs
+            .iter()
+            .map(|g| (g.to_u32() as u16, class_def1.get(*g)))
+            .filter(|(_, class)| (*class as usize) < class1_count)
+            .collect();
+
+        // used to estimate the sizes of the split coverage and ClassDef1 tables
+        let mut glyphs_per_class = vec![0_usize; class1_count];
+        let mut ranges_per_class = vec![0_usize; class1_count];
+        let mut prev: Option<(u16, u16)> = None;
+        for (g, class) in glyph_classes.iter() {
+            glyphs_per_class[*class as usize] += 1;
+            if prev != Some((g.wrapping_sub(1), *class)) {
+                ranges_per_class[*class as usize] += 1;
+            }
+            prev = Some((*g, *class));
+        }
+
+        // device tables referenced from each Class1Record
+        let mut record_links: Vec<Vec<(usize, ObjIdx)>> = vec![Vec::new(); class1_count];
+        for (pos, link) in self.vertices[subtable_idx].real_links.iter() {
+            let pos = *pos as usize;
+            if pos < FORMAT2_HEADER_LEN {
+                continue;
+            }
+            let class = (pos - FORMAT2_HEADER_LEN) / class1_record_len;
+            if let Some(links) = record_links.get_mut(class) {
+                links.push((pos, link.obj_idx()));
+            }
+        }
+
+        let class_def2_size = self.vertices[class_def2_idx].table_size();
+        let mut split_points = Vec::new();
+        let mut visited = IntSet::empty();
+        let mut accumulated = FORMAT2_HEADER_LEN;
+        let mut coverage_size = 4;
+        let mut class_def1_size = 4;
+        for class in 0..class1_count {
+            let accumulated_delta = class1_record_len
+                + record_links[class]
+                    .iter()
+                    .map(|(_, obj_idx)| self.subgraph_size(*obj_idx, &mut visited))
+                    .sum::<usize>();
+            accumulated += accumulated_delta;
+            coverage_size += 2 * glyphs_per_class[class];
+            class_def1_size += 6 * ranges_per_class[class];
+
+            // the largest table is packed last, only its start has to be reachable
+            let largest = coverage_size.max(class_def1_size).max(class_def2_size);
+            let total = accumulated + coverage_size + class_def1_size + class_def2_size - largest;
+            let last_split = split_points.last().copied().unwrap_or(0);
+            if total > MAX_TABLE_SIZE && class > last_split {
+                split_points.push(class);
+                // this class didn't fit, it starts the next split
+                accumulated = FORMAT2_HEADER_LEN + accumulated_delta;
+                coverage_size = 4 + 2 * glyphs_per_class[class];
+                class_def1_size = 4 + 6 * ranges_per_class[class];
+                visited.clear();
+                for (_, obj_idx) in record_links[class].iter() {
+                    self.subgraph_size(*obj_idx, &mut visited);
+                }
+            }
+        }
+
+        if split_points.is_empty() {
+            return Ok(Vec::new());
+        }
+        split_points.push(class1_count);
+
+        let bytes = self.vertex_bytes(subtable_idx).to_vec();
+        let mut new_subtables = Vec::with_capacity(split_points.len() - 1);
+        let mut start = 0;
+        for end in split_points {
+            let split_glyph_classes: Vec<(u16, u16)> = glyph_classes
+                .iter()
+                .filter(|(_, class)| (start..end).contains(&(*class as usize)))
+                .map(|(g, class)| (*g, class - start as u16))
+                .collect();
+            let split_glyphs: Vec<_> = split_glyph_classes
+                .iter()
+                .map(|(g, _)| (*g as u32).into())
+                .collect();
+            let coverage_bytes = coverage_bytes(&split_glyphs)?;
+            let class_def_bytes = serialize_to_bytes::<ClassDef>(
+                &split_glyph_classes,
+                8 + 6 * split_glyph_classes.len(),
+            )?;
+
+            let (new_coverage_idx, new_class_def1_idx) = if start == 0 {
+                (
+                    self.replace_or_add_vertex(
+                        coverage_idx,
+                        &coverage_bytes,
+                        FnvHashMap::default(),
+                    ),
+                    self.replace_or_add_vertex(
+                        class_def1_idx,
+                        &class_def_bytes,
+                        FnvHashMap::default(),
+                    ),
+                )
+            } else {
+                (
+                    self.add_vertex(&coverage_bytes, FnvHashMap::default()),
+                    self.add_vertex(&class_def_bytes, FnvHashMap::default()),
+                )
+            };
+
+            let records_start = FORMAT2_HEADER_LEN + start * class1_record_len;
+            let records_end = FORMAT2_HEADER_LEN + end * class1_record_len;
+            let mut new_bytes = bytes[0..FORMAT2_HEADER_LEN].to_vec();
+            new_bytes[12..14].copy_from_slice(&((end - start) as u16).to_be_bytes());
+            new_bytes.extend_from_slice(&bytes[records_start..records_end]);
+
+            let mut links: FnvHashMap<u32, Link> = FnvHashMap::default();
+            links.extend([
+                offset16(2, new_coverage_idx),
+                offset16(8, new_class_def1_idx),
+                offset16(10, class_def2_idx),
+            ]);
+            for (pos, obj_idx) in record_links[start..end].iter().flatten() {
+                let new_pos = pos - records_start + FORMAT2_HEADER_LEN;
+                links.extend([offset16(new_pos as u32, *obj_idx)]);
+            }
+
+            if start == 0 {
+                self.replace_vertex(subtable_idx, &new_bytes, links);
+            } else {
+                new_subtables.push(self.add_vertex(&new_bytes, links));
+            }
+            start = end;
+        }
+        Ok(new_subtables)
+    }
+}
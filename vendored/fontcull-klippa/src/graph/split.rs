@@ -0,0 +1,316 @@
+//! Split GSUB/GPOS subtables whose subgraph is too large to be addressed with
+//! 16-bit offsets
+//! ref: <https://github.com/harfbuzz/harfbuzz/blob/main/src/graph/split-helpers.hh>
+
+use super::{Graph, RepackErrorFlags};
+use crate::{
+    serialize::{Link, LinkWidth, ObjIdx, OffsetWhence, Serializer},
+    Serialize,
+};
+use fnv::FnvHashMap;
+use fontcull_write_fonts::{
+    read::{
+        collections::IntSet,
+        tables::{gpos::Gpos, gsub::Gsub, layout::CoverageTable},
+        FontData, FontRead, TopLevelTable,
+    },
+    types::{GlyphId, Tag},
+};
+
+pub(super) const MAX_TABLE_SIZE: usize = u16::MAX as usize;
+
+const LOOKUP_HEADER_LEN: usize = 6;
+const USE_MARK_FILTERING_SET: u16 = 0x0010;
+
+impl Graph {
+    // Splits the GPOS PairPos and MarkBasePos subtables and the GSUB
+    // LigatureSubst subtables that don't fit in 16-bit offsets into several
+    // subtables. Lookups that get new subtables are promoted to extension
+    // lookups, so each subtable can be packed in its own space.
+    // Returns true if any subtable was split.
+    pub(crate) fn split_subtables(&mut self, tag: Tag) -> Result<bool, RepackErrorFlags> {
+        let extension_type = match tag {
+            Gsub::TAG => 7,
+            Gpos::TAG => 9,
+            _ => return Ok(false),
+        };
+
+        self.update_parents()?;
+        let mut split = false;
+        for lookup_idx in self.lookup_indices() {
+            split |= self.split_lookup_subtables(lookup_idx, tag, extension_type)?;
+        }
+
+        if split {
+            self.parents_invalid = true;
+            self.distance_invalid = true;
+            self.positions_invalid = true;
+        }
+        Ok(split)
+    }
+
+    fn split_lookup_subtables(
+        &mut self,
+        lookup_idx: ObjIdx,
+        tag: Tag,
+        extension_type: u16,
+    ) -> Result<bool, RepackErrorFlags> {
+        let lookup_bytes = self.vertex_bytes(lookup_idx);
+        if lookup_bytes.len() < LOOKUP_HEADER_LEN {
+            return Err(RepackErrorFlags::REPACK_ERROR_SPLIT_SUBTABLE);
+        }
+        let lookup_type = read_u16(lookup_bytes, 0);
+        let lookup_flag = read_u16(lookup_bytes, 2);
+        let subtable_count = read_u16(lookup_bytes, 4) as usize;
+        let is_extension = lookup_type == extension_type;
+
+        let mut children = Vec::with_capacity(subtable_count);
+        let mut split = false;
+        for i in 0..subtable_count {
+            let link_pos = (LOOKUP_HEADER_LEN + 2 * i) as u32;
+            let Some(child_idx) = self.link_at(lookup_idx, link_pos) else {
+                return Ok(false);
+            };
+
+            let (subtable_idx, subtable_type) = if is_extension {
+                let ext_bytes = self.vertex_bytes(child_idx);
+                match (ext_bytes.get(2..4), self.link_at(child_idx, 4)) {
+                    (Some(ty), Some(subtable_idx)) => {
+                        (subtable_idx, u16::from_be_bytes([ty[0], ty[1]]))
+                    }
+                    _ => return Ok(false),
+                }
+            } else {
+                (child_idx, lookup_type)
+            };
+
+            // shared subtables are left as they are
+            if self.vertices[subtable_idx].incoming_edges() > 1 {
+                children.push(child_idx);
+                continue;
+            }
+
+            let new_subtables = match (tag, subtable_type) {
+                (Gpos::TAG, 2) => self.split_pair_pos(subtable_idx)?,
+                (Gpos::TAG, 4) => self.split_mark_base_pos(subtable_idx)?,
+                (Gsub::TAG, 4) => self.split_ligature_subst(subtable_idx)?,
+                _ => Vec::new(),
+            };
+
+            // the original subtable object is reused for the first split
+            children.push(child_idx);
+            for new_subtable_idx in new_subtables {
+                split = true;
+                let new_child_idx = if is_extension {
+                    self.add_extension_subtable(subtable_type, new_subtable_idx)
+                } else {
+                    new_subtable_idx
+                };
+                children.push(new_child_idx);
+            }
+        }
+
+        if !split {
+            return Ok(false);
+        }
+        let Ok(new_count) = u16::try_from(children.len()) else {
+            return Err(RepackErrorFlags::REPACK_ERROR_SPLIT_SUBTABLE);
+        };
+
+        let lookup_bytes = self.vertex_bytes(lookup_idx);
+        let mut bytes = lookup_bytes[0..4].to_vec();
+        bytes.extend_from_slice(&new_count.to_be_bytes());
+        bytes.resize(LOOKUP_HEADER_LEN + 2 * children.len(), 0);
+        if lookup_flag & USE_MARK_FILTERING_SET != 0 {
+            let pos = LOOKUP_HEADER_LEN + 2 * subtable_count;
+            let Some(mark_filtering_set) = lookup_bytes.get(pos..pos + 2) else {
+                return Err(RepackErrorFlags::REPACK_ERROR_SPLIT_SUBTABLE);
+            };
+            bytes.extend_from_slice(mark_filtering_set);
+        }
+
+        let links = children
+            .iter()
+            .enumerate()
+            .map(|(i, child_idx)| offset16((LOOKUP_HEADER_LEN + 2 * i) as u32, *child_idx))
+            .collect();
+        self.replace_vertex(lookup_idx, &bytes, links);
+
+        if !is_extension {
+            self.promote_lookup(lookup_idx, extension_type)?;
+        }
+        Ok(true)
+    }
+
+    // GSUB LigatureSubstFormat1: format, coverage, ligatureSetCount and an
+    // array of offsets to LigatureSet tables in coverage order
+    fn split_ligature_subst(
+        &mut self,
+        subtable_idx: ObjIdx,
+    ) -> Result<Vec<ObjIdx>, RepackErrorFlags> {
+        if self.vertex_bytes(subtable_idx).get(0..2) != Some(&[0, 1]) {
+            return Ok(Vec::new());
+        }
+        self.split_coverage_indexed_subtable(subtable_idx, 6)
+    }
+
+    // Splits a subtable made of a header, which starts with format and a
+    // coverage offset and ends with a count, followed by an array of offsets
+    // indexed by coverage index, e.g. PairPosFormat1 and LigatureSubstFormat1.
+    // Returns the new subtables, the first split is kept in subtable_idx.
+    // ref: <https://github.com/harfbuzz/harfbuzz/blob/main/src/graph/pairpos-graph.hh>
+    pub(super) fn split_coverage_indexed_subtable(
+        &mut self,
+        subtable_idx: ObjIdx,
+        header_len: usize,
+    ) -> Result<Vec<ObjIdx>, RepackErrorFlags> {
+        let bytes = self.vertex_bytes(subtable_idx);
+        if bytes.len() < header_len {
+            return Err(RepackErrorFlags::REPACK_ERROR_SPLIT_SUBTABLE);
+        }
+        let count = read_u16(bytes, header_len - 2) as usize;
+        let Some(coverage_idx) = self.link_at(subtable_idx, 2) else {
+            return Ok(Vec::new());
+        };
+        let glyphs = self.coverage_glyphs(coverage_idx)?;
+        if glyphs.len() != count {
+            return Ok(Vec::new());
+        }
+        let coverage_size = self.vertices[coverage_idx].table_size();
+
+        let children: Vec<Option<ObjIdx>> = (0..count)
+            .map(|i| self.link_at(subtable_idx, (header_len + 2 * i) as u32))
+            .collect();
+
+        let mut split_points = Vec::new();
+        let mut visited = IntSet::empty();
+        let mut accumulated = header_len;
+        let mut partial_coverage_size = 4;
+        for (i, child_idx) in children.iter().enumerate() {
+            let child_size = child_idx.map_or(0, |idx| self.subgraph_size(idx, &mut visited));
+            let accumulated_delta = child_size + 2;
+            accumulated += accumulated_delta;
+            partial_coverage_size += 2;
+            let last_split = split_points.last().copied().unwrap_or(0);
+            if accumulated + coverage_size.min(partial_coverage_size) > MAX_TABLE_SIZE
+                && i > last_split
+            {
+                split_points.push(i);
+                // this entry didn't fit, it starts the next split
+                accumulated = header_len + accumulated_delta;
+                partial_coverage_size = 6;
+                visited.clear();
+                if let Some(idx) = child_idx {
+                    self.subgraph_size(*idx, &mut visited);
+                }
+            }
+        }
+
+        if split_points.is_empty() {
+            return Ok(Vec::new());
+        }
+        split_points.push(count);
+
+        let header = self.vertex_bytes(subtable_idx)[0..header_len].to_vec();
+        let mut new_subtables = Vec::with_capacity(split_points.len() - 1);
+        let mut start = 0;
+        for end in split_points {
+            let coverage_bytes = coverage_bytes(&glyphs[start..end])?;
+            let new_coverage_idx = if start == 0 {
+                self.replace_or_add_vertex(coverage_idx, &coverage_bytes, FnvHashMap::default())
+            } else {
+                self.add_vertex(&coverage_bytes, FnvHashMap::default())
+            };
+
+            let mut bytes = header.clone();
+            bytes[header_len - 2..header_len]
+                .copy_from_slice(&((end - start) as u16).to_be_bytes());
+            bytes.resize(header_len + 2 * (end - start), 0);
+
+            let mut links: FnvHashMap<u32, Link> = FnvHashMap::default();
+            links.extend([offset16(2, new_coverage_idx)]);
+            for (i, child_idx) in children[start..end].iter().enumerate() {
+                if let Some(child_idx) = child_idx {
+                    links.extend([offset16((header_len + 2 * i) as u32, *child_idx)]);
+                }
+            }
+
+            if start == 0 {
+                self.replace_vertex(subtable_idx, &bytes, links);
+            } else {
+                new_subtables.push(self.add_vertex(&bytes, links));
+            }
+            start = end;
+        }
+        Ok(new_subtables)
+    }
+
+    // Replaces obj_idx in place if it has a single parent, otherwise adds a new
+    // object so that the other parents keep the original one.
+    pub(super) fn replace_or_add_vertex(
+        &mut self,
+        obj_idx: ObjIdx,
+        bytes: &[u8],
+        real_links: FnvHashMap<u32, Link>,
+    ) -> ObjIdx {
+        if self.vertices[obj_idx].incoming_edges() > 1 {
+            return self.add_vertex(bytes, real_links);
+        }
+        self.replace_vertex(obj_idx, bytes, real_links);
+        obj_idx
+    }
+
+    // Returns the total size of the objects reachable from obj_idx, objects
+    // already in visited are not counted again
+    pub(super) fn subgraph_size(&self, obj_idx: ObjIdx, visited: &mut IntSet<u32>) -> usize {
+        if !visited.insert(obj_idx as u32) {
+            return 0;
+        }
+        let v = &self.vertices[obj_idx];
+        v.table_size()
+            + v.real_links
+                .values()
+                .map(|l| self.subgraph_size(l.obj_idx(), visited))
+                .sum::<usize>()
+    }
+
+    pub(super) fn coverage_glyphs(
+        &self,
+        coverage_idx: ObjIdx,
+    ) -> Result<Vec<GlyphId>, RepackErrorFlags> {
+        let coverage = CoverageTable::read(FontData::new(self.vertex_bytes(coverage_idx)))
+            .map_err(|_| RepackErrorFlags::REPACK_ERROR_SPLIT_SUBTABLE)?;
+        Ok(coverage.iter().map(GlyphId::from).collect())
+    }
+}
+
+pub(super) fn read_u16(bytes: &[u8], pos: usize) -> u16 {
+    u16::from_be_bytes([bytes[pos], bytes[pos + 1]])
+}
+
+pub(super) fn offset16(pos: u32, obj_idx: ObjIdx) -> (u32, Link) {
+    (
+        pos,
+        Link::new(LinkWidth::Two, OffsetWhence::Head, pos, obj_idx),
+    )
+}
+
+pub(super) fn coverage_bytes(glyphs: &[GlyphId]) -> Result<Vec<u8>, RepackErrorFlags> {
+    serialize_to_bytes::<CoverageTable>(glyphs, 4 + 6 * glyphs.len())
+}
+
+// serializes a table without offsets on its own
+pub(super) fn serialize_to_bytes<'a, T: Serialize<'a>>(
+    args: T::Args,
+    size: usize,
+) -> Result<Vec<u8>, RepackErrorFlags> {
+    let mut s = Serializer::new(size);
+    s.start_serialize()
+        .and_then(|_| T::serialize(&mut s, args))
+        .map_err(|_| RepackErrorFlags::REPACK_ERROR_SPLIT_SUBTABLE)?;
+    s.end_serialize();
+    if s.in_error() {
+        return Err(RepackErrorFlags::REPACK_ERROR_SPLIT_SUBTABLE);
+    }
+    Ok(s.copy_bytes())
+}
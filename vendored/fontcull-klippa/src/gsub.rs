@@ -4,13 +4,14 @@ mod ligature_subst;
 mod multiple_subst;
 mod reverse_chain_single_subst;
 mod single_subst;
+pub(crate) mod text_closure;
 
 use crate::{
     collect_features_with_retained_subs, find_duplicate_features,
     offset::SerializeSubset,
     prune_features, remap_feature_indices, remap_indices,
     serialize::{SerializeErrorFlags, Serializer},
-    LayoutClosure, NameIdClosure, Plan, PruneLangSysContext, Subset, SubsetError,
+    LayoutClosure, NameIdClosure, Plan, PruneLangSysContext, Subset, SubsetError, SubsetFlags,
     SubsetLayoutContext, SubsetState, SubsetTable,
 };
 use fnv::FnvHashMap;
@@ -101,9 +102,16 @@ impl LayoutClosure for Gsub<'_> {
         let Ok(mut lookup_indices) = self.collect_lookups(&feature_indices) else {
             return;
         };
-        let Ok(_) = self.closure_glyphs(&lookup_indices, &mut plan.glyphset_gsub) else {
-            return;
-        };
+        // with texts the glyphs are closed over by text_closure instead
+        if plan.layout_texts.is_empty()
+            && !plan
+                .subset_flags
+                .contains(SubsetFlags::SUBSET_FLAGS_NO_LAYOUT_CLOSURE)
+        {
+            let Ok(_) = self.closure_glyphs(&lookup_indices, &mut plan.glyphset_gsub) else {
+                return;
+            };
+        }
         let Ok(_) = self.closure_lookups(&plan.glyphset_gsub, &mut lookup_indices) else {
             return;
         };
@@ -235,7 +243,6 @@ impl<'a> SubsetTable<'a> for SubstitutionLookup<'_> {
     }
 }
 
-// TODO: support extension lookup type
 impl<'a> SubsetTable<'a> for SubstitutionSubtables<'a> {
     type ArgsForSubset = (&'a SubsetState, &'a FontRef<'a>, &'a FnvHashMap<u16, u16>);
     type Output = u16;
@@ -246,13 +253,15 @@ impl<'a> SubsetTable<'a> for SubstitutionSubtables<'a> {
         args: Self::ArgsForSubset,
     ) -> Result<u16, SerializeErrorFlags> {
         match self {
-            SubstitutionSubtables::Single(subtables) => subtables.subset(plan, s, args),
-            SubstitutionSubtables::Multiple(subtables) => subtables.subset(plan, s, args),
-            SubstitutionSubtables::Alternate(subtables) => subtables.subset(plan, s, args),
-            SubstitutionSubtables::Ligature(subtables) => subtables.subset(plan, s, args),
-            SubstitutionSubtables::Contextual(subtables) => subtables.subset(plan, s, args),
-            SubstitutionSubtables::ChainContextual(subtables) => subtables.subset(plan, s, args),
-            SubstitutionSubtables::Reverse(subtables) => subtables.subset(plan, s, args),
+            SubstitutionSubtables::Single(subtables) => subtables.subset(plan, s, (args, 1)),
+            SubstitutionSubtables::Multiple(subtables) => subtables.subset(plan, s, (args, 2)),
+            SubstitutionSubtables::Alternate(subtables) => subtables.subset(plan, s, (args, 3)),
+            SubstitutionSubtables::Ligature(subtables) => subtables.subset(plan, s, (args, 4)),
+            SubstitutionSubtables::Contextual(subtables) => subtables.subset(plan, s, (args, 5)),
+            SubstitutionSubtables::ChainContextual(subtables) => {
+                subtables.subset(plan, s, (args, 6))
+            }
+            SubstitutionSubtables::Reverse(subtables) => subtables.subset(plan, s, (args, 8)),
         }
     }
 }
@@ -280,6 +289,34 @@ mod test {
         assert!(plan.name_ids.contains(NameId::new(257)));
     }
 
+    #[test]
+    fn test_no_layout_closure() {
+        use fontcull_write_fonts::read::types::GlyphId;
+
+        let font = FontRef::new(include_bytes!("../test-data/fonts/Roboto-Regular.ttf")).unwrap();
+        let mut plan = Plan {
+            subset_flags: SubsetFlags::SUBSET_FLAGS_NO_LAYOUT_CLOSURE,
+            layout_scripts: IntSet::all(),
+            layout_features: IntSet::all(),
+            ..Default::default()
+        };
+        // f and i
+        plan.glyphset_gsub
+            .extend([GlyphId::new(74), GlyphId::new(77)]);
+        font.gsub()
+            .unwrap()
+            .closure_glyphs_lookups_features(&mut plan);
+        // the fi ligature isn't added, but the lookups that apply to f and i are kept
+        assert_eq!(plan.glyphset_gsub.len(), 2);
+        assert!(!plan.gsub_lookups.is_empty());
+
+        plan.subset_flags = SubsetFlags::default();
+        font.gsub()
+            .unwrap()
+            .closure_glyphs_lookups_features(&mut plan);
+        assert!(plan.glyphset_gsub.contains(GlyphId::new(444)));
+    }
+
     #[test]
     fn test_prune_features_wo_variations() {
         let font = FontRef::new(test_data::CONTEXTUAL).unwrap();
@@ -313,4 +350,58 @@ mod test {
         assert_eq!(retained_features.len(), 1);
         assert!(retained_features.contains(0));
     }
+
+    #[test]
+    fn test_subset_extension_lookup() {
+        use fontcull_write_fonts::read::{
+            tables::layout::Subtables,
+            types::{GlyphId, GlyphId16},
+            FontData, FontRead,
+        };
+
+        let font = FontRef::new(include_bytes!(
+            "../test-data/fonts/NotoNastaliqUrdu-Regular.ttf"
+        ))
+        .unwrap();
+        let gsub_lookups = font.gsub().unwrap().lookup_list().unwrap();
+        // an extension lookup wrapping a multiple substitution
+        let lookup = gsub_lookups.lookups().get(152).unwrap();
+        assert_eq!(lookup.lookup_type(), 7);
+
+        let mut plan = Plan::default();
+        for (old_gid, new_gid) in [(362_u32, 5_u32), (512, 6), (966, 11)] {
+            plan.glyph_map_gsub
+                .insert(GlyphId::from(old_gid), GlyphId::from(new_gid));
+            plan.glyphset_gsub.insert(GlyphId::from(old_gid));
+        }
+
+        let mut s = Serializer::new(1024);
+        assert_eq!(s.start_serialize(), Ok(()));
+        let subset_state = SubsetState::default();
+        lookup
+            .subset(&plan, &mut s, (&subset_state, &font, &plan.gsub_lookups))
+            .unwrap();
+        assert!(!s.in_error());
+        s.end_serialize();
+
+        let subsetted_data = s.copy_bytes();
+        let subsetted = SubstitutionLookup::read(FontData::new(&subsetted_data)).unwrap();
+        assert_eq!(subsetted.lookup_type(), 7);
+        let SubstitutionSubtables::Multiple(subtables @ Subtables::Extension(_)) =
+            subsetted.subtables().unwrap()
+        else {
+            panic!("extension wrapper is not kept!");
+        };
+        assert_eq!(subtables.len(), 1);
+        let multiple_subst = subtables.get(0).unwrap();
+        let coverage: Vec<_> = multiple_subst.coverage().unwrap().iter().collect();
+        assert_eq!(coverage, [GlyphId16::new(5), GlyphId16::new(6)]);
+        let sequence = multiple_subst.sequences().get(1).unwrap();
+        let substitutes: Vec<_> = sequence
+            .substitute_glyph_ids()
+            .iter()
+            .map(|gid| gid.get().to_u16())
+            .collect();
+        assert_eq!(substitutes, [6, 11, 11]);
+    }
 }
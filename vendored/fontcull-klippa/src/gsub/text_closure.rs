@@ -0,0 +1,586 @@
+//! GSUB glyph closure over the texts a font renders
+//!
+//! The glyph set closure keeps every glyph a substitution can reach from the
+//! glyph set, e.g. a ligature whose components are all kept, even when they
+//! never follow each other in a text. Here the glyph sequences of the texts are
+//! kept in a lattice instead: the boundaries between glyphs are nodes and each
+//! glyph is an edge from its start to its end boundary, so whatever a run of
+//! glyphs can be substituted with spans the same boundaries as the run.
+//!
+//! Only the lookups of the features of the scripts a text uses apply to it,
+//! with every language system of those scripts since the text's language isn't
+//! known. Like HarfBuzz, a script the font has no tag for falls back to `DFLT`,
+//! `dflt` and then `latn`.
+//!
+//! This stays an over-approximation of shaping: lookups are applied in any
+//! order until no new glyphs show up, and the lookups called from contextual
+//! lookups are applied wherever their own coverage matches.
+
+mod scripts;
+
+use crate::Plan;
+use fnv::{FnvHashMap, FnvHashSet};
+use fontcull_write_fonts::read::{
+    collections::IntSet,
+    tables::{
+        gdef::Gdef,
+        gsub::{
+            AlternateSubstFormat1, Gsub, LigatureSubstFormat1, MultipleSubstFormat1,
+            ReverseChainSingleSubstFormat1, SingleSubst, SubstitutionLookup, SubstitutionSubtables,
+        },
+        layout::{ClassDef, CoverageTable, LookupFlag, ScriptList},
+    },
+    types::{GlyphId, Tag},
+    ReadError,
+};
+
+// past these the glyph set closure is used instead
+const MAX_ROUNDS: usize = 12;
+const MAX_EDGES: usize = 1 << 22;
+
+// scripts HarfBuzz tries when the font has none of a text's script tags
+const FALLBACK_SCRIPTS: [Tag; 3] = [Tag::new(b"DFLT"), Tag::new(b"dflt"), Tag::new(b"latn")];
+
+/// The glyph sequence of a text GSUB closure is limited to, with its scripts
+pub(crate) struct LayoutText {
+    glyphs: Vec<GlyphId>,
+    // None if a character's script isn't known, then every script applies
+    scripts: Option<Vec<&'static [Tag]>>,
+}
+
+impl LayoutText {
+    pub(crate) fn new(text: &str, glyphs: Vec<GlyphId>) -> Self {
+        Self {
+            glyphs,
+            scripts: scripts::text_scripts(text),
+        }
+    }
+}
+
+/// Adds the glyphs the layout features of the plan can substitute into its
+/// texts to the GSUB glyph set
+pub(crate) fn closure_glyphs_for_texts(gsub: &Gsub, gdef: Option<&Gdef>, plan: &mut Plan) {
+    let Ok(script_list) = gsub.script_list() else {
+        return;
+    };
+
+    // texts are closed over together with the others that use the same scripts
+    let mut groups: FnvHashMap<Option<Vec<Tag>>, Vec<&[GlyphId]>> = FnvHashMap::default();
+    for text in &plan.layout_texts {
+        let scripts = text
+            .scripts
+            .as_ref()
+            .map(|scripts| select_scripts(&script_list, scripts, &plan.layout_scripts));
+        groups.entry(scripts).or_default().push(&text.glyphs);
+    }
+    let text_glyphs: IntSet<GlyphId> = plan
+        .layout_texts
+        .iter()
+        .flat_map(|text| text.glyphs.iter().copied())
+        .collect();
+    // glyphs that aren't in any text are closed over as texts of their own, in
+    // every script
+    let other_glyphs: Vec<[GlyphId; 1]> = plan
+        .glyphset_gsub
+        .iter()
+        .filter(|g| !text_glyphs.contains(*g))
+        .map(|g| [g])
+        .collect();
+    groups
+        .entry(None)
+        .or_default()
+        .extend(other_glyphs.iter().map(|g| g.as_slice()));
+
+    let mut glyphs = plan.glyphset_gsub.clone();
+    for (scripts, texts) in groups {
+        let scripts = match scripts {
+            Some(scripts) => scripts.into_iter().collect(),
+            None => plan.layout_scripts.clone(),
+        };
+        let Ok(feature_indices) =
+            gsub.collect_features(&scripts, &IntSet::all(), &plan.layout_features)
+        else {
+            return;
+        };
+        let Ok(lookup_indices) = gsub.collect_lookups(&feature_indices) else {
+            return;
+        };
+        let Ok(group_glyphs) = closure_glyphs(gsub, gdef, &lookup_indices, &texts) else {
+            return;
+        };
+        glyphs.union(&group_glyphs);
+    }
+    plan.glyphset_gsub = glyphs;
+}
+
+// The script tag of each script the font has, or the first fallback it has,
+// among the scripts the plan keeps
+fn select_scripts(
+    script_list: &ScriptList,
+    scripts: &[&[Tag]],
+    layout_scripts: &IntSet<Tag>,
+) -> Vec<Tag> {
+    let has_script = |tag: &&Tag| script_list.index_for_tag(**tag).is_some();
+    let mut selected: Vec<Tag> = scripts
+        .iter()
+        .filter_map(|tags| tags.iter().find(has_script))
+        .copied()
+        .collect();
+    if selected.len() < scripts.len() || scripts.is_empty() {
+        selected.extend(FALLBACK_SCRIPTS.iter().find(has_script));
+    }
+    selected.retain(|tag| layout_scripts.contains(*tag));
+    selected.sort();
+    selected.dedup();
+    selected
+}
+
+// Returns the glyphs lookups substitute into texts, along with their own.
+fn closure_glyphs(
+    gsub: &Gsub,
+    gdef: Option<&Gdef>,
+    lookup_indices: &IntSet<u16>,
+    texts: &[&[GlyphId]],
+) -> Result<IntSet<GlyphId>, ReadError> {
+    let mut lattice = Lattice::default();
+    for text in texts {
+        lattice.add_sequence(text);
+    }
+
+    let skip = SkipContext::new(gdef);
+    let lookup_list = gsub.lookup_list()?;
+    let lookups = lookup_list.lookups();
+    let mut rounds = 0;
+    loop {
+        let mut active_lookups = lookup_indices.clone();
+        lookup_list.closure_lookups(&lattice.glyphs, &mut active_lookups)?;
+
+        let num_edges = lattice.num_edges();
+        for lookup_index in active_lookups.iter() {
+            let lookup = lookups.get(lookup_index as usize)?;
+            lattice.apply_lookup(&lookup, &skip)?;
+        }
+        if lattice.num_edges() == num_edges {
+            break;
+        }
+
+        rounds += 1;
+        if rounds >= MAX_ROUNDS || lattice.num_edges() > MAX_EDGES {
+            let mut glyphs: IntSet<GlyphId> =
+                texts.iter().flat_map(|t| t.iter().copied()).collect();
+            gsub.closure_glyphs(lookup_indices, &mut glyphs)?;
+            return Ok(glyphs);
+        }
+    }
+    Ok(lattice.glyphs)
+}
+
+#[derive(Default)]
+struct Lattice {
+    // edges leaving each boundary: (glyph, end boundary)
+    edges: Vec<Vec<(GlyphId, u32)>>,
+    seen: FnvHashSet<(u32, GlyphId, u32)>,
+    glyphs: IntSet<GlyphId>,
+    // boundaries between the glyphs a multiple substitution put in place of an
+    // edge, so that applying it again adds no new ones
+    sequence_boundaries: FnvHashMap<(u32, u32, Vec<GlyphId>), Vec<u32>>,
+}
+
+impl Lattice {
+    fn add_boundary(&mut self) -> u32 {
+        self.edges.push(Vec::new());
+        (self.edges.len() - 1) as u32
+    }
+
+    fn add_edge(&mut self, start: u32, glyph: GlyphId, end: u32) {
+        if self.seen.insert((start, glyph, end)) {
+            self.edges[start as usize].push((glyph, end));
+            self.glyphs.insert(glyph);
+        }
+    }
+
+    fn num_edges(&self) -> usize {
+        self.seen.len()
+    }
+
+    fn add_sequence(&mut self, glyphs: &[GlyphId]) {
+        let mut start = self.add_boundary();
+        for glyph in glyphs {
+            let end = self.add_boundary();
+            self.add_edge(start, *glyph, end);
+            start = end;
+        }
+    }
+
+    // all edges as (start, glyph, end), in boundary order
+    fn all_edges(&self) -> Vec<(u32, GlyphId, u32)> {
+        self.edges
+            .iter()
+            .enumerate()
+            .flat_map(|(start, out)| out.iter().map(move |(g, end)| (start as u32, *g, *end)))
+            .collect()
+    }
+
+    fn apply_lookup(
+        &mut self,
+        lookup: &SubstitutionLookup,
+        skip: &SkipContext,
+    ) -> Result<(), ReadError> {
+        let edges = self.all_edges();
+        match lookup.subtables()? {
+            SubstitutionSubtables::Single(subtables) => {
+                for subtable in subtables.iter() {
+                    self.apply_single(&subtable?, &edges)?;
+                }
+            }
+            SubstitutionSubtables::Multiple(subtables) => {
+                for subtable in subtables.iter() {
+                    self.apply_multiple(&subtable?, &edges)?;
+                }
+            }
+            SubstitutionSubtables::Alternate(subtables) => {
+                for subtable in subtables.iter() {
+                    self.apply_alternate(&subtable?, &edges)?;
+                }
+            }
+            SubstitutionSubtables::Ligature(subtables) => {
+                let flag = lookup.lookup_flag();
+                let mark_filtering_set = lookup.mark_filtering_set();
+                for subtable in subtables.iter() {
+                    let skips = |glyph| skip.skips(glyph, flag, mark_filtering_set);
+                    self.apply_ligature(&subtable?, &edges, skips)?;
+                }
+            }
+            SubstitutionSubtables::Reverse(subtables) => {
+                for subtable in subtables.iter() {
+                    self.apply_reverse(&subtable?, &edges)?;
+                }
+            }
+            // the lookups these call are applied on their own
+            SubstitutionSubtables::Contextual(_) | SubstitutionSubtables::ChainContextual(_) => (),
+        }
+        Ok(())
+    }
+
+    fn apply_single(
+        &mut self,
+        subtable: &SingleSubst,
+        edges: &[(u32, GlyphId, u32)],
+    ) -> Result<(), ReadError> {
+        match subtable {
+            SingleSubst::Format1(table) => {
+                let coverage = table.coverage()?;
+                let delta = table.delta_glyph_id() as i32;
+                for (start, glyph, end) in covered(&coverage, edges) {
+                    let new_glyph = (glyph.to_u32() as i32 + delta) & 0xFFFF;
+                    self.add_edge(start, GlyphId::from(new_glyph as u32), end);
+                }
+            }
+            SingleSubst::Format2(table) => {
+                let coverage = table.coverage()?;
+                let substitutes = table.substitute_glyph_ids();
+                for (start, glyph, end) in covered(&coverage, edges) {
+                    let Some(new_glyph) = coverage
+                        .get(glyph)
+                        .and_then(|idx| substitutes.get(idx as usize))
+                    else {
+                        continue;
+                    };
+                    self.add_edge(start, new_glyph.get().into(), end);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_multiple(
+        &mut self,
+        subtable: &MultipleSubstFormat1,
+        edges: &[(u32, GlyphId, u32)],
+    ) -> Result<(), ReadError> {
+        let coverage = subtable.coverage()?;
+        let sequences = subtable.sequences();
+        for (start, glyph, end) in covered(&coverage, edges) {
+            let Some(idx) = coverage.get(glyph) else {
+                continue;
+            };
+            let sequence: Vec<GlyphId> = sequences
+                .get(idx as usize)?
+                .substitute_glyph_ids()
+                .iter()
+                .map(|g| g.get().into())
+                .collect();
+            match sequence.as_slice() {
+                // the glyph is deleted, what follows it can follow what precedes it
+                [] => {
+                    for (next_glyph, next_end) in self.edges[end as usize].clone() {
+                        self.add_edge(start, next_glyph, next_end);
+                    }
+                }
+                [new_glyph] => self.add_edge(start, *new_glyph, end),
+                _ => {
+                    let key = (start, end, sequence);
+                    let boundaries = match self.sequence_boundaries.get(&key) {
+                        Some(boundaries) => boundaries.clone(),
+                        None => {
+                            let mut boundaries = vec![start];
+                            for _ in 1..key.2.len() {
+                                boundaries.push(self.add_boundary());
+                            }
+                            boundaries.push(end);
+                            self.sequence_boundaries
+                                .insert(key.clone(), boundaries.clone());
+                            boundaries
+                        }
+                    };
+                    for (new_glyph, ends) in key.2.iter().zip(boundaries.windows(2)) {
+                        self.add_edge(ends[0], *new_glyph, ends[1]);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_alternate(
+        &mut self,
+        subtable: &AlternateSubstFormat1,
+        edges: &[(u32, GlyphId, u32)],
+    ) -> Result<(), ReadError> {
+        let coverage = subtable.coverage()?;
+        let alternate_sets = subtable.alternate_sets();
+        for (start, glyph, end) in covered(&coverage, edges) {
+            let Some(idx) = coverage.get(glyph) else {
+                continue;
+            };
+            for new_glyph in alternate_sets.get(idx as usize)?.alternate_glyph_ids() {
+                self.add_edge(start, new_glyph.get().into(), end);
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_ligature(
+        &mut self,
+        subtable: &LigatureSubstFormat1,
+        edges: &[(u32, GlyphId, u32)],
+        skips: impl Fn(GlyphId) -> bool,
+    ) -> Result<(), ReadError> {
+        let coverage = subtable.coverage()?;
+        let ligature_sets = subtable.ligature_sets();
+        for (start, glyph, end) in covered(&coverage, edges) {
+            let Some(idx) = coverage.get(glyph) else {
+                continue;
+            };
+            for ligature in ligature_sets.get(idx as usize)?.ligatures().iter() {
+                let ligature = ligature?;
+                let components: Vec<GlyphId> = ligature
+                    .component_glyph_ids()
+                    .iter()
+                    .map(|g| g.get().into())
+                    .collect();
+                for ligature_end in self.match_components(end, &components, &skips) {
+                    self.add_edge(start, ligature.ligature_glyph().into(), ligature_end);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Returns the end boundaries of the paths from start that match components,
+    // going past the glyphs the lookup skips.
+    fn match_components(
+        &self,
+        start: u32,
+        components: &[GlyphId],
+        skips: impl Fn(GlyphId) -> bool,
+    ) -> Vec<u32> {
+        let mut ends = Vec::new();
+        let mut visited = FnvHashSet::default();
+        let mut stack = vec![(start, 0)];
+        while let Some((boundary, matched)) = stack.pop() {
+            if matched == components.len() {
+                ends.push(boundary);
+                continue;
+            }
+            if !visited.insert((boundary, matched)) {
+                continue;
+            }
+            for (glyph, end) in self.edges[boundary as usize].iter() {
+                if *glyph == components[matched] {
+                    stack.push((*end, matched + 1));
+                }
+                if skips(*glyph) {
+                    stack.push((*end, matched));
+                }
+            }
+        }
+        ends
+    }
+
+    fn apply_reverse(
+        &mut self,
+        subtable: &ReverseChainSingleSubstFormat1,
+        edges: &[(u32, GlyphId, u32)],
+    ) -> Result<(), ReadError> {
+        let coverage = subtable.coverage()?;
+        let substitutes = subtable.substitute_glyph_ids();
+        for (start, glyph, end) in covered(&coverage, edges) {
+            let Some(new_glyph) = coverage
+                .get(glyph)
+                .and_then(|idx| substitutes.get(idx as usize))
+            else {
+                continue;
+            };
+            self.add_edge(start, new_glyph.get().into(), end);
+        }
+        Ok(())
+    }
+}
+
+fn covered<'a>(
+    coverage: &'a CoverageTable,
+    edges: &'a [(u32, GlyphId, u32)],
+) -> impl Iterator<Item = (u32, GlyphId, u32)> + 'a {
+    edges
+        .iter()
+        .copied()
+        .filter(|(_, glyph, _)| coverage.get(*glyph).is_some())
+}
+
+// GDEF data that decides which glyphs a lookup skips when matching the
+// components of a ligature
+struct SkipContext<'a> {
+    glyph_classes: Option<ClassDef<'a>>,
+    mark_attach_classes: Option<ClassDef<'a>>,
+    mark_glyph_sets: Vec<CoverageTable<'a>>,
+}
+
+impl<'a> SkipContext<'a> {
+    fn new(gdef: Option<&Gdef<'a>>) -> Self {
+        let mark_glyph_sets = gdef
+            .and_then(|gdef| gdef.mark_glyph_sets_def())
+            .and_then(|sets| sets.ok())
+            .map(|sets| sets.coverages().iter().filter_map(|c| c.ok()).collect())
+            .unwrap_or_default();
+        Self {
+            glyph_classes: gdef.and_then(|gdef| gdef.glyph_class_def()?.ok()),
+            mark_attach_classes: gdef.and_then(|gdef| gdef.mark_attach_class_def()?.ok()),
+            mark_glyph_sets,
+        }
+    }
+
+    fn skips(&self, glyph: GlyphId, flag: LookupFlag, mark_filtering_set: Option<u16>) -> bool {
+        let Some(glyph_classes) = &self.glyph_classes else {
+            return false;
+        };
+        match glyph_classes.get(glyph) {
+            1 => flag.contains(LookupFlag::IGNORE_BASE_GLYPHS),
+            2 => flag.contains(LookupFlag::IGNORE_LIGATURES),
+            3 => {
+                if flag.contains(LookupFlag::IGNORE_MARKS) {
+                    true
+                } else if flag.contains(LookupFlag::USE_MARK_FILTERING_SET) {
+                    mark_filtering_set
+                        .and_then(|set| self.mark_glyph_sets.get(set as usize))
+                        .is_none_or(|set| set.get(glyph).is_none())
+                } else if let Some(class) = flag.mark_attachment_class() {
+                    self.mark_attach_classes
+                        .as_ref()
+                        .is_none_or(|classes| classes.get(glyph) != class)
+                } else {
+                    false
+                }
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{SubsetFlags, DEFAULT_LAYOUT_FEATURES};
+    use fontcull_write_fonts::read::{FontRef, TableProvider};
+
+    fn text_closure_glyphs(texts: &[&str]) -> Vec<u32> {
+        let font =
+            FontRef::new(include_bytes!("../../test-data/fonts/Roboto-Regular.ttf")).unwrap();
+        let unicodes: IntSet<u32> = "fil".chars().map(|c| c as u32).collect();
+        let plan = Plan::new_with_texts(
+            &IntSet::empty(),
+            &unicodes,
+            texts,
+            &font,
+            SubsetFlags::default(),
+            &IntSet::empty(),
+            &IntSet::all(),
+            &DEFAULT_LAYOUT_FEATURES.iter().copied().collect(),
+            &IntSet::empty(),
+            &IntSet::empty(),
+        );
+        plan.glyphset_gsub.iter().map(|g| g.to_u32()).collect()
+    }
+
+    #[test]
+    fn test_text_closure() {
+        // f, i, l and all of their ligatures
+        assert_eq!(
+            text_closure_glyphs(&[]),
+            [0, 74, 77, 80, 444, 445, 446, 447]
+        );
+        // f and i are next to each other only in the first text
+        assert_eq!(text_closure_glyphs(&["fi", "l"]), [0, 74, 77, 80, 444]);
+        assert_eq!(text_closure_glyphs(&["if", "l"]), [0, 74, 77, 80]);
+        // the space is in the text, l is closed over on its own
+        assert_eq!(text_closure_glyphs(&["i f"]), [0, 4, 74, 77, 80]);
+    }
+
+    #[test]
+    fn test_select_scripts() {
+        let font =
+            FontRef::new(include_bytes!("../../test-data/fonts/Roboto-Regular.ttf")).unwrap();
+        let script_list = font.gsub().unwrap().script_list().unwrap();
+        let select = |scripts: &[&[Tag]], layout_scripts: &IntSet<Tag>| {
+            select_scripts(&script_list, scripts, layout_scripts)
+        };
+
+        let latn: &[Tag] = &[Tag::new(b"latn")];
+        let deva: &[Tag] = &[Tag::new(b"dev2"), Tag::new(b"deva")];
+        assert_eq!(select(&[latn], &IntSet::all()), latn);
+        // the font has no Devanagari, nor does a text of digits have a script
+        assert_eq!(
+            select(&[latn, deva], &IntSet::all()),
+            [Tag::new(b"DFLT"), Tag::new(b"latn")]
+        );
+        assert_eq!(select(&[], &IntSet::all()), [Tag::new(b"DFLT")]);
+        assert!(select(&[latn], &IntSet::empty()).is_empty());
+    }
+
+    #[test]
+    fn test_text_closure_scripts() {
+        let font =
+            FontRef::new(include_bytes!("../../test-data/fonts/Roboto-Regular.ttf")).unwrap();
+        let gsub = font.gsub().unwrap();
+        // the glyphs of f and i, in a text of the given scripts
+        let closure = |text: &str| {
+            let mut plan = Plan {
+                layout_scripts: IntSet::all(),
+                layout_features: DEFAULT_LAYOUT_FEATURES.iter().copied().collect(),
+                ..Default::default()
+            };
+            let glyphs = vec![GlyphId::new(74), GlyphId::new(77)];
+            plan.layout_texts.push(LayoutText::new(text, glyphs));
+            closure_glyphs_for_texts(&gsub, font.gdef().ok().as_ref(), &mut plan);
+            plan.glyphset_gsub
+                .iter()
+                .map(|g| g.to_u32())
+                .collect::<Vec<_>>()
+        };
+
+        // only latn has a liga feature
+        assert_eq!(closure("fi"), [74, 77, 444]);
+        assert_eq!(closure("жи"), [74, 77]);
+        assert_eq!(closure("fж"), [74, 77, 444]);
+    }
+}
@@ -0,0 +1,240 @@
+//! The scripts of the characters of a text, as OpenType script tags
+//!
+//! Ranges follow the Unicode blocks of each script rather than the Scripts.txt
+//! property, so a few punctuation marks and digits inside a script's block count
+//! as that script. Characters outside these ranges make the text's scripts
+//! unknown, and then every script's lookups apply to it.
+
+use fontcull_write_fonts::types::Tag;
+
+const fn tag(tag: &[u8; 4]) -> Tag {
+    Tag::new(tag)
+}
+
+const LATN: &[Tag] = &[tag(b"latn")];
+const GREK: &[Tag] = &[tag(b"grek")];
+const CYRL: &[Tag] = &[tag(b"cyrl")];
+const ARMN: &[Tag] = &[tag(b"armn")];
+const HEBR: &[Tag] = &[tag(b"hebr")];
+const ARAB: &[Tag] = &[tag(b"arab")];
+const SYRC: &[Tag] = &[tag(b"syrc")];
+const THAA: &[Tag] = &[tag(b"thaa")];
+const NKO: &[Tag] = &[tag(b"nko ")];
+const SAMR: &[Tag] = &[tag(b"samr")];
+const MAND: &[Tag] = &[tag(b"mand")];
+// Indic scripts have a newer shaping model tag, preferred when the font has it
+const DEVA: &[Tag] = &[tag(b"dev2"), tag(b"deva")];
+const BENG: &[Tag] = &[tag(b"bng2"), tag(b"beng")];
+const GURU: &[Tag] = &[tag(b"gur2"), tag(b"guru")];
+const GUJR: &[Tag] = &[tag(b"gjr2"), tag(b"gujr")];
+const ORYA: &[Tag] = &[tag(b"ory2"), tag(b"orya")];
+const TAML: &[Tag] = &[tag(b"tml2"), tag(b"taml")];
+const TELU: &[Tag] = &[tag(b"tel2"), tag(b"telu")];
+const KNDA: &[Tag] = &[tag(b"knd2"), tag(b"knda")];
+const MLYM: &[Tag] = &[tag(b"mlm2"), tag(b"mlym")];
+const MYMR: &[Tag] = &[tag(b"mym2"), tag(b"mymr")];
+const SINH: &[Tag] = &[tag(b"sinh")];
+const THAI: &[Tag] = &[tag(b"thai")];
+const LAO: &[Tag] = &[tag(b"lao ")];
+const TIBT: &[Tag] = &[tag(b"tibt")];
+const GEOR: &[Tag] = &[tag(b"geor")];
+const HANG: &[Tag] = &[tag(b"hang")];
+const ETHI: &[Tag] = &[tag(b"ethi")];
+const CHER: &[Tag] = &[tag(b"cher")];
+const CANS: &[Tag] = &[tag(b"cans")];
+const OGAM: &[Tag] = &[tag(b"ogam")];
+const RUNR: &[Tag] = &[tag(b"runr")];
+const KHMR: &[Tag] = &[tag(b"khmr")];
+const MONG: &[Tag] = &[tag(b"mong")];
+const BALI: &[Tag] = &[tag(b"bali")];
+const SUND: &[Tag] = &[tag(b"sund")];
+const TFNG: &[Tag] = &[tag(b"tfng")];
+const KANA: &[Tag] = &[tag(b"kana")];
+const BOPO: &[Tag] = &[tag(b"bopo")];
+const HANI: &[Tag] = &[tag(b"hani")];
+const YI: &[Tag] = &[tag(b"yi  ")];
+const VAII: &[Tag] = &[tag(b"vaii")];
+const JAVA: &[Tag] = &[tag(b"java")];
+
+// (first, last, script), sorted and not overlapping. None is for characters
+// shared by every script (punctuation, symbols, combining marks), which take
+// the script of the text around them.
+#[rustfmt::skip]
+const RANGES: &[(u32, u32, Option<&[Tag]>)] = &[
+    (0x0000, 0x0040, None),
+    (0x0041, 0x005A, Some(LATN)),
+    (0x005B, 0x0060, None),
+    (0x0061, 0x007A, Some(LATN)),
+    (0x007B, 0x00A9, None),
+    (0x00AA, 0x00AA, Some(LATN)),
+    (0x00AB, 0x00B9, None),
+    (0x00BA, 0x00BA, Some(LATN)),
+    (0x00BB, 0x00BF, None),
+    (0x00C0, 0x00D6, Some(LATN)),
+    (0x00D7, 0x00D7, None),
+    (0x00D8, 0x00F6, Some(LATN)),
+    (0x00F7, 0x00F7, None),
+    (0x00F8, 0x02B8, Some(LATN)),
+    (0x02B9, 0x036F, None),
+    (0x0370, 0x03FF, Some(GREK)),
+    (0x0400, 0x052F, Some(CYRL)),
+    (0x0530, 0x058F, Some(ARMN)),
+    (0x0590, 0x05FF, Some(HEBR)),
+    (0x0600, 0x06FF, Some(ARAB)),
+    (0x0700, 0x074F, Some(SYRC)),
+    (0x0750, 0x077F, Some(ARAB)),
+    (0x0780, 0x07BF, Some(THAA)),
+    (0x07C0, 0x07FF, Some(NKO)),
+    (0x0800, 0x083F, Some(SAMR)),
+    (0x0840, 0x085F, Some(MAND)),
+    (0x0860, 0x086F, Some(SYRC)),
+    (0x0870, 0x08FF, Some(ARAB)),
+    (0x0900, 0x097F, Some(DEVA)),
+    (0x0980, 0x09FF, Some(BENG)),
+    (0x0A00, 0x0A7F, Some(GURU)),
+    (0x0A80, 0x0AFF, Some(GUJR)),
+    (0x0B00, 0x0B7F, Some(ORYA)),
+    (0x0B80, 0x0BFF, Some(TAML)),
+    (0x0C00, 0x0C7F, Some(TELU)),
+    (0x0C80, 0x0CFF, Some(KNDA)),
+    (0x0D00, 0x0D7F, Some(MLYM)),
+    (0x0D80, 0x0DFF, Some(SINH)),
+    (0x0E00, 0x0E7F, Some(THAI)),
+    (0x0E80, 0x0EFF, Some(LAO)),
+    (0x0F00, 0x0FFF, Some(TIBT)),
+    (0x1000, 0x109F, Some(MYMR)),
+    (0x10A0, 0x10FF, Some(GEOR)),
+    (0x1100, 0x11FF, Some(HANG)),
+    (0x1200, 0x139F, Some(ETHI)),
+    (0x13A0, 0x13FF, Some(CHER)),
+    (0x1400, 0x167F, Some(CANS)),
+    (0x1680, 0x169F, Some(OGAM)),
+    (0x16A0, 0x16FF, Some(RUNR)),
+    (0x1780, 0x17FF, Some(KHMR)),
+    (0x1800, 0x18AF, Some(MONG)),
+    (0x18B0, 0x18FF, Some(CANS)),
+    (0x19E0, 0x19FF, Some(KHMR)),
+    (0x1AB0, 0x1AFF, None),
+    (0x1B00, 0x1B7F, Some(BALI)),
+    (0x1B80, 0x1BBF, Some(SUND)),
+    (0x1C80, 0x1C8F, Some(CYRL)),
+    (0x1C90, 0x1CBF, Some(GEOR)),
+    (0x1D00, 0x1DBF, Some(LATN)),
+    (0x1DC0, 0x1DFF, None),
+    (0x1E00, 0x1EFF, Some(LATN)),
+    (0x1F00, 0x1FFF, Some(GREK)),
+    (0x2000, 0x2070, None),
+    (0x2071, 0x2071, Some(LATN)),
+    (0x2072, 0x207E, None),
+    (0x207F, 0x207F, Some(LATN)),
+    (0x2080, 0x208F, None),
+    (0x2090, 0x209C, Some(LATN)),
+    (0x209D, 0x2BFF, None),
+    (0x2C60, 0x2C7F, Some(LATN)),
+    (0x2D00, 0x2D2F, Some(GEOR)),
+    (0x2D30, 0x2D7F, Some(TFNG)),
+    (0x2D80, 0x2DDF, Some(ETHI)),
+    (0x2DE0, 0x2DFF, Some(CYRL)),
+    (0x2E00, 0x2E7F, None),
+    (0x2E80, 0x2FDF, Some(HANI)),
+    (0x2FF0, 0x3004, None),
+    (0x3005, 0x3005, Some(HANI)),
+    (0x3006, 0x3006, None),
+    (0x3007, 0x3007, Some(HANI)),
+    (0x3008, 0x3020, None),
+    (0x3021, 0x3029, Some(HANI)),
+    (0x302A, 0x3037, None),
+    (0x3038, 0x303B, Some(HANI)),
+    (0x303C, 0x303F, None),
+    (0x3040, 0x30FF, Some(KANA)),
+    (0x3100, 0x312F, Some(BOPO)),
+    (0x3130, 0x318F, Some(HANG)),
+    (0x3190, 0x319F, None),
+    (0x31A0, 0x31BF, Some(BOPO)),
+    (0x31C0, 0x31EF, None),
+    (0x31F0, 0x31FF, Some(KANA)),
+    (0x3200, 0x33FF, None),
+    (0x3400, 0x4DBF, Some(HANI)),
+    (0x4DC0, 0x4DFF, None),
+    (0x4E00, 0x9FFF, Some(HANI)),
+    (0xA000, 0xA4CF, Some(YI)),
+    (0xA500, 0xA63F, Some(VAII)),
+    (0xA640, 0xA69F, Some(CYRL)),
+    (0xA700, 0xA71F, None),
+    (0xA720, 0xA7FF, Some(LATN)),
+    (0xA8E0, 0xA8FF, Some(DEVA)),
+    (0xA960, 0xA97F, Some(HANG)),
+    (0xA980, 0xA9DF, Some(JAVA)),
+    (0xA9E0, 0xA9FF, Some(MYMR)),
+    (0xAA60, 0xAA7F, Some(MYMR)),
+    (0xAB00, 0xAB2F, Some(ETHI)),
+    (0xAB30, 0xAB6F, Some(LATN)),
+    (0xAB70, 0xABBF, Some(CHER)),
+    (0xAC00, 0xD7FF, Some(HANG)),
+    (0xF900, 0xFAFF, Some(HANI)),
+    (0xFB00, 0xFB06, Some(LATN)),
+    (0xFB13, 0xFB17, Some(ARMN)),
+    (0xFB1D, 0xFB4F, Some(HEBR)),
+    (0xFB50, 0xFDFF, Some(ARAB)),
+    (0xFE00, 0xFE6F, None),
+    (0xFE70, 0xFEFF, Some(ARAB)),
+    (0xFF00, 0xFF20, None),
+    (0xFF21, 0xFF3A, Some(LATN)),
+    (0xFF3B, 0xFF40, None),
+    (0xFF41, 0xFF5A, Some(LATN)),
+    (0xFF5B, 0xFF65, None),
+    (0xFF66, 0xFF9F, Some(KANA)),
+    (0xFFA0, 0xFFDF, Some(HANG)),
+    (0xFFE0, 0xFFFF, None),
+    (0x1F000, 0x1FBFF, None),
+    (0x20000, 0x3134F, Some(HANI)),
+    (0xE0000, 0xE01EF, None),
+];
+
+/// Tags of the scripts of a text's characters, each with its preferred tag
+/// first, or None if one of them is in no known range
+pub(crate) fn text_scripts(text: &str) -> Option<Vec<&'static [Tag]>> {
+    let mut scripts: Vec<&'static [Tag]> = Vec::new();
+    for c in text.chars() {
+        let c = c as u32;
+        let idx = RANGES
+            .binary_search_by(|(first, last, _)| {
+                if *last < c {
+                    std::cmp::Ordering::Less
+                } else if *first > c {
+                    std::cmp::Ordering::Greater
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+            .ok()?;
+        if let Some(script) = RANGES[idx].2 {
+            if !scripts.contains(&script) {
+                scripts.push(script);
+            }
+        }
+    }
+    Some(scripts)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ranges_are_sorted() {
+        for pair in RANGES.windows(2) {
+            assert!(pair[0].0 <= pair[0].1 && pair[0].1 < pair[1].0, "{pair:x?}");
+        }
+    }
+
+    #[test]
+    fn test_text_scripts() {
+        assert_eq!(text_scripts("fi, 12"), Some(vec![LATN]));
+        assert_eq!(text_scripts("Ab Жж б"), Some(vec![LATN, CYRL]));
+        assert_eq!(text_scripts("हिन्दी।"), Some(vec![DEVA]));
+        assert_eq!(text_scripts("12 — 3"), Some(vec![]));
+        // Tai Le isn't known
+        assert_eq!(text_scripts("a\u{1950}"), None);
+    }
+}
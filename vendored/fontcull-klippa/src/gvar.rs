@@ -4,22 +4,51 @@ use std::mem::size_of;
 use crate::{serialize::Serializer, Plan, Subset, SubsetError, SubsetFlags};
 
 use fontcull_write_fonts::{
-    read::{tables::gvar::Gvar, types::GlyphId, FontRef, TopLevelTable},
+    dump_table,
+    read::{
+        tables::{
+            glyf::Glyph,
+            gvar::{GlyphDelta, Gvar},
+            variations::TupleVariation,
+        },
+        types::GlyphId,
+        FontRef, TableProvider, TopLevelTable,
+    },
+    tables::gvar::{
+        iup::iup_delta_optimize, GlyphDelta as OutputGlyphDelta, GlyphDeltas, GlyphVariations,
+        Gvar as OutputGvar, Tent,
+    },
     types::Scalar,
     FontBuilder,
 };
+use kurbo::{Point, Vec2};
 
 const FIXED_HEADER_SIZE: u32 = 20;
+// same tolerance as hb-subset uses when optimizing deltas
+const IUP_TOLERANCE: f64 = 0.5;
+
 // reference: subset() for gvar table in harfbuzz
 // https://github.com/harfbuzz/harfbuzz/blob/63d09dbefcf7ad9f794ca96445d37b6d8c3c9124/src/hb-ot-var-gvar-table.hh#L411
 impl Subset for Gvar<'_> {
     fn subset(
         &self,
         plan: &Plan,
-        _font: &FontRef,
+        font: &FontRef,
         s: &mut Serializer,
         _builder: &mut FontBuilder,
     ) -> Result<(), SubsetError> {
+        if plan
+            .subset_flags
+            .contains(SubsetFlags::SUBSET_FLAGS_OPTIMIZE_IUP_DELTAS)
+        {
+            // fall back to copying the variation data when it can't be re-optimized
+            if let Some(gvar_data) = subset_with_iup_optimization(self, plan, font) {
+                s.embed_bytes(&gvar_data)
+                    .map_err(|_| SubsetError::SubsetTableError(Gvar::TAG))?;
+                return Ok(());
+            }
+        }
+
         //table header: from version to sharedTuplesOffset
         s.embed_bytes(self.offset_data().as_bytes().get(0..12).unwrap())
             .map_err(|_| SubsetError::SubsetTableError(Gvar::TAG))?;
@@ -155,6 +184,201 @@ fn subset_with_offset_type<OffsetType: GvarOffset>(
     Ok(())
 }
 
+// Decode the deltas of each kept glyph, infer the un-referenced ones and re-encode
+// them with IUP optimization. Shared tuples and shared point numbers are
+// recomputed for the subset glyphs when the table is rebuilt.
+fn subset_with_iup_optimization(gvar: &Gvar, plan: &Plan, font: &FontRef) -> Option<Vec<u8>> {
+    let glyf = font.glyf().ok()?;
+    let loca = font.loca(None).ok()?;
+    let axis_count = gvar.axis_count();
+
+    let mut variations: Vec<GlyphVariations> = (0..plan.num_output_glyphs as u32)
+        .map(|gid| GlyphVariations::new(GlyphId::new(gid), Vec::new()))
+        .collect();
+    for (new_gid, old_gid) in plan.new_to_old_gid_list.iter() {
+        if *new_gid == GlyphId::NOTDEF
+            && !plan
+                .subset_flags
+                .contains(SubsetFlags::SUBSET_FLAGS_NOTDEF_OUTLINE)
+        {
+            continue;
+        }
+        let Some(var_data) = gvar.glyph_variation_data(*old_gid).ok()? else {
+            continue;
+        };
+        let outline = VarOutline::new(loca.get_glyf(*old_gid, &glyf).ok()?)?;
+
+        let mut glyph_deltas = Vec::new();
+        for tuple in var_data.tuples() {
+            let deltas = outline.optimize_deltas(&tuple)?;
+            // a tuple without any encoded delta has no effect, and an empty
+            // point number list would mean "all points"
+            if !deltas.iter().any(|delta| delta.required) {
+                continue;
+            }
+            glyph_deltas.push(GlyphDeltas::new(tuple_tents(&tuple, axis_count)?, deltas));
+        }
+        let slot = variations.get_mut(new_gid.to_u32() as usize)?;
+        *slot = GlyphVariations::new(*new_gid, glyph_deltas);
+    }
+
+    let gvar_out = OutputGvar::new(variations, axis_count).ok()?;
+    dump_table(&gvar_out).ok()
+}
+
+fn tuple_tents(tuple: &TupleVariation<GlyphDelta>, axis_count: u16) -> Option<Vec<Tent>> {
+    let peak = tuple.peak();
+    let intermediate = tuple.intermediate_start().zip(tuple.intermediate_end());
+    (0..axis_count as usize)
+        .map(|i| {
+            let region = match &intermediate {
+                Some((start, end)) => Some((start.get(i)?, end.get(i)?)),
+                None => None,
+            };
+            Some(Tent::new(peak.get(i)?, region))
+        })
+        .collect()
+}
+
+/// The points a glyph's variation deltas apply to, including the 4 phantom points.
+struct VarOutline {
+    coords: Vec<Point>,
+    // contour end points of a simple glyph, None for composite glyphs
+    contour_ends: Option<Vec<usize>>,
+}
+
+impl VarOutline {
+    fn new(glyph: Option<Glyph>) -> Option<Self> {
+        // phantom points are never part of a contour, so their position doesn't
+        // matter when inferring or optimizing deltas
+        let phantom_points = [Point::ZERO; 4];
+        match glyph {
+            None => Some(Self {
+                coords: phantom_points.to_vec(),
+                contour_ends: Some(Vec::new()),
+            }),
+            Some(Glyph::Simple(simple)) => {
+                let mut coords: Vec<Point> = simple
+                    .points()
+                    .map(|point| Point::new(point.x as f64, point.y as f64))
+                    .collect();
+                let contour_ends: Vec<usize> = simple
+                    .end_pts_of_contours()
+                    .iter()
+                    .map(|end| end.get() as usize)
+                    .collect();
+                if coords.len() != simple.num_points()
+                    || contour_ends.windows(2).any(|ends| ends[0] >= ends[1])
+                {
+                    return None;
+                }
+                coords.extend(phantom_points);
+                Some(Self {
+                    coords,
+                    contour_ends: Some(contour_ends),
+                })
+            }
+            Some(Glyph::Composite(composite)) => {
+                let num_components = composite.components().count();
+                Some(Self {
+                    coords: vec![Point::ZERO; num_components + 4],
+                    contour_ends: None,
+                })
+            }
+        }
+    }
+
+    // every point's delta for this tuple, with the un-referenced ones inferred
+    fn deltas(&self, tuple: &TupleVariation<GlyphDelta>) -> Option<Vec<Vec2>> {
+        let num_points = self.coords.len();
+        let mut deltas = vec![Vec2::ZERO; num_points];
+        let mut touched = vec![false; num_points];
+        for delta in tuple.deltas() {
+            let idx = delta.position as usize;
+            *deltas.get_mut(idx)? += Vec2::new(delta.x_delta as f64, delta.y_delta as f64);
+            touched[idx] = true;
+        }
+        // component deltas are never interpolated: any delta that isn't
+        // encoded is zero
+        if let Some(contour_ends) = &self.contour_ends {
+            infer_deltas(&self.coords, contour_ends, &mut deltas, &touched);
+        }
+        Some(deltas)
+    }
+
+    fn optimize_deltas(&self, tuple: &TupleVariation<GlyphDelta>) -> Option<Vec<OutputGlyphDelta>> {
+        // optimize the deltas that will actually be encoded: rounding after
+        // optimization could change what the kept deltas interpolate to
+        let deltas: Vec<Vec2> = self
+            .deltas(tuple)?
+            .into_iter()
+            .map(|delta| Vec2::new((delta.x + 0.5).floor(), (delta.y + 0.5).floor()))
+            .collect();
+        if deltas
+            .iter()
+            .any(|delta| delta.x.abs() > i16::MAX as f64 || delta.y.abs() > i16::MAX as f64)
+        {
+            return None;
+        }
+
+        match &self.contour_ends {
+            Some(contour_ends) => {
+                iup_delta_optimize(deltas, self.coords.clone(), IUP_TOLERANCE, contour_ends).ok()
+            }
+            None => Some(
+                deltas
+                    .iter()
+                    .map(|delta| {
+                        let (x, y) = (delta.x as i16, delta.y as i16);
+                        OutputGlyphDelta::new(x, y, x != 0 || y != 0)
+                    })
+                    .collect(),
+            ),
+        }
+    }
+}
+
+// Infer the deltas of points that are not referenced by a tuple, see
+// <https://learn.microsoft.com/en-us/typography/opentype/spec/gvar#inferred-deltas-for-un-referenced-point-numbers>
+fn infer_deltas(coords: &[Point], contour_ends: &[usize], deltas: &mut [Vec2], touched: &[bool]) {
+    let mut start = 0;
+    for &end in contour_ends {
+        let touched_points: Vec<usize> = (start..=end).filter(|i| touched[*i]).collect();
+        for (i, &prev) in touched_points.iter().enumerate() {
+            let next = touched_points[(i + 1) % touched_points.len()];
+            let mut idx = if prev == end { start } else { prev + 1 };
+            while idx != next {
+                let (p, p1, p2) = (coords[idx], coords[prev], coords[next]);
+                let (d1, d2) = (deltas[prev], deltas[next]);
+                deltas[idx] = Vec2::new(
+                    interpolate(p.x, p1.x, p2.x, d1.x, d2.x),
+                    interpolate(p.y, p1.y, p2.y, d1.y, d2.y),
+                );
+                idx = if idx == end { start } else { idx + 1 };
+            }
+        }
+        start = end + 1;
+    }
+}
+
+fn interpolate(coord: f64, c1: f64, c2: f64, d1: f64, d2: f64) -> f64 {
+    if c1 == c2 {
+        return if d1 == d2 { d1 } else { 0.0 };
+    }
+    let (c1, c2, d1, d2) = if c1 < c2 {
+        (c1, c2, d1, d2)
+    } else {
+        (c2, c1, d2, d1)
+    };
+    if coord <= c1 {
+        d1
+    } else if coord >= c2 {
+        d2
+    } else {
+        d1 + (coord - c1) * (d2 - d1) / (c2 - c1)
+    }
+}
+
 trait GvarOffset: Scalar {
     fn stored_value(val: u32) -> Self;
 }
@@ -170,3 +394,103 @@ impl GvarOffset for u32 {
         val
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fontcull_write_fonts::read::{types::F2Dot14, FontData, FontRead};
+    use std::collections::HashMap;
+
+    fn subset_gvar(font: &FontRef, old_gids: &[u32], flags: SubsetFlags) -> Vec<u8> {
+        let mut plan = Plan {
+            subset_flags: flags,
+            num_output_glyphs: old_gids.len(),
+            ..Default::default()
+        };
+        plan.new_to_old_gid_list.extend(
+            old_gids
+                .iter()
+                .enumerate()
+                .map(|(new_gid, old_gid)| (GlyphId::new(new_gid as u32), GlyphId::new(*old_gid))),
+        );
+
+        let gvar = font.gvar().unwrap();
+        let mut s = Serializer::new(gvar.offset_data().len());
+        assert_eq!(s.start_serialize(), Ok(()));
+        let mut builder = FontBuilder::default();
+        gvar.subset(&plan, font, &mut s, &mut builder).unwrap();
+        s.end_serialize();
+        assert!(!s.in_error());
+        s.copy_bytes()
+    }
+
+    // sum of the full deltas of a glyph's tuples, keyed by their regions
+    fn glyph_deltas(
+        gvar: &Gvar,
+        gid: u32,
+        outline: &VarOutline,
+    ) -> HashMap<Vec<F2Dot14>, Vec<Vec2>> {
+        let mut ret: HashMap<Vec<F2Dot14>, Vec<Vec2>> = HashMap::new();
+        let Some(var_data) = gvar.glyph_variation_data(GlyphId::new(gid)).unwrap() else {
+            return ret;
+        };
+        for tuple in var_data.tuples() {
+            let mut region: Vec<F2Dot14> = tuple.peak().values().iter().map(|v| v.get()).collect();
+            if let Some((start, end)) = tuple.intermediate_start().zip(tuple.intermediate_end()) {
+                region.extend(start.values().iter().chain(end.values()).map(|v| v.get()));
+            }
+            let deltas = outline.deltas(&tuple).unwrap();
+            let sum = ret
+                .entry(region)
+                .or_insert_with(|| vec![Vec2::ZERO; deltas.len()]);
+            sum.iter_mut()
+                .zip(deltas)
+                .for_each(|(sum, delta)| *sum += delta);
+        }
+        ret
+    }
+
+    #[test]
+    fn test_subset_gvar_optimize_iup_deltas() {
+        let font =
+            FontRef::new(include_bytes!("../test-data/fonts/RobotoFlex-Variable.ttf")).unwrap();
+        let old_gids: Vec<u32> = (0..200).collect();
+
+        let copied = subset_gvar(&font, &old_gids, SubsetFlags::default());
+        let optimized = subset_gvar(
+            &font,
+            &old_gids,
+            SubsetFlags::SUBSET_FLAGS_OPTIMIZE_IUP_DELTAS,
+        );
+        assert!(optimized.len() < copied.len());
+
+        let copied = Gvar::read(FontData::new(&copied)).unwrap();
+        let optimized = Gvar::read(FontData::new(&optimized)).unwrap();
+        assert_eq!(optimized.glyph_count(), copied.glyph_count());
+        assert_eq!(optimized.axis_count(), copied.axis_count());
+
+        let loca = font.loca(None).unwrap();
+        let glyf = font.glyf().unwrap();
+        for gid in old_gids {
+            let glyph = loca.get_glyf(GlyphId::new(gid), &glyf).unwrap();
+            let outline = VarOutline::new(glyph).unwrap();
+            let expected = glyph_deltas(&copied, gid, &outline);
+            let actual = glyph_deltas(&optimized, gid, &outline);
+            // tuples without deltas are dropped
+            assert!(actual.keys().all(|region| expected.contains_key(region)));
+            for (region, expected) in expected {
+                let actual = actual
+                    .get(&region)
+                    .cloned()
+                    .unwrap_or_else(|| vec![Vec2::ZERO; expected.len()]);
+                for (a, e) in actual.iter().zip(&expected) {
+                    // half a unit for rounding plus the IUP tolerance
+                    assert!(
+                        (a.x - e.x).abs() <= 1.0 && (a.y - e.y).abs() <= 1.0,
+                        "gid {gid}: {a:?} vs {e:?}"
+                    );
+                }
+            }
+        }
+    }
+}
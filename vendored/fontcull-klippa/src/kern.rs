@@ -0,0 +1,861 @@
+//! impl subset() for kern
+
+use std::collections::BTreeMap;
+
+use crate::serialize::{SerializeErrorFlags, Serializer};
+use crate::{Plan, Subset, SubsetError, SubsetFlags, SubsetState, KERN};
+use fnv::FnvHashMap;
+use fontcull_write_fonts::{
+    dump_table,
+    read::{
+        tables::kern::{Kern, Subtable, Subtable2, Subtable3, SubtableKind},
+        FontData, FontRead, FontRef, TableProvider, TopLevelTable,
+    },
+    tables::gpos::ValueRecord,
+    tables::{
+        gpos::{Gpos, PairPos, PairSet, PairValueRecord, PositionLookup, PositionLookupList},
+        layout::{
+            Feature, FeatureList, FeatureRecord, LangSys, Lookup, LookupFlag, Script, ScriptList,
+            ScriptRecord,
+        },
+    },
+    types::{GlyphId, GlyphId16, MajorMinor, Tag},
+    FontBuilder,
+};
+
+// OpenType kern subtable coverage bits
+// <https://learn.microsoft.com/en-us/typography/opentype/spec/kern#kerning-subtables>
+const OT_COVERAGE_MINIMUM: u16 = 1 << 1;
+const OT_COVERAGE_OVERRIDE: u16 = 1 << 3;
+
+// Pairs in one PairPos subtable, so it stays well below 64K of 16-bit offsets
+const MAX_PAIRS_PER_SUBTABLE: usize = 8000;
+
+const KERN_FEATURE: Tag = Tag::new(b"kern");
+
+/// Kerning pairs collected from the kern table, keyed by new (left, right) glyph IDs
+pub(crate) type KernPairs = BTreeMap<(u16, u16), i32>;
+
+// reference: kern subtable formats in the OpenType and Apple specs
+// <https://learn.microsoft.com/en-us/typography/opentype/spec/kern>
+// <https://developer.apple.com/fonts/TrueType-Reference-Manual/RM06/Chap6kern.html>
+impl Subset for Kern<'_> {
+    fn subset_with_state(
+        &self,
+        plan: &Plan,
+        font: &FontRef,
+        state: &mut SubsetState,
+        s: &mut Serializer,
+        _builder: &mut FontBuilder,
+    ) -> Result<(), SubsetError> {
+        let to_gpos = converts_to_gpos(plan, font);
+        let num_tables_pos = match self {
+            Kern::Ot(_) => {
+                s.embed(0_u16)
+                    .map_err(|_| SubsetError::SubsetTableError(KERN))?;
+                s.embed(0_u16)
+            }
+            Kern::Aat(_) => {
+                s.embed(MajorMinor::VERSION_1_0)
+                    .map_err(|_| SubsetError::SubsetTableError(KERN))?;
+                s.embed(0_u32)
+            }
+        }
+        .map_err(|_| SubsetError::SubsetTableError(KERN))?;
+
+        // collected apart from state, which keeps its pairs when the
+        // serializer runs out of room and the table is subset again
+        let mut kern_pairs = KernPairs::new();
+        let mut num_tables: u32 = 0;
+        // an OpenType subtable longer than its 16-bit length field can only
+        // be the last one, whose end is found from the end of the table
+        let mut overlong = false;
+        for subtable in self.subtables() {
+            let subtable = subtable.map_err(|_| SubsetError::SubsetTableError(KERN))?;
+            if to_gpos && collect_gpos_pairs(&subtable, plan, &mut kern_pairs) {
+                continue;
+            }
+
+            let snap = s.snapshot();
+            match subset_subtable(&subtable, plan, s) {
+                Ok(length) => {
+                    if overlong {
+                        tracing::warn!(
+                            "kern subtable {} is longer than 64K and not the last one",
+                            num_tables - 1
+                        );
+                        return Err(SubsetError::SubsetTableError(KERN));
+                    }
+                    overlong = matches!(self, Kern::Ot(_)) && length > u16::MAX as usize;
+                    num_tables += 1;
+                }
+                Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY) => s.revert_snapshot(snap),
+                Err(_) => return Err(SubsetError::SubsetTableError(KERN)),
+            }
+        }
+
+        state.kern_pairs.extend(kern_pairs);
+
+        // all subtables were dropped or moved to GPOS, so the table is omitted
+        if num_tables == 0 {
+            return Err(SubsetError::SubsetTableError(KERN));
+        }
+        match self {
+            Kern::Ot(_) => s.copy_assign(num_tables_pos, num_tables as u16),
+            Kern::Aat(_) => s.copy_assign(num_tables_pos, num_tables),
+        }
+        Ok(())
+    }
+}
+
+/// Whether the kern table moves to a GPOS `kern` feature: asked for, GPOS and
+/// its `kern` feature are kept, and the font's GPOS doesn't kern already, in
+/// which case shapers ignore the kern table anyway
+fn converts_to_gpos(plan: &Plan, font: &FontRef) -> bool {
+    if !plan
+        .subset_flags
+        .contains(SubsetFlags::SUBSET_FLAGS_KERN_TO_GPOS)
+        || plan.drop_tables.contains(Gpos::TAG)
+        || !plan.layout_features.contains(KERN_FEATURE)
+    {
+        return false;
+    }
+    let Ok(gpos) = font.gpos() else {
+        return true;
+    };
+    gpos.feature_list().map_or(true, |features| {
+        !features
+            .feature_records()
+            .iter()
+            .any(|record| record.feature_tag() == KERN_FEATURE)
+    })
+}
+
+/// Returns the length of the subset subtable
+fn subset_subtable(
+    subtable: &Subtable,
+    plan: &Plan,
+    s: &mut Serializer,
+) -> Result<usize, SerializeErrorFlags> {
+    let start = s.head();
+    let length_pos = match subtable {
+        Subtable::Ot(st) => {
+            s.embed(st.version())?;
+            let pos = s.embed(0_u16)?;
+            s.embed(st.coverage())?;
+            pos
+        }
+        Subtable::Aat(st) => {
+            let pos = s.embed(0_u32)?;
+            s.embed(st.coverage())?;
+            s.embed(st.tuple_index())?;
+            pos
+        }
+    };
+    let header_len = s.head() - start;
+
+    match subtable
+        .kind()
+        .map_err(|_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)?
+    {
+        SubtableKind::Format0(format0) => {
+            let pairs = format0.pairs().iter().filter_map(|pair| {
+                let left = plan.glyph_map.get(&GlyphId::from(pair.left()))?;
+                let right = plan.glyph_map.get(&GlyphId::from(pair.right()))?;
+                Some((left.to_u32() as u16, right.to_u32() as u16, pair.value()))
+            });
+            subset_format0(pairs.collect(), s)?
+        }
+        SubtableKind::Format2(format2) => subset_format2(&format2, header_len, plan, s)?,
+        SubtableKind::Format3(format3) => subset_format3(&format3, plan, s)?,
+        // TODO: subset the glyph classes of format 1 state tables
+        SubtableKind::Format1(_) => {
+            tracing::warn!("dropping a kern format 1 subtable, state tables aren't subset");
+            return Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY);
+        }
+    }
+
+    let length = s.head() - start;
+    match subtable {
+        // The last OpenType subtable may be longer than its 16-bit length
+        // says, see Subtables in read-fonts
+        Subtable::Ot(_) => s.copy_assign(length_pos, length.min(u16::MAX as usize) as u16),
+        Subtable::Aat(_) => s.check_assign::<u32>(
+            length_pos,
+            length,
+            SerializeErrorFlags::SERIALIZE_ERROR_INT_OVERFLOW,
+        )?,
+    }
+    Ok(length)
+}
+
+fn subset_format0(
+    mut pairs: Vec<(u16, u16, i16)>,
+    s: &mut Serializer,
+) -> Result<(), SerializeErrorFlags> {
+    if pairs.is_empty() {
+        return Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY);
+    }
+    pairs.sort_unstable_by_key(|(left, right, _)| (*left, *right));
+
+    let num_pairs: u16 = pairs
+        .len()
+        .try_into()
+        .map_err(|_| SerializeErrorFlags::SERIALIZE_ERROR_INT_OVERFLOW)?;
+    // binary search header, in units of 6-byte pairs, clamped like fonttools
+    // does for subtables of more than 10922 pairs
+    let entry_selector = num_pairs.ilog2();
+    let search_range = 6 * (1_u32 << entry_selector);
+    let range_shift = num_pairs as u32 * 6 - search_range;
+    s.embed(num_pairs)?;
+    s.embed(search_range.min(u16::MAX as u32) as u16)?;
+    s.embed(entry_selector as u16)?;
+    s.embed(range_shift.min(u16::MAX as u32) as u16)?;
+
+    for (left, right, value) in pairs {
+        s.embed(left)?;
+        s.embed(right)?;
+        s.embed(value)?;
+    }
+    Ok(())
+}
+
+// Format 2 keeps its kerning array and rewrites the class tables for the new
+// glyph IDs. Left class values are offsets from the start of the subtable, so
+// they move with the array.
+fn subset_format2(
+    format2: &Subtable2,
+    header_len: usize,
+    plan: &Plan,
+    s: &mut Serializer,
+) -> Result<(), SerializeErrorFlags> {
+    let read_error = |_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR;
+    let row_width = format2.data.read_at::<u16>(0).map_err(read_error)?;
+    let old_array_start = format2.array_offset + format2.header_len;
+
+    // new gid -> row offset into the array, for left glyphs that have a row
+    let left: BTreeMap<u16, usize> = class_values(&format2.left_offset_table, &plan.glyph_map)
+        .filter_map(|(gid, value)| Some((gid, value.checked_sub(old_array_start)?)))
+        .collect();
+    // new gid -> byte offset of the column in a row
+    let right: BTreeMap<u16, usize> =
+        class_values(&format2.right_offset_table, &plan.glyph_map).collect();
+    if left.is_empty() || right.is_empty() {
+        return Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY);
+    }
+
+    let array_len = left.values().max().unwrap() + right.values().max().unwrap() + 2;
+    let array = format2
+        .data
+        .slice(format2.array_offset..format2.array_offset + array_len)
+        .ok_or(SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)?;
+
+    let class_table_len =
+        |table: &BTreeMap<u16, usize>| 4 + 2 * table_span(table.keys().copied()) as usize;
+    // header, then row width and three offsets, then both class tables
+    let left_table_start = header_len + 8;
+    let right_table_start = left_table_start + class_table_len(&left);
+    let new_array_start = right_table_start + class_table_len(&right);
+
+    s.embed(row_width)?;
+    for offset in [left_table_start, right_table_start, new_array_start] {
+        let pos = s.embed(0_u16)?;
+        s.check_assign::<u16>(
+            pos,
+            offset,
+            SerializeErrorFlags::SERIALIZE_ERROR_OFFSET_OVERFLOW,
+        )?;
+    }
+    serialize_class_table(s, &left, new_array_start)?;
+    serialize_class_table(s, &right, 0)?;
+    s.embed_bytes(array.as_bytes())?;
+    Ok(())
+}
+
+/// (new gid, class value) for the retained glyphs with a non-zero value in a
+/// format 2 class table
+fn class_values<'a>(
+    table: &'a fontcull_write_fonts::read::tables::kern::Subtable2ClassTable,
+    glyph_map: &'a FnvHashMap<GlyphId, GlyphId>,
+) -> impl Iterator<Item = (u16, usize)> + 'a {
+    let first = table.first_glyph().to_u32();
+    table
+        .offsets()
+        .iter()
+        .enumerate()
+        .filter(|(_, value)| value.get() != 0)
+        .filter_map(move |(i, value)| {
+            let new_gid = glyph_map.get(&GlyphId::new(first + i as u32))?;
+            Some((new_gid.to_u32() as u16, value.get() as usize))
+        })
+}
+
+fn table_span(gids: impl Iterator<Item = u16> + Clone) -> u16 {
+    let first = gids.clone().min().unwrap_or(0);
+    let last = gids.max().unwrap_or(0);
+    last - first + 1
+}
+
+fn serialize_class_table(
+    s: &mut Serializer,
+    values: &BTreeMap<u16, usize>,
+    bias: usize,
+) -> Result<(), SerializeErrorFlags> {
+    let first = *values.keys().next().unwrap();
+    let count = table_span(values.keys().copied());
+    s.embed(first)?;
+    s.embed(count)?;
+    for gid in first..first + count {
+        let pos = s.embed(0_u16)?;
+        if let Some(value) = values.get(&gid) {
+            s.check_assign::<u16>(
+                pos,
+                value + bias,
+                SerializeErrorFlags::SERIALIZE_ERROR_OFFSET_OVERFLOW,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// Format 3 keeps its values and kern index, and has one left and one right
+// class per glyph
+fn subset_format3(
+    format3: &Subtable3,
+    plan: &Plan,
+    s: &mut Serializer,
+) -> Result<(), SerializeErrorFlags> {
+    let num_glyphs: u16 = plan
+        .num_output_glyphs
+        .try_into()
+        .map_err(|_| SerializeErrorFlags::SERIALIZE_ERROR_INT_OVERFLOW)?;
+    s.embed(num_glyphs)?;
+    s.embed(format3.kern_value_count())?;
+    s.embed(format3.left_class_count())?;
+    s.embed(format3.right_class_count())?;
+    s.embed(format3.flags())?;
+    for value in format3.kern_value() {
+        s.embed(value.get())?;
+    }
+    for classes in [format3.left_class(), format3.right_class()] {
+        for new_gid in 0..num_glyphs {
+            let class = plan
+                .reverse_glyph_map
+                .get(&GlyphId::from(new_gid))
+                .and_then(|old_gid| classes.get(old_gid.to_u32() as usize))
+                .copied()
+                .unwrap_or(0);
+            s.embed(class)?;
+        }
+    }
+    s.embed_bytes(format3.kern_index())?;
+    Ok(())
+}
+
+/// Add the pairs of a horizontal kern subtable to `pairs`, returning false
+/// for subtables GPOS can't express: vertical, cross-stream, variable and
+/// minimum values, and state tables
+fn collect_gpos_pairs(subtable: &Subtable, plan: &Plan, pairs: &mut KernPairs) -> bool {
+    let coverage = match subtable {
+        Subtable::Ot(st) => st.coverage(),
+        Subtable::Aat(_) => 0,
+    };
+    if !subtable.is_horizontal()
+        || subtable.is_cross_stream()
+        || subtable.is_variable()
+        || coverage & OT_COVERAGE_MINIMUM != 0
+    {
+        return false;
+    }
+    let Ok(kind) = subtable.kind() else {
+        return false;
+    };
+
+    let mut add = |left: GlyphId, right: GlyphId, value: i32| {
+        let key = (left.to_u32() as u16, right.to_u32() as u16);
+        if coverage & OT_COVERAGE_OVERRIDE != 0 {
+            pairs.insert(key, value);
+        } else {
+            *pairs.entry(key).or_default() += value;
+        }
+    };
+    match kind {
+        SubtableKind::Format0(format0) => {
+            for pair in format0.pairs() {
+                let (Some(left), Some(right)) = (
+                    plan.glyph_map.get(&GlyphId::from(pair.left())),
+                    plan.glyph_map.get(&GlyphId::from(pair.right())),
+                ) else {
+                    continue;
+                };
+                add(*left, *right, pair.value() as i32);
+            }
+        }
+        SubtableKind::Format2(format2) => {
+            let left = class_groups(plan, |gid| format2_class(&format2.left_offset_table, gid));
+            let right = class_groups(plan, |gid| format2_class(&format2.right_offset_table, gid));
+            add_class_pairs(&left, &right, |l, r| format2.kerning(l, r), add);
+        }
+        SubtableKind::Format3(format3) => {
+            let class = |classes: &[u8], gid: GlyphId| classes.get(gid.to_u32() as usize).copied();
+            let left = class_groups(plan, |gid| class(format3.left_class(), gid).map(u16::from));
+            let right = class_groups(plan, |gid| class(format3.right_class(), gid).map(u16::from));
+            add_class_pairs(&left, &right, |l, r| format3.kerning(l, r), add);
+        }
+        SubtableKind::Format1(_) => return false,
+    }
+    true
+}
+
+/// Retained glyphs as (new gid, old gid), grouped by their class in a format 2
+/// or 3 subtable; glyphs without a class are left out
+type ClassGroups = BTreeMap<u16, Vec<(GlyphId, GlyphId)>>;
+
+fn class_groups(plan: &Plan, class: impl Fn(GlyphId) -> Option<u16>) -> ClassGroups {
+    let mut groups = ClassGroups::new();
+    for (new_gid, old_gid) in &plan.new_to_old_gid_list {
+        if let Some(class) = class(*old_gid) {
+            groups.entry(class).or_default().push((*new_gid, *old_gid));
+        }
+    }
+    groups
+}
+
+/// The class value of a glyph in a format 2 class table, 0 for glyphs outside
+/// of it
+fn format2_class(
+    table: &fontcull_write_fonts::read::tables::kern::Subtable2ClassTable,
+    gid: GlyphId,
+) -> Option<u16> {
+    let index = gid.to_u32().checked_sub(table.first_glyph().to_u32());
+    Some(
+        index
+            .and_then(|i| table.offsets().get(i as usize))
+            .map_or(0, |value| value.get()),
+    )
+}
+
+/// Look up the kerning of every left and right class once, using the first
+/// glyph of each class, and add the non-zero values for all glyphs of both
+fn add_class_pairs(
+    left: &ClassGroups,
+    right: &ClassGroups,
+    kerning: impl Fn(GlyphId, GlyphId) -> Option<i32>,
+    mut add: impl FnMut(GlyphId, GlyphId, i32),
+) {
+    for left_glyphs in left.values() {
+        for right_glyphs in right.values() {
+            let Some(value) = kerning(left_glyphs[0].1, right_glyphs[0].1).filter(|v| *v != 0)
+            else {
+                continue;
+            };
+            for (new_left, _) in left_glyphs {
+                for (new_right, _) in right_glyphs {
+                    add(*new_left, *new_right, value);
+                }
+            }
+        }
+    }
+}
+
+/// Add `pairs` to a GPOS table (or a new one) as a lookup of the `kern`
+/// feature, in every script and language system
+pub(crate) fn add_kern_feature(
+    gpos: Option<&[u8]>,
+    pairs: &KernPairs,
+) -> Result<Vec<u8>, SubsetError> {
+    let gpos_error = |_| SubsetError::SubsetTableError(Gpos::TAG);
+    let mut gpos = match gpos {
+        Some(data) => Gpos::read(FontData::new(data)).map_err(gpos_error)?,
+        None => Gpos::new(
+            ScriptList::new(Vec::new()),
+            FeatureList::new(Vec::new()),
+            PositionLookupList::new(Vec::new()),
+        ),
+    };
+
+    let lookup_index = gpos.lookup_list.lookups.len() as u16;
+    gpos.lookup_list.lookups.push(
+        PositionLookup::Pair(Lookup::new(LookupFlag::empty(), pair_pos_subtables(pairs))).into(),
+    );
+
+    // feature records are sorted by tag, so later features move up by one
+    let features = &mut gpos.feature_list.feature_records;
+    let feature_index = features
+        .iter()
+        .position(|record| record.feature_tag > KERN_FEATURE)
+        .unwrap_or(features.len());
+    features.insert(
+        feature_index,
+        FeatureRecord::new(KERN_FEATURE, Feature::new(None, vec![lookup_index])),
+    );
+    let feature_index = feature_index as u16;
+    let remap = |index: &mut u16| {
+        if *index != 0xFFFF && *index >= feature_index {
+            *index += 1;
+        }
+    };
+
+    let scripts = &mut gpos.script_list.script_records;
+    if scripts.is_empty() {
+        scripts.push(ScriptRecord::new(
+            Tag::new(b"DFLT"),
+            Script::new(Some(LangSys::new(Vec::new())), Vec::new()),
+        ));
+    }
+    for record in scripts.iter_mut() {
+        let script = &mut *record.script;
+        let lang_syses = script
+            .default_lang_sys
+            .as_mut()
+            .into_iter()
+            .chain(script.lang_sys_records.iter_mut().map(|r| &mut *r.lang_sys));
+        for lang_sys in lang_syses {
+            remap(&mut lang_sys.required_feature_index);
+            lang_sys.feature_indices.iter_mut().for_each(remap);
+            lang_sys.feature_indices.push(feature_index);
+            lang_sys.feature_indices.sort_unstable();
+        }
+    }
+    if let Some(variations) = gpos.feature_variations.as_mut() {
+        for record in &mut variations.feature_variation_records {
+            if let Some(substitution) = record.feature_table_substitution.as_mut() {
+                for record in &mut substitution.substitutions {
+                    remap(&mut record.feature_index);
+                }
+            }
+        }
+    }
+
+    dump_table(&gpos).map_err(|_| SubsetError::SubsetTableError(Gpos::TAG))
+}
+
+fn pair_pos_subtables(pairs: &KernPairs) -> Vec<PairPos> {
+    let mut subtables = Vec::new();
+    let mut coverage: Vec<GlyphId16> = Vec::new();
+    let mut pair_sets: Vec<PairSet> = Vec::new();
+    let mut num_pairs = 0;
+
+    let mut pairs = pairs
+        .iter()
+        .map(|(&(left, right), &value)| {
+            (
+                left,
+                right,
+                value.clamp(i16::MIN as i32, i16::MAX as i32) as i16,
+            )
+        })
+        .filter(|(_, _, value)| *value != 0)
+        .peekable();
+    while let Some((left, right, value)) = pairs.next() {
+        let mut records = vec![pair_value_record(right, value)];
+        while let Some((_, right, value)) = pairs.next_if(|(next_left, _, _)| *next_left == left) {
+            records.push(pair_value_record(right, value));
+        }
+        // split between first glyphs, a glyph's pairs stay in one subtable
+        if num_pairs > 0 && num_pairs + records.len() > MAX_PAIRS_PER_SUBTABLE {
+            subtables.push(PairPos::format_1(
+                std::mem::take(&mut coverage).into(),
+                std::mem::take(&mut pair_sets),
+            ));
+            num_pairs = 0;
+        }
+        num_pairs += records.len();
+        coverage.push(GlyphId16::new(left));
+        pair_sets.push(PairSet::new(records));
+    }
+    if !pair_sets.is_empty() {
+        subtables.push(PairPos::format_1(coverage.into(), pair_sets));
+    }
+    subtables
+}
+
+fn pair_value_record(right: u16, value: i16) -> PairValueRecord {
+    PairValueRecord::new(
+        GlyphId16::new(right),
+        ValueRecord::new().with_x_advance(value),
+        ValueRecord::new(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fontcull_font_test_data::bebuffer::BeBuffer;
+    use fontcull_skrifa::raw::FontRead;
+
+    // from the read-fonts kern tests, with an unretained pair for glyph 6
+    #[rustfmt::skip]
+    const KERN_FORMAT_0: [u8; 42] = [
+        0x00, 0x00, 0x00, 0x01, // version 0, 1 subtable
+        0x00, 0x00, 0x00, 0x26, 0x00, 0x01, // version 0, length 38, format 0 horizontal
+        0x00, 0x04, 0x00, 0x18, 0x00, 0x02, 0x00, 0x00, // 4 pairs, search header
+        0x00, 0x04, 0x00, 0x0C, 0xFF, 0xD8, // l=4, r=12, v=-40
+        0x00, 0x04, 0x00, 0x1C, 0x00, 0x28, // l=4, r=28, v=40
+        0x00, 0x05, 0x00, 0x28, 0xFF, 0xCE, // l=5, r=40, v=-50
+        0x00, 0x06, 0x00, 0x0C, 0x00, 0x0A, // l=6, r=12, v=10
+    ];
+
+    fn plan(old_gids: &[u32]) -> Plan {
+        let mut plan = Plan::default();
+        for (new_gid, old_gid) in old_gids.iter().enumerate() {
+            let (old_gid, new_gid) = (GlyphId::new(*old_gid), GlyphId::new(new_gid as u32));
+            plan.glyph_map.insert(old_gid, new_gid);
+            plan.reverse_glyph_map.insert(new_gid, old_gid);
+            plan.new_to_old_gid_list.push((new_gid, old_gid));
+        }
+        plan.num_output_glyphs = old_gids.len();
+        plan.layout_features.insert(KERN_FEATURE);
+        plan
+    }
+
+    fn subset(kern: &Kern, plan: &Plan, state: &mut SubsetState) -> Result<Vec<u8>, SubsetError> {
+        let mut s = Serializer::new(1 << 17);
+        assert_eq!(s.start_serialize(), Ok(()));
+        // unrelated font, only its GPOS is looked at
+        let font = FontRef::new(fontcull_font_test_data::GLYF_COMPONENTS).unwrap();
+        let mut builder = FontBuilder::default();
+        let ret = kern.subset_with_state(plan, &font, state, &mut s, &mut builder);
+        s.end_serialize();
+        assert!(!s.in_error());
+        ret.map(|_| s.copy_bytes())
+    }
+
+    #[test]
+    fn test_subset_kern_format0() {
+        let buf = BeBuffer::new().extend(KERN_FORMAT_0);
+        let kern = Kern::read(FontData::new(buf.data())).unwrap();
+
+        let subsetted = subset(&kern, &plan(&[0, 4, 12, 40, 5]), &mut Default::default()).unwrap();
+        let subsetted = Kern::read(FontData::new(&subsetted)).unwrap();
+        let subtable = subsetted.subtables().next().unwrap().unwrap();
+        let SubtableKind::Format0(format0) = subtable.kind().unwrap() else {
+            panic!("expected a format 0 subtable");
+        };
+        let pairs: Vec<_> = format0
+            .pairs()
+            .iter()
+            .map(|pair| (pair.left().to_u16(), pair.right().to_u16(), pair.value()))
+            .collect();
+        assert_eq!(pairs, [(1, 2, -40), (4, 3, -50)]);
+        assert_eq!((format0.search_range(), format0.entry_selector()), (12, 1));
+        assert_eq!(format0.range_shift(), 0);
+
+        // no pairs left, no table
+        assert!(subset(&kern, &plan(&[0, 4, 5]), &mut Default::default()).is_err());
+    }
+
+    #[test]
+    fn test_subset_kern_overlong_subtable() {
+        // format 0 horizontal subtable of the pairs, with its length clamped
+        let format0 = |pairs: &[(u16, u16)]| {
+            let length = 14 + 6 * pairs.len();
+            let mut buf = BeBuffer::new()
+                .push(0_u16)
+                .push(length.min(u16::MAX as usize) as u16)
+                .push(1_u16)
+                .push(pairs.len() as u16)
+                .extend([0_u16, 0, 0]);
+            for (left, right) in pairs {
+                buf = buf.push(*left).push(*right).push(-10_i16);
+            }
+            buf.data().to_vec()
+        };
+        let large: Vec<_> = (0..110)
+            .flat_map(|l| (0..100).map(move |r| (l, r)))
+            .collect();
+        let small = format0(&[(1, 2)]);
+        let large = format0(&large);
+        let kern = |subtables: [&[u8]; 2]| {
+            let mut data = vec![0, 0, 0, 2];
+            subtables.iter().for_each(|subtable| data.extend(*subtable));
+            data
+        };
+        let plan = plan(&(0..110).collect::<Vec<_>>());
+
+        let data = kern([&small, &large]);
+        let subsetted = subset(
+            &Kern::read(FontData::new(&data)).unwrap(),
+            &plan,
+            &mut Default::default(),
+        )
+        .unwrap();
+        let subsetted = Kern::read(FontData::new(&subsetted)).unwrap();
+        let num_pairs: Vec<_> = subsetted
+            .subtables()
+            .map(|subtable| match subtable.unwrap().kind().unwrap() {
+                SubtableKind::Format0(format0) => format0.pairs().len(),
+                _ => panic!("expected a format 0 subtable"),
+            })
+            .collect();
+        assert_eq!(num_pairs, [1, 11000]);
+
+        // a subtable after it would start at the wrong position
+        let data = kern([&large, &small]);
+        assert!(subset(
+            &Kern::read(FontData::new(&data)).unwrap(),
+            &plan,
+            &mut Default::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_subset_kern_format2() {
+        #[rustfmt::skip]
+        let buf = BeBuffer::new().extend([
+            0x00_u16, 0x0001, // version 0, 1 subtable
+            0x0000, 0x002A, 0x0201, // version 0, length 42, format 2 horizontal
+            0x0004, 0x000E, 0x0018, 0x0022, // row width, left, right and array offsets
+            0x0003, 0x0003, 0x0022, 0x0000, 0x0026, // left: glyphs 3-5, rows 0, none, 1
+            0x000A, 0x0003, 0x0000, 0x0002, 0x0000, // right: glyphs 10-12, columns 0, 1, 0
+            0xFFF6, 0x0014, 0x001E, 0x0028, // array: [-10, 20], [30, 40]
+        ]);
+        let kern = Kern::read(FontData::new(buf.data())).unwrap();
+        let kerning = |kern: &Kern, left: u32, right: u32| {
+            let subtable = kern.subtables().next().unwrap().unwrap();
+            let SubtableKind::Format2(format2) = subtable.kind().unwrap() else {
+                panic!("expected a format 2 subtable");
+            };
+            format2.kerning(GlyphId::new(left), GlyphId::new(right))
+        };
+        assert_eq!(kerning(&kern, 5, 11), Some(40));
+
+        // old 5 -> new 1, old 11 -> new 2, old 10 -> new 3
+        let subsetted = subset(&kern, &plan(&[0, 5, 11, 10]), &mut Default::default()).unwrap();
+        let subsetted = Kern::read(FontData::new(&subsetted)).unwrap();
+        assert_eq!(kerning(&subsetted, 1, 2), Some(40));
+        assert_eq!(kerning(&subsetted, 1, 3), Some(30));
+        assert_eq!(kerning(&subsetted, 2, 3), None);
+    }
+
+    #[test]
+    fn test_kern_to_gpos_classes() {
+        #[rustfmt::skip]
+        let format2 = BeBuffer::new().extend([
+            0x00_u16, 0x0001, // version 0, 1 subtable
+            0x0000, 0x002A, 0x0201, // version 0, length 42, format 2 horizontal
+            0x0004, 0x000E, 0x0018, 0x0022, // row width, left, right and array offsets
+            0x0003, 0x0003, 0x0022, 0x0000, 0x0026, // left: glyphs 3-5, rows 0, none, 1
+            0x000A, 0x0003, 0x0000, 0x0002, 0x0000, // right: glyphs 10-12, columns 0, 1, 0
+            0xFFF6, 0x0014, 0x001E, 0x0028, // array: [-10, 20], [30, 40]
+        ]);
+        #[rustfmt::skip]
+        let format3 = BeBuffer::new().extend([
+            0x00_u16, 0x0001, // version 0, 1 subtable
+            0x0000, 0x0022, 0x0301, // version 0, length 34, format 3 horizontal
+            0x0006, 0x0302, 0x0200, // 6 glyphs, 3 values, 2 left and 2 right classes
+            0x0000, 0xFFEC, 0x001E, // values: 0, -20, 30
+            0x0000, 0x0101, 0x0001, // left classes of glyphs 0-5
+            0x0001, 0x0001, 0x0100, // right classes of glyphs 0-5
+            0x0001, 0x0200, // kern index: [0, 1], [2, 0]
+        ]);
+
+        for (buf, old_gids) in [
+            (format2, [0, 3, 4, 5, 10, 11, 12, 20]),
+            (format3, [0, 1, 2, 3, 4, 5, 6, 7]),
+        ] {
+            let kern = Kern::read(FontData::new(buf.data())).unwrap();
+            let subtable = kern.subtables().next().unwrap().unwrap();
+            let kind = subtable.kind().unwrap();
+            let mut plan = plan(&old_gids);
+            plan.subset_flags = SubsetFlags::SUBSET_FLAGS_KERN_TO_GPOS;
+
+            let mut expected = KernPairs::new();
+            for (new_left, old_left) in &plan.new_to_old_gid_list {
+                for (new_right, old_right) in &plan.new_to_old_gid_list {
+                    let value = match &kind {
+                        SubtableKind::Format2(format2) => format2.kerning(*old_left, *old_right),
+                        SubtableKind::Format3(format3) => format3.kerning(*old_left, *old_right),
+                        _ => None,
+                    };
+                    if let Some(value) = value.filter(|value| *value != 0) {
+                        let key = (new_left.to_u32() as u16, new_right.to_u32() as u16);
+                        expected.insert(key, value);
+                    }
+                }
+            }
+            assert!(!expected.is_empty());
+
+            let mut state = SubsetState::default();
+            assert!(subset(&kern, &plan, &mut state).is_err());
+            assert_eq!(state.kern_pairs, expected);
+        }
+    }
+
+    #[test]
+    fn test_kern_to_gpos_retry() {
+        #[rustfmt::skip]
+        let buf = BeBuffer::new().extend(KERN_FORMAT_0).extend([
+            0x00_u16, 0x0012, 0x0005, // version 0, length 18, format 0 horizontal cross-stream
+            0x0001, 0x0006, 0x0000, 0x0000, // 1 pair, search header
+            0x0005, 0x0028, 0x000A, // l=5, r=40, v=10
+        ]);
+        let mut data = buf.data().to_vec();
+        // 2 subtables
+        data[3] = 2;
+        let font_data = FontBuilder::new()
+            .add_raw(KERN, data)
+            .copy_missing_tables(FontRef::new(fontcull_font_test_data::GLYF_COMPONENTS).unwrap())
+            .build();
+        let font = FontRef::new(&font_data).unwrap();
+        let mut plan = plan(&[0, 4, 12, 40, 5]);
+        plan.subset_flags = SubsetFlags::SUBSET_FLAGS_KERN_TO_GPOS;
+
+        // the cross-stream subtable doesn't fit, so the table is subset again
+        let mut s = Serializer::new(4);
+        let mut state = SubsetState::default();
+        let table_len = font.table_data(KERN).unwrap().len() as u32;
+        crate::try_subset(
+            KERN,
+            &font,
+            &plan,
+            &mut FontBuilder::new(),
+            &mut s,
+            table_len,
+            &mut state,
+        )
+        .unwrap();
+        assert!(s.allocated() > 4);
+        assert_eq!(
+            state.kern_pairs,
+            KernPairs::from([((1, 2), -40), ((4, 3), -50)])
+        );
+    }
+
+    #[test]
+    fn test_kern_to_gpos() {
+        let buf = BeBuffer::new().extend(KERN_FORMAT_0);
+        let kern = Kern::read(FontData::new(buf.data())).unwrap();
+        let mut plan = plan(&[0, 4, 12, 40, 5]);
+        plan.subset_flags = SubsetFlags::SUBSET_FLAGS_KERN_TO_GPOS;
+
+        // every subtable moves to GPOS, so there's no kern table
+        let mut state = SubsetState::default();
+        assert!(subset(&kern, &plan, &mut state).is_err());
+        assert_eq!(
+            state.kern_pairs,
+            KernPairs::from([((1, 2), -40), ((4, 3), -50)])
+        );
+
+        let gpos = add_kern_feature(None, &state.kern_pairs).unwrap();
+        let gpos = fontcull_skrifa::raw::tables::gpos::Gpos::read(FontData::new(&gpos)).unwrap();
+        let features = gpos.feature_list().unwrap();
+        assert_eq!(features.feature_records()[0].feature_tag(), KERN_FEATURE);
+        let script = gpos.script_list().unwrap().script_records()[0];
+        assert_eq!(script.script_tag(), Tag::new(b"DFLT"));
+        let lookup = gpos.lookup_list().unwrap().lookups().get(0).unwrap();
+        let fontcull_skrifa::raw::tables::gpos::PositionLookup::Pair(lookup) = lookup else {
+            panic!("expected a pair lookup");
+        };
+        let Ok(fontcull_skrifa::raw::tables::gpos::PairPos::Format1(pair_pos)) =
+            lookup.subtables().get(0)
+        else {
+            panic!("expected a format 1 pair subtable");
+        };
+        let pair_set = pair_pos.pair_sets().get(1).unwrap();
+        let record = pair_set.pair_value_records().get(0).unwrap();
+        assert_eq!(record.second_glyph().to_u16(), 3);
+        assert_eq!(record.value_record1().x_advance(), Some(-50));
+    }
+}
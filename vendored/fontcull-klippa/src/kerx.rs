@@ -0,0 +1,327 @@
+//! impl subset() for kerx
+
+use std::collections::BTreeMap;
+
+use crate::aat::{new_gid, remap_lookup, serialize_lookup, StateMachine};
+use crate::serialize::{SerializeErrorFlags, Serializer};
+use crate::{Plan, Subset, SubsetError, KERX};
+use fontcull_write_fonts::{
+    read::{
+        tables::kerx::{Kerx, Subtable, Subtable0, Subtable2, Subtable6, SubtableKind},
+        FontData, FontRef,
+    },
+    FontBuilder,
+};
+
+// Format 4 flags: offset of the control point actions
+const ACTIONS_OFFSET: u32 = 0x00FF_FFFF;
+// Format 6 flags: row and column indices and kerning values are 32-bit
+const VALUES_ARE_LONG: u32 = 0x0000_0001;
+
+// reference: the kerx table in the Apple spec
+// <https://developer.apple.com/fonts/TrueType-Reference-Manual/RM06/Chap6kerx.html>
+impl Subset for Kerx<'_> {
+    fn subset(
+        &self,
+        plan: &Plan,
+        _font: &FontRef,
+        s: &mut Serializer,
+        _builder: &mut FontBuilder,
+    ) -> Result<(), SubsetError> {
+        // Version 3 and later add glyph coverage tables after the subtables,
+        // which only speed up shaping. They aren't rebuilt, so the subset is
+        // version 2.
+        s.embed(2_u16)
+            .and_then(|_| s.embed(0_u16))
+            .map_err(|_| SubsetError::SubsetTableError(KERX))?;
+        let num_tables_pos = s
+            .embed(0_u32)
+            .map_err(|_| SubsetError::SubsetTableError(KERX))?;
+
+        let mut num_tables = 0_u32;
+        for subtable in self.subtables().iter() {
+            // subtables that can't be read are dropped
+            let Ok(subtable) = subtable else {
+                continue;
+            };
+            let snap = s.snapshot();
+            match subset_subtable(&subtable, plan, s) {
+                Ok(()) => num_tables += 1,
+                Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY)
+                | Err(SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR) => s.revert_snapshot(snap),
+                Err(_) => return Err(SubsetError::SubsetTableError(KERX)),
+            }
+        }
+
+        if num_tables == 0 {
+            return Err(SubsetError::SubsetTableError(KERX));
+        }
+        s.copy_assign(num_tables_pos, num_tables);
+        Ok(())
+    }
+}
+
+fn subset_subtable(
+    subtable: &Subtable,
+    plan: &Plan,
+    s: &mut Serializer,
+) -> Result<(), SerializeErrorFlags> {
+    let read_error = |_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR;
+    let start = s.head();
+    let length_pos = s.embed(0_u32)?;
+    s.embed(subtable.coverage())?;
+    s.embed(subtable.tuple_count())?;
+
+    let data = FontData::new(subtable.data());
+    match subtable.kind().map_err(read_error)? {
+        // TODO: subset format 0 subtables with variation values
+        SubtableKind::Format0(_) if subtable.tuple_count() != 0 => {
+            return Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY)
+        }
+        SubtableKind::Format0(format0) => subset_format0(&format0, plan, s)?,
+        // Formats 1 and 4 only have glyph IDs in their class table. The
+        // kerning values and control point actions that follow the state
+        // machine are copied as they are.
+        SubtableKind::Format1(_) => subset_state_machine(data, plan, s, |offset| offset, |_| 0)?,
+        SubtableKind::Format4(_) => subset_state_machine(
+            data,
+            plan,
+            s,
+            |flags| flags & ACTIONS_OFFSET,
+            |flags| flags & !ACTIONS_OFFSET,
+        )?,
+        SubtableKind::Format2(format2) => subset_format2(&format2, data, plan, s)?,
+        SubtableKind::Format6(format6) => subset_format6(&format6, data, plan, s)?,
+    }
+
+    // keep subtables 4-byte aligned
+    s.pad((4 - (s.head() - start) % 4) % 4)?;
+    s.check_assign::<u32>(
+        length_pos,
+        s.head() - start,
+        SerializeErrorFlags::SERIALIZE_ERROR_INT_OVERFLOW,
+    )?;
+    Ok(())
+}
+
+fn subset_format0(
+    format0: &Subtable0,
+    plan: &Plan,
+    s: &mut Serializer,
+) -> Result<(), SerializeErrorFlags> {
+    let mut pairs: Vec<(u16, u16, i16)> = format0
+        .pairs()
+        .iter()
+        .filter_map(|pair| {
+            let left = new_gid(plan, pair.left().to_u16())?;
+            let right = new_gid(plan, pair.right().to_u16())?;
+            Some((left, right, pair.value()))
+        })
+        .collect();
+    if pairs.is_empty() {
+        return Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY);
+    }
+    pairs.sort_unstable_by_key(|(left, right, _)| (*left, *right));
+
+    // binary search header, in units of 6-byte pairs
+    let num_pairs = pairs.len() as u32;
+    let entry_selector = num_pairs.ilog2();
+    let search_range = 6 * (1 << entry_selector);
+    s.embed(num_pairs)?;
+    s.embed(search_range)?;
+    s.embed(entry_selector)?;
+    s.embed(num_pairs * 6 - search_range)?;
+    for (left, right, value) in pairs {
+        s.embed(left)?;
+        s.embed(right)?;
+        s.embed(value)?;
+    }
+    Ok(())
+}
+
+/// Write a format 1 or 4 state machine with a new class table, followed by
+/// the rest of the subtable from the offset in the field after the header.
+/// `offset` and `other_bits` split that field.
+fn subset_state_machine(
+    data: FontData,
+    plan: &Plan,
+    s: &mut Serializer,
+    offset: impl Fn(u32) -> u32,
+    other_bits: impl Fn(u32) -> u32,
+) -> Result<(), SerializeErrorFlags> {
+    let read_error = |_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR;
+    let machine = StateMachine::read(data, 2).map_err(read_error)?;
+    let field = data
+        .read_at::<u32>(StateMachine::HEADER_LEN)
+        .map_err(read_error)?;
+    let rest = data
+        .split_off(offset(field) as usize)
+        .ok_or(SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)?;
+
+    let start = s.head();
+    machine.serialize(s, plan, 1)?;
+    let new_offset = s.head() - start;
+    if offset(new_offset as u32) as usize != new_offset {
+        return Err(s.set_err(SerializeErrorFlags::SERIALIZE_ERROR_OFFSET_OVERFLOW));
+    }
+    s.copy_assign(
+        start + StateMachine::HEADER_LEN,
+        other_bits(field) | new_offset as u32,
+    );
+    s.embed_bytes(rest.as_bytes())?;
+    Ok(())
+}
+
+// Offsets in formats 2 and 6 are from the start of the subtable, its length,
+// coverage and tuple count included
+const SUBTABLE_HEADER_LEN: usize = 12;
+
+// Format 2 class tables hold row and column indices into the kerning array,
+// which keeps the rows and columns the kept glyphs use
+fn subset_format2(
+    format2: &Subtable2,
+    data: FontData,
+    plan: &Plan,
+    s: &mut Serializer,
+) -> Result<(), SerializeErrorFlags> {
+    let read_error = |_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR;
+    let row_width = data.read_at::<u32>(0).map_err(read_error)?;
+    let left = remap_lookup::<u16>(&format2.left_offset_table.lookup, plan).map_err(read_error)?;
+    let right =
+        remap_lookup::<u16>(&format2.right_offset_table.lookup, plan).map_err(read_error)?;
+    if left.is_empty() || right.is_empty() {
+        return Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY);
+    }
+    let max_index = |values: &BTreeMap<u16, u32>| *values.values().max().unwrap() as usize;
+    let array_len = (max_index(&left) + max_index(&right) + 1).min(format2.array.len());
+
+    let start = s.head() - SUBTABLE_HEADER_LEN;
+    s.embed(row_width)?;
+    let offsets_pos = s.embed(0_u32)?;
+    s.embed(0_u32)?;
+    s.embed(0_u32)?;
+    s.copy_assign(offsets_pos, (s.head() - start) as u32);
+    serialize_lookup(s, &left, false)?;
+    s.copy_assign(offsets_pos + 4, (s.head() - start) as u32);
+    serialize_lookup(s, &right, false)?;
+    s.copy_assign(offsets_pos + 8, (s.head() - start) as u32);
+    for value in &format2.array[..array_len] {
+        s.embed(value.get())?;
+    }
+    Ok(())
+}
+
+// Format 6 keeps its kerning array, and the kerning vector of variable subtables
+fn subset_format6(
+    format6: &Subtable6,
+    data: FontData,
+    plan: &Plan,
+    s: &mut Serializer,
+) -> Result<(), SerializeErrorFlags> {
+    let read_error = |_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR;
+    let flags = data.read_at::<u32>(0).map_err(read_error)?;
+    let row_count = data.read_at::<u16>(4).map_err(read_error)?;
+    let column_count = data.read_at::<u16>(6).map_err(read_error)?;
+    let long_values = flags & VALUES_ARE_LONG != 0;
+    let value_size = if long_values { 4 } else { 2 };
+    let offset = |pos: usize| -> Result<usize, SerializeErrorFlags> {
+        let offset = data.read_at::<u32>(pos).map_err(read_error)? as usize;
+        offset
+            .checked_sub(SUBTABLE_HEADER_LEN)
+            .ok_or(SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)
+    };
+    let array_start = offset(16)?;
+    let array_len = row_count as usize * column_count as usize * value_size;
+    let array = data
+        .slice(array_start..(array_start + array_len).min(data.len()))
+        .ok_or(SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)?;
+    let (rows, columns, vector) = match format6 {
+        Subtable6::ShortValues(rows, columns, _, vector) => (&rows.lookup, &columns.lookup, vector),
+        Subtable6::LongValues(rows, columns, _, vector) => (&rows.lookup, &columns.lookup, vector),
+    };
+    let vector = match vector {
+        Some(_) => Some(
+            data.split_off(offset(20)?)
+                .ok_or(SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)?,
+        ),
+        None => None,
+    };
+    let remap = |lookup| match long_values {
+        true => remap_lookup::<u32>(lookup, plan),
+        false => remap_lookup::<u16>(lookup, plan),
+    };
+    let rows = remap(rows).map_err(read_error)?;
+    let columns = remap(columns).map_err(read_error)?;
+    if rows.is_empty() || columns.is_empty() {
+        return Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY);
+    }
+
+    let start = s.head() - SUBTABLE_HEADER_LEN;
+    s.embed(flags)?;
+    s.embed(row_count)?;
+    s.embed(column_count)?;
+    let offsets_pos = s.head();
+    for _ in 0..3 + vector.is_some() as usize {
+        s.embed(0_u32)?;
+    }
+    s.copy_assign(offsets_pos, (s.head() - start) as u32);
+    serialize_lookup(s, &rows, long_values)?;
+    s.copy_assign(offsets_pos + 4, (s.head() - start) as u32);
+    serialize_lookup(s, &columns, long_values)?;
+    s.copy_assign(offsets_pos + 8, (s.head() - start) as u32);
+    s.embed_bytes(array.as_bytes())?;
+    if let Some(vector) = vector {
+        s.copy_assign(offsets_pos + 12, (s.head() - start) as u32);
+        s.embed_bytes(vector.as_bytes())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fontcull_font_test_data::bebuffer::BeBuffer;
+    use fontcull_write_fonts::{read::FontRead, types::GlyphId};
+
+    #[test]
+    fn test_subset_kerx_format0() {
+        let buf = BeBuffer::new()
+            .extend([2_u16, 0]) // version, padding
+            .extend([1_u32]) // 1 subtable
+            .extend([46_u32, 0, 0]) // length, format 0 horizontal, no tuples
+            .extend([3_u32, 12, 1, 6]) // 3 pairs, binary search header
+            .extend([4_u16, 12])
+            .extend([(-40_i16) as u16])
+            .extend([4_u16, 28, 40])
+            .extend([6_u16, 12, 10]);
+        let kerx = Kerx::read(FontData::new(buf.data())).unwrap();
+
+        // old 4 -> new 1, old 12 -> new 2, old 6 -> new 3
+        let mut plan = Plan::default();
+        for (new_gid, old_gid) in [(0, 0), (1, 4), (2, 12), (3, 6)] {
+            let (new_gid, old_gid) = (GlyphId::new(new_gid), GlyphId::new(old_gid));
+            plan.glyph_map.insert(old_gid, new_gid);
+            plan.new_to_old_gid_list.push((new_gid, old_gid));
+        }
+
+        let mut s = Serializer::new(1024);
+        assert_eq!(s.start_serialize(), Ok(()));
+        let font = FontRef::new(fontcull_font_test_data::GLYF_COMPONENTS).unwrap();
+        let mut builder = FontBuilder::default();
+        kerx.subset(&plan, &font, &mut s, &mut builder).unwrap();
+        s.end_serialize();
+        let subsetted = s.copy_bytes();
+        let subsetted = Kerx::read(FontData::new(&subsetted)).unwrap();
+
+        let subtable = subsetted.subtables().iter().next().unwrap().unwrap();
+        let SubtableKind::Format0(format0) = subtable.kind().unwrap() else {
+            panic!("expected a format 0 subtable");
+        };
+        let pairs: Vec<_> = format0
+            .pairs()
+            .iter()
+            .map(|pair| (pair.left().to_u16(), pair.right().to_u16(), pair.value()))
+            .collect();
+        assert_eq!(pairs, [(1, 2, -40), (3, 2, 10)]);
+    }
+}
@@ -1747,6 +1747,8 @@ impl<'a> SubsetTable<'a> for FeatureTableSubstitutionRecord {
     }
 }
 
+// args: the usual subtable args and the lookup type of the subtables, which is
+// written into the extension subtables of extension lookups
 impl<'a, T, Ext> SubsetTable<'a> for Subtables<'a, T, Ext>
 where
     T: SubsetTable<
@@ -1757,7 +1759,7 @@ where
         + 'a,
     Ext: ExtensionLookup<'a, T> + 'a,
 {
-    type ArgsForSubset = T::ArgsForSubset;
+    type ArgsForSubset = (T::ArgsForSubset, u16);
     type Output = u16;
     fn subset(
         &self,
@@ -1765,6 +1767,8 @@ where
         s: &mut Serializer,
         args: Self::ArgsForSubset,
     ) -> Result<Self::Output, SerializeErrorFlags> {
+        let (args, lookup_type) = args;
+        let is_extension = matches!(self, Subtables::Extension(_));
         let mut count = 0_u16;
         for sub in self.iter() {
             let sub =
@@ -1778,7 +1782,18 @@ where
             }
             let snap = s.snapshot();
             let offset_pos = s.embed(0_u16)?;
-            match Offset16::serialize_subset(&sub, s, plan, args, offset_pos) {
+            let ret = if is_extension {
+                Offset16::serialize_subset(
+                    &ExtensionSubtable(sub),
+                    s,
+                    plan,
+                    (args, lookup_type),
+                    offset_pos,
+                )
+            } else {
+                Offset16::serialize_subset(&sub, s, plan, args, offset_pos)
+            };
+            match ret {
                 Ok(_) => count += 1,
                 Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY) => s.revert_snapshot(snap),
                 Err(e) => {
@@ -1791,6 +1806,34 @@ where
     }
 }
 
+/// A lookup subtable that is written behind an ExtensionSubstFormat1 or
+/// ExtensionPosFormat1 table, which share the same layout
+struct ExtensionSubtable<T>(T);
+
+impl<'a, T> SubsetTable<'a> for ExtensionSubtable<T>
+where
+    T: SubsetTable<
+        'a,
+        ArgsForSubset = (&'a SubsetState, &'a FontRef<'a>, &'a FnvHashMap<u16, u16>),
+    >,
+{
+    type ArgsForSubset = (T::ArgsForSubset, u16);
+    type Output = T::Output;
+    fn subset(
+        &self,
+        plan: &Plan,
+        s: &mut Serializer,
+        args: Self::ArgsForSubset,
+    ) -> Result<Self::Output, SerializeErrorFlags> {
+        let (args, lookup_type) = args;
+        // format
+        s.embed(1_u16)?;
+        s.embed(lookup_type)?;
+        let offset_pos = s.embed(0_u32)?;
+        Offset32::serialize_subset(&self.0, s, plan, args, offset_pos)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -1,10 +1,15 @@
 //! try to define Subset trait so I can add methods for Hmtx
 //! TODO: make it generic for all tables
+mod aat;
+mod ankr;
 mod base;
+mod bitmap;
 mod cblc;
 mod cmap;
 mod colr;
 mod cpal;
+mod eblc;
+mod feat;
 mod fvar;
 mod gdef;
 mod glyf_loca;
@@ -18,8 +23,12 @@ mod head;
 mod hmtx;
 mod hvar;
 mod inc_bimap;
+mod kern;
+mod kerx;
 mod layout;
+mod math;
 mod maxp;
+mod morx;
 mod name;
 mod offset;
 mod offset_array;
@@ -31,7 +40,10 @@ mod repack;
 mod sbix;
 pub mod serialize;
 mod stat;
+mod svg;
+mod trak;
 mod variations;
+mod vmtx;
 mod vorg;
 mod vvar;
 use crate::repack::resolve_overflows;
@@ -41,11 +53,12 @@ use layout::{
     collect_features_with_retained_subs, find_duplicate_features, prune_features,
     remap_feature_indices, PruneLangSysContext, SubsetLayoutContext,
 };
+use math::Math;
 pub use parsing_util::{
     parse_name_ids, parse_name_languages, parse_tag_list, parse_unicodes, populate_gids,
 };
 
-use fnv::FnvHashMap;
+use fnv::{FnvHashMap, FnvHashSet};
 use fontcull_skrifa::MetadataProvider;
 use fontcull_write_fonts::types::GlyphId;
 use fontcull_write_fonts::types::Tag;
@@ -58,10 +71,12 @@ use fontcull_write_fonts::{
             cblc::Cblc,
             cff::Cff,
             cff2::Cff2,
-            cmap::{Cmap, CmapSubtable},
+            cmap::{Cmap, CmapSubtable, MapVariant},
             colr::Colr,
             cpal::Cpal,
             cvar::Cvar,
+            ebdt::Ebdt,
+            eblc::Eblc,
             gasp,
             gdef::Gdef,
             glyf::{Glyf, Glyph},
@@ -76,11 +91,12 @@ use fontcull_write_fonts::{
             os2::Os2,
             post::Post,
             sbix::Sbix,
+            vmtx::Vmtx,
             vorg::Vorg,
             vvar::Vvar,
         },
         types::NameId,
-        FontRef, TableProvider, TopLevelTable,
+        FontRead, FontRef, TableProvider, TopLevelTable,
     },
     tables::cmap::PlatformId,
 };
@@ -217,7 +233,7 @@ impl SubsetFlags {
     pub const SUBSET_FLAGS_SET_OVERLAPS_FLAG: Self = Self(0x0010);
 
     //If set the subsetter will not drop unrecognized tables and instead pass them through untouched.
-    //This flag is UNIMPLEMENTED yet
+    //This includes tables that reference glyph IDs, which will then point at the wrong glyphs.
     pub const SUBSET_FLAGS_PASSTHROUGH_UNRECOGNIZED: Self = Self(0x0020);
 
     //If set the notdef glyph outline will be retained in the final subset.
@@ -232,13 +248,19 @@ impl SubsetFlags {
     pub const SUBSET_FLAGS_NO_PRUNE_UNICODE_RANGES: Self = Self(0x0100);
 
     //If set don't perform glyph closure on layout substitution rules (GSUB)
-    //This flag is UNIMPLEMENTED yet
     pub const SUBSET_FLAGS_NO_LAYOUT_CLOSURE: Self = Self(0x0200);
 
     //If set perform IUP delta optimization on the remaining gvar table's deltas.
-    //This flag is UNIMPLEMENTED yet
     pub const SUBSET_FLAGS_OPTIMIZE_IUP_DELTAS: Self = Self(0x0400);
 
+    //If set all SVG documents will be gzip compressed, otherwise only the ones that
+    //were compressed in the source font are. Not an hb-subset flag.
+    pub const SUBSET_FLAGS_GZIP_SVG: Self = Self(0x4000);
+
+    //If set the horizontal pairs of the kern table will be moved into a GPOS kern feature,
+    //for shapers that ignore the kern table. Not an hb-subset flag.
+    pub const SUBSET_FLAGS_KERN_TO_GPOS: Self = Self(0x8000);
+
     /// Returns `true` if all of the flags in `other` are contained within `self`.
     #[inline]
     pub const fn contains(&self, other: Self) -> bool {
@@ -274,6 +296,12 @@ impl From<u16> for SubsetFlags {
     }
 }
 
+impl From<SubsetFlags> for u16 {
+    fn from(value: SubsetFlags) -> Self {
+        value.0
+    }
+}
+
 impl std::ops::BitOrAssign for SubsetFlags {
     /// Adds the set of flags.
     #[inline]
@@ -288,6 +316,7 @@ pub struct Plan {
     unicodes: IntSet<u32>,
     glyphs_requested: IntSet<GlyphId>,
     glyphset_gsub: IntSet<GlyphId>,
+    glyphset_mathed: IntSet<GlyphId>,
     glyphset_colred: IntSet<GlyphId>,
     glyphset: IntSet<GlyphId>,
     /// Old->New glyph id mapping,
@@ -311,6 +340,8 @@ pub struct Plan {
     name_languages: IntSet<u16>,
     layout_scripts: IntSet<Tag>,
     layout_features: IntSet<Tag>,
+    // the texts GSUB closure is limited to, see Plan::new_with_texts
+    layout_texts: Vec<gsub::text_closure::LayoutText>,
 
     //active old->new feature index map after removing redundant langsys and prune_features
     gsub_features: FnvHashMap<u16, u16>,
@@ -373,6 +404,41 @@ impl Plan {
         layout_features: &IntSet<Tag>,
         name_ids: &IntSet<NameId>,
         name_languages: &IntSet<u16>,
+    ) -> Self {
+        Self::new_with_texts(
+            input_gids,
+            input_unicodes,
+            &[],
+            font,
+            flags,
+            drop_tables,
+            layout_scripts,
+            layout_features,
+            name_ids,
+            name_languages,
+        )
+    }
+
+    /// Like [`Plan::new`], but the GSUB closure only keeps the glyphs that
+    /// substitutions can produce for the glyph sequences of `texts`, instead of
+    /// every glyph they can reach from the retained glyphs. Only the features of
+    /// the scripts a text uses apply to it.
+    ///
+    /// The characters of `texts` are retained along with `input_unicodes`.
+    /// Glyphs that aren't in any text are closed over on their own. With no
+    /// texts this is the same as [`Plan::new`].
+    #[allow(clippy::too_many_arguments)]
+    pub fn new_with_texts(
+        input_gids: &IntSet<GlyphId>,
+        input_unicodes: &IntSet<u32>,
+        texts: &[&str],
+        font: &FontRef,
+        flags: SubsetFlags,
+        drop_tables: &IntSet<Tag>,
+        layout_scripts: &IntSet<Tag>,
+        layout_features: &IntSet<Tag>,
+        name_ids: &IntSet<NameId>,
+        name_languages: &IntSet<u16>,
     ) -> Self {
         let mut this = Plan {
             glyphs_requested: input_gids.clone(),
@@ -386,12 +452,13 @@ impl Plan {
             ..Default::default()
         };
 
-        // ref: <https://github.com/harfbuzz/harfbuzz/blob/b5a65e0f20c30a7f13b2f6619479a6d666e603e0/src/hb-subset-input.cc#L71>
-        let default_no_subset_tables = [gasp::Gasp::TAG, FPGM, PREP, VDMX, DSIG];
         this.no_subset_tables
-            .extend(default_no_subset_tables.iter().copied());
+            .extend(DEFAULT_NO_SUBSET_TABLES.iter().copied());
 
-        this.populate_unicodes_to_retain(input_gids, input_unicodes, font);
+        let mut input_unicodes = input_unicodes.clone();
+        input_unicodes.extend(texts.iter().flat_map(|text| text.chars().map(|c| c as u32)));
+        this.populate_unicodes_to_retain(input_gids, &input_unicodes, font);
+        this.populate_layout_texts(texts, font);
         this.populate_gids_to_retain(font);
         this.create_old_gid_to_new_gid_map();
 
@@ -486,6 +553,35 @@ impl Plan {
         self.collect_variation_selectors(font, input_unicodes);
     }
 
+    // maps each distinct text to the glyphs of its characters, a variation
+    // selector picks the variant glyph of the character before it
+    fn populate_layout_texts(&mut self, texts: &[&str], font: &FontRef) {
+        let charmap = font.charmap();
+        let mut seen = FnvHashSet::default();
+        for text in texts.iter().filter(|text| seen.insert(**text)) {
+            let mut glyphs = Vec::with_capacity(text.len());
+            let mut chars = text.chars().peekable();
+            while let Some(c) = chars.next() {
+                let variant = chars
+                    .peek()
+                    .and_then(|selector| charmap.map_variant(c, *selector));
+                let glyph = match variant {
+                    Some(variant) => {
+                        chars.next();
+                        match variant {
+                            MapVariant::Variant(glyph) => Some(glyph),
+                            MapVariant::UseDefault => charmap.map(c),
+                        }
+                    }
+                    None => charmap.map(c),
+                };
+                glyphs.push(glyph.unwrap_or(GlyphId::NOTDEF));
+            }
+            self.layout_texts
+                .push(gsub::text_closure::LayoutText::new(text, glyphs));
+        }
+    }
+
     fn collect_variation_selectors(&mut self, font: &FontRef, input_unicodes: &IntSet<u32>) {
         if let Ok(cmap) = font.cmap() {
             let encoding_records = cmap.encoding_records();
@@ -528,14 +624,38 @@ impl Plan {
         // layout closure
         self.layout_populate_gids_to_retain(font);
 
-        //skip glyph closure for MATH table, it's not supported yet
+        // glyphs AAT shaping can substitute in
+        if !self.drop_tables.contains(MORX) {
+            if let Ok(morx) = font.morx() {
+                morx::closure_glyphs(&morx, self.font_num_glyphs, &mut self.glyphset_gsub);
+                remove_invalid_gids(&mut self.glyphset_gsub, self.font_num_glyphs);
+            }
+        }
+
+        //glyph closure for MATH
+        self.glyphset_mathed = self.glyphset_gsub.clone();
+        if !self.drop_tables.contains(MATH) {
+            if let Some(math) = font.table_data(MATH).and_then(|data| Math::read(data).ok()) {
+                math::closure_glyphs(&math, &mut self.glyphset_mathed);
+                remove_invalid_gids(&mut self.glyphset_mathed, self.font_num_glyphs);
+            }
+        }
 
         //glyph closure for COLR
         if !self.drop_tables.contains(Tag::new(b"COLR")) {
             self.colr_closure(font);
             remove_invalid_gids(&mut self.glyphset_colred, self.font_num_glyphs);
         } else {
-            self.glyphset_colred = self.glyphset_gsub.clone();
+            self.glyphset_colred = self.glyphset_mathed.clone();
+        }
+        self.svg_palette_closure(font);
+
+        // components of composite EBDT bitmaps
+        if !self.drop_tables.contains(Ebdt::TAG) {
+            if let (Ok(eblc), Ok(ebdt)) = (font.eblc(), font.ebdt()) {
+                eblc::closure_glyphs(&eblc, &ebdt, &mut self.glyphset_colred);
+                remove_invalid_gids(&mut self.glyphset_colred, self.font_num_glyphs);
+            }
         }
 
         /* Populate a full set of glyphs to retain by adding all referenced composite glyphs. */
@@ -565,6 +685,14 @@ impl Plan {
     fn layout_populate_gids_to_retain(&mut self, font: &FontRef) {
         if !self.drop_tables.contains(Tag::new(b"GSUB")) {
             if let Ok(gsub) = font.gsub() {
+                if !self.layout_texts.is_empty()
+                    && !self
+                        .subset_flags
+                        .contains(SubsetFlags::SUBSET_FLAGS_NO_LAYOUT_CLOSURE)
+                {
+                    let gdef = font.gdef().ok();
+                    gsub::text_closure::closure_glyphs_for_texts(&gsub, gdef.as_ref(), self);
+                }
                 gsub.closure_glyphs_lookups_features(self);
             }
         }
@@ -619,7 +747,7 @@ impl Plan {
 
     fn colr_closure(&mut self, font: &FontRef) {
         if let Ok(colr) = font.colr() {
-            colr.v0_closure_glyphs(&self.glyphset_gsub, &mut self.glyphset_colred);
+            colr.v0_closure_glyphs(&self.glyphset_mathed, &mut self.glyphset_colred);
             let mut layer_indices = IntSet::empty();
             let mut palette_indices = IntSet::empty();
             let mut variation_indices = IntSet::empty();
@@ -700,10 +828,30 @@ impl Plan {
                 }
             }
         } else {
-            self.glyphset_colred.union(&self.glyphset_gsub);
+            self.glyphset_colred.union(&self.glyphset_mathed);
         }
     }
 
+    // SVG glyphs pick CPAL colors by entry index, so when they use any, every
+    // palette entry is kept at its index
+    fn svg_palette_closure(&mut self, font: &FontRef) {
+        if self.drop_tables.contains(SVG) || self.drop_tables.contains(Cpal::TAG) {
+            return;
+        }
+        let (Ok(svg), Ok(cpal)) = (font.svg(), font.cpal()) else {
+            return;
+        };
+        if cpal.num_palette_entries() == 0 || !svg::uses_palette(&svg) {
+            return;
+        }
+        let mut palette_indices: IntSet<u16> = self.colr_palettes.keys().copied().collect();
+        palette_indices.insert_range(0..=cpal.num_palette_entries() - 1);
+        let _ = std::mem::replace(
+            &mut self.colr_palettes,
+            remap_palette_indices(palette_indices),
+        );
+    }
+
     fn nameid_closure(&mut self, font: &FontRef) {
         if !self.drop_tables.contains(Tag::new(b"STAT")) {
             if let Ok(stat) = font.stat() {
@@ -724,6 +872,18 @@ impl Plan {
             }
         }
 
+        if !self.drop_tables.contains(FEAT) {
+            if let Ok(feat) = font.feat() {
+                feat.collect_name_ids(self);
+            }
+        }
+
+        if !self.drop_tables.contains(TRAK) {
+            if let Ok(trak) = font.trak() {
+                trak.collect_name_ids(self);
+            }
+        }
+
         if !self.drop_tables.contains(Tag::new(b"GSUB")) {
             if let Ok(gsub) = font.gsub() {
                 gsub.collect_name_ids(self);
@@ -960,6 +1120,8 @@ fn remap_palette_indices(indices: IntSet<u16>) -> FnvHashMap<u16, u16> {
 pub struct SubsetState {
     // whether GDEF ItemVariationStore is retained after subsetting
     has_gdef_varstore: bool,
+    // kern table pairs to add to GPOS, with SUBSET_FLAGS_KERN_TO_GPOS
+    kern_pairs: kern::KernPairs,
 }
 
 #[derive(Debug, Error)]
@@ -1038,6 +1200,96 @@ pub const PREP: Tag = Tag::new(b"prep");
 pub const SILF: Tag = Tag::new(b"Silf");
 pub const SILL: Tag = Tag::new(b"Sill");
 pub const VDMX: Tag = Tag::new(b"VDMX");
+pub const AVAR: Tag = Tag::new(b"avar");
+pub const MVAR: Tag = Tag::new(b"MVAR");
+pub const STAT: Tag = Tag::new(b"STAT");
+pub const FVAR: Tag = Tag::new(b"fvar");
+pub const META: Tag = Tag::new(b"meta");
+pub const LTAG: Tag = Tag::new(b"ltag");
+pub const FEAT: Tag = Tag::new(b"feat");
+pub const TRAK: Tag = Tag::new(b"trak");
+pub const VHEA: Tag = Tag::new(b"vhea");
+pub const EBDT: Tag = Tag::new(b"EBDT");
+pub const EBLC: Tag = Tag::new(b"EBLC");
+pub const MATH: Tag = Tag::new(b"MATH");
+pub const SVG: Tag = Tag::new(b"SVG ");
+pub const ANKR: Tag = Tag::new(b"ankr");
+pub const BSLN: Tag = Tag::new(b"bsln");
+pub const JUST: Tag = Tag::new(b"just");
+pub const LCAR: Tag = Tag::new(b"lcar");
+pub const OPBD: Tag = Tag::new(b"opbd");
+pub const PROP: Tag = Tag::new(b"prop");
+
+// Tables subset_table() has a subsetter for, including tables written by another
+// table's subsetter (e.g. hhea by hmtx) and CFF/CFF2, which are passed through for now
+const SUBSET_TABLES: [Tag; 36] = [
+    Base::TAG,
+    Cbdt::TAG,
+    Cblc::TAG,
+    Ebdt::TAG,
+    Eblc::TAG,
+    Cmap::TAG,
+    Colr::TAG,
+    Cpal::TAG,
+    Gdef::TAG,
+    Glyf::TAG,
+    Gpos::TAG,
+    Gsub::TAG,
+    Gvar::TAG,
+    Hdmx::TAG,
+    Head::TAG,
+    Hhea::TAG,
+    Hmtx::TAG,
+    Hvar::TAG,
+    KERN,
+    KERX,
+    MORX,
+    ANKR,
+    Vvar::TAG,
+    Loca::TAG,
+    Maxp::TAG,
+    MATH,
+    Name::TAG,
+    Os2::TAG,
+    Post::TAG,
+    Sbix::TAG,
+    SVG,
+    Vmtx::TAG,
+    VHEA,
+    Vorg::TAG,
+    Cff::TAG,
+    Cff2::TAG,
+];
+
+// Tables the plan passes through untouched unless they are dropped
+// ref: <https://github.com/harfbuzz/harfbuzz/blob/b5a65e0f20c30a7f13b2f6619479a6d666e603e0/src/hb-subset-input.cc#L71>
+const DEFAULT_NO_SUBSET_TABLES: [Tag; 5] = [gasp::Gasp::TAG, FPGM, PREP, VDMX, DSIG];
+
+// Tables that don't reference glyph IDs, so they are passed through untouched
+const GLYPH_INDEPENDENT_TABLES: [Tag; 15] = [
+    AVAR,
+    Cvar::TAG,
+    CVT,
+    FVAR,
+    MVAR,
+    STAT,
+    META,
+    LTAG,
+    FEAT,
+    TRAK,
+    PCLT,
+    SILL,
+    gasp::Gasp::TAG,
+    VDMX,
+    EBSC,
+];
+
+// Tables indexed by glyph ID that klippa can't subset yet. Passed through, they
+// would point at the wrong glyphs once glyph IDs are remapped, so they are dropped
+// unless SUBSET_FLAGS_PASSTHROUGH_UNRECOGNIZED is set.
+pub const GLYPH_DEPENDENT_TABLES: [Tag; 11] = [
+    MORT, BSLN, JUST, LCAR, OPBD, PROP, LTSH, JSTF, SILF, GLAT, GLOC,
+];
 // This trait is implemented for all font top-level tables
 pub trait Subset {
     /// Subset this table, if successful a subset version of this table will be added to builder
@@ -1106,6 +1358,11 @@ pub fn subset_font(font: &FontRef, plan: &Plan) -> Result<Vec<u8>, SubsetError>
     for (tag, table_len) in tags_with_dependencies {
         subset(tag, font, plan, &mut builder, table_len, &mut state)?;
     }
+
+    // kern pairs that weren't merged into a subset GPOS table get a GPOS of their own
+    if !state.kern_pairs.is_empty() {
+        builder.add_raw(Gpos::TAG, kern::add_kern_feature(None, &state.kern_pairs)?);
+    }
     Ok(builder.build())
 }
 
@@ -1146,7 +1403,6 @@ fn subset<'a>(
         return Ok(());
     }
 
-    //TODO: complete overflow resolution
     let subsetted_data = if !s.offset_overflow() {
         s.copy_bytes()
     } else {
@@ -1155,6 +1411,14 @@ fn subset<'a>(
     };
 
     if !subsetted_data.is_empty() {
+        let subsetted_data = if table_tag == Gpos::TAG && !state.kern_pairs.is_empty() {
+            kern::add_kern_feature(
+                Some(&subsetted_data),
+                &std::mem::take(&mut state.kern_pairs),
+            )?
+        } else {
+            subsetted_data
+        };
         builder.add_raw(table_tag, subsetted_data);
     }
     Ok(())
@@ -1199,6 +1463,17 @@ fn subset_table<'a>(
         return passthrough_table(tag, font, s);
     }
 
+    // glyph dependent or unrecognized table, see dropped_tables()
+    if !is_recognized_table(tag) {
+        if plan
+            .subset_flags
+            .contains(SubsetFlags::SUBSET_FLAGS_PASSTHROUGH_UNRECOGNIZED)
+        {
+            return passthrough_table(tag, font, s);
+        }
+        return Ok(());
+    }
+
     match tag {
         Base::TAG => font
             .base()
@@ -1213,6 +1488,14 @@ fn subset_table<'a>(
             .map_err(|_| SubsetError::SubsetTableError(Cblc::TAG))?
             .subset(plan, font, s, builder),
 
+        //Skip, handled by Eblc
+        Ebdt::TAG => Ok(()),
+
+        Eblc::TAG => font
+            .eblc()
+            .map_err(|_| SubsetError::SubsetTableError(Eblc::TAG))?
+            .subset(plan, font, s, builder),
+
         Cmap::TAG => font
             .cmap()
             .map_err(|_| SubsetError::SubsetTableError(Cmap::TAG))?
@@ -1223,7 +1506,7 @@ fn subset_table<'a>(
             .map_err(|_| SubsetError::SubsetTableError(Colr::TAG))?
             .subset(plan, font, s, builder),
 
-        //TODO: if SVG is present and we support subsetting SVG table, pass through CPAL table
+        // palette entries SVG documents use are kept by svg_palette_closure()
         // see fonttools: <https://github.com/fonttools/fonttools/blob/64e5277d040e1a5c84f21f8fb8a5dc7d8ad3c3fa/Lib/fontTools/subset/__init__.py#L2545>
         Cpal::TAG => font
             .cpal()
@@ -1275,6 +1558,26 @@ fn subset_table<'a>(
             .map_err(|_| SubsetError::SubsetTableError(Hmtx::TAG))?
             .subset(plan, font, s, builder),
 
+        KERN => font
+            .kern()
+            .map_err(|_| SubsetError::SubsetTableError(KERN))?
+            .subset_with_state(plan, font, state, s, builder),
+
+        KERX => font
+            .kerx()
+            .map_err(|_| SubsetError::SubsetTableError(KERX))?
+            .subset(plan, font, s, builder),
+
+        MORX => font
+            .morx()
+            .map_err(|_| SubsetError::SubsetTableError(MORX))?
+            .subset(plan, font, s, builder),
+
+        ANKR => font
+            .ankr()
+            .map_err(|_| SubsetError::SubsetTableError(ANKR))?
+            .subset(plan, font, s, builder),
+
         Hvar::TAG => font
             .hvar()
             .map_err(|_| SubsetError::SubsetTableError(Hvar::TAG))?
@@ -1293,6 +1596,12 @@ fn subset_table<'a>(
             .map_err(|_| SubsetError::SubsetTableError(Maxp::TAG))?
             .subset(plan, font, s, builder),
 
+        MATH => font
+            .table_data(MATH)
+            .and_then(|data| Math::read(data).ok())
+            .ok_or(SubsetError::SubsetTableError(MATH))?
+            .subset(plan, font, s, builder),
+
         Name::TAG => font
             .name()
             .map_err(|_| SubsetError::SubsetTableError(Name::TAG))?
@@ -1313,15 +1622,56 @@ fn subset_table<'a>(
             .map_err(|_| SubsetError::SubsetTableError(Sbix::TAG))?
             .subset(plan, font, s, builder),
 
+        SVG => font
+            .svg()
+            .map_err(|_| SubsetError::SubsetTableError(SVG))?
+            .subset(plan, font, s, builder),
+
+        Vmtx::TAG => font
+            .vmtx()
+            .map_err(|_| SubsetError::SubsetTableError(Vmtx::TAG))?
+            .subset(plan, font, s, builder),
+
+        //Skip, handled by Vmtx
+        VHEA => Ok(()),
+
         Vorg::TAG => font
             .vorg()
             .map_err(|_| SubsetError::SubsetTableError(Vorg::TAG))?
             .subset(plan, font, s, builder),
 
+        //TODO: subset CFF and CFF2, until then their charstrings are passed through untouched
+        Cff::TAG | Cff2::TAG => passthrough_table(tag, font, s),
+
+        // GLYPH_INDEPENDENT_TABLES
         _ => passthrough_table(tag, font, s),
     }
 }
 
+/// Tables of the font that subset_font() drops because it can't subset them: the
+/// [`GLYPH_DEPENDENT_TABLES`] and any table it doesn't recognize
+///
+/// Tables dropped on request (`drop_tables` or `SUBSET_FLAGS_NO_HINTING`) aren't
+/// included. Nothing is dropped with `SUBSET_FLAGS_PASSTHROUGH_UNRECOGNIZED`.
+pub fn dropped_tables(font: &FontRef, flags: SubsetFlags) -> Vec<Tag> {
+    if flags.contains(SubsetFlags::SUBSET_FLAGS_PASSTHROUGH_UNRECOGNIZED) {
+        return Vec::new();
+    }
+    font.table_directory()
+        .table_records()
+        .iter()
+        .map(|record| record.tag())
+        .filter(|tag| !is_recognized_table(*tag))
+        .collect()
+}
+
+// Tables subset_font() subsets or passes through, subset_table() drops the rest
+fn is_recognized_table(tag: Tag) -> bool {
+    SUBSET_TABLES.contains(&tag)
+        || DEFAULT_NO_SUBSET_TABLES.contains(&tag)
+        || GLYPH_INDEPENDENT_TABLES.contains(&tag)
+}
+
 fn passthrough_table(tag: Tag, font: &FontRef<'_>, s: &mut Serializer) -> Result<(), SubsetError> {
     if let Some(data) = font.data_for_tag(tag) {
         s.embed_bytes(data.as_bytes())
@@ -1476,4 +1826,67 @@ mod test {
         assert!(plan.glyphset.contains(GlyphId::new(4)));
         assert!(plan.glyphset.contains(GlyphId::new(7)));
     }
+
+    #[test]
+    fn dropped_tables_match_subset_table() {
+        for tag in SUBSET_TABLES
+            .iter()
+            .chain(&DEFAULT_NO_SUBSET_TABLES)
+            .chain(&GLYPH_INDEPENDENT_TABLES)
+        {
+            assert!(is_recognized_table(*tag), "{tag} isn't recognized");
+        }
+        for tag in GLYPH_DEPENDENT_TABLES {
+            assert!(!is_recognized_table(tag), "{tag} is recognized");
+        }
+
+        let fftm = Tag::new(b"FFTM");
+        let font_data = FontBuilder::new()
+            .add_raw(LTSH, vec![0, 0, 0, 4, 1, 1, 1, 1])
+            .add_raw(fftm, vec![0; 28])
+            .copy_missing_tables(FontRef::new(fontcull_font_test_data::GLYF_COMPONENTS).unwrap())
+            .build();
+        let font = FontRef::new(&font_data).unwrap();
+        assert_eq!(
+            dropped_tables(&font, SubsetFlags::default()),
+            vec![fftm, LTSH]
+        );
+
+        let subset = |tag, plan: &Plan| {
+            let mut s = Serializer::new(1024);
+            s.start_serialize().unwrap();
+            let mut state = SubsetState::default();
+            subset_table(
+                tag,
+                &font,
+                plan,
+                &mut FontBuilder::new(),
+                &mut s,
+                &mut state,
+            )
+            .unwrap();
+            s.end_serialize();
+            s.copy_bytes()
+        };
+        let mut plan = Plan::default();
+        assert!(subset(fftm, &plan).is_empty());
+        assert!(subset(LTSH, &plan).is_empty());
+
+        plan.subset_flags = SubsetFlags::SUBSET_FLAGS_PASSTHROUGH_UNRECOGNIZED;
+        assert!(dropped_tables(&font, plan.subset_flags).is_empty());
+        let mut s = Serializer::new(1024);
+        s.start_serialize().unwrap();
+        let mut state = SubsetState::default();
+        subset_table(
+            fftm,
+            &font,
+            &plan,
+            &mut FontBuilder::new(),
+            &mut s,
+            &mut state,
+        )
+        .unwrap();
+        s.end_serialize();
+        assert_eq!(s.copy_bytes(), vec![0; 28]);
+    }
 }
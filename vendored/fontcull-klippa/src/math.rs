@@ -0,0 +1,677 @@
+//! impl subset() for MATH
+//!
+//! read-fonts has no MATH table, so the subtables are read directly from their
+//! [`FontData`].
+
+use crate::{
+    offset::{SerializeCopy, SerializeSerialize, SerializeSubset},
+    serialize::{SerializeErrorFlags, Serializer},
+    Plan, Subset, SubsetError, SubsetTable, MATH,
+};
+use fontcull_write_fonts::{
+    read::{
+        collections::IntSet,
+        tables::layout::{CoverageTable, DeviceOrVariationIndex},
+        FontData, FontRead, FontRef, ReadError,
+    },
+    types::{GlyphId, Offset16},
+    FontBuilder,
+};
+
+// scriptPercentScaleDown, scriptScriptPercentScaleDown,
+// delimitedSubFormulaMinHeight and displayOperatorMinHeight
+const MATH_CONSTANTS_HEADER_LEN: usize = 8;
+const MATH_CONSTANTS_VALUE_RECORD_COUNT: usize = 51;
+const MATH_VALUE_RECORD_LEN: usize = 4;
+const MATH_KERN_INFO_RECORD_LEN: usize = 8;
+const MATH_GLYPH_VARIANT_RECORD_LEN: usize = 4;
+const GLYPH_PART_RECORD_LEN: usize = 10;
+
+/// The [MATH](https://learn.microsoft.com/en-us/typography/opentype/spec/math) table
+pub(crate) struct Math<'a>(FontData<'a>);
+
+struct MathConstants<'a>(FontData<'a>);
+struct MathGlyphInfo<'a>(FontData<'a>);
+// MathItalicsCorrectionInfo and MathTopAccentAttachment: a coverage table and
+// a MathValueRecord for each covered glyph
+struct MathGlyphValues<'a>(FontData<'a>);
+struct MathKernInfo<'a>(FontData<'a>);
+struct MathKern<'a>(FontData<'a>);
+struct MathVariants<'a>(FontData<'a>);
+struct MathGlyphConstruction<'a>(FontData<'a>);
+struct GlyphAssembly<'a>(FontData<'a>);
+
+impl<'a> FontRead<'a> for Math<'a> {
+    fn read(data: FontData<'a>) -> Result<Self, ReadError> {
+        // majorVersion, minorVersion and the three subtable offsets
+        data.read_at::<u16>(8)?;
+        Ok(Self(data))
+    }
+}
+
+impl<'a> Math<'a> {
+    fn variants(&self) -> Result<Option<MathVariants<'a>>, ReadError> {
+        Ok(offset_data(self.0, 8)?.map(MathVariants))
+    }
+}
+
+/// Returns the data an Offset16 at `pos` points to, None if it's null.
+fn offset_data(data: FontData, pos: usize) -> Result<Option<FontData>, ReadError> {
+    let offset: u16 = data.read_at(pos)?;
+    if offset == 0 {
+        return Ok(None);
+    }
+    data.split_off(offset as usize)
+        .map(Some)
+        .ok_or(ReadError::OutOfBounds)
+}
+
+fn read_coverage(data: FontData, pos: usize) -> Result<CoverageTable, SerializeErrorFlags> {
+    offset_data(data, pos)
+        .ok()
+        .flatten()
+        .and_then(|coverage| CoverageTable::read(coverage).ok())
+        .ok_or(SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)
+}
+
+// (new gid, coverage index) of the retained glyphs in `coverage`
+fn retained_coverage_glyphs(coverage: &CoverageTable, plan: &Plan) -> Vec<(GlyphId, usize)> {
+    coverage
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, gid)| {
+            plan.glyph_map
+                .get(&GlyphId::from(gid))
+                .map(|new_gid| (*new_gid, idx))
+        })
+        .collect()
+}
+
+fn serialize_coverage(
+    s: &mut Serializer,
+    glyphs: &[(GlyphId, usize)],
+    pos: usize,
+) -> Result<(), SerializeErrorFlags> {
+    let glyphs: Vec<GlyphId> = glyphs.iter().map(|(new_gid, _)| *new_gid).collect();
+    Offset16::serialize_serialize::<CoverageTable>(s, &glyphs, pos)
+}
+
+/// Copies the MathValueRecord at `pos` in `parent`, along with the Device table
+/// it points to.
+fn serialize_value_record(
+    s: &mut Serializer,
+    parent: FontData,
+    pos: usize,
+) -> Result<(), SerializeErrorFlags> {
+    let value: i16 = parent
+        .read_at(pos)
+        .map_err(|_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)?;
+    s.embed(value)?;
+    let device_pos = s.embed(0_u16)?;
+
+    let Some(device) = offset_data(parent, pos + 2)
+        .map_err(|_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)?
+    else {
+        return Ok(());
+    };
+    // MATH has no ItemVariationStore, so a VariationIndex table is copied
+    // like a Device table
+    let device_bytes = match DeviceOrVariationIndex::read(device) {
+        Ok(DeviceOrVariationIndex::Device(device)) => device.min_table_bytes(),
+        Ok(DeviceOrVariationIndex::VariationIndex(var_idx)) => var_idx.min_table_bytes(),
+        Err(_) => return Err(SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR),
+    };
+    Offset16::serialize_copy_from_bytes(device_bytes, s, device_pos)
+}
+
+fn serialize_optional_subtable<'a, T: SubsetTable<'a, ArgsForSubset = (), Output = ()>>(
+    table: Option<T>,
+    plan: &Plan,
+    s: &mut Serializer,
+) -> Result<(), SerializeErrorFlags> {
+    let pos = s.embed(0_u16)?;
+    let Some(table) = table else {
+        return Ok(());
+    };
+    match Offset16::serialize_subset(&table, s, plan, (), pos) {
+        Ok(()) | Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY) => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+// reference: subset() for MATH in harfbuzz
+// <https://github.com/harfbuzz/harfbuzz/blob/a070f9ebbe88dc71b248af9731dd49ec93f4e9e6/src/hb-ot-math-table.hh#L1069>
+impl Subset for Math<'_> {
+    fn subset(
+        &self,
+        plan: &Plan,
+        _font: &FontRef,
+        s: &mut Serializer,
+        _builder: &mut FontBuilder,
+    ) -> Result<(), SubsetError> {
+        serialize_math(self.0, plan, s).map_err(|_| SubsetError::SubsetTableError(MATH))
+    }
+}
+
+fn serialize_math(
+    data: FontData,
+    plan: &Plan,
+    s: &mut Serializer,
+) -> Result<(), SerializeErrorFlags> {
+    let subtable =
+        |pos| offset_data(data, pos).map_err(|_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR);
+    let version = data
+        .slice(0..4)
+        .ok_or(SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)?;
+    s.embed_bytes(version.as_bytes())?;
+
+    serialize_optional_subtable(subtable(4)?.map(MathConstants), plan, s)?;
+    serialize_optional_subtable(subtable(6)?.map(MathGlyphInfo), plan, s)?;
+    serialize_optional_subtable(subtable(8)?.map(MathVariants), plan, s)
+}
+
+impl SubsetTable<'_> for MathConstants<'_> {
+    type ArgsForSubset = ();
+    type Output = ();
+    fn subset(
+        &self,
+        _plan: &Plan,
+        s: &mut Serializer,
+        _args: (),
+    ) -> Result<(), SerializeErrorFlags> {
+        let header = self
+            .0
+            .slice(0..MATH_CONSTANTS_HEADER_LEN)
+            .ok_or(SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)?;
+        s.embed_bytes(header.as_bytes())?;
+
+        for idx in 0..MATH_CONSTANTS_VALUE_RECORD_COUNT {
+            let pos = MATH_CONSTANTS_HEADER_LEN + idx * MATH_VALUE_RECORD_LEN;
+            serialize_value_record(s, self.0, pos)?;
+        }
+
+        // radicalDegreeBottomRaisePercent
+        let pos =
+            MATH_CONSTANTS_HEADER_LEN + MATH_CONSTANTS_VALUE_RECORD_COUNT * MATH_VALUE_RECORD_LEN;
+        let radical_degree_bottom_raise_percent: i16 = self
+            .0
+            .read_at(pos)
+            .map_err(|_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)?;
+        s.embed(radical_degree_bottom_raise_percent).map(|_| ())
+    }
+}
+
+impl SubsetTable<'_> for MathGlyphInfo<'_> {
+    type ArgsForSubset = ();
+    type Output = ();
+    fn subset(
+        &self,
+        plan: &Plan,
+        s: &mut Serializer,
+        _args: (),
+    ) -> Result<(), SerializeErrorFlags> {
+        let subtable = |pos| {
+            offset_data(self.0, pos).map_err(|_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)
+        };
+        // mathItalicsCorrectionInfo and mathTopAccentAttachment
+        serialize_optional_subtable(subtable(0)?.map(MathGlyphValues), plan, s)?;
+        serialize_optional_subtable(subtable(2)?.map(MathGlyphValues), plan, s)?;
+
+        // extendedShapeCoverage
+        let extended_shape_pos = s.embed(0_u16)?;
+        if subtable(4)?.is_some() {
+            let coverage = read_coverage(self.0, 4)?;
+            let glyphs = retained_coverage_glyphs(&coverage, plan);
+            if !glyphs.is_empty() {
+                serialize_coverage(s, &glyphs, extended_shape_pos)?;
+            }
+        }
+
+        serialize_optional_subtable(subtable(6)?.map(MathKernInfo), plan, s)
+    }
+}
+
+impl SubsetTable<'_> for MathGlyphValues<'_> {
+    type ArgsForSubset = ();
+    type Output = ();
+    fn subset(
+        &self,
+        plan: &Plan,
+        s: &mut Serializer,
+        _args: (),
+    ) -> Result<(), SerializeErrorFlags> {
+        let coverage = read_coverage(self.0, 0)?;
+        let count: u16 = self
+            .0
+            .read_at(2)
+            .map_err(|_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)?;
+        let glyphs: Vec<_> = retained_coverage_glyphs(&coverage, plan)
+            .into_iter()
+            .filter(|(_, idx)| *idx < count as usize)
+            .collect();
+        if glyphs.is_empty() {
+            return Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY);
+        }
+
+        let coverage_pos = s.embed(0_u16)?;
+        s.embed(glyphs.len() as u16)?;
+        for (_, idx) in &glyphs {
+            serialize_value_record(s, self.0, 4 + idx * MATH_VALUE_RECORD_LEN)?;
+        }
+        serialize_coverage(s, &glyphs, coverage_pos)
+    }
+}
+
+impl SubsetTable<'_> for MathKernInfo<'_> {
+    type ArgsForSubset = ();
+    type Output = ();
+    fn subset(
+        &self,
+        plan: &Plan,
+        s: &mut Serializer,
+        _args: (),
+    ) -> Result<(), SerializeErrorFlags> {
+        let coverage = read_coverage(self.0, 0)?;
+        let count: u16 = self
+            .0
+            .read_at(2)
+            .map_err(|_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)?;
+        let glyphs: Vec<_> = retained_coverage_glyphs(&coverage, plan)
+            .into_iter()
+            .filter(|(_, idx)| *idx < count as usize)
+            .collect();
+        if glyphs.is_empty() {
+            return Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY);
+        }
+
+        let coverage_pos = s.embed(0_u16)?;
+        s.embed(glyphs.len() as u16)?;
+        for (_, idx) in &glyphs {
+            // topRight, topLeft, bottomRight and bottomLeft MathKern offsets
+            let record_pos = 4 + idx * MATH_KERN_INFO_RECORD_LEN;
+            for corner in 0..4 {
+                let math_kern = offset_data(self.0, record_pos + corner * 2)
+                    .map_err(|_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)?;
+                serialize_optional_subtable(math_kern.map(MathKern), plan, s)?;
+            }
+        }
+        serialize_coverage(s, &glyphs, coverage_pos)
+    }
+}
+
+impl SubsetTable<'_> for MathKern<'_> {
+    type ArgsForSubset = ();
+    type Output = ();
+    fn subset(
+        &self,
+        _plan: &Plan,
+        s: &mut Serializer,
+        _args: (),
+    ) -> Result<(), SerializeErrorFlags> {
+        let height_count: u16 = self
+            .0
+            .read_at(0)
+            .map_err(|_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)?;
+        s.embed(height_count)?;
+        // correctionHeight values, then one more kernValues
+        for idx in 0..height_count as usize * 2 + 1 {
+            serialize_value_record(s, self.0, 2 + idx * MATH_VALUE_RECORD_LEN)?;
+        }
+        Ok(())
+    }
+}
+
+impl SubsetTable<'_> for MathVariants<'_> {
+    type ArgsForSubset = ();
+    type Output = ();
+    fn subset(
+        &self,
+        plan: &Plan,
+        s: &mut Serializer,
+        _args: (),
+    ) -> Result<(), SerializeErrorFlags> {
+        let read_err = |_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR;
+        let min_connector_overlap: u16 = self.0.read_at(0).map_err(read_err)?;
+        let vert_count: u16 = self.0.read_at(6).map_err(read_err)?;
+        let horiz_count: u16 = self.0.read_at(8).map_err(read_err)?;
+        // the horizontal construction offsets follow the vertical ones
+        let vert_glyphs = self.retained_glyphs(plan, 2, vert_count, 10)?;
+        let horiz_glyphs =
+            self.retained_glyphs(plan, 4, horiz_count, 10 + vert_count as usize * 2)?;
+
+        s.embed(min_connector_overlap)?;
+        let vert_coverage_pos = s.embed(0_u16)?;
+        let horiz_coverage_pos = s.embed(0_u16)?;
+        s.embed(vert_glyphs.len() as u16)?;
+        s.embed(horiz_glyphs.len() as u16)?;
+
+        for (_, construction_pos) in vert_glyphs.iter().chain(&horiz_glyphs) {
+            let construction = offset_data(self.0, *construction_pos)
+                .map_err(|_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)?;
+            serialize_optional_subtable(construction.map(MathGlyphConstruction), plan, s)?;
+        }
+
+        if !vert_glyphs.is_empty() {
+            serialize_coverage(s, &vert_glyphs, vert_coverage_pos)?;
+        }
+        if !horiz_glyphs.is_empty() {
+            serialize_coverage(s, &horiz_glyphs, horiz_coverage_pos)?;
+        }
+        Ok(())
+    }
+}
+
+impl<'a> MathVariants<'a> {
+    // (new gid, construction offset position) of the retained glyphs in the
+    // coverage table at `coverage_pos`
+    fn retained_glyphs(
+        &self,
+        plan: &Plan,
+        coverage_pos: usize,
+        count: u16,
+        first_offset_pos: usize,
+    ) -> Result<Vec<(GlyphId, usize)>, SerializeErrorFlags> {
+        if offset_data(self.0, coverage_pos)
+            .map_err(|_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)?
+            .is_none()
+        {
+            return Ok(Vec::new());
+        }
+        let coverage = read_coverage(self.0, coverage_pos)?;
+        Ok(retained_coverage_glyphs(&coverage, plan)
+            .into_iter()
+            .filter(|(_, idx)| *idx < count as usize)
+            .map(|(new_gid, idx)| (new_gid, first_offset_pos + idx * 2))
+            .collect())
+    }
+
+    // the constructions of the glyphs in `gids`
+    fn constructions(
+        &self,
+        gids: &IntSet<GlyphId>,
+    ) -> Result<Vec<MathGlyphConstruction<'a>>, ReadError> {
+        let vert_count = self.0.read_at::<u16>(6)? as usize;
+        let mut constructions = Vec::new();
+        for (coverage_pos, first_offset_pos) in [(2, 10), (4, 10 + vert_count * 2)] {
+            let Some(coverage) = offset_data(self.0, coverage_pos)? else {
+                continue;
+            };
+            let coverage = CoverageTable::read(coverage)?;
+            for (idx, gid) in coverage.iter().enumerate() {
+                if !gids.contains(GlyphId::from(gid)) {
+                    continue;
+                }
+                if let Some(construction) = offset_data(self.0, first_offset_pos + idx * 2)? {
+                    constructions.push(MathGlyphConstruction(construction));
+                }
+            }
+        }
+        Ok(constructions)
+    }
+}
+
+impl MathGlyphConstruction<'_> {
+    fn variant_glyphs(&self) -> Result<impl Iterator<Item = (GlyphId, u16)> + '_, ReadError> {
+        let count: u16 = self.0.read_at(2)?;
+        let records = self
+            .0
+            .slice(4..4 + count as usize * MATH_GLYPH_VARIANT_RECORD_LEN)
+            .ok_or(ReadError::OutOfBounds)?;
+        Ok((0..count as usize).filter_map(move |idx| {
+            let pos = idx * MATH_GLYPH_VARIANT_RECORD_LEN;
+            let gid: u16 = records.read_at(pos).ok()?;
+            let advance_measurement: u16 = records.read_at(pos + 2).ok()?;
+            Some((GlyphId::from(gid), advance_measurement))
+        }))
+    }
+}
+
+impl SubsetTable<'_> for MathGlyphConstruction<'_> {
+    type ArgsForSubset = ();
+    type Output = ();
+    fn subset(
+        &self,
+        plan: &Plan,
+        s: &mut Serializer,
+        _args: (),
+    ) -> Result<(), SerializeErrorFlags> {
+        let read_err = |_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR;
+        let assembly = offset_data(self.0, 0).map_err(read_err)?;
+        serialize_optional_subtable(assembly.map(GlyphAssembly), plan, s)?;
+
+        let count_pos = s.embed(0_u16)?;
+        let mut count = 0_u16;
+        for (gid, advance_measurement) in self.variant_glyphs().map_err(read_err)? {
+            let Some(new_gid) = plan.glyph_map.get(&gid) else {
+                continue;
+            };
+            s.embed(new_gid.to_u32() as u16)?;
+            s.embed(advance_measurement)?;
+            count += 1;
+        }
+        s.copy_assign(count_pos, count);
+        Ok(())
+    }
+}
+
+impl GlyphAssembly<'_> {
+    fn part_glyphs(&self) -> Result<impl Iterator<Item = GlyphId> + '_, ReadError> {
+        let count: u16 = self.0.read_at(MATH_VALUE_RECORD_LEN)?;
+        let parts_start = MATH_VALUE_RECORD_LEN + 2;
+        self.0
+            .slice(parts_start..parts_start + count as usize * GLYPH_PART_RECORD_LEN)
+            .ok_or(ReadError::OutOfBounds)?;
+        Ok((0..count as usize).filter_map(move |idx| {
+            let gid: u16 = self
+                .0
+                .read_at(parts_start + idx * GLYPH_PART_RECORD_LEN)
+                .ok()?;
+            Some(GlyphId::from(gid))
+        }))
+    }
+}
+
+impl SubsetTable<'_> for GlyphAssembly<'_> {
+    type ArgsForSubset = ();
+    type Output = ();
+    fn subset(
+        &self,
+        plan: &Plan,
+        s: &mut Serializer,
+        _args: (),
+    ) -> Result<(), SerializeErrorFlags> {
+        let read_err = |_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR;
+        // italicsCorrection
+        serialize_value_record(s, self.0, 0)?;
+
+        let parts: Vec<GlyphId> = self.part_glyphs().map_err(read_err)?.collect();
+        s.embed(parts.len() as u16)?;
+        let parts_start = MATH_VALUE_RECORD_LEN + 2;
+        for (idx, gid) in parts.iter().enumerate() {
+            // an assembly missing a part can't be drawn
+            let Some(new_gid) = plan.glyph_map.get(gid) else {
+                return Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY);
+            };
+            s.embed(new_gid.to_u32() as u16)?;
+            // connector lengths, fullAdvance and partFlags
+            let pos = parts_start + idx * GLYPH_PART_RECORD_LEN + 2;
+            let part = self
+                .0
+                .slice(pos..pos + GLYPH_PART_RECORD_LEN - 2)
+                .ok_or(SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)?;
+            s.embed_bytes(part.as_bytes())?;
+        }
+        Ok(())
+    }
+}
+
+/// Adds the size variants and assembly parts of the glyphs in `gids` to it.
+pub(crate) fn closure_glyphs(math: &Math, gids: &mut IntSet<GlyphId>) {
+    let Ok(Some(variants)) = math.variants() else {
+        return;
+    };
+    let Ok(constructions) = variants.constructions(gids) else {
+        return;
+    };
+
+    let mut variant_glyphs = IntSet::empty();
+    for construction in constructions {
+        if let Ok(glyphs) = construction.variant_glyphs() {
+            variant_glyphs.extend_unsorted(glyphs.map(|(gid, _)| gid));
+        }
+        if let Ok(Some(assembly)) = offset_data(construction.0, 0) {
+            if let Ok(parts) = GlyphAssembly(assembly).part_glyphs() {
+                variant_glyphs.extend_unsorted(parts);
+            }
+        }
+    }
+    gids.union(&variant_glyphs);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fontcull_font_test_data::bebuffer::BeBuffer;
+
+    #[rustfmt::skip]
+    fn math_table() -> BeBuffer {
+        BeBuffer::new()
+            .extend([1_u16, 0, 0, 10, 38]) // version, no constants, glyph info, variants
+            // MathGlyphInfo: italics correction only
+            .extend([8_u16, 0, 0, 0])
+            // MathItalicsCorrectionInfo: glyphs 3 and 5
+            .extend([12_u16, 2]).extend([100_i16, 0, 50, 0])
+            .extend([1_u16, 2, 3, 5])
+            // MathVariants: glyph 3 has a vertical construction
+            .extend([5_u16, 12, 0, 1, 0, 18])
+            .extend([1_u16, 1, 3])
+            // MathGlyphConstruction: size variants 10 and 11
+            .extend([12_u16, 2, 10, 100, 11, 200])
+            // GlyphAssembly: parts 12 and 13
+            .extend([0_i16, 0]).extend([2_u16])
+            .extend([12_u16, 0, 10, 100, 0])
+            .extend([13_u16, 10, 0, 100, 1])
+    }
+
+    fn plan_with_map(map: &[(u32, u32)]) -> Plan {
+        let mut plan = Plan::default();
+        for (old_gid, new_gid) in map {
+            let (old_gid, new_gid) = (GlyphId::new(*old_gid), GlyphId::new(*new_gid));
+            plan.glyphset.insert(old_gid);
+            plan.glyph_map.insert(old_gid, new_gid);
+            plan.new_to_old_gid_list.push((new_gid, old_gid));
+        }
+        plan
+    }
+
+    fn subset_math(plan: &Plan) -> Vec<u8> {
+        let buf = math_table();
+        let math = Math::read(FontData::new(buf.data())).unwrap();
+        let font = FontRef::new(fontcull_font_test_data::GLYF_COMPONENTS).unwrap();
+        let mut builder = FontBuilder::default();
+        let mut s = Serializer::new(1024);
+        assert_eq!(s.start_serialize(), Ok(()));
+        math.subset(plan, &font, &mut s, &mut builder).unwrap();
+        s.end_serialize();
+        s.copy_bytes()
+    }
+
+    fn subtable(data: FontData, pos: usize) -> FontData {
+        offset_data(data, pos).unwrap().unwrap()
+    }
+
+    fn coverage_glyphs(data: FontData, pos: usize) -> Vec<u32> {
+        CoverageTable::read(subtable(data, pos))
+            .unwrap()
+            .iter()
+            .map(|gid| gid.to_u32())
+            .collect()
+    }
+
+    #[test]
+    fn test_math_closure() {
+        let buf = math_table();
+        let math = Math::read(FontData::new(buf.data())).unwrap();
+
+        let mut gids: IntSet<GlyphId> = [0_u32, 3].into_iter().map(GlyphId::new).collect();
+        closure_glyphs(&math, &mut gids);
+        let gids: Vec<u32> = gids.iter().map(|gid| gid.to_u32()).collect();
+        assert_eq!(gids, [0, 3, 10, 11, 12, 13]);
+
+        let mut gids: IntSet<GlyphId> = [0_u32, 5].into_iter().map(GlyphId::new).collect();
+        closure_glyphs(&math, &mut gids);
+        assert_eq!(gids.len(), 2);
+    }
+
+    #[test]
+    fn test_subset_math() {
+        let plan = plan_with_map(&[(0, 0), (3, 1), (10, 2), (11, 3), (12, 4), (13, 5)]);
+        let out = subset_math(&plan);
+        let math = FontData::new(&out);
+        assert_eq!(out[..4], [0, 1, 0, 0]);
+        assert!(offset_data(math, 4).unwrap().is_none());
+
+        // glyph 5 loses its italics correction
+        let glyph_info = subtable(math, 6);
+        let italics = subtable(glyph_info, 0);
+        assert_eq!(coverage_glyphs(italics, 0), [1]);
+        assert_eq!(italics.read_at::<u16>(2).unwrap(), 1);
+        assert_eq!(italics.read_at::<i16>(4).unwrap(), 100);
+        assert!(offset_data(glyph_info, 2).unwrap().is_none());
+
+        let variants = subtable(math, 8);
+        assert_eq!(variants.read_at::<u16>(0).unwrap(), 5);
+        assert_eq!(coverage_glyphs(variants, 2), [1]);
+        assert_eq!(variants.read_at::<u16>(6).unwrap(), 1);
+        let construction = subtable(variants, 10);
+        let variant_glyphs: Vec<_> = MathGlyphConstruction(construction)
+            .variant_glyphs()
+            .unwrap()
+            .map(|(gid, advance)| (gid.to_u32(), advance))
+            .collect();
+        assert_eq!(variant_glyphs, [(2, 100), (3, 200)]);
+        let assembly = GlyphAssembly(subtable(construction, 0));
+        let parts: Vec<u32> = assembly
+            .part_glyphs()
+            .unwrap()
+            .map(|gid| gid.to_u32())
+            .collect();
+        assert_eq!(parts, [4, 5]);
+        // flags of the second part
+        assert_eq!(assembly.0.read_at::<u16>(6 + 10 + 8).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_subset_math_constants() {
+        let mut buf = BeBuffer::new().extend([80_i16, 60, 1300, 1500]);
+        // axisHeight has a device table, the other values don't
+        for idx in 0..MATH_CONSTANTS_VALUE_RECORD_COUNT {
+            let device_offset = if idx == 2 { 214_u16 } else { 0 };
+            buf = buf.extend([idx as i16 * 10]).extend([device_offset]);
+        }
+        let buf = buf.extend([-60_i16]).extend([12_u16, 12, 1, 0x1000]);
+        let constants = MathConstants(FontData::new(buf.data()));
+
+        let mut s = Serializer::new(1024);
+        assert_eq!(s.start_serialize(), Ok(()));
+        constants.subset(&Plan::default(), &mut s, ()).unwrap();
+        s.end_serialize();
+        let out = s.copy_bytes();
+        assert_eq!(out, buf.data());
+    }
+
+    #[test]
+    fn test_subset_math_drops_incomplete_assembly() {
+        // without part 13 the assembly can't be drawn, the size variant stays
+        let plan = plan_with_map(&[(0, 0), (3, 1), (10, 2), (12, 3)]);
+        let out = subset_math(&plan);
+        let construction = subtable(subtable(FontData::new(&out), 8), 10);
+        assert!(offset_data(construction, 0).unwrap().is_none());
+        let variant_glyphs: Vec<_> = MathGlyphConstruction(construction)
+            .variant_glyphs()
+            .unwrap()
+            .map(|(gid, _)| gid.to_u32())
+            .collect();
+        assert_eq!(variant_glyphs, [2]);
+    }
+}
@@ -0,0 +1,524 @@
+//! impl subset() for morx
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+use crate::aat::{
+    lookup_entries, new_gid, remap_glyph_lookup, serialize_lookup, Entry, StateMachine,
+    DELETED_GLYPH,
+};
+use crate::serialize::{SerializeErrorFlags, Serializer};
+use crate::{Plan, Subset, SubsetError, MORX};
+use fontcull_write_fonts::{
+    read::{
+        collections::IntSet,
+        tables::aat::Lookup,
+        tables::morx::{
+            Chain, ContextualSubtable, InsertionSubtable, LigatureSubtable, Morx, Subtable,
+            SubtableKind,
+        },
+        types::BigEndian,
+        FontData, FontRef,
+    },
+    types::{GlyphId, GlyphId16},
+    FontBuilder,
+};
+
+// Ligature entry flag: run the ligature actions at ligActionIndex
+const PERFORM_ACTION: u16 = 0x2000;
+// Ligature action bits
+const LIG_ACTION_LAST: u32 = 0x8000_0000;
+const LIG_ACTION_STORE: u32 = 0x4000_0000;
+const LIG_ACTION_OFFSET: u32 = 0x3FFF_FFFF;
+// Insertion entry flags: glyph counts for currentInsertIndex and markedInsertIndex
+const CURRENT_INSERT_COUNT: u16 = 0x03E0;
+const MARKED_INSERT_COUNT: u16 = 0x001F;
+// Contextual and insertion entries use this index for no action
+const NO_INDEX: u16 = 0xFFFF;
+
+const CLOSURE_MAX_STAGES: u8 = 12;
+
+// reference: the morx table in the Apple spec
+// <https://developer.apple.com/fonts/TrueType-Reference-Manual/RM06/Chap6morx.html>
+impl Subset for Morx<'_> {
+    fn subset(
+        &self,
+        plan: &Plan,
+        _font: &FontRef,
+        s: &mut Serializer,
+        _builder: &mut FontBuilder,
+    ) -> Result<(), SubsetError> {
+        // Version 3 adds glyph coverage tables after the chains, which only
+        // speed up shaping. They aren't rebuilt, so the subset is version 2.
+        s.embed(2_u16)
+            .and_then(|_| s.embed(0_u16))
+            .and_then(|_| s.embed(self.n_chains()))
+            .map_err(|_| SubsetError::SubsetTableError(MORX))?;
+
+        let mut num_subtables = 0;
+        for chain in self.chains().iter() {
+            let chain = chain.map_err(|_| SubsetError::SubsetTableError(MORX))?;
+            num_subtables +=
+                subset_chain(&chain, plan, s).map_err(|_| SubsetError::SubsetTableError(MORX))?;
+        }
+        if num_subtables == 0 {
+            return Err(SubsetError::SubsetTableError(MORX));
+        }
+        Ok(())
+    }
+}
+
+/// Write a chain with its subtables subset, returning how many subtables are
+/// kept
+fn subset_chain(
+    chain: &Chain,
+    plan: &Plan,
+    s: &mut Serializer,
+) -> Result<u32, SerializeErrorFlags> {
+    let start = s.head();
+    s.embed(chain.default_flags())?;
+    let length_pos = s.embed(0_u32)?;
+    s.embed(chain.n_feature_entries())?;
+    let num_subtables_pos = s.embed(0_u32)?;
+    for feature in chain.features() {
+        s.embed(feature.feature_type())?;
+        s.embed(feature.feature_settings())?;
+        s.embed(feature.enable_flags())?;
+        s.embed(feature.disable_flags())?;
+    }
+
+    let mut num_subtables = 0_u32;
+    for subtable in chain.subtables().iter() {
+        // subtables that can't be read are dropped
+        let Ok(subtable) = subtable else {
+            continue;
+        };
+        let snap = s.snapshot();
+        match subset_subtable(&subtable, plan, s) {
+            Ok(()) => num_subtables += 1,
+            Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY)
+            | Err(SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR) => s.revert_snapshot(snap),
+            Err(e) => return Err(e),
+        }
+    }
+
+    s.copy_assign(num_subtables_pos, num_subtables);
+    s.check_assign::<u32>(
+        length_pos,
+        s.head() - start,
+        SerializeErrorFlags::SERIALIZE_ERROR_INT_OVERFLOW,
+    )?;
+    Ok(num_subtables)
+}
+
+fn subset_subtable(
+    subtable: &Subtable,
+    plan: &Plan,
+    s: &mut Serializer,
+) -> Result<(), SerializeErrorFlags> {
+    let read_error = |_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR;
+    let start = s.head();
+    let length_pos = s.embed(0_u32)?;
+    s.embed(subtable.coverage())?;
+    s.embed(subtable.sub_feature_flags())?;
+
+    let data = FontData::new(subtable.data());
+    match subtable.kind().map_err(read_error)? {
+        SubtableKind::Rearrangement(_) => StateMachine::read(data, 0)
+            .map_err(read_error)?
+            .serialize(s, plan, 0)?,
+        SubtableKind::Contextual(contextual) => subset_contextual(&contextual, data, plan, s)?,
+        SubtableKind::Ligature(ligature) => subset_ligature(&ligature, data, plan, s)?,
+        SubtableKind::NonContextual(lookup) => {
+            let substitutions = remap_glyph_lookup(&lookup.lookup, plan).map_err(read_error)?;
+            // nothing left to substitute
+            if substitutions.is_empty() {
+                return Err(SerializeErrorFlags::SERIALIZE_ERROR_EMPTY);
+            }
+            serialize_lookup(s, &substitutions, false)?
+        }
+        SubtableKind::Insertion(insertion) => subset_insertion(&insertion, data, plan, s)?,
+    }
+
+    // keep subtables 4-byte aligned
+    s.pad((4 - (s.head() - start) % 4) % 4)?;
+    s.check_assign::<u32>(
+        length_pos,
+        s.head() - start,
+        SerializeErrorFlags::SERIALIZE_ERROR_INT_OVERFLOW,
+    )?;
+    Ok(())
+}
+
+/// The substitution tables a contextual entry uses, for the marked and the
+/// current glyph
+fn substitution_indices(entry: &Entry) -> impl Iterator<Item = usize> {
+    [entry.payload(0), entry.payload(1)]
+        .into_iter()
+        .filter(|index| *index != NO_INDEX)
+        .map(|index| index as usize)
+}
+
+// Contextual subtables keep their substitution tables in place, since entries
+// refer to them by index
+fn subset_contextual(
+    contextual: &ContextualSubtable,
+    data: FontData,
+    plan: &Plan,
+    s: &mut Serializer,
+) -> Result<(), SerializeErrorFlags> {
+    let read_error = |_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR;
+    let machine = StateMachine::read(data, 4).map_err(read_error)?;
+    let num_lookups = machine
+        .entries()
+        .flat_map(|entry| substitution_indices(&entry))
+        .max()
+        .map_or(0, |max| max + 1);
+
+    let start = s.head();
+    machine.serialize(s, plan, 1)?;
+    let array_start = s.head();
+    s.copy_assign(
+        start + StateMachine::HEADER_LEN,
+        (array_start - start) as u32,
+    );
+    for _ in 0..num_lookups {
+        s.embed(0_u32)?;
+    }
+    for i in 0..num_lookups {
+        let lookup = contextual.lookups.get(i).map_err(read_error)?;
+        let substitutions = remap_glyph_lookup(&lookup.lookup, plan).map_err(read_error)?;
+        s.copy_assign(array_start + 4 * i, (s.head() - array_start) as u32);
+        serialize_lookup(s, &substitutions, false)?;
+    }
+    Ok(())
+}
+
+/// The ligature parts of a ligature subtable, with the component array cut
+/// where the ligature array starts
+struct Ligatures<'a> {
+    actions: &'a [BigEndian<u32>],
+    components: &'a [BigEndian<u16>],
+    ligatures: &'a [BigEndian<GlyphId16>],
+}
+
+impl<'a> Ligatures<'a> {
+    fn new(ligature: &LigatureSubtable<'a>, data: FontData) -> Self {
+        let offset = |pos| data.read_at::<u32>(pos).unwrap_or(0) as usize;
+        let header_len = StateMachine::HEADER_LEN;
+        let (components_offset, ligatures_offset) =
+            (offset(header_len + 4), offset(header_len + 8));
+        let mut components = ligature.components;
+        if ligatures_offset > components_offset {
+            let len = (ligatures_offset - components_offset) / 2;
+            components = &components[..len.min(components.len())];
+        }
+        Self {
+            actions: ligature.ligature_actions,
+            components,
+            ligatures: ligature.ligatures,
+        }
+    }
+
+    /// The actions run for the action index of an entry, up to the last one
+    fn actions_from(&self, index: u16) -> impl Iterator<Item = u32> + 'a {
+        let actions = self.actions.get(index as usize..).unwrap_or_default();
+        let mut done = false;
+        actions
+            .iter()
+            .map(|action| action.get())
+            .take_while(move |action| {
+                let take = !done;
+                done |= action & LIG_ACTION_LAST != 0;
+                take
+            })
+    }
+}
+
+/// Sign extended component offset of a ligature action
+fn component_offset(action: u32) -> i64 {
+    (((action & LIG_ACTION_OFFSET) << 2) as i32 >> 2) as i64
+}
+
+// Ligature actions index the component array with glyph IDs, so each action
+// gets a new slice of the component array covering the new glyph IDs. The
+// ligature array stays in place, since component values sum to indices into it.
+fn subset_ligature(
+    ligature: &LigatureSubtable,
+    data: FontData,
+    plan: &Plan,
+    s: &mut Serializer,
+) -> Result<(), SerializeErrorFlags> {
+    let machine =
+        StateMachine::read(data, 2).map_err(|_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)?;
+    let ligatures = Ligatures::new(ligature, data);
+    let num_actions = machine
+        .entries()
+        .filter(|entry| entry.flags & PERFORM_ACTION != 0)
+        .map(|entry| entry.payload(0) as usize + ligatures.actions_from(entry.payload(0)).count())
+        .max()
+        .unwrap_or(0);
+    let actions: Vec<u32> = ligatures
+        .actions
+        .iter()
+        .take(num_actions)
+        .map(|action| action.get())
+        .collect();
+
+    // old component offset -> new one, and the new component array
+    let mut new_offsets = BTreeMap::new();
+    let mut components: Vec<u16> = Vec::new();
+    for action in &actions {
+        let offset = component_offset(*action);
+        if new_offsets.contains_key(&offset) {
+            continue;
+        }
+        let values: BTreeMap<u16, u16> = plan
+            .new_to_old_gid_list
+            .iter()
+            .filter_map(|(new_gid, old_gid)| {
+                let index = usize::try_from(offset + old_gid.to_u32() as i64).ok()?;
+                let value = ligatures.components.get(index)?.get();
+                Some((new_gid.to_u32() as u16, value))
+            })
+            .collect();
+        let (Some(first), Some(last)) = (values.keys().next(), values.keys().next_back()) else {
+            // no kept glyph has a component here, point every glyph past the array
+            new_offsets.insert(offset, -(1 << 29));
+            continue;
+        };
+        new_offsets.insert(offset, components.len() as i64 - *first as i64);
+        components.extend((*first..=*last).map(|gid| values.get(&gid).copied().unwrap_or(0)));
+    }
+
+    let start = s.head();
+    machine.serialize(s, plan, 3)?;
+    let fields_pos = start + StateMachine::HEADER_LEN;
+    s.copy_assign(fields_pos, (s.head() - start) as u32);
+    for action in &actions {
+        let new_offset = new_offsets[&component_offset(*action)] as u32 & LIG_ACTION_OFFSET;
+        s.embed(action & !LIG_ACTION_OFFSET | new_offset)?;
+    }
+    s.copy_assign(fields_pos + 4, (s.head() - start) as u32);
+    for value in components {
+        s.embed(value)?;
+    }
+    s.copy_assign(fields_pos + 8, (s.head() - start) as u32);
+    for glyph in ligatures.ligatures {
+        s.embed(new_gid(plan, glyph.get().to_u16()).unwrap_or(DELETED_GLYPH))?;
+    }
+    Ok(())
+}
+
+/// The ranges of the insertion glyph table an insertion entry inserts, at the
+/// current and at the marked glyph
+fn insertions(entry: &Entry) -> impl Iterator<Item = Range<usize>> {
+    [
+        (entry.payload(0), (entry.flags & CURRENT_INSERT_COUNT) >> 5),
+        (entry.payload(1), entry.flags & MARKED_INSERT_COUNT),
+    ]
+    .into_iter()
+    .filter(|(index, _)| *index != NO_INDEX)
+    .map(|(index, count)| index as usize..index as usize + count as usize)
+}
+
+fn subset_insertion(
+    insertion: &InsertionSubtable,
+    data: FontData,
+    plan: &Plan,
+    s: &mut Serializer,
+) -> Result<(), SerializeErrorFlags> {
+    let machine =
+        StateMachine::read(data, 4).map_err(|_| SerializeErrorFlags::SERIALIZE_ERROR_READ_ERROR)?;
+    let num_glyphs = machine
+        .entries()
+        .flat_map(|entry| insertions(&entry))
+        .map(|range| range.end)
+        .max()
+        .unwrap_or(0);
+
+    let start = s.head();
+    machine.serialize(s, plan, 1)?;
+    s.copy_assign(start + StateMachine::HEADER_LEN, (s.head() - start) as u32);
+    for glyph in insertion.glyphs.iter().take(num_glyphs) {
+        s.embed(new_gid(plan, glyph.get().to_u16()).unwrap_or(DELETED_GLYPH))?;
+    }
+    Ok(())
+}
+
+/// Add the glyphs morx substitutions can produce from `glyphs`
+///
+/// Only state machine entries reachable with the classes of `glyphs` are
+/// followed, and every ligature their component values can sum to is added.
+pub(crate) fn closure_glyphs(morx: &Morx, num_glyphs: usize, glyphs: &mut IntSet<GlyphId>) {
+    for _ in 0..CLOSURE_MAX_STAGES {
+        let mut added = IntSet::empty();
+        for chain in morx.chains().iter().filter_map(|chain| chain.ok()) {
+            for subtable in chain
+                .subtables()
+                .iter()
+                .filter_map(|subtable| subtable.ok())
+            {
+                let _ = subtable_closure(&subtable, num_glyphs, glyphs, &mut added);
+            }
+        }
+        added.remove_all(glyphs.iter());
+        if added.is_empty() {
+            return;
+        }
+        glyphs.union(&added);
+    }
+}
+
+fn subtable_closure(
+    subtable: &Subtable,
+    num_glyphs: usize,
+    glyphs: &IntSet<GlyphId>,
+    added: &mut IntSet<GlyphId>,
+) -> Result<(), fontcull_write_fonts::read::ReadError> {
+    let data = FontData::new(subtable.data());
+    let mut add = |gid: u16| {
+        if gid != DELETED_GLYPH {
+            added.insert(GlyphId::from(gid));
+        }
+    };
+    let substitutions = |lookup: &Lookup| {
+        lookup_entries::<u16>(lookup, num_glyphs).map(|entries| {
+            entries
+                .into_iter()
+                .filter(|(gid, _)| glyphs.contains(GlyphId::from(*gid)))
+                .map(|(_, value)| value as u16)
+                .collect::<Vec<_>>()
+        })
+    };
+
+    match subtable.kind()? {
+        SubtableKind::Rearrangement(_) => (),
+        SubtableKind::NonContextual(lookup) => {
+            substitutions(&lookup.lookup)?.into_iter().for_each(add)
+        }
+        SubtableKind::Contextual(contextual) => {
+            let machine = StateMachine::read(data, 4)?;
+            let indices: BTreeSet<usize> = machine
+                .reachable_entries(glyphs)
+                .iter()
+                .flat_map(substitution_indices)
+                .collect();
+            for index in indices {
+                let lookup = contextual.lookups.get(index)?;
+                substitutions(&lookup.lookup)?
+                    .into_iter()
+                    .for_each(&mut add);
+            }
+        }
+        SubtableKind::Ligature(ligature) => {
+            let machine = StateMachine::read(data, 2)?;
+            let ligatures = Ligatures::new(&ligature, data);
+            for entry in machine.reachable_entries(glyphs) {
+                if entry.flags & PERFORM_ACTION == 0 {
+                    continue;
+                }
+                // every sum of component values the actions can reach
+                let mut sums = BTreeSet::from([0_usize]);
+                for action in ligatures.actions_from(entry.payload(0)) {
+                    let offset = component_offset(action);
+                    let values: BTreeSet<usize> = glyphs
+                        .iter()
+                        .filter_map(|gid| {
+                            let index = usize::try_from(offset + gid.to_u32() as i64).ok()?;
+                            Some(ligatures.components.get(index)?.get() as usize)
+                        })
+                        .collect();
+                    sums = sums
+                        .iter()
+                        .flat_map(|sum| values.iter().map(move |value| sum + value))
+                        .filter(|sum| *sum < ligatures.ligatures.len())
+                        .collect();
+                    if action & (LIG_ACTION_STORE | LIG_ACTION_LAST) != 0 {
+                        sums.iter()
+                            .for_each(|sum| add(ligatures.ligatures[*sum].get().to_u16()));
+                    }
+                }
+            }
+        }
+        SubtableKind::Insertion(insertion) => {
+            let machine = StateMachine::read(data, 4)?;
+            for entry in machine.reachable_entries(glyphs) {
+                for range in insertions(&entry) {
+                    if let Some(inserted) = insertion.glyphs.get(range) {
+                        inserted.iter().for_each(|gid| add(gid.get().to_u16()));
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fontcull_font_test_data::bebuffer::BeBuffer;
+    use fontcull_write_fonts::read::FontRead;
+
+    // one chain with a noncontextual subtable substituting 3 -> 10 and 4 -> 11
+    fn noncontextual_morx() -> BeBuffer {
+        BeBuffer::new()
+            .extend([2_u16, 0]) // version, unused
+            .extend([1_u32]) // 1 chain
+            .extend([1_u32, 52, 0, 1]) // default flags, length, 0 features, 1 subtable
+            .extend([36_u32, 4, 1]) // length, noncontextual coverage, sub feature flags
+            .extend([6_u16, 4, 3, 8, 1, 4]) // single format, binary search header
+            .extend([3_u16, 10, 4, 11, 0xFFFF, 0])
+    }
+
+    fn plan(old_gids: &[u32]) -> Plan {
+        let mut plan = Plan::default();
+        for (new_gid, old_gid) in old_gids.iter().enumerate() {
+            let (old_gid, new_gid) = (GlyphId::new(*old_gid), GlyphId::new(new_gid as u32));
+            plan.glyph_map.insert(old_gid, new_gid);
+            plan.new_to_old_gid_list.push((new_gid, old_gid));
+        }
+        plan.num_output_glyphs = old_gids.len();
+        plan
+    }
+
+    #[test]
+    fn test_subset_morx_noncontextual() {
+        let buf = noncontextual_morx();
+        let morx = Morx::read(FontData::new(buf.data())).unwrap();
+
+        let mut s = Serializer::new(1024);
+        assert_eq!(s.start_serialize(), Ok(()));
+        let font = FontRef::new(fontcull_font_test_data::GLYF_COMPONENTS).unwrap();
+        let mut builder = FontBuilder::default();
+        morx.subset(&plan(&[0, 3, 10]), &font, &mut s, &mut builder)
+            .unwrap();
+        s.end_serialize();
+        let subsetted = s.copy_bytes();
+        let subsetted = Morx::read(FontData::new(&subsetted)).unwrap();
+
+        let chain = subsetted.chains().iter().next().unwrap().unwrap();
+        assert_eq!(chain.default_flags(), 1);
+        let subtable = chain.subtables().iter().next().unwrap().unwrap();
+        assert_eq!(subtable.sub_feature_flags(), 1);
+        let SubtableKind::NonContextual(lookup) = subtable.kind().unwrap() else {
+            panic!("expected a noncontextual subtable");
+        };
+        // old 3 -> new 1, old 10 -> new 2, and old 4 isn't kept
+        assert_eq!(lookup.value(1), Ok(2));
+        assert!(lookup.value(0).is_err());
+        assert!(lookup.value(3).is_err());
+    }
+
+    #[test]
+    fn test_morx_closure() {
+        let buf = noncontextual_morx();
+        let morx = Morx::read(FontData::new(buf.data())).unwrap();
+
+        let mut glyphs: IntSet<GlyphId> = [GlyphId::new(0), GlyphId::new(3)].into_iter().collect();
+        closure_glyphs(&morx, 20, &mut glyphs);
+        let glyphs: Vec<u32> = glyphs.iter().map(|gid| gid.to_u32()).collect();
+        assert_eq!(glyphs, [0, 3, 10]);
+    }
+}
@@ -39,6 +39,9 @@ pub(crate) fn resolve_graph_overflows(
     }
 
     if tag == Gsub::TAG || tag == Gpos::TAG {
+        if graph.split_subtables(tag)? {
+            graph.sort_shortest_distance()?;
+        }
         if graph.assign_spaces()? {
             graph.sort_shortest_distance()?;
         } else {
@@ -46,6 +49,30 @@ pub(crate) fn resolve_graph_overflows(
         }
     }
 
+    if resolve_overflows_in_rounds(graph, max_round)? {
+        return Ok(());
+    }
+
+    // As a last resort, move lookup subtables behind extension lookups, the
+    // subtables of a promoted lookup then get their own 32-bit offset and space
+    let extension_type = match tag {
+        Gsub::TAG => Some(7),
+        Gpos::TAG => Some(9),
+        _ => None,
+    };
+    if let Some(extension_type) = extension_type {
+        if graph.promote_extension_lookups(extension_type)?
+            && resolve_overflows_in_rounds(graph, max_round)?
+        {
+            return Ok(());
+        }
+    }
+
+    Err(RepackErrorFlags::REPACK_ERROR_NO_RESOLUTION)
+}
+
+// returns true if all overflows are resolved
+fn resolve_overflows_in_rounds(graph: &mut Graph, max_round: u8) -> Result<bool, RepackErrorFlags> {
     let mut round = 0;
     let mut overflows = graph.overflows();
     while !overflows.is_empty() && round < max_round {
@@ -59,13 +86,7 @@ pub(crate) fn resolve_graph_overflows(
         graph.sort_shortest_distance()?;
         let _ = std::mem::replace(&mut overflows, graph.overflows());
     }
-
-    //TODO: add more overflow resolution
-    if overflows.is_empty() {
-        Ok(())
-    } else {
-        Err(RepackErrorFlags::REPACK_ERROR_NO_RESOLUTION)
-    }
+    Ok(overflows.is_empty())
 }
 
 #[cfg(test)]
@@ -75,6 +96,7 @@ pub(crate) mod test {
         add_24_offset, add_object, add_offset, add_virtual_offset, add_wide_offset,
         populate_serializer_with_dedup_overflow, populate_serializer_with_overflow, start_object,
     };
+    use crate::serialize::ObjIdx;
 
     fn populate_serializer_spaces(s: &mut Serializer, with_overflow: bool) {
         let large_string = [b'a'; 70000];
@@ -679,6 +701,213 @@ pub(crate) mod test {
         s.end_serialize();
     }
 
+    // GSUB with a single substitution lookup for each of subtable_sizes, whose
+    // subtable is an object of that size
+    fn populate_serializer_with_extension_promotion(s: &mut Serializer, subtable_sizes: &[usize]) {
+        s.start_serialize().unwrap();
+
+        let mut lookups = Vec::new();
+        for size in subtable_sizes {
+            let subtable = add_object(s, &vec![b'a'; *size], *size);
+            // lookup type 1, lookup flag 0, 1 subtable
+            start_object(s, &[0, 1, 0, 0, 0, 1], 6);
+            add_offset(s, subtable);
+            lookups.push(s.pop_pack(false).unwrap());
+        }
+
+        start_object(s, &(lookups.len() as u16).to_be_bytes(), 2);
+        for lookup in lookups {
+            add_offset(s, lookup);
+        }
+        let lookup_list = s.pop_pack(false).unwrap();
+        let script_list = add_object(s, &[0, 0], 2);
+        let feature_list = add_object(s, &[0, 0], 2);
+
+        // GSUB 1.0 header
+        start_object(s, &[0, 1, 0, 0], 4);
+        add_offset(s, script_list);
+        add_offset(s, feature_list);
+        add_offset(s, lookup_list);
+        s.pop_pack(false).unwrap();
+        s.end_serialize();
+    }
+
+    // coverage format 1 of consecutive glyphs
+    fn add_coverage(s: &mut Serializer, glyphs: std::ops::Range<u16>) -> ObjIdx {
+        start_object(s, &[0, 1], 2);
+        s.embed(glyphs.len() as u16).unwrap();
+        for g in glyphs {
+            s.embed(g).unwrap();
+        }
+        s.pop_pack(false).unwrap()
+    }
+
+    // GSUB/GPOS 1.0 header with a single lookup of lookup_type
+    fn add_single_lookup_table(s: &mut Serializer, lookup_type: u16, subtable: ObjIdx) {
+        start_object(s, &lookup_type.to_be_bytes(), 2);
+        // lookup flag 0, 1 subtable
+        s.embed_bytes(&[0, 0, 0, 1]).unwrap();
+        add_offset(s, subtable);
+        let lookup = s.pop_pack(false).unwrap();
+
+        start_object(s, &[0, 1], 2);
+        add_offset(s, lookup);
+        let lookup_list = s.pop_pack(false).unwrap();
+        let script_list = add_object(s, &[0, 0], 2);
+        let feature_list = add_object(s, &[0, 0], 2);
+
+        start_object(s, &[0, 1, 0, 0], 4);
+        add_offset(s, script_list);
+        add_offset(s, feature_list);
+        add_offset(s, lookup_list);
+        s.pop_pack(false).unwrap();
+        s.end_serialize();
+    }
+
+    // PairPosFormat1 with 250 pairs for each first glyph, the x advance of a
+    // pair is its first glyph
+    fn populate_serializer_with_large_pair_pos1(s: &mut Serializer, num_pair_sets: u16) {
+        s.start_serialize().unwrap();
+        let mut pair_sets = Vec::new();
+        for first in 0..num_pair_sets {
+            start_object(s, &250_u16.to_be_bytes(), 2);
+            for second in 0..250_u16 {
+                s.embed(second).unwrap();
+                s.embed(first).unwrap();
+            }
+            pair_sets.push(s.pop_pack(false).unwrap());
+        }
+        let coverage = add_coverage(s, 0..num_pair_sets);
+
+        start_object(s, &[0, 1], 2);
+        add_offset(s, coverage);
+        // valueFormat1 x advance, valueFormat2 0
+        s.embed_bytes(&[0, 4, 0, 0]).unwrap();
+        s.embed(num_pair_sets).unwrap();
+        for pair_set in pair_sets {
+            add_offset(s, pair_set);
+        }
+        let subtable = s.pop_pack(false).unwrap();
+        add_single_lookup_table(s, 2, subtable);
+    }
+
+    // PairPosFormat2 where glyph g is in class1 g and glyph 1000 + j is in
+    // class2 j, the x advance of a class pair is the sum of the classes
+    fn populate_serializer_with_large_pair_pos2(s: &mut Serializer, class1_count: u16) {
+        let class2_count = 100_u16;
+        s.start_serialize().unwrap();
+        let coverage = add_coverage(s, 0..class1_count);
+
+        start_object(s, &[0, 1, 0, 1], 4);
+        s.embed(class1_count - 1).unwrap();
+        for class in 1..class1_count {
+            s.embed(class).unwrap();
+        }
+        let class_def1 = s.pop_pack(false).unwrap();
+
+        start_object(s, &[0, 1], 2);
+        s.embed(1001_u16).unwrap();
+        s.embed(class2_count - 1).unwrap();
+        for class in 1..class2_count {
+            s.embed(class).unwrap();
+        }
+        let class_def2 = s.pop_pack(false).unwrap();
+
+        start_object(s, &[0, 2], 2);
+        add_offset(s, coverage);
+        // valueFormat1 x advance, valueFormat2 0
+        s.embed_bytes(&[0, 4, 0, 0]).unwrap();
+        add_offset(s, class_def1);
+        add_offset(s, class_def2);
+        s.embed(class1_count).unwrap();
+        s.embed(class2_count).unwrap();
+        for class1 in 0..class1_count {
+            for class2 in 0..class2_count {
+                s.embed(class1 + class2).unwrap();
+            }
+        }
+        let subtable = s.pop_pack(false).unwrap();
+        add_single_lookup_table(s, 2, subtable);
+    }
+
+    // MarkBasePosFormat1 where mark glyph c is in mark class c and the
+    // anchor of base glyph 100 + b for mark class c is (b, c)
+    fn populate_serializer_with_large_mark_base_pos(s: &mut Serializer, num_bases: u16) {
+        let class_count = 4_u16;
+        s.start_serialize().unwrap();
+
+        let mut mark_anchors = Vec::new();
+        for class in 0..class_count {
+            start_object(s, &[0, 1, 0, 0], 4);
+            s.embed(class).unwrap();
+            mark_anchors.push(s.pop_pack(false).unwrap());
+        }
+        start_object(s, &class_count.to_be_bytes(), 2);
+        for (class, anchor) in mark_anchors.into_iter().enumerate() {
+            s.embed(class as u16).unwrap();
+            add_offset(s, anchor);
+        }
+        let mark_array = s.pop_pack(false).unwrap();
+
+        let mut base_anchors = Vec::new();
+        for base in 0..num_bases {
+            for class in 0..class_count {
+                start_object(s, &[0, 1], 2);
+                s.embed(base).unwrap();
+                s.embed(class).unwrap();
+                base_anchors.push(s.pop_pack(false).unwrap());
+            }
+        }
+        start_object(s, &num_bases.to_be_bytes(), 2);
+        for anchor in base_anchors {
+            add_offset(s, anchor);
+        }
+        let base_array = s.pop_pack(false).unwrap();
+
+        let mark_coverage = add_coverage(s, 0..class_count);
+        let base_coverage = add_coverage(s, 100..100 + num_bases);
+
+        start_object(s, &[0, 1], 2);
+        add_offset(s, mark_coverage);
+        add_offset(s, base_coverage);
+        s.embed(class_count).unwrap();
+        add_offset(s, mark_array);
+        add_offset(s, base_array);
+        let subtable = s.pop_pack(false).unwrap();
+        add_single_lookup_table(s, 4, subtable);
+    }
+
+    // LigatureSubstFormat1 where first glyph g has 30 ligatures, ligature j
+    // of it is glyph g + j with the component 1000 + j
+    fn populate_serializer_with_large_ligature_subst(s: &mut Serializer, num_sets: u16) {
+        s.start_serialize().unwrap();
+        let mut ligature_sets = Vec::new();
+        for first in 0..num_sets {
+            let mut ligatures = Vec::new();
+            for j in 0..30_u16 {
+                start_object(s, &(first + j).to_be_bytes(), 2);
+                s.embed(2_u16).unwrap();
+                s.embed(1000 + j).unwrap();
+                ligatures.push(s.pop_pack(false).unwrap());
+            }
+            start_object(s, &30_u16.to_be_bytes(), 2);
+            for ligature in ligatures {
+                add_offset(s, ligature);
+            }
+            ligature_sets.push(s.pop_pack(false).unwrap());
+        }
+        let coverage = add_coverage(s, 0..num_sets);
+
+        start_object(s, &[0, 1], 2);
+        add_offset(s, coverage);
+        s.embed(num_sets).unwrap();
+        for ligature_set in ligature_sets {
+            add_offset(s, ligature_set);
+        }
+        let subtable = s.pop_pack(false).unwrap();
+        add_single_lookup_table(s, 4, subtable);
+    }
+
     fn run_resolve_overflow_test(
         overflowing: &Serializer,
         expected: &Serializer,
@@ -888,4 +1117,259 @@ pub(crate) mod test {
         assert_eq!(out[9], b'b');
         assert_eq!(out[12], b'd');
     }
+
+    #[test]
+    fn test_resolve_overflows_via_extension_promotion() {
+        use fontcull_write_fonts::read::{
+            tables::{gsub::SubstitutionSubtables, layout::Subtables},
+            FontData, FontRead,
+        };
+
+        let buf_size = 160000;
+        let mut s = Serializer::new(buf_size);
+        populate_serializer_with_extension_promotion(&mut s, &[70000, 70000]);
+        assert!(s.offset_overflow());
+
+        // no 32-bit offsets to isolate subtables with, so only promotion resolves this
+        let out = resolve_overflows(&s, Tag::from_u32(0), 32);
+        assert!(out.is_err());
+
+        let out = resolve_overflows(&s, Gsub::TAG, 32).unwrap();
+        // the second subtable fits after the first one is moved to its own
+        // space, which takes an 8 byte extension subtable
+        assert_eq!(out.len(), 10 + 2 * 2 + (2 + 2 * 2) + 2 * (8 + 70000) + 8);
+
+        let gsub = Gsub::read(FontData::new(&out)).unwrap();
+        let lookups = gsub.lookup_list().unwrap().lookups();
+        assert_eq!(lookups.len(), 2);
+        let extension_count = lookups
+            .iter()
+            .filter(|lookup| {
+                let lookup = lookup.as_ref().unwrap();
+                let Ok(SubstitutionSubtables::Single(subtables)) = lookup.subtables() else {
+                    panic!("not a single substitution lookup!");
+                };
+                assert_eq!(subtables.len(), 1);
+                matches!(subtables, Subtables::Extension(_))
+            })
+            .count();
+        assert_eq!(extension_count, 1);
+    }
+
+    #[test]
+    fn test_resolve_overflows_promotes_largest_lookups_first() {
+        use fontcull_write_fonts::read::{FontData, FontRead};
+
+        let buf_size = 160000;
+        let mut s = Serializer::new(buf_size);
+        populate_serializer_with_extension_promotion(&mut s, &[40000, 50000, 30000]);
+        assert!(s.offset_overflow());
+
+        let out = resolve_overflows(&s, Gsub::TAG, 32).unwrap();
+        assert_eq!(
+            out.len(),
+            10 + 2 * 2 + (2 + 3 * 2) + 3 * 8 + 8 + 40000 + 50000 + 30000
+        );
+
+        let gsub = Gsub::read(FontData::new(&out)).unwrap();
+        let lookup_types: Vec<_> = gsub
+            .lookup_list()
+            .unwrap()
+            .lookups()
+            .iter()
+            .map(|lookup| lookup.unwrap().lookup_type())
+            .collect();
+        assert_eq!(lookup_types, [1, 7, 1]);
+    }
+
+    #[test]
+    fn test_resolve_overflows_via_splitting_pair_pos1() {
+        use fontcull_write_fonts::read::{
+            tables::{
+                gpos::{PairPos, PositionSubtables},
+                layout::Subtables,
+            },
+            FontData, FontRead,
+        };
+
+        let mut s = Serializer::new(200000);
+        populate_serializer_with_large_pair_pos1(&mut s, 100);
+        assert!(s.offset_overflow());
+
+        let out = resolve_overflows(&s, Gpos::TAG, 32).unwrap();
+        let gpos = Gpos::read(FontData::new(&out)).unwrap();
+        let lookup = gpos.lookup_list().unwrap().lookups().get(0).unwrap();
+        assert_eq!(lookup.lookup_type(), 9);
+        let Ok(PositionSubtables::Pair(subtables)) = lookup.subtables() else {
+            panic!("unexpected lookup subtables");
+        };
+        assert!(matches!(subtables, Subtables::Extension(_)));
+        assert!(subtables.len() > 1);
+
+        let mut first_glyphs = Vec::new();
+        for subtable in subtables.iter() {
+            let Ok(PairPos::Format1(pair_pos)) = subtable else {
+                panic!("unexpected PairPos subtable");
+            };
+            let coverage = pair_pos.coverage().unwrap();
+            for (glyph, pair_set) in coverage.iter().zip(pair_pos.pair_sets().iter()) {
+                let pair_set = pair_set.unwrap();
+                assert_eq!(pair_set.pair_value_count(), 250);
+                let record = pair_set.pair_value_records().get(249).unwrap();
+                assert_eq!(record.second_glyph().to_u16(), 249);
+                assert_eq!(
+                    record.value_record1().x_advance(),
+                    Some(glyph.to_u16() as i16)
+                );
+                first_glyphs.push(glyph.to_u16());
+            }
+        }
+        assert_eq!(first_glyphs, (0..100).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_resolve_overflows_via_splitting_pair_pos2() {
+        use fontcull_write_fonts::read::{
+            tables::{
+                gpos::{PairPos, PositionSubtables},
+                layout::Subtables,
+            },
+            FontData, FontRead,
+        };
+        use fontcull_write_fonts::types::GlyphId16;
+
+        let mut s = Serializer::new(200000);
+        populate_serializer_with_large_pair_pos2(&mut s, 400);
+        assert!(s.offset_overflow());
+
+        let out = resolve_overflows(&s, Gpos::TAG, 32).unwrap();
+        let gpos = Gpos::read(FontData::new(&out)).unwrap();
+        let lookup = gpos.lookup_list().unwrap().lookups().get(0).unwrap();
+        assert_eq!(lookup.lookup_type(), 9);
+        let Ok(PositionSubtables::Pair(subtables)) = lookup.subtables() else {
+            panic!("unexpected lookup subtables");
+        };
+        assert!(matches!(subtables, Subtables::Extension(_)));
+        assert!(subtables.len() > 1);
+
+        let mut first_glyphs = Vec::new();
+        for subtable in subtables.iter() {
+            let Ok(PairPos::Format2(pair_pos)) = subtable else {
+                panic!("unexpected PairPos subtable");
+            };
+            let class_def1 = pair_pos.class_def1().unwrap();
+            let class_def2 = pair_pos.class_def2().unwrap();
+            let class1_records = pair_pos.class1_records();
+            for glyph in pair_pos.coverage().unwrap().iter() {
+                let class1 = class_def1.get(glyph);
+                let record = class1_records.get(class1 as usize).unwrap();
+                for class2_glyph in [1000_u16, 1001, 1099] {
+                    let class2 = class_def2.get(GlyphId16::new(class2_glyph));
+                    let class2_record = record.class2_records().get(class2 as usize).unwrap();
+                    assert_eq!(
+                        class2_record.value_record1().x_advance(),
+                        Some((glyph.to_u16() + class2_glyph - 1000) as i16)
+                    );
+                }
+                first_glyphs.push(glyph.to_u16());
+            }
+        }
+        assert_eq!(first_glyphs, (0..400).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_resolve_overflows_via_splitting_mark_base_pos() {
+        use fontcull_write_fonts::read::{
+            tables::{gpos::PositionSubtables, layout::Subtables},
+            FontData, FontRead,
+        };
+
+        let mut s = Serializer::new(200000);
+        populate_serializer_with_large_mark_base_pos(&mut s, 3000);
+        assert!(s.offset_overflow());
+
+        let out = resolve_overflows(&s, Gpos::TAG, 32).unwrap();
+        let gpos = Gpos::read(FontData::new(&out)).unwrap();
+        let lookup = gpos.lookup_list().unwrap().lookups().get(0).unwrap();
+        assert_eq!(lookup.lookup_type(), 9);
+        let Ok(PositionSubtables::MarkToBase(subtables)) = lookup.subtables() else {
+            panic!("unexpected lookup subtables");
+        };
+        assert!(matches!(subtables, Subtables::Extension(_)));
+        assert!(subtables.len() > 1);
+
+        let mut mark_glyphs = Vec::new();
+        for subtable in subtables.iter() {
+            let subtable = subtable.unwrap();
+            let mark_array = subtable.mark_array().unwrap();
+            let base_array = subtable.base_array().unwrap();
+            let base_records = base_array.base_records();
+            let marks = subtable.mark_coverage().unwrap().iter();
+            for (glyph, mark_record) in marks.zip(mark_array.mark_records()) {
+                let mark_class = mark_record.mark_class();
+                assert!(mark_class < subtable.mark_class_count());
+                let mark_anchor = mark_record.mark_anchor(mark_array.offset_data()).unwrap();
+                assert_eq!(mark_anchor.y_coordinate(), glyph.to_u16() as i16);
+
+                for (i, base) in subtable.base_coverage().unwrap().iter().enumerate() {
+                    let base_anchor = base_records
+                        .get(i)
+                        .unwrap()
+                        .base_anchors(base_array.offset_data())
+                        .get(mark_class as usize)
+                        .unwrap()
+                        .unwrap();
+                    assert_eq!(base_anchor.x_coordinate(), (base.to_u16() - 100) as i16);
+                    assert_eq!(base_anchor.y_coordinate(), glyph.to_u16() as i16);
+                }
+                mark_glyphs.push(glyph.to_u16());
+            }
+        }
+        assert_eq!(mark_glyphs, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_resolve_overflows_via_splitting_ligature_subst() {
+        use fontcull_write_fonts::read::{
+            tables::{gsub::SubstitutionSubtables, layout::Subtables},
+            FontData, FontRead,
+        };
+
+        let mut s = Serializer::new(200000);
+        populate_serializer_with_large_ligature_subst(&mut s, 400);
+        assert!(s.offset_overflow());
+
+        let out = resolve_overflows(&s, Gsub::TAG, 32).unwrap();
+        let gsub = Gsub::read(FontData::new(&out)).unwrap();
+        let lookup = gsub.lookup_list().unwrap().lookups().get(0).unwrap();
+        assert_eq!(lookup.lookup_type(), 7);
+        let Ok(SubstitutionSubtables::Ligature(subtables)) = lookup.subtables() else {
+            panic!("unexpected lookup subtables");
+        };
+        assert!(matches!(subtables, Subtables::Extension(_)));
+        assert!(subtables.len() > 1);
+
+        let mut first_glyphs = Vec::new();
+        for subtable in subtables.iter() {
+            let subtable = subtable.unwrap();
+            let coverage = subtable.coverage().unwrap();
+            for (glyph, ligature_set) in coverage.iter().zip(subtable.ligature_sets().iter()) {
+                let ligatures = ligature_set.unwrap().ligatures();
+                assert_eq!(ligatures.len(), 30);
+                for (j, ligature) in ligatures.iter().enumerate() {
+                    let ligature = ligature.unwrap();
+                    assert_eq!(
+                        ligature.ligature_glyph().to_u16(),
+                        glyph.to_u16() + j as u16
+                    );
+                    assert_eq!(
+                        ligature.component_glyph_ids()[0].get().to_u16(),
+                        1000 + j as u16
+                    );
+                }
+                first_glyphs.push(glyph.to_u16());
+            }
+        }
+        assert_eq!(first_glyphs, (0..400).collect::<Vec<_>>());
+    }
 }
@@ -169,6 +169,21 @@ pub(crate) struct Link {
 }
 
 impl Link {
+    pub(crate) fn new(
+        width: LinkWidth,
+        whence: OffsetWhence,
+        position: u32,
+        objidx: ObjIdx,
+    ) -> Self {
+        Self {
+            width,
+            whence,
+            position,
+            objidx,
+            ..Default::default()
+        }
+    }
+
     pub(crate) fn obj_idx(&self) -> ObjIdx {
         self.objidx
     }
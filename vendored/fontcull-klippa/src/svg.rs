@@ -0,0 +1,384 @@
+//! impl subset() for SVG table
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::ops::Range;
+
+use crate::serialize::{SerializeErrorFlags, Serializer};
+use crate::{Plan, Subset, SubsetError, SubsetFlags, SVG};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use fnv::FnvHashMap;
+use fontcull_write_fonts::{
+    read::{tables::svg::Svg, FontRef},
+    types::GlyphId,
+    FontBuilder,
+};
+
+const GZIP_MAGIC: &[u8] = &[0x1F, 0x8B];
+const GLYPH_ID_PREFIX: &[u8] = b"glyph";
+// SVG glyphs use CPAL colors through these custom properties
+const PALETTE_VARIABLE: &[u8] = b"var(--color";
+
+// version, document list offset and reserved field
+const HEADER_LEN: u32 = 10;
+// start and end glyph IDs, document offset and length
+const DOCUMENT_RECORD_LEN: u32 = 12;
+
+// reference: the SVG table in the OpenType spec
+// <https://learn.microsoft.com/en-us/typography/opentype/spec/svg>
+//
+// Each document record is subset to the retained glyphs of its range. The
+// elements of dropped glyphs are cut out of shared documents, `glyphNNN` ids
+// are renamed to the new glyph IDs, and identical documents are stored once.
+impl Subset for Svg<'_> {
+    fn subset(
+        &self,
+        plan: &Plan,
+        _font: &FontRef,
+        s: &mut Serializer,
+        _builder: &mut FontBuilder,
+    ) -> Result<(), SubsetError> {
+        let document_list = self
+            .svg_document_list()
+            .map_err(|_| SubsetError::SubsetTableError(SVG))?;
+        let data = document_list.offset_data();
+        let gzip_all = plan
+            .subset_flags
+            .contains(SubsetFlags::SUBSET_FLAGS_GZIP_SVG);
+
+        let mut documents: Vec<Vec<u8>> = Vec::new();
+        let mut document_indices: FnvHashMap<Vec<u8>, usize> = FnvHashMap::default();
+        // (first new glyph, last new glyph, document index)
+        let mut records: Vec<(u16, u16, usize)> = Vec::new();
+        for record in document_list.document_records() {
+            let old_gids = record.start_glyph_id().to_u32()..=record.end_glyph_id().to_u32();
+            let new_gids: BTreeMap<u32, u32> = old_gids
+                .clone()
+                .filter_map(|old_gid| {
+                    let new_gid = plan.glyph_map.get(&GlyphId::new(old_gid))?;
+                    Some((old_gid, new_gid.to_u32()))
+                })
+                .collect();
+            if new_gids.is_empty() {
+                continue;
+            }
+            let start = record.svg_doc_offset() as usize;
+            let Some(document) = data.slice(start..start + record.svg_doc_length() as usize) else {
+                continue;
+            };
+            let document = document.as_bytes();
+            let compressed = document.starts_with(GZIP_MAGIC);
+            let Some(document) = decompress(document) else {
+                continue;
+            };
+
+            let dropped: Vec<u32> = old_gids
+                .filter(|old_gid| !new_gids.contains_key(old_gid))
+                .collect();
+            let document = subset_document(document, &dropped, &new_gids);
+            let document = if compressed || gzip_all {
+                compress(&document).map_err(|_| SubsetError::SubsetTableError(SVG))?
+            } else {
+                document
+            };
+            let index = *document_indices
+                .entry(document)
+                .or_insert_with_key(|document| {
+                    documents.push(document.clone());
+                    documents.len() - 1
+                });
+
+            // a record for each run of consecutive new glyph IDs
+            for new_gid in new_gids.values().map(|gid| *gid as u16) {
+                match records.last_mut() {
+                    Some((_, last, last_index)) if *last_index == index && *last + 1 == new_gid => {
+                        *last = new_gid
+                    }
+                    _ => records.push((new_gid, new_gid, index)),
+                }
+            }
+        }
+        if records.is_empty() {
+            return Err(SubsetError::SubsetTableError(SVG));
+        }
+        records.sort_unstable();
+
+        serialize_svg(s, self.version(), &records, &documents)
+            .map_err(|_| SubsetError::SubsetTableError(SVG))
+    }
+}
+
+fn serialize_svg(
+    s: &mut Serializer,
+    version: u16,
+    records: &[(u16, u16, usize)],
+    documents: &[Vec<u8>],
+) -> Result<(), SerializeErrorFlags> {
+    s.embed(version)?;
+    s.embed(HEADER_LEN)?;
+    s.embed(0_u32)?;
+
+    s.embed(records.len() as u16)?;
+    let mut offsets = Vec::with_capacity(documents.len());
+    let mut offset = 2 + DOCUMENT_RECORD_LEN as usize * records.len();
+    for document in documents {
+        offsets.push(offset);
+        offset += document.len();
+    }
+    for (first, last, index) in records {
+        let offset: u32 = offsets[*index]
+            .try_into()
+            .map_err(|_| s.set_err(SerializeErrorFlags::SERIALIZE_ERROR_INT_OVERFLOW))?;
+        s.embed(*first)?;
+        s.embed(*last)?;
+        s.embed(offset)?;
+        s.embed(documents[*index].len() as u32)?;
+    }
+    for document in documents {
+        s.embed_bytes(document)?;
+    }
+    Ok(())
+}
+
+/// A document as plain text, which gzip compressed documents aren't
+fn decompress(document: &[u8]) -> Option<Vec<u8>> {
+    if !document.starts_with(GZIP_MAGIC) {
+        return Some(document.to_vec());
+    }
+    let mut decompressed = Vec::new();
+    GzDecoder::new(document)
+        .read_to_end(&mut decompressed)
+        .ok()?;
+    Some(decompressed)
+}
+
+fn compress(document: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(document)?;
+    encoder.finish()
+}
+
+/// Whether any document of the table uses CPAL colors, which then must be kept
+pub(crate) fn uses_palette(svg: &Svg) -> bool {
+    let Ok(document_list) = svg.svg_document_list() else {
+        return false;
+    };
+    let data = document_list.offset_data();
+    document_list.document_records().iter().any(|record| {
+        let start = record.svg_doc_offset() as usize;
+        data.slice(start..start + record.svg_doc_length() as usize)
+            .and_then(|document| decompress(document.as_bytes()))
+            .is_some_and(|document| find(&document, PALETTE_VARIABLE, 0).is_some())
+    })
+}
+
+/// Cut the elements of `dropped` glyphs out of a document and rename the
+/// `glyphNNN` ids, and references to them, to the new glyph IDs
+fn subset_document(
+    mut document: Vec<u8>,
+    dropped: &[u32],
+    new_gids: &BTreeMap<u32, u32>,
+) -> Vec<u8> {
+    for gid in dropped {
+        while let Some(range) = glyph_element(&document, *gid) {
+            document.drain(range);
+        }
+    }
+
+    let mut subset = Vec::with_capacity(document.len());
+    let mut pos = 0;
+    while let Some(found) = find(&document, GLYPH_ID_PREFIX, pos) {
+        let digits_start = found + GLYPH_ID_PREFIX.len();
+        let digits_end = document[digits_start..]
+            .iter()
+            .position(|byte| !byte.is_ascii_digit())
+            .map_or(document.len(), |len| digits_start + len);
+        let new_gid = is_glyph_id(&document, found)
+            .then(|| std::str::from_utf8(&document[digits_start..digits_end]).ok())
+            .flatten()
+            .and_then(|digits| digits.parse::<u32>().ok())
+            .and_then(|old_gid| new_gids.get(&old_gid));
+        match new_gid {
+            Some(new_gid) => {
+                subset.extend_from_slice(&document[pos..digits_start]);
+                subset.extend_from_slice(new_gid.to_string().as_bytes());
+                pos = digits_end;
+            }
+            None => {
+                subset.extend_from_slice(&document[pos..digits_start]);
+                pos = digits_start;
+            }
+        }
+    }
+    subset.extend_from_slice(&document[pos..]);
+    subset
+}
+
+/// Whether the `glyph` at `pos` starts an id value or a `#` reference, and is
+/// followed by digits
+fn is_glyph_id(document: &[u8], pos: usize) -> bool {
+    let before = &document[..pos];
+    let starts_id =
+        before.ends_with(b"#") || before.ends_with(b"id=\"") || before.ends_with(b"id='");
+    let has_digits = document
+        .get(pos + GLYPH_ID_PREFIX.len())
+        .is_some_and(|byte| byte.is_ascii_digit());
+    starts_id && has_digits
+}
+
+/// The bytes of the element with the id `glyph<gid>`, from its start tag to
+/// the end of its end tag
+fn glyph_element(document: &[u8], gid: u32) -> Option<Range<usize>> {
+    let mut pos = 0;
+    let id_start = loop {
+        let found = [&b"id=\""[..], b"id='"]
+            .iter()
+            .filter_map(|id| {
+                let value = [*id, GLYPH_ID_PREFIX, gid.to_string().as_bytes()].concat();
+                find(document, &value, pos).map(|found| (found, value.len()))
+            })
+            .min()?;
+        // the value must end there, and `id` must be a whole attribute name
+        let (found, len) = found;
+        let quoted = matches!(document.get(found + len), Some(b'"' | b'\''));
+        let whole = found > 0 && document[found - 1].is_ascii_whitespace();
+        if quoted && whole {
+            break found;
+        }
+        pos = found + 1;
+    };
+    let start = document[..id_start]
+        .iter()
+        .rposition(|byte| *byte == b'<')?;
+
+    let mut depth = 0_usize;
+    let mut pos = start;
+    loop {
+        let tag = pos + document[pos..].iter().position(|byte| *byte == b'<')?;
+        let rest = &document[tag..];
+        pos = if rest.starts_with(b"<!--") {
+            tag + find(rest, b"-->", 0)? + 3
+        } else if rest.starts_with(b"<![CDATA[") {
+            tag + find(rest, b"]]>", 0)? + 3
+        } else if rest.starts_with(b"<?") || rest.starts_with(b"<!") {
+            tag + find(rest, b">", 0)? + 1
+        } else {
+            let end = tag + tag_end(rest)?;
+            if rest.starts_with(b"</") {
+                depth = depth.checked_sub(1)?;
+            } else if document[end - 2] != b'/' {
+                depth += 1;
+            }
+            end
+        };
+        if depth == 0 {
+            return Some(start..pos);
+        }
+    }
+}
+
+/// The length of the tag at the start of `tag`, with `>` in quoted attribute
+/// values skipped
+fn tag_end(tag: &[u8]) -> Option<usize> {
+    let mut quote = None;
+    for (i, byte) in tag.iter().enumerate() {
+        match (quote, byte) {
+            (None, b'"' | b'\'') => quote = Some(*byte),
+            (Some(open), _) if open == *byte => quote = None,
+            (None, b'>') => return Some(i + 1),
+            _ => (),
+        }
+    }
+    None
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    haystack
+        .get(from..)?
+        .windows(needle.len())
+        .position(|window| window == needle)
+        .map(|pos| from + pos)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fontcull_font_test_data::bebuffer::BeBuffer;
+    use fontcull_write_fonts::read::{FontData, FontRead};
+
+    const SHARED: &[u8] = br##"<svg xmlns="http://www.w3.org/2000/svg"><defs><path id="p" d="M0 0"/></defs><g id="glyph1"><use href="#p"/></g><g id="glyph2"><g><rect width="2"/></g></g><path id='glyph3' d="M1 1"/></svg>"##;
+    const PLAIN: &[u8] = br#"<svg xmlns="http://www.w3.org/2000/svg"><circle r="1" fill="var(--color0, red)"/></svg>"#;
+
+    fn svg_table(records: &[(u16, u16, &[u8])]) -> BeBuffer {
+        let mut buf = BeBuffer::new()
+            .extend([0_u16])
+            .extend([HEADER_LEN, 0])
+            .push(records.len() as u16);
+        let mut offset = 2 + DOCUMENT_RECORD_LEN * records.len() as u32;
+        for (first, last, document) in records {
+            buf = buf
+                .extend([*first, *last])
+                .extend([offset, document.len() as u32]);
+            offset += document.len() as u32;
+        }
+        for (_, _, document) in records {
+            buf = buf.extend(document.iter().copied());
+        }
+        buf
+    }
+
+    fn subset(svg: &Svg, old_gids: &[u32], flags: SubsetFlags) -> Vec<u8> {
+        let mut plan = Plan::default();
+        for (new_gid, old_gid) in old_gids.iter().enumerate() {
+            plan.glyph_map
+                .insert(GlyphId::new(*old_gid), GlyphId::new(new_gid as u32));
+        }
+        plan.subset_flags = flags;
+        let mut s = Serializer::new(1024);
+        assert_eq!(s.start_serialize(), Ok(()));
+        let font = FontRef::new(fontcull_font_test_data::GLYF_COMPONENTS).unwrap();
+        let mut builder = FontBuilder::default();
+        svg.subset(&plan, &font, &mut s, &mut builder).unwrap();
+        s.end_serialize();
+        s.copy_bytes()
+    }
+
+    #[test]
+    fn test_subset_shared_document() {
+        let buf = svg_table(&[(1, 3, SHARED), (5, 5, PLAIN)]);
+        let svg = Svg::read(FontData::new(buf.data())).unwrap();
+
+        // old 1 -> new 1, old 3 -> new 2, and old 2 is cut out
+        let subsetted = subset(&svg, &[0, 1, 3], SubsetFlags::default());
+        let subsetted = Svg::read(FontData::new(&subsetted)).unwrap();
+        let records: Vec<_> = subsetted
+            .svg_document_list()
+            .unwrap()
+            .document_records()
+            .iter()
+            .map(|r| (r.start_glyph_id().to_u16(), r.end_glyph_id().to_u16()))
+            .collect();
+        assert_eq!(records, [(1, 2)]);
+        let document = subsetted.glyph_data(GlyphId::new(2)).unwrap().unwrap();
+        assert_eq!(
+            std::str::from_utf8(document).unwrap(),
+            r##"<svg xmlns="http://www.w3.org/2000/svg"><defs><path id="p" d="M0 0"/></defs><g id="glyph1"><use href="#p"/></g><path id='glyph2' d="M1 1"/></svg>"##
+        );
+    }
+
+    #[test]
+    fn test_subset_dedup_and_gzip() {
+        let buf = svg_table(&[(1, 1, PLAIN), (2, 2, PLAIN), (4, 4, SHARED)]);
+        let svg = Svg::read(FontData::new(buf.data())).unwrap();
+
+        let subsetted = subset(&svg, &[0, 1, 2], SubsetFlags::SUBSET_FLAGS_GZIP_SVG);
+        let subsetted = Svg::read(FontData::new(&subsetted)).unwrap();
+        let document_list = subsetted.svg_document_list().unwrap();
+        // glyphs 1 and 2 share one document, now a single record
+        assert_eq!(document_list.num_entries(), 1);
+        let document = subsetted.glyph_data(GlyphId::new(2)).unwrap().unwrap();
+        assert!(document.starts_with(GZIP_MAGIC));
+        assert_eq!(decompress(document).unwrap(), PLAIN);
+        assert!(uses_palette(&subsetted));
+    }
+}
@@ -0,0 +1,17 @@
+//! impl NameIdClosure for trak table
+
+use crate::{NameIdClosure, Plan};
+use fontcull_write_fonts::read::tables::trak::Trak;
+
+// trak has no glyph IDs and is passed through, but its tracks are named
+impl NameIdClosure for Trak<'_> {
+    fn collect_name_ids(&self, plan: &mut Plan) {
+        for track_data in [self.horiz(), self.vert()].into_iter().flatten() {
+            let Ok(track_data) = track_data else {
+                continue;
+            };
+            plan.name_ids
+                .extend_unsorted(track_data.track_table().iter().map(|x| x.name_index()));
+        }
+    }
+}
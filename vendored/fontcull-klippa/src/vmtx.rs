@@ -0,0 +1,194 @@
+//! impl subset() for vmtx
+
+use crate::serialize::Serializer;
+use crate::{Plan, Subset, SubsetError, SubsetError::SubsetTableError};
+use fontcull_write_fonts::types::{FWord, GlyphId, UfWord};
+use fontcull_write_fonts::{
+    read::{
+        tables::{vhea::Vhea, vmtx::Vmtx},
+        FontRef, TableProvider, TopLevelTable,
+    },
+    FontBuilder,
+};
+
+// reference: subset() for vmtx/vhea in harfbuzz, which shares its code with hmtx
+// https://github.com/harfbuzz/harfbuzz/blob/a070f9ebbe88dc71b248af9731dd49ec93f4e6e6/src/hb-ot-hmtx-table.hh#L214
+impl Subset for Vmtx<'_> {
+    fn subset(
+        &self,
+        plan: &Plan,
+        font: &FontRef,
+        s: &mut Serializer,
+        builder: &mut FontBuilder,
+    ) -> Result<(), SubsetError> {
+        let v_metrics = self.v_metrics();
+        let side_bearings = self.top_side_bearings();
+
+        let last_gid = plan.num_output_glyphs - 1;
+        if last_gid >= v_metrics.len() + side_bearings.len() {
+            return Err(SubsetTableError(Vmtx::TAG));
+        }
+
+        let new_num_v_metrics = compute_new_num_v_metrics(self, plan);
+        //subsetted vmtx table length
+        let vmtx_cap = new_num_v_metrics * 4 + (plan.num_output_glyphs - new_num_v_metrics) * 2;
+        s.allocate_size(vmtx_cap, false)
+            .map_err(|_| SubsetError::SubsetTableError(Vmtx::TAG))?;
+
+        for (new_gid, old_gid) in &plan.new_to_old_gid_list {
+            let new_gid = new_gid.to_u32() as usize;
+            if new_gid < new_num_v_metrics {
+                let idx = 4 * new_gid;
+                let advance = UfWord::from(self.advance(*old_gid).unwrap());
+                s.copy_assign(idx, advance);
+
+                let tsb = FWord::from(self.side_bearing(*old_gid).unwrap());
+                s.copy_assign(idx + 2, tsb);
+            } else {
+                let idx = 4 * new_num_v_metrics + (new_gid - new_num_v_metrics) * 2;
+                let tsb = FWord::from(self.side_bearing(*old_gid).unwrap());
+                s.copy_assign(idx, tsb);
+            }
+        }
+
+        let Ok(vhea) = font.vhea() else {
+            return Ok(());
+        };
+
+        let mut vhea_out = vhea.offset_data().as_bytes().to_owned();
+        let new_num_v_metrics = (new_num_v_metrics as u16).to_be_bytes();
+        vhea_out
+            .get_mut(34..36)
+            .unwrap()
+            .copy_from_slice(&new_num_v_metrics);
+
+        builder.add_raw(Vhea::TAG, vhea_out);
+        Ok(())
+    }
+}
+
+fn compute_new_num_v_metrics(vmtx: &Vmtx, plan: &Plan) -> usize {
+    let mut num_long_metrics = plan.num_output_glyphs.min(0xFFFF);
+    let last_advance = get_new_gid_advance(vmtx, GlyphId::from(num_long_metrics as u32 - 1), plan);
+
+    while num_long_metrics > 1 {
+        let advance = get_new_gid_advance(vmtx, GlyphId::from(num_long_metrics as u32 - 2), plan);
+        if advance != last_advance {
+            break;
+        }
+        num_long_metrics -= 1;
+    }
+    num_long_metrics
+}
+
+fn get_new_gid_advance(vmtx: &Vmtx, new_gid: GlyphId, plan: &Plan) -> u16 {
+    let Some(old_gid) = plan.reverse_glyph_map.get(&new_gid) else {
+        return 0;
+    };
+    vmtx.advance(*old_gid).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fontcull_write_fonts::tables::{
+        vhea::Vhea as WriteVhea,
+        vmtx::{LongMetric, Vmtx as WriteVmtx},
+    };
+
+    // 5 glyphs with advances 500, 600, 700, 700, 700 and top side bearings
+    // 10, 20, 30, 40, 50; the last glyph's advance is implied
+    fn font_data() -> Vec<u8> {
+        let v_metrics = [(500, 10), (600, 20), (700, 30), (700, 40)]
+            .into_iter()
+            .map(|(advance, side_bearing)| LongMetric {
+                advance,
+                side_bearing,
+            })
+            .collect();
+        let vmtx = WriteVmtx::new(v_metrics, vec![50]);
+        let vhea = WriteVhea::new(
+            FWord::new(500),
+            FWord::new(-500),
+            FWord::new(0),
+            UfWord::new(700),
+            FWord::new(10),
+            FWord::new(0),
+            FWord::new(700),
+            0,
+            1,
+            0,
+            4,
+        );
+        let mut builder = FontBuilder::new();
+        builder.add_table(&vhea).unwrap();
+        builder.add_table(&vmtx).unwrap();
+        builder.build()
+    }
+
+    fn plan(old_gids: &[u32]) -> Plan {
+        let mut plan = Plan::default();
+        for (new_gid, old_gid) in old_gids.iter().enumerate() {
+            let (old_gid, new_gid) = (GlyphId::new(*old_gid), GlyphId::new(new_gid as u32));
+            plan.glyph_map.insert(old_gid, new_gid);
+            plan.reverse_glyph_map.insert(new_gid, old_gid);
+            plan.new_to_old_gid_list.push((new_gid, old_gid));
+        }
+        plan.num_output_glyphs = old_gids.len();
+        plan
+    }
+
+    // returns the subset vmtx and the numOfLongVerMetrics of the new vhea
+    fn subset(old_gids: &[u32]) -> (Vec<u8>, u16) {
+        let data = font_data();
+        let font = FontRef::new(&data).unwrap();
+        let vmtx = font.vmtx().unwrap();
+
+        let mut s = Serializer::new(1024);
+        assert_eq!(s.start_serialize(), Ok(()));
+        let mut builder = FontBuilder::new();
+        vmtx.subset(&plan(old_gids), &font, &mut s, &mut builder)
+            .unwrap();
+        s.end_serialize();
+        assert!(!s.in_error());
+
+        let vhea_font = builder.build();
+        let vhea = FontRef::new(&vhea_font).unwrap().vhea().unwrap();
+        (s.copy_bytes(), vhea.number_of_long_ver_metrics())
+    }
+
+    #[test]
+    fn test_subset_vmtx_truncates_long_metrics() {
+        // the advances are 500, 600, 700, 700: the last 700 is implied
+        let (vmtx, num_long_metrics) = subset(&[0, 1, 3, 4]);
+        assert_eq!(num_long_metrics, 3);
+        assert_eq!(
+            vmtx,
+            [
+                0x01, 0xF4, 0x00, 0x0A, // 500, 10
+                0x02, 0x58, 0x00, 0x14, // 600, 20
+                0x02, 0xBC, 0x00, 0x28, // 700, 40
+                0x00, 0x32, // 50
+            ]
+        );
+
+        // all of the kept glyphs have the same advance
+        let (vmtx, num_long_metrics) = subset(&[2, 3, 4]);
+        assert_eq!(num_long_metrics, 1);
+        assert_eq!(vmtx, [0x02, 0xBC, 0x00, 0x1E, 0x00, 0x28, 0x00, 0x32]);
+    }
+
+    #[test]
+    fn test_subset_vmtx_keeps_distinct_last_advance() {
+        let (vmtx, num_long_metrics) = subset(&[0, 4, 1]);
+        assert_eq!(num_long_metrics, 3);
+        assert_eq!(
+            vmtx,
+            [
+                0x01, 0xF4, 0x00, 0x0A, // 500, 10
+                0x02, 0xBC, 0x00, 0x32, // 700, 50
+                0x02, 0x58, 0x00, 0x14, // 600, 20
+            ]
+        );
+    }
+}